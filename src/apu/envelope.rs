@@ -0,0 +1,71 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+/// The volume envelope, NRx2, clocked at 64Hz by the frame sequencer.
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Envelope {
+    register: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    const INCREASE_MASK: u8 = 0b1000;
+
+    pub fn get_register(&self) -> u8 {
+        self.register
+    }
+
+    /// Takes effect on the next trigger.
+    pub fn set_register(&mut self, value: u8) {
+        self.register = value;
+    }
+
+    /// The DAC is on unless the initial volume is 0 and the envelope decreases.
+    pub fn is_dac_enabled(&self) -> bool {
+        self.register & 0xF8 != 0
+    }
+
+    pub fn get_volume(&self) -> u8 {
+        self.volume
+    }
+
+    fn get_period(&self) -> u8 {
+        self.register & 0b111
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.get_period();
+    }
+
+    pub fn clock(&mut self) {
+        // a period of 0 stops the envelope
+        if self.get_period() == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return;
+        }
+        self.timer = self.get_period();
+        if self.register & Self::INCREASE_MASK != 0 {
+            self.volume = (self.volume + 1).min(15);
+        } else {
+            self.volume = self.volume.saturating_sub(1);
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.put_u8(self.register);
+        state.put_u8(self.volume);
+        state.put_u8(self.timer);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.register = state.get_u8()?;
+        self.volume = state.get_u8()? & 0x0F;
+        self.timer = state.get_u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+/// Turns a channel off after a delay, clocked at 256Hz by the frame sequencer.
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LengthCounter {
+    counter: u16,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    /// Load the length written in NRx1, the counter goes from `max - value` to 0.
+    pub fn load(&mut self, value: u8, max: u16) {
+        self.counter = max - u16::from(value);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// A trigger with an expired counter restarts it at the max.
+    pub fn trigger(&mut self, max: u16) {
+        if self.counter == 0 {
+            self.counter = max;
+        }
+    }
+
+    /// Returns true when the counter expires, turning the channel off.
+    pub fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+        self.counter -= 1;
+        self.counter == 0
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.put_u16(self.counter);
+        state.put_bool(self.enabled);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.counter = state.get_u16()?;
+        self.enabled = state.get_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,588 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+pub use self::noise::Noise;
+pub use self::resampler::Resampler;
+pub use self::square::Square;
+pub use self::wave::Wave;
+
+mod envelope;
+mod length;
+pub mod noise;
+pub mod resampler;
+pub mod square;
+pub mod wave;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 4] = [
+        Channel::Square1,
+        Channel::Square2,
+        Channel::Wave,
+        Channel::Noise,
+    ];
+
+    /// Bit of the channel in NR51 (right side) and NR52.
+    const fn get_mask(self) -> u8 {
+        match self {
+            Channel::Square1 => 1 << 0,
+            Channel::Square2 => 1 << 1,
+            Channel::Wave => 1 << 2,
+            Channel::Noise => 1 << 3,
+        }
+    }
+}
+
+/// The Audio Processing Unit, owns the sound registers (0xFF10-0xFF26) and the wave RAM (0xFF30-0xFF3F).
+///
+/// The channels are clocked every M-cycle, and their length, envelope and sweep by the frame sequencer,
+/// itself clocked at 512Hz by the falling edge of bit 4 of DIV.
+/// A stereo sample is produced every M-cycle, and resampled to the rate set with `set_output_rate`,
+/// see `take_samples`.
+///
+/// It starts turned off, like at power on, the boot ROM turns it on.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Apu {
+    channel1: Square,
+    channel2: Square,
+    channel3: Wave,
+    channel4: Noise,
+    powered: bool,
+    /// NR50, volume of each side.
+    master_volume: u8,
+    /// NR51, bits 4-7 send channels 1-4 to the left, bits 0-3 to the right.
+    panning: u8,
+    /// Step of the frame sequencer, 0 to 7.
+    sequencer_step: u8,
+    /// Last value of the DIV bit clocking the frame sequencer.
+    div_bit: bool,
+    /// Interleaved stereo samples not taken yet.
+    samples: Vec<i16>,
+    /// None to output at `SAMPLE_RATE`.
+    resampler: Option<Resampler>,
+    /// Channels left out of the mix by the host, the emulated state is unaffected.
+    muted: u8,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Apu {
+            channel1: Square::new(true),
+            channel2: Square::new(false),
+            channel3: Wave::default(),
+            channel4: Noise::default(),
+            powered: false,
+            master_volume: 0,
+            panning: 0,
+            sequencer_step: 0,
+            div_bit: false,
+            samples: Vec::new(),
+            resampler: None,
+            muted: 0,
+        }
+    }
+}
+
+impl Apu {
+    pub const NR10_REGISTER: u16 = 0xFF10;
+    pub const NR11_REGISTER: u16 = 0xFF11;
+    pub const NR12_REGISTER: u16 = 0xFF12;
+    pub const NR13_REGISTER: u16 = 0xFF13;
+    pub const NR14_REGISTER: u16 = 0xFF14;
+    pub const NR21_REGISTER: u16 = 0xFF16;
+    pub const NR22_REGISTER: u16 = 0xFF17;
+    pub const NR23_REGISTER: u16 = 0xFF18;
+    pub const NR24_REGISTER: u16 = 0xFF19;
+    pub const NR30_REGISTER: u16 = 0xFF1A;
+    pub const NR31_REGISTER: u16 = 0xFF1B;
+    pub const NR32_REGISTER: u16 = 0xFF1C;
+    pub const NR33_REGISTER: u16 = 0xFF1D;
+    pub const NR34_REGISTER: u16 = 0xFF1E;
+    pub const NR41_REGISTER: u16 = 0xFF20;
+    pub const NR42_REGISTER: u16 = 0xFF21;
+    pub const NR43_REGISTER: u16 = 0xFF22;
+    pub const NR44_REGISTER: u16 = 0xFF23;
+    pub const NR50_REGISTER: u16 = 0xFF24;
+    pub const NR51_REGISTER: u16 = 0xFF25;
+    pub const NR52_REGISTER: u16 = 0xFF26;
+    pub const WAVE_RAM_START: u16 = 0xFF30;
+    pub const WAVE_RAM_END: u16 = 0xFF3F;
+
+    /// One sample per M-cycle.
+    pub const SAMPLE_RATE: u32 = 1 << 20;
+    /// Bit of the timer counter clocking the frame sequencer, bit 4 of DIV.
+    const SEQUENCER_BIT: u16 = 1 << 12;
+    const POWER_MASK: u8 = 0x80;
+    /// Amplitude of a channel at full volume, leaves room to mix 4 of them.
+    const CHANNEL_AMPLITUDE: f32 = 8192.0;
+
+    /// Whether `addr` is one of the registers of the APU.
+    pub const fn is_register(addr: u16) -> bool {
+        matches!(addr, Self::NR10_REGISTER..=Self::NR14_REGISTER)
+            || matches!(addr, Self::NR21_REGISTER..=Self::NR34_REGISTER)
+            || matches!(addr, Self::NR41_REGISTER..=Self::NR52_REGISTER)
+            || matches!(addr, Self::WAVE_RAM_START..=Self::WAVE_RAM_END)
+    }
+
+    pub fn get_register(&self, addr: u16) -> u8 {
+        match addr {
+            Self::NR10_REGISTER..=Self::NR14_REGISTER => self
+                .channel1
+                .get_register((addr - Self::NR10_REGISTER) as u8),
+            Self::NR21_REGISTER..=Self::NR24_REGISTER => self
+                .channel2
+                .get_register((addr - Self::NR21_REGISTER + 1) as u8),
+            Self::NR30_REGISTER..=Self::NR34_REGISTER => self
+                .channel3
+                .get_register((addr - Self::NR30_REGISTER) as u8),
+            Self::NR41_REGISTER..=Self::NR44_REGISTER => self
+                .channel4
+                .get_register((addr - Self::NR41_REGISTER + 1) as u8),
+            Self::NR50_REGISTER => self.master_volume,
+            Self::NR51_REGISTER => self.panning,
+            Self::NR52_REGISTER => self.get_status(),
+            Self::WAVE_RAM_START..=Self::WAVE_RAM_END => {
+                self.channel3.read_ram(addr - Self::WAVE_RAM_START)
+            }
+            _ => 0xFF,
+        }
+    }
+
+    pub fn set_register(&mut self, addr: u16, value: u8) {
+        if addr == Self::NR52_REGISTER {
+            self.set_powered(value & Self::POWER_MASK != 0);
+            return;
+        }
+        // while off, only the wave RAM can be written
+        if !self.powered && !matches!(addr, Self::WAVE_RAM_START..=Self::WAVE_RAM_END) {
+            return;
+        }
+        match addr {
+            Self::NR10_REGISTER..=Self::NR14_REGISTER => self
+                .channel1
+                .set_register((addr - Self::NR10_REGISTER) as u8, value),
+            Self::NR21_REGISTER..=Self::NR24_REGISTER => self
+                .channel2
+                .set_register((addr - Self::NR21_REGISTER + 1) as u8, value),
+            Self::NR30_REGISTER..=Self::NR34_REGISTER => self
+                .channel3
+                .set_register((addr - Self::NR30_REGISTER) as u8, value),
+            Self::NR41_REGISTER..=Self::NR44_REGISTER => self
+                .channel4
+                .set_register((addr - Self::NR41_REGISTER + 1) as u8, value),
+            Self::NR50_REGISTER => self.master_volume = value,
+            Self::NR51_REGISTER => self.panning = value,
+            Self::WAVE_RAM_START..=Self::WAVE_RAM_END => {
+                self.channel3.write_ram(addr - Self::WAVE_RAM_START, value)
+            }
+            _ => {}
+        }
+    }
+
+    /// NR52, the power and whether each channel is playing.
+    fn get_status(&self) -> u8 {
+        0x70 | (u8::from(self.powered) << 7)
+            | u8::from(self.channel1.is_enabled())
+            | (u8::from(self.channel2.is_enabled()) << 1)
+            | (u8::from(self.channel3.is_enabled()) << 2)
+            | (u8::from(self.channel4.is_enabled()) << 3)
+    }
+
+    pub fn is_powered(&self) -> bool {
+        self.powered
+    }
+
+    /// Turning the APU off clears all its registers, turning it on restarts the frame sequencer.
+    fn set_powered(&mut self, powered: bool) {
+        if self.powered && !powered {
+            self.channel1 = Square::new(true);
+            self.channel2 = Square::new(false);
+            self.channel3.power_off();
+            self.channel4 = Noise::default();
+            self.master_volume = 0;
+            self.panning = 0;
+        } else if !self.powered && powered {
+            self.sequencer_step = 0;
+        }
+        self.powered = powered;
+    }
+
+    pub fn get_channel1(&self) -> &Square {
+        &self.channel1
+    }
+
+    pub fn get_channel2(&self) -> &Square {
+        &self.channel2
+    }
+
+    pub fn get_channel3(&self) -> &Wave {
+        &self.channel3
+    }
+
+    pub fn get_channel4(&self) -> &Noise {
+        &self.channel4
+    }
+
+    /// Advance one M-cycle, `div_counter` is the counter of the timer after its own tick.
+    ///
+    /// Cycles: 4
+    pub fn tick(&mut self, div_counter: u16) {
+        let div_bit = div_counter & Self::SEQUENCER_BIT != 0;
+        if self.div_bit && !div_bit {
+            self.clock_sequencer();
+        }
+        self.div_bit = div_bit;
+        self.channel1.tick();
+        self.channel2.tick();
+        self.channel3.tick();
+        self.channel4.tick();
+        // samples are dropped past one second of them, if nobody takes them
+        if self.samples.len() < self.get_output_rate() as usize * 2 {
+            let sample = self.mix();
+            match &mut self.resampler {
+                Some(resampler) => resampler.push(sample, &mut self.samples),
+                None => self.samples.extend_from_slice(&sample),
+            }
+        }
+    }
+
+    fn clock_sequencer(&mut self) {
+        let step = self.sequencer_step;
+        self.sequencer_step = (step + 1) & 0b111;
+        if step & 1 == 0 {
+            self.channel1.clock_length();
+            self.channel2.clock_length();
+            self.channel3.clock_length();
+            self.channel4.clock_length();
+        }
+        if step == 2 || step == 6 {
+            self.channel1.clock_sweep();
+        }
+        if step == 7 {
+            self.channel1.clock_envelope();
+            self.channel2.clock_envelope();
+            self.channel4.clock_envelope();
+        }
+    }
+
+    /// A DAC maps the digital output 0-15 to -1.0-1.0, a disabled one outputs 0.
+    fn to_analog(output: u8, dac_enabled: bool) -> f32 {
+        if dac_enabled {
+            f32::from(output) / 7.5 - 1.0
+        } else {
+            0.0
+        }
+    }
+
+    /// Left and right samples.
+    fn mix(&self) -> [i16; 2] {
+        let outputs = [
+            Self::to_analog(self.channel1.get_output(), self.channel1.is_dac_enabled()),
+            Self::to_analog(self.channel2.get_output(), self.channel2.is_dac_enabled()),
+            Self::to_analog(self.channel3.get_output(), self.channel3.is_dac_enabled()),
+            Self::to_analog(self.channel4.get_output(), self.channel4.is_dac_enabled()),
+        ];
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (channel, output) in Channel::ALL.into_iter().zip(outputs) {
+            let mask = channel.get_mask();
+            if self.muted & mask != 0 {
+                continue;
+            }
+            if self.panning & (mask << 4) != 0 {
+                left += output;
+            }
+            if self.panning & mask != 0 {
+                right += output;
+            }
+        }
+        // each side goes from 1/8 to 8/8
+        let volume = |bits: u8| f32::from((bits & 0b111) + 1) / 8.0 * Self::CHANNEL_AMPLITUDE;
+        [
+            (left * volume(self.master_volume >> 4)) as i16,
+            (right * volume(self.master_volume)) as i16,
+        ]
+    }
+
+    /// Interleaved stereo samples produced since the last call, at the output rate.
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        std::mem::take(&mut self.samples)
+    }
+
+    /// Resample the output to `rate`, like 44100 or 48000, samples not taken yet are dropped.
+    pub fn set_output_rate(&mut self, rate: u32) {
+        self.samples.clear();
+        self.resampler = (rate < Self::SAMPLE_RATE).then(|| Resampler::new(rate));
+    }
+
+    /// Mute or unmute a channel in the output, the game still sees it playing.
+    pub fn set_channel_enabled(&mut self, channel: Channel, enabled: bool) {
+        if enabled {
+            self.muted &= !channel.get_mask();
+        } else {
+            self.muted |= channel.get_mask();
+        }
+    }
+
+    pub fn is_channel_enabled(&self, channel: Channel) -> bool {
+        self.muted & channel.get_mask() == 0
+    }
+
+    /// Mute every channel but `channel`.
+    pub fn solo_channel(&mut self, channel: Channel) {
+        for other in Channel::ALL {
+            self.set_channel_enabled(other, other == channel);
+        }
+    }
+
+    /// Power cycle, the output settings (rate and muted channels) are kept.
+    pub fn reset(&mut self) {
+        let mut resampler = self.resampler.take();
+        if let Some(resampler) = &mut resampler {
+            resampler.clear();
+        }
+        *self = Apu {
+            resampler,
+            muted: self.muted,
+            ..Default::default()
+        };
+    }
+
+    /// `SAMPLE_RATE` unless resampled.
+    pub fn get_output_rate(&self) -> u32 {
+        self.resampler
+            .as_ref()
+            .map_or(Self::SAMPLE_RATE, Resampler::get_output_rate)
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.channel1.save_state(state);
+        self.channel2.save_state(state);
+        self.channel3.save_state(state);
+        self.channel4.save_state(state);
+        state.put_bool(self.powered);
+        state.put_u8(self.master_volume);
+        state.put_u8(self.panning);
+        state.put_u8(self.sequencer_step);
+        state.put_bool(self.div_bit);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.channel1.load_state(state)?;
+        self.channel2.load_state(state)?;
+        self.channel3.load_state(state)?;
+        self.channel4.load_state(state)?;
+        self.powered = state.get_bool()?;
+        self.master_volume = state.get_u8()?;
+        self.panning = state.get_u8()?;
+        self.sequencer_step = state.get_u8()? & 0b111;
+        self.div_bit = state.get_bool()?;
+        self.samples.clear();
+        if let Some(resampler) = &mut self.resampler {
+            resampler.clear();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Apu, Channel};
+
+    /// Turned on, every channel on both sides at full volume.
+    fn apu() -> Apu {
+        let mut apu = Apu::default();
+        apu.set_register(Apu::NR52_REGISTER, 0x80);
+        apu.set_register(Apu::NR50_REGISTER, 0x77);
+        apu.set_register(Apu::NR51_REGISTER, 0xFF);
+        apu
+    }
+
+    /// Run `cycles` M-cycles with the DIV counter running from 0.
+    fn run(apu: &mut Apu, counter: &mut u16, cycles: usize) {
+        for _ in 0..cycles {
+            *counter = counter.wrapping_add(4);
+            apu.tick(*counter);
+        }
+    }
+
+    #[test]
+    fn square_wave() {
+        let mut apu = apu();
+        let mut counter = 0;
+        // 50% duty, full volume, frequency 2032 (16 M-cycles per step)
+        apu.set_register(Apu::NR21_REGISTER, 0x80);
+        apu.set_register(Apu::NR22_REGISTER, 0xF0);
+        apu.set_register(Apu::NR23_REGISTER, 0xF0);
+        apu.set_register(Apu::NR24_REGISTER, 0x87);
+        assert!(apu.get_channel2().is_enabled());
+        run(&mut apu, &mut counter, 16 * 8);
+        let samples = apu.take_samples();
+        assert_eq!(samples.len(), 16 * 8 * 2);
+        // 1 waveform, half high
+        let high = samples.iter().filter(|&&s| s > 0).count();
+        assert_eq!(high, 16 * 4 * 2);
+        assert_eq!(samples.iter().copied().max(), Some(8192));
+    }
+
+    #[test]
+    fn length_and_sweep() {
+        let mut apu = apu();
+        let mut counter = 0;
+        // length of 1, expires on the first length clock
+        apu.set_register(Apu::NR21_REGISTER, 0x3F);
+        apu.set_register(Apu::NR22_REGISTER, 0xF0);
+        apu.set_register(Apu::NR24_REGISTER, 0xC0);
+        assert!(apu.get_channel2().is_enabled());
+        // the frame sequencer runs at 512Hz, every 2048 M-cycles
+        run(&mut apu, &mut counter, 2048);
+        assert!(!apu.get_channel2().is_enabled());
+
+        // sweeping up from 0x400 overflows on the 1st sweep clock
+        apu.set_register(Apu::NR10_REGISTER, 0x11);
+        apu.set_register(Apu::NR12_REGISTER, 0xF0);
+        apu.set_register(Apu::NR13_REGISTER, 0x00);
+        apu.set_register(Apu::NR14_REGISTER, 0x84);
+        assert!(apu.get_channel1().is_enabled());
+        assert_eq!(apu.get_register(Apu::NR10_REGISTER), 0x91);
+        run(&mut apu, &mut counter, 2048 * 4);
+        assert!(!apu.get_channel1().is_enabled());
+    }
+
+    #[test]
+    fn wave_channel() {
+        let mut apu = apu();
+        let mut counter = 0;
+        for (i, addr) in (Apu::WAVE_RAM_START..=Apu::WAVE_RAM_END).enumerate() {
+            apu.set_register(addr, (i as u8) << 4 | 0x0F);
+        }
+        // 2 M-cycles per sample, at 100%
+        apu.set_register(Apu::NR30_REGISTER, 0x80);
+        apu.set_register(Apu::NR32_REGISTER, 0x20);
+        apu.set_register(Apu::NR33_REGISTER, 0xFC);
+        apu.set_register(Apu::NR34_REGISTER, 0x87);
+        assert!(apu.get_channel3().is_enabled());
+        assert_eq!(apu.get_register(Apu::NR32_REGISTER), 0xBF);
+
+        // the trigger delays the 1st sample, which is the 2nd of the wave RAM
+        run(&mut apu, &mut counter, 3);
+        assert_eq!(apu.get_channel3().get_output(), 0);
+        run(&mut apu, &mut counter, 1);
+        assert_eq!(apu.get_channel3().get_output(), 0x0F);
+        // the wave RAM is only accessible right when the channel reads it
+        assert_eq!(apu.get_register(Apu::WAVE_RAM_START + 5), 0x0F);
+        run(&mut apu, &mut counter, 1);
+        assert_eq!(apu.get_register(Apu::WAVE_RAM_START), 0xFF);
+        run(&mut apu, &mut counter, 1);
+        assert_eq!(apu.get_channel3().get_output(), 1);
+        apu.set_register(Apu::WAVE_RAM_START + 5, 0x42);
+        assert_eq!(apu.get_register(Apu::WAVE_RAM_START + 5), 0x42);
+
+        // turning the DAC off stops the channel and gives the wave RAM back
+        apu.set_register(Apu::NR30_REGISTER, 0x00);
+        assert!(!apu.get_channel3().is_enabled());
+        assert_eq!(apu.get_register(Apu::WAVE_RAM_START + 5), 0x5F);
+        assert_eq!(apu.get_register(Apu::WAVE_RAM_START + 1), 0x42);
+    }
+
+    #[test]
+    fn noise_channel() {
+        /// Output of the channel on each of the M-cycles following the skipped ones.
+        fn outputs(apu: &mut Apu, skip: usize, count: usize) -> Vec<u8> {
+            let mut counter = 0;
+            run(apu, &mut counter, skip);
+            (0..count)
+                .map(|_| {
+                    run(apu, &mut counter, 1);
+                    apu.get_channel4().get_output()
+                })
+                .collect()
+        }
+
+        // 7 bits LFSR shifted every 2 M-cycles, it repeats after 127 shifts
+        let mut apu = apu();
+        apu.set_register(Apu::NR42_REGISTER, 0xF0);
+        apu.set_register(Apu::NR43_REGISTER, 0x08);
+        apu.set_register(Apu::NR44_REGISTER, 0x80);
+        assert_eq!(apu.get_register(Apu::NR43_REGISTER), 0x08);
+        let short = outputs(&mut apu, 64, 127 * 2 * 2);
+        assert_eq!(short[..127 * 2], short[127 * 2..]);
+        assert!(short.contains(&0) && short.contains(&15));
+
+        // the 15 bits one doesn't
+        apu.set_register(Apu::NR43_REGISTER, 0x00);
+        apu.set_register(Apu::NR44_REGISTER, 0x80);
+        let long = outputs(&mut apu, 64, 127 * 2 * 2);
+        assert_ne!(long[..127 * 2], long[127 * 2..]);
+    }
+
+    #[test]
+    fn mixer() {
+        let mut apu = apu();
+        let mut counter = 0;
+        // channel 2 high at full volume, only on the left at half volume
+        apu.set_register(Apu::NR50_REGISTER, 0x37);
+        apu.set_register(Apu::NR51_REGISTER, 0x20);
+        apu.set_register(Apu::NR21_REGISTER, 0x80);
+        apu.set_register(Apu::NR22_REGISTER, 0xF0);
+        apu.set_register(Apu::NR24_REGISTER, 0x80);
+        run(&mut apu, &mut counter, 1);
+        assert_eq!(apu.take_samples(), [4096, 0]);
+        assert_eq!(apu.get_register(Apu::NR52_REGISTER), 0xF2);
+
+        // turning it off clears the registers but the wave RAM, and ignores writes
+        apu.set_register(Apu::WAVE_RAM_START, 0x42);
+        apu.set_register(Apu::NR52_REGISTER, 0x00);
+        assert_eq!(apu.get_register(Apu::NR52_REGISTER), 0x70);
+        assert_eq!(apu.get_register(Apu::NR50_REGISTER), 0x00);
+        assert_eq!(apu.get_register(Apu::NR22_REGISTER), 0x00);
+        apu.set_register(Apu::NR51_REGISTER, 0xFF);
+        assert_eq!(apu.get_register(Apu::NR51_REGISTER), 0x00);
+        assert_eq!(apu.get_register(Apu::WAVE_RAM_START), 0x42);
+        run(&mut apu, &mut counter, 1);
+        assert_eq!(apu.take_samples(), [0, 0]);
+    }
+
+    #[test]
+    fn resampling() {
+        let mut apu = apu();
+        let mut counter = 0;
+        // the DAC of channel 2 is on but the channel doesn't play, a constant -1.0
+        apu.set_register(Apu::NR22_REGISTER, 0xF0);
+        apu.set_output_rate(48000);
+        run(&mut apu, &mut counter, Apu::SAMPLE_RATE as usize / 16);
+        let samples = apu.take_samples();
+        assert_eq!(samples.len(), 48000 / 16 * 2);
+        assert!(samples.iter().all(|&sample| sample == -8192));
+    }
+
+    #[test]
+    fn mute_and_solo() {
+        let mut apu = apu();
+        let mut counter = 0;
+        // constant -1.0 from the DACs of channels 2 and 4
+        apu.set_register(Apu::NR22_REGISTER, 0xF0);
+        apu.set_register(Apu::NR42_REGISTER, 0xF0);
+        run(&mut apu, &mut counter, 1);
+        assert_eq!(apu.take_samples(), [-16384, -16384]);
+
+        apu.set_channel_enabled(Channel::Noise, false);
+        run(&mut apu, &mut counter, 1);
+        assert_eq!(apu.take_samples(), [-8192, -8192]);
+        assert!(!apu.is_channel_enabled(Channel::Noise));
+
+        apu.solo_channel(Channel::Wave);
+        run(&mut apu, &mut counter, 1);
+        assert_eq!(apu.take_samples(), [0, 0]);
+        // the registers don't see it
+        assert_eq!(apu.get_register(Apu::NR22_REGISTER), 0xF0);
+        assert!(apu.get_channel2().is_dac_enabled());
+    }
+}
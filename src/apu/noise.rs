@@ -0,0 +1,162 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+use super::{envelope::Envelope, length::LengthCounter};
+
+/// Channel 4, outputs the low bit of a LFSR, NR41-NR44.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Noise {
+    length: LengthCounter,
+    envelope: Envelope,
+    /// NR43, clock shift, LFSR width and divisor.
+    polynomial: u8,
+    /// 15 bits linear feedback shift register.
+    lfsr: u16,
+    /// Clock cycles until the next LFSR shift.
+    timer: u32,
+    enabled: bool,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Noise {
+            length: LengthCounter::default(),
+            envelope: Envelope::default(),
+            polynomial: 0,
+            lfsr: Self::LFSR_RESET,
+            timer: 0,
+            enabled: false,
+        }
+    }
+}
+
+impl Noise {
+    const MAX_LENGTH: u16 = 64;
+    const TRIGGER_MASK: u8 = 0x80;
+    const LENGTH_ENABLE_MASK: u8 = 0x40;
+    const SHORT_MODE_MASK: u8 = 0b1000;
+    const LFSR_RESET: u16 = 0x7FFF;
+
+    /// Register NR4`index`, unused and write only bits read as 1.
+    pub fn get_register(&self, index: u8) -> u8 {
+        match index {
+            1 => 0xFF,
+            2 => self.envelope.get_register(),
+            3 => self.polynomial,
+            _ => 0xBF | (u8::from(self.length.is_enabled()) << 6),
+        }
+    }
+
+    pub fn set_register(&mut self, index: u8, value: u8) {
+        match index {
+            1 => self.length.load(value & 0x3F, Self::MAX_LENGTH),
+            2 => {
+                self.envelope.set_register(value);
+                if !self.envelope.is_dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.polynomial = value,
+            _ => {
+                self.length
+                    .set_enabled(value & Self::LENGTH_ENABLE_MASK != 0);
+                if value & Self::TRIGGER_MASK != 0 {
+                    self.trigger();
+                }
+            }
+        }
+    }
+
+    /// Clock cycles between 2 shifts, the divisor shifted left by the clock shift.
+    fn get_period(&self) -> u32 {
+        let divisor = match self.polynomial & 0b111 {
+            0 => 8,
+            code => u32::from(code) * 16,
+        };
+        divisor << (self.polynomial >> 4)
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.is_dac_enabled();
+        self.length.trigger(Self::MAX_LENGTH);
+        self.timer = self.get_period();
+        self.envelope.trigger();
+        self.lfsr = Self::LFSR_RESET;
+    }
+
+    /// Whether the channel is playing, as read in NR52.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_dac_enabled(&self) -> bool {
+        self.envelope.is_dac_enabled()
+    }
+
+    /// Advance one M-cycle.
+    ///
+    /// Cycles: 4
+    pub fn tick(&mut self) {
+        // with a clock shift of 14 or 15 the LFSR isn't clocked
+        if !self.enabled || self.polynomial >> 4 >= 14 {
+            return;
+        }
+        let mut cycles = 4;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.get_period();
+            self.shift();
+        }
+        self.timer -= cycles;
+    }
+
+    fn shift(&mut self) {
+        let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+        // the short mode also feeds bit 6, making a 7 bits LFSR
+        if self.polynomial & Self::SHORT_MODE_MASK != 0 {
+            self.lfsr = (self.lfsr & !(1 << 6)) | (feedback << 6);
+        }
+    }
+
+    /// 256Hz
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// 64Hz
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Digital output, 0 to 15.
+    pub fn get_output(&self) -> u8 {
+        // the output is the inverted low bit
+        if self.enabled && self.lfsr & 1 == 0 {
+            self.envelope.get_volume()
+        } else {
+            0
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.length.save_state(state);
+        self.envelope.save_state(state);
+        state.put_u8(self.polynomial);
+        state.put_u16(self.lfsr);
+        state.put_u32(self.timer);
+        state.put_bool(self.enabled);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.length.load_state(state)?;
+        self.envelope.load_state(state)?;
+        self.polynomial = state.get_u8()?;
+        self.lfsr = state.get_u16()? & 0x7FFF;
+        self.timer = state.get_u32()?;
+        self.enabled = state.get_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+use super::Apu;
+
+/// Brings the stereo output of the APU down to the rate of the audio device.
+///
+/// Each output sample is the average of the input samples it covers,
+/// which also filters out most of what the output rate can't represent.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Resampler {
+    output_rate: u32,
+    /// Progress toward the next output sample, one is due every `Apu::SAMPLE_RATE`.
+    phase: u32,
+    sum: [i64; 2],
+    count: u32,
+}
+
+impl Resampler {
+    /// The output rate is clamped between 1Hz and `Apu::SAMPLE_RATE`.
+    pub fn new(output_rate: u32) -> Self {
+        Resampler {
+            output_rate: output_rate.clamp(1, Apu::SAMPLE_RATE),
+            phase: 0,
+            sum: [0; 2],
+            count: 0,
+        }
+    }
+
+    pub fn get_output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    /// Feed one stereo sample at `Apu::SAMPLE_RATE`, the output ones are appended to `output`.
+    pub fn push(&mut self, sample: [i16; 2], output: &mut Vec<i16>) {
+        self.sum[0] += i64::from(sample[0]);
+        self.sum[1] += i64::from(sample[1]);
+        self.count += 1;
+        self.phase += self.output_rate;
+        if self.phase >= Apu::SAMPLE_RATE {
+            self.phase -= Apu::SAMPLE_RATE;
+            let count = i64::from(self.count);
+            output.push((self.sum[0] / count) as i16);
+            output.push((self.sum[1] / count) as i16);
+            self.sum = [0; 2];
+            self.count = 0;
+        }
+    }
+
+    /// Drop the partial sample, after the input jumped (state load, reset).
+    pub fn clear(&mut self) {
+        self.phase = 0;
+        self.sum = [0; 2];
+        self.count = 0;
+    }
+}
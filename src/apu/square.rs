@@ -0,0 +1,262 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+use super::{envelope::Envelope, length::LengthCounter};
+
+/// Waveforms of the 4 duty cycles, 12.5%, 25%, 50% and 75%.
+const DUTY_PATTERNS: [u8; 4] = [0b00000001, 0b10000001, 0b10000111, 0b01111110];
+
+/// The frequency sweep of channel 1, NR10.
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Sweep {
+    register: u8,
+    /// Frequency the sweep computes from, copied on trigger.
+    shadow: u16,
+    timer: u8,
+    enabled: bool,
+    /// A subtraction was computed since the trigger, clearing the negate bit then kills the channel.
+    negated: bool,
+}
+
+impl Sweep {
+    const NEGATE_MASK: u8 = 0b1000;
+
+    fn get_period(&self) -> u8 {
+        (self.register >> 4) & 0b111
+    }
+
+    fn get_shift(&self) -> u8 {
+        self.register & 0b111
+    }
+
+    fn reload_timer(&mut self) {
+        // a period of 0 is treated as 8
+        self.timer = match self.get_period() {
+            0 => 8,
+            period => period,
+        };
+    }
+
+    /// The next frequency, above 2047 the channel is turned off.
+    fn compute(&mut self) -> u16 {
+        let delta = self.shadow >> self.get_shift();
+        if self.register & Self::NEGATE_MASK != 0 {
+            self.negated = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+}
+
+/// A pulse channel, channel 1 has a frequency sweep and channel 2 doesn't.
+///
+/// The registers are NRx0 to NRx4, NR10-NR14 for channel 1 and NR21-NR24 for channel 2
+/// (channel 2 has no NRx0).
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Square {
+    sweep: Option<Sweep>,
+    duty: u8,
+    /// Position in the waveform, 0 to 7.
+    duty_step: u8,
+    length: LengthCounter,
+    envelope: Envelope,
+    /// 11 bits, NRx3 and the low bits of NRx4.
+    frequency: u16,
+    /// Clock cycles until the next waveform step.
+    timer: u16,
+    enabled: bool,
+}
+
+impl Square {
+    const MAX_LENGTH: u16 = 64;
+    const TRIGGER_MASK: u8 = 0x80;
+    const LENGTH_ENABLE_MASK: u8 = 0x40;
+
+    pub fn new(with_sweep: bool) -> Self {
+        Square {
+            sweep: with_sweep.then(Sweep::default),
+            duty: 0,
+            duty_step: 0,
+            length: LengthCounter::default(),
+            envelope: Envelope::default(),
+            frequency: 0,
+            timer: 0,
+            enabled: false,
+        }
+    }
+
+    /// Register NRx`index`, unused and write only bits read as 1.
+    pub fn get_register(&self, index: u8) -> u8 {
+        match index {
+            0 => match &self.sweep {
+                Some(sweep) => 0x80 | sweep.register,
+                None => 0xFF,
+            },
+            1 => (self.duty << 6) | 0x3F,
+            2 => self.envelope.get_register(),
+            3 => 0xFF,
+            _ => 0xBF | (u8::from(self.length.is_enabled()) << 6),
+        }
+    }
+
+    pub fn set_register(&mut self, index: u8, value: u8) {
+        match index {
+            0 => {
+                if let Some(sweep) = &mut self.sweep {
+                    sweep.register = value & 0x7F;
+                    if sweep.negated && value & Sweep::NEGATE_MASK == 0 {
+                        self.enabled = false;
+                    }
+                }
+            }
+            1 => {
+                self.duty = value >> 6;
+                self.length.load(value & 0x3F, Self::MAX_LENGTH);
+            }
+            2 => {
+                self.envelope.set_register(value);
+                if !self.envelope.is_dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.frequency = (self.frequency & 0x700) | u16::from(value),
+            _ => {
+                self.frequency = (self.frequency & 0xFF) | (u16::from(value & 0b111) << 8);
+                self.length
+                    .set_enabled(value & Self::LENGTH_ENABLE_MASK != 0);
+                if value & Self::TRIGGER_MASK != 0 {
+                    self.trigger();
+                }
+            }
+        }
+    }
+
+    fn get_period(&self) -> u16 {
+        (2048 - self.frequency) * 4
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.is_dac_enabled();
+        self.length.trigger(Self::MAX_LENGTH);
+        self.timer = self.get_period();
+        self.envelope.trigger();
+        if let Some(sweep) = &mut self.sweep {
+            sweep.shadow = self.frequency;
+            sweep.negated = false;
+            sweep.reload_timer();
+            sweep.enabled = sweep.get_period() != 0 || sweep.get_shift() != 0;
+            // the overflow check is done right away
+            if sweep.get_shift() != 0 && sweep.compute() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// Whether the channel is playing, as read in NR52.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_dac_enabled(&self) -> bool {
+        self.envelope.is_dac_enabled()
+    }
+
+    /// Advance one M-cycle.
+    ///
+    /// Cycles: 4
+    pub fn tick(&mut self) {
+        self.timer = self.timer.saturating_sub(4);
+        if self.timer == 0 {
+            self.timer = self.get_period();
+            self.duty_step = (self.duty_step + 1) & 0b111;
+        }
+    }
+
+    /// 256Hz
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// 64Hz
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// 128Hz
+    pub fn clock_sweep(&mut self) {
+        let Some(sweep) = &mut self.sweep else {
+            return;
+        };
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer > 0 {
+            return;
+        }
+        sweep.reload_timer();
+        if !sweep.enabled || sweep.get_period() == 0 {
+            return;
+        }
+        let frequency = sweep.compute();
+        if frequency > 2047 {
+            self.enabled = false;
+        } else if sweep.get_shift() != 0 {
+            sweep.shadow = frequency;
+            self.frequency = frequency;
+            // and checked again with the new one
+            if sweep.compute() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// Digital output, 0 to 15.
+    pub fn get_output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        let high = DUTY_PATTERNS[usize::from(self.duty)] >> (7 - self.duty_step) & 1 != 0;
+        if high {
+            self.envelope.get_volume()
+        } else {
+            0
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        if let Some(sweep) = &self.sweep {
+            state.put_u8(sweep.register);
+            state.put_u16(sweep.shadow);
+            state.put_u8(sweep.timer);
+            state.put_bool(sweep.enabled);
+            state.put_bool(sweep.negated);
+        }
+        state.put_u8(self.duty);
+        state.put_u8(self.duty_step);
+        self.length.save_state(state);
+        self.envelope.save_state(state);
+        state.put_u16(self.frequency);
+        state.put_u16(self.timer);
+        state.put_bool(self.enabled);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        if let Some(sweep) = &mut self.sweep {
+            sweep.register = state.get_u8()? & 0x7F;
+            sweep.shadow = state.get_u16()?;
+            sweep.timer = state.get_u8()?;
+            sweep.enabled = state.get_bool()?;
+            sweep.negated = state.get_bool()?;
+        }
+        self.duty = state.get_u8()? & 0b11;
+        self.duty_step = state.get_u8()? & 0b111;
+        self.length.load_state(state)?;
+        self.envelope.load_state(state)?;
+        self.frequency = state.get_u16()? & 0x7FF;
+        self.timer = state.get_u16()?;
+        self.enabled = state.get_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+use super::length::LengthCounter;
+
+/// Channel 3, plays the 32 4-bit samples of the wave RAM, NR30-NR34 and 0xFF30-0xFF3F.
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Wave {
+    dac_enabled: bool,
+    length: LengthCounter,
+    /// Bits 5-6 of NR32, 0 mutes, then 100%, 50% and 25%.
+    output_level: u8,
+    /// 11 bits, NR33 and the low bits of NR34.
+    frequency: u16,
+    /// Clock cycles until the next sample.
+    timer: u16,
+    /// Sample being played, 0 to 31.
+    position: u8,
+    /// Last sample read from the wave RAM, what the channel outputs.
+    sample: u8,
+    /// The wave RAM was read during the last M-cycle.
+    just_read: bool,
+    ram: [u8; Self::RAM_SIZE],
+    enabled: bool,
+}
+
+impl Wave {
+    pub const RAM_SIZE: usize = 16;
+
+    const MAX_LENGTH: u16 = 256;
+    const DAC_MASK: u8 = 0x80;
+    const TRIGGER_MASK: u8 = 0x80;
+    const LENGTH_ENABLE_MASK: u8 = 0x40;
+    /// The first sample is read a bit later after a trigger.
+    const TRIGGER_DELAY: u16 = 6;
+
+    /// Register NR3`index`, unused and write only bits read as 1.
+    pub fn get_register(&self, index: u8) -> u8 {
+        match index {
+            0 => 0x7F | (u8::from(self.dac_enabled) << 7),
+            1 => 0xFF,
+            2 => 0x9F | (self.output_level << 5),
+            3 => 0xFF,
+            _ => 0xBF | (u8::from(self.length.is_enabled()) << 6),
+        }
+    }
+
+    pub fn set_register(&mut self, index: u8, value: u8) {
+        match index {
+            0 => {
+                self.dac_enabled = value & Self::DAC_MASK != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(value, Self::MAX_LENGTH),
+            2 => self.output_level = (value >> 5) & 0b11,
+            3 => self.frequency = (self.frequency & 0x700) | u16::from(value),
+            _ => {
+                self.frequency = (self.frequency & 0xFF) | (u16::from(value & 0b111) << 8);
+                self.length
+                    .set_enabled(value & Self::LENGTH_ENABLE_MASK != 0);
+                if value & Self::TRIGGER_MASK != 0 {
+                    self.trigger();
+                }
+            }
+        }
+    }
+
+    /// Turning the APU off clears everything but the wave RAM.
+    pub fn power_off(&mut self) {
+        *self = Wave {
+            ram: self.ram,
+            ..Default::default()
+        };
+    }
+
+    /// While the channel plays, the wave RAM can only be accessed when the channel reads it,
+    /// and the access goes to the byte being played whatever the address.
+    /// Otherwise reads give 0xFF.
+    pub fn read_ram(&self, offset: u16) -> u8 {
+        if !self.enabled {
+            self.ram[usize::from(offset)]
+        } else if self.just_read {
+            self.ram[self.get_played_byte()]
+        } else {
+            0xFF
+        }
+    }
+
+    /// Same restrictions as reading, writes are ignored when the channel can't be accessed.
+    pub fn write_ram(&mut self, offset: u16, value: u8) {
+        if !self.enabled {
+            self.ram[usize::from(offset)] = value;
+        } else if self.just_read {
+            self.ram[self.get_played_byte()] = value;
+        }
+    }
+
+    fn get_played_byte(&self) -> usize {
+        usize::from(self.position / 2)
+    }
+
+    fn get_period(&self) -> u16 {
+        (2048 - self.frequency) * 2
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger(Self::MAX_LENGTH);
+        self.timer = self.get_period() + Self::TRIGGER_DELAY;
+        // the sample buffer isn't refilled, the last sample plays until the next read
+        self.position = 0;
+    }
+
+    /// Whether the channel is playing, as read in NR52.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    /// Advance one M-cycle, at the highest frequencies more than 1 sample can be read.
+    ///
+    /// Cycles: 4
+    pub fn tick(&mut self) {
+        self.just_read = false;
+        if !self.enabled {
+            return;
+        }
+        let mut cycles = 4;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.get_period();
+            self.position = (self.position + 1) & 31;
+            let byte = self.ram[self.get_played_byte()];
+            // high nibble first
+            self.sample = if self.position & 1 == 0 {
+                byte >> 4
+            } else {
+                byte & 0x0F
+            };
+            self.just_read = true;
+        }
+        self.timer -= cycles;
+    }
+
+    /// 256Hz
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// Digital output, 0 to 15.
+    pub fn get_output(&self) -> u8 {
+        if !self.enabled || self.output_level == 0 {
+            return 0;
+        }
+        self.sample >> (self.output_level - 1)
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.put_bool(self.dac_enabled);
+        self.length.save_state(state);
+        state.put_u8(self.output_level);
+        state.put_u16(self.frequency);
+        state.put_u16(self.timer);
+        state.put_u8(self.position);
+        state.put_u8(self.sample);
+        state.put_bool(self.just_read);
+        state.put_bytes(&self.ram);
+        state.put_bool(self.enabled);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.dac_enabled = state.get_bool()?;
+        self.length.load_state(state)?;
+        self.output_level = state.get_u8()? & 0b11;
+        self.frequency = state.get_u16()? & 0x7FF;
+        self.timer = state.get_u16()?;
+        self.position = state.get_u8()? & 31;
+        self.sample = state.get_u8()? & 0x0F;
+        self.just_read = state.get_bool()?;
+        state.get_bytes_into(&mut self.ram)?;
+        self.enabled = state.get_bool()?;
+        Ok(())
+    }
+}
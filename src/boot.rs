@@ -0,0 +1,376 @@
+use std::fmt::Display;
+
+use crate::{
+    apu::Apu,
+    config::Model,
+    cpu::{registers::LongRegister, Cpu},
+    memory::{interrupts::Interrupt, joypad::Joypad, timer::Timer, Memory},
+    ppu::Ppu,
+};
+
+/// Header of the cartridge read by the boot.
+pub const LOGO_START: u16 = 0x0104;
+pub const LOGO_END: u16 = 0x0134;
+pub const HEADER_CHECKSUM: u16 = 0x014D;
+const CHECKSUM_START: u16 = 0x0134;
+
+/// Internal counter of the timer when the DMG boot ROM hands over, DIV reads 0xAB.
+const POST_BOOT_COUNTER: u16 = 0xABCC;
+
+/// The logo the boot ROM compares the header with.
+pub const LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+/// Why the boot ROM refuses to start the cartridge, it hangs forever instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderError {
+    /// The logo of the header isn't the expected one.
+    Logo,
+    /// The header checksum at 0x014D doesn't match the header.
+    Checksum { expected: u8, actual: u8 },
+}
+
+impl Display for HeaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeaderError::Logo => write!(f, "the logo of the cartridge header is invalid"),
+            HeaderError::Checksum { expected, actual } => write!(
+                f,
+                "header checksum is {:#04X} but the header sums to {:#04X}",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HeaderError {}
+
+/// The checks of the boot ROM, on the cartridge as seen from the bus.
+pub fn check_header(memory: &Memory) -> Result<(), HeaderError> {
+    if !(LOGO_START..LOGO_END)
+        .map(|addr| memory.get(addr))
+        .eq(LOGO.iter().copied())
+    {
+        return Err(HeaderError::Logo);
+    }
+    let expected = (CHECKSUM_START..HEADER_CHECKSUM).fold(0u8, |sum, addr| {
+        sum.wrapping_sub(memory.get(addr)).wrapping_sub(1)
+    });
+    let actual = memory.get(HEADER_CHECKSUM);
+    if expected != actual {
+        return Err(HeaderError::Checksum { expected, actual });
+    }
+    Ok(())
+}
+
+/// State once the boot ROM hands over to the cartridge at 0x0100, the CPU registers
+/// depend on the model and on the header for the DMG, like the real boot does.
+/// Games use A to find out where they run (0x01 DMG/SGB, 0xFF MGB, 0x11 CGB).
+///
+/// The IO registers get the values documented for the DMG, except the ones that
+/// would trigger something when written (the sound channels, OAM DMA):
+/// channel 1 is silent instead of finishing the boot sound.
+pub fn apply_post_boot_state(cpu: &mut Cpu) {
+    // H and C are set by the header checksum computation, unless it ended at 0
+    let checksum = cpu.get_bus().get(HEADER_CHECKSUM);
+    let flags = if checksum == 0 { 0x80 } else { 0xB0 };
+    let [af, bc, de, hl] = match cpu.get_bus().get_config().model {
+        Model::Dmg => [0x0100 | flags, 0x0013, 0x00D8, 0x014D],
+        Model::Mgb => [0xFF00 | flags, 0x0013, 0x00D8, 0x014D],
+        Model::Sgb => [0x0100, 0x0014, 0x0000, 0xC060],
+        Model::Cgb => [0x1180, 0x0000, 0xFF56, 0x000D],
+    };
+    for (reg, value) in [
+        (LongRegister::AF, af),
+        (LongRegister::BC, bc),
+        (LongRegister::DE, de),
+        (LongRegister::HL, hl),
+        (LongRegister::SP, 0xFFFE),
+        (LongRegister::PC, 0x0100),
+    ] {
+        cpu.put_long_reg(reg, value);
+    }
+    let memory = cpu.get_bus_mut();
+    // the APU has to be powered on first, the other registers ignore writes otherwise
+    for (addr, value) in [
+        (Joypad::REGISTER, 0x30),
+        (Timer::TAC_REGISTER, 0xF8),
+        (Apu::NR52_REGISTER, 0x80),
+        (Apu::NR10_REGISTER, 0x80),
+        (Apu::NR11_REGISTER, 0xBF),
+        (Apu::NR12_REGISTER, 0xF3),
+        (Apu::NR21_REGISTER, 0x3F),
+        (Apu::NR22_REGISTER, 0x00),
+        (Apu::NR30_REGISTER, 0x7F),
+        (Apu::NR32_REGISTER, 0x9F),
+        (Apu::NR41_REGISTER, 0xFF),
+        (Apu::NR42_REGISTER, 0x00),
+        (Apu::NR43_REGISTER, 0x00),
+        (Apu::NR50_REGISTER, 0x77),
+        (Apu::NR51_REGISTER, 0xF3),
+        (Ppu::LCDC_REGISTER, 0x91),
+        (Ppu::SCY_REGISTER, 0x00),
+        (Ppu::SCX_REGISTER, 0x00),
+        (Ppu::LYC_REGISTER, 0x00),
+        (Ppu::BGP_REGISTER, 0xFC),
+        (Ppu::WY_REGISTER, 0x00),
+        (Ppu::WX_REGISTER, 0x00),
+    ] {
+        memory.put(addr, value);
+    }
+    memory.get_timer_mut().set_counter(POST_BOOT_COUNTER);
+    memory.request_interrupt(Interrupt::VBlank);
+}
+
+/// High level emulation of the DMG boot ROM, for when no dump is available.
+///
+/// It draws the logo of the cartridge header (and the ®) like the boot ROM,
+/// scrolls it down from the top of the screen, waits a bit and jumps to the cartridge
+/// with the registers the real boot leaves.
+/// The CPU doesn't execute anything meanwhile, the rest of the machine runs normally.
+///
+/// With `EmuConfig::check_header`, a cartridge failing the header checks makes
+/// it hang after the animation, like the real boot ROM.
+#[derive(Debug)]
+pub struct HleBoot {
+    /// Frame count of the PPU the last time the scroll was updated.
+    last_frame: u64,
+    frames: u32,
+    /// Result of the header checks, if enforced.
+    header_error: Option<HeaderError>,
+}
+
+impl HleBoot {
+    /// SCY at the start, the logo is right above the screen.
+    const START_SCROLL: u8 = 0x64;
+    /// Frames waited once the logo is in place, the boot ROM plays its sound there.
+    const HOLD_FRAMES: u32 = 64;
+
+    const REGISTERED: [u8; 8] = [0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C];
+    const REGISTERED_TILE: u8 = 25;
+
+    /// Set up the VRAM and the LCD, like the beginning of the boot ROM.
+    pub fn start(memory: &mut Memory) -> Self {
+        for addr in 0x8000..0xA000 {
+            memory.put(addr, 0);
+        }
+        let logo: Vec<u8> = (LOGO_START..LOGO_END)
+            .map(|addr| memory.get(addr))
+            .collect();
+        for (i, &byte) in logo.iter().enumerate() {
+            // each nibble is 2 rows of a tile, starting at tile 1
+            let addr = 0x8010 + i as u16 * 8;
+            for (j, nibble) in [byte >> 4, byte & 0x0F].into_iter().enumerate() {
+                let row = Self::double_bits(nibble);
+                let addr = addr + j as u16 * 4;
+                // only the low bitplane, so color 1
+                memory.put(addr, row);
+                memory.put(addr + 2, row);
+            }
+        }
+        let registered = 0x8000 + u16::from(Self::REGISTERED_TILE) * 16;
+        for (i, &row) in Self::REGISTERED.iter().enumerate() {
+            memory.put(registered + i as u16 * 2, row);
+        }
+        for i in 0..12 {
+            memory.put(0x9904 + i, 1 + i as u8);
+            memory.put(0x9924 + i, 13 + i as u8);
+        }
+        memory.put(0x9910, Self::REGISTERED_TILE);
+
+        memory.put(Ppu::SCY_REGISTER, Self::START_SCROLL);
+        memory.put(Ppu::BGP_REGISTER, 0xFC);
+        memory.put(Ppu::LCDC_REGISTER, 0x91);
+        let header_error = if memory.get_config().check_header {
+            check_header(memory).err()
+        } else {
+            None
+        };
+        HleBoot {
+            last_frame: memory.get_ppu().get_frame_count(),
+            frames: 0,
+            header_error,
+        }
+    }
+
+    /// 4 bits to 8, each bit twice.
+    const fn double_bits(nibble: u8) -> u8 {
+        let mut result = 0;
+        let mut bit = 0;
+        while bit < 4 {
+            if nibble & (1 << bit) != 0 {
+                result |= 0b11 << (bit * 2);
+            }
+            bit += 1;
+        }
+        result
+    }
+
+    /// Run one M-cycle, returns true once the boot is over and the post boot state applied.
+    ///
+    /// Cycles: 4
+    pub fn step(&mut self, cpu: &mut Cpu) -> bool {
+        cpu.cycle();
+        let frame = cpu.get_bus().get_ppu().get_frame_count();
+        if frame == self.last_frame {
+            return false;
+        }
+        self.last_frame = frame;
+        self.frames += 1;
+        let memory = cpu.get_bus_mut();
+        let scroll = memory.get(Ppu::SCY_REGISTER);
+        if scroll > 0 {
+            memory.put(Ppu::SCY_REGISTER, scroll - 1);
+            return false;
+        }
+        if self.frames < u32::from(Self::START_SCROLL) + Self::HOLD_FRAMES || self.is_hung() {
+            return false;
+        }
+        apply_post_boot_state(cpu);
+        true
+    }
+
+    /// Why the boot hangs, once the animation is over.
+    pub fn get_hang_reason(&self) -> Option<HeaderError> {
+        self.header_error
+            .filter(|_| self.frames >= u32::from(Self::START_SCROLL) + Self::HOLD_FRAMES)
+    }
+
+    fn is_hung(&self) -> bool {
+        self.get_hang_reason().is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        apu::Apu,
+        config::{BootMode, EmuConfig, Model},
+        cpu::registers::LongRegister,
+        emulator::Emulator,
+        memory::timer::Timer,
+        ppu::Ppu,
+    };
+
+    use super::{HeaderError, HleBoot, LOGO};
+
+    #[test]
+    fn double_bits() {
+        assert_eq!(HleBoot::double_bits(0b1010), 0b11001100);
+        assert_eq!(HleBoot::double_bits(0b0001), 0b00000011);
+    }
+
+    #[test]
+    fn hle_boot() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0104] = 0xCE;
+        rom[0x014D] = 0x42;
+        let config = EmuConfig {
+            boot: BootMode::Hle,
+            ..Default::default()
+        };
+        let mut emulator = Emulator::from_rom_with_config(rom, config).unwrap();
+        assert_eq!(emulator.get_cpu().get_bus().get(0x8010), 0xF0);
+        assert_eq!(emulator.get_cpu().get_bus().get(0x8014), 0xFC);
+
+        emulator.run_frame();
+        assert_eq!(emulator.get_cpu().get_bus().get(Ppu::SCY_REGISTER), 0x63);
+        // the logo is scrolled into place, the top of the first tile is at line 64
+        for _ in 0..0x63 {
+            emulator.run_frame();
+        }
+        assert_eq!(emulator.get_cpu().get_bus().get(Ppu::SCY_REGISTER), 0);
+        assert_eq!(emulator.get_framebuffer()[64 * Ppu::WIDTH + 32], 3);
+        assert_eq!(emulator.get_cpu().get_pc(), 0);
+
+        emulator.run_until(
+            |emu| emu.get_cpu().get_pc() == 0x0101,
+            100 * Emulator::CYCLES_PER_FRAME,
+        );
+        let cpu = emulator.get_cpu();
+        assert_eq!(cpu.get_long_reg(LongRegister::AF), 0x01B0);
+        assert_eq!(cpu.get_long_reg(LongRegister::SP), 0xFFFE);
+        assert_eq!(cpu.get_bus().get(Ppu::LCDC_REGISTER), 0x91);
+    }
+
+    #[test]
+    fn skip_boot() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0104] = 0xCE;
+        rom[0x014D] = 0x42;
+        let config = EmuConfig {
+            boot: BootMode::Skip,
+            ..Default::default()
+        };
+        let emulator = Emulator::from_rom_with_config(rom, config).unwrap();
+        assert!(!emulator.is_booting());
+        let cpu = emulator.get_cpu();
+        assert_eq!(cpu.get_pc(), 0x0100);
+        assert_eq!(cpu.get_long_reg(LongRegister::AF), 0x01B0);
+        assert_eq!(cpu.get_long_reg(LongRegister::HL), 0x014D);
+        let memory = cpu.get_bus();
+        // the logo is left in the VRAM
+        assert_eq!(memory.get(0x8010), 0xF0);
+        assert_eq!(memory.get(Timer::DIV_REGISTER), 0xAB);
+        assert_eq!(memory.get(Apu::NR52_REGISTER), 0xF0);
+        assert_eq!(memory.get(Apu::NR11_REGISTER), 0xBF);
+        assert_eq!(memory.get(Apu::NR50_REGISTER), 0x77);
+        assert_eq!(memory.get(Ppu::BGP_REGISTER), 0xFC);
+        assert_eq!(memory.get(Ppu::SCY_REGISTER), 0);
+        assert_eq!(memory.get_interrupt_flag(), 0xE1);
+
+        let config = EmuConfig {
+            model: Model::Cgb,
+            boot: BootMode::Skip,
+            ..Default::default()
+        };
+        let emulator = Emulator::from_rom_with_config(vec![0; 0x8000], config).unwrap();
+        let cpu = emulator.get_cpu();
+        assert_eq!(cpu.get_long_reg(LongRegister::AF), 0x1180);
+        assert_eq!(cpu.get_long_reg(LongRegister::DE), 0xFF56);
+    }
+
+    #[test]
+    fn header_check() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0104..0x0134].copy_from_slice(&LOGO);
+        rom[0x0134..0x0138].copy_from_slice(b"TEST");
+        let config = EmuConfig {
+            boot: BootMode::Hle,
+            check_header: true,
+            ..Default::default()
+        };
+        let boot = |rom: Vec<u8>| {
+            let mut emulator = Emulator::from_rom_with_config(rom, config.clone()).unwrap();
+            emulator.run_until(
+                |emu| emu.get_cpu().get_pc() == 0x0101,
+                200 * Emulator::CYCLES_PER_FRAME,
+            );
+            emulator
+        };
+
+        // bad checksum
+        let emulator = boot(rom.clone());
+        assert!(emulator.is_booting());
+        assert_eq!(
+            emulator.get_boot_hang_reason(),
+            Some(HeaderError::Checksum {
+                expected: 0xA7,
+                actual: 0
+            })
+        );
+
+        rom[0x014D] = 0xA7;
+        let emulator = boot(rom.clone());
+        assert!(!emulator.is_booting());
+        assert_eq!(emulator.get_cpu().get_pc(), 0x0101);
+
+        // bad logo
+        rom[0x0104] = 0;
+        let emulator = boot(rom);
+        assert_eq!(emulator.get_boot_hang_reason(), Some(HeaderError::Logo));
+    }
+}
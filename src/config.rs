@@ -0,0 +1,125 @@
+/// Content of the RAM at power on.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RamInit {
+    #[default]
+    Zero,
+    Filled(u8),
+    /// Garbage from the seed, like the real hardware (minus the actual patterns).
+    Random,
+}
+
+/// The hardware revision emulated, the ones that differ in a way games or tests can see.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Model {
+    #[default]
+    Dmg,
+    /// Game Boy Pocket and Light, a DMG with a different boot.
+    Mgb,
+    Cgb,
+    /// Super Game Boy, the DMG hardware in a SNES cartridge.
+    Sgb,
+}
+
+impl Model {
+    /// Whether the CGB registers (VRAM and WRAM banks, palettes, speed switch...) exist.
+    pub fn is_cgb(self) -> bool {
+        self == Model::Cgb
+    }
+
+    /// The DMG PPU briefly enables every STAT source when STAT is written,
+    /// which can request a spurious interrupt.
+    pub fn has_stat_write_bug(self) -> bool {
+        !self.is_cgb()
+    }
+}
+
+/// How the machine gets from power on to the cartridge entry point,
+/// when no boot ROM dump is given (see `EmulatorBuilder::with_boot_rom`).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BootMode {
+    /// Start executing the cartridge at 0x0000 with everything cleared.
+    #[default]
+    Cold,
+    /// Emulate the boot ROM (logo animation and final registers) without needing a dump.
+    Hle,
+    /// Start the cartridge at 0x0100 right away, in the state the boot ROM leaves.
+    Skip,
+}
+
+/// Settings of the emulated machine.
+///
+/// Every random behavior draws from a generator seeded with `seed`,
+/// so a run is fully reproducible from the config and the inputs.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EmuConfig {
+    pub model: Model,
+    pub seed: u64,
+    /// Applied to WRAM and HRAM.
+    pub ram_init: RamInit,
+    /// Reads of the unusable area (0xFEA0-0xFEFF) return noise instead of a fixed value.
+    pub open_bus_noise: bool,
+    pub boot: BootMode,
+    /// Hang at boot when the logo or the checksum of the cartridge header is wrong,
+    /// like the boot ROM does. Off by default so homebrews with a bad header still run.
+    pub check_header: bool,
+    /// LY always reads 0x90 from the CPU, as in the reference logs of gameboy-doctor,
+    /// so the code waiting on VBlank runs the same as there whatever the PPU timing.
+    pub stub_ly: bool,
+}
+
+impl EmuConfig {
+    /// What gameboy-doctor compares against: a DMG starting at 0x0100 with LY stubbed.
+    /// Trace with `TraceFormat::Doctor` to get its log format.
+    pub fn gameboy_doctor() -> Self {
+        EmuConfig {
+            model: Model::Dmg,
+            boot: BootMode::Skip,
+            stub_ly: true,
+            ..Default::default()
+        }
+    }
+}
+
+/// SplitMix64, small and with a single `u64` of state so it fits in savestates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+
+    pub fn fill(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    pub fn get_state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn set_state(&mut self, state: u64) {
+        self.state = state;
+    }
+}
@@ -1,8 +1,28 @@
+/// Counts the clock cycles, in both clock domains of the CGB.
+///
+/// In double speed the CPU (and the timer and serial port) runs twice as fast as the rest of the
+/// machine, so an M-cycle of the CPU is only 2 cycles of the PPU clock.
 #[derive(Debug, Default)]
-pub struct Cyclic {}
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cyclic {
+    /// Cycles of the PPU clock (4MHz), the real time.
+    cycles: u64,
+    /// Cycles of the CPU clock, twice as many as `cycles` in double speed.
+    cpu_cycles: u64,
+}
 
 impl Cyclic {
-    pub fn cycle(&mut self) {
-        todo!()
+    /// Cycles: 4
+    pub fn cycle(&mut self, double_speed: bool) {
+        self.cpu_cycles += 4;
+        self.cycles += if double_speed { 2 } else { 4 };
+    }
+
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn get_cpu_cycles(&self) -> u64 {
+        self.cpu_cycles
     }
 }
@@ -0,0 +1,101 @@
+use std::fmt::Display;
+
+/// An executed instruction, as recorded in the `PcHistory`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HistoryEntry {
+    pub pc: u16,
+    pub opcode: u8,
+}
+
+/// Ring buffer of the last executed instructions.
+///
+/// Crash reports come with the emulated code that led to them:
+/// it is in the checked arithmetic panics and in the `EmuError::InternalPanic` of every
+/// guarded run (see `Emulator::run_guarded`), and `Emulator::get_pc_history` gives it
+/// to frontends that want to print it themselves.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PcHistory {
+    entries: Vec<HistoryEntry>,
+    capacity: usize,
+    /// Index of the oldest entry once the buffer is full.
+    next: usize,
+}
+
+impl PcHistory {
+    pub const DEFAULT_CAPACITY: usize = 32;
+
+    pub fn new(capacity: usize) -> Self {
+        PcHistory {
+            entries: Vec::with_capacity(capacity),
+            capacity,
+            next: 0,
+        }
+    }
+
+    pub fn push(&mut self, pc: u16, opcode: u8) {
+        let entry = HistoryEntry { pc, opcode };
+        if self.entries.len() < self.capacity {
+            self.entries.push(entry);
+        } else if self.capacity > 0 {
+            self.entries[self.next] = entry;
+            self.next = (self.next + 1) % self.capacity;
+        }
+    }
+
+    /// Iterate from the oldest to the most recent instruction.
+    pub fn iter(&self) -> impl Iterator<Item = &HistoryEntry> + '_ {
+        let (newest, oldest) = self.entries.split_at(self.next);
+        oldest.iter().chain(newest)
+    }
+
+    pub fn last(&self) -> Option<&HistoryEntry> {
+        self.iter().last()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.next = 0;
+    }
+}
+
+impl Default for PcHistory {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+impl Display for PcHistory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "last {} executed instructions:", self.len())?;
+        for entry in self.iter() {
+            writeln!(f, "  {:#06X}: {:#04X}", entry.pc, entry.opcode)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HistoryEntry, PcHistory};
+
+    #[test]
+    fn keeps_last_entries_in_order() {
+        let mut history = PcHistory::new(4);
+        for pc in 0..10 {
+            history.push(pc, pc as u8 * 2);
+        }
+        let pcs: Vec<u16> = history.iter().map(|entry| entry.pc).collect();
+        assert_eq!(pcs, [6, 7, 8, 9]);
+        assert_eq!(history.last(), Some(&HistoryEntry { pc: 9, opcode: 18 }));
+    }
+}
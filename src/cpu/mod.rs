@@ -1,21 +1,62 @@
-use crate::memory::Memory;
+#[cfg(all(debug_assertions, feature = "checked-arithmetic"))]
+use std::panic::{self, AssertUnwindSafe};
+
+#[cfg(test)]
+use crate::memory::mbc::RomOnly;
+use crate::{
+    instructions::Instruction,
+    memory::{bus::Bus, interrupts::Interrupt, Memory},
+    savestate::{SaveStateError, StateReader, StateWriter},
+};
 
 use self::{
     cyclic::Cyclic,
+    history::PcHistory,
     registers::{Flags, LongRegister, Register, Registers, SetFlags},
 };
 
 pub mod cyclic;
+pub mod history;
 pub mod registers;
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CpuState {
+    #[default]
+    Running,
+    /// Stopped by HALT until an interrupt is pending.
+    Halted,
+    /// Stopped by STOP until a joypad press, the whole machine is stopped.
+    Stopped,
+    /// Hung after an illegal opcode, only a reset gets out of it.
+    Locked,
+}
+
+/// The SM83 core, generic over the bus it talks to so tests can plug in a mock.
 #[derive(Debug, Default)]
-pub struct Cpu {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cpu<B: Bus = Memory> {
+    state: CpuState,
     registers: Registers,
-    memory: Memory,
+    bus: B,
     cyclic: Cyclic,
+    /// Interrupt Master Enable
+    ime: bool,
+    /// Instructions left before EI takes effect.
+    ime_delay: u8,
+    /// Set by HALT with IME off and an interrupt already pending,
+    /// the next opcode fetch doesn't increment PC.
+    halt_bug: bool,
+    /// Address of the instruction being executed, for diagnostics.
+    instruction_pc: u16,
+    /// The instruction being executed, if already decoded, for diagnostics.
+    instruction: Option<Instruction>,
+    history: PcHistory,
 }
 
-impl Cpu {
+impl<B: Bus> Cpu<B> {
+    const INTERRUPT_FLAG_REGISTER: u16 = 0xFF0F;
+
     /// Cycles: 4
     pub fn current_byte(&mut self) -> u8 {
         let addr = self.get_pc();
@@ -25,7 +66,12 @@ impl Cpu {
     /// Cycles: 4
     pub fn advance(&mut self) -> u8 {
         let byte = self.current_byte();
-        self.advance_by(1);
+        if self.halt_bug {
+            // the byte after HALT is read twice
+            self.halt_bug = false;
+        } else {
+            self.advance_by(1);
+        }
         byte
     }
 
@@ -76,37 +122,86 @@ impl Cpu {
     /// Cycles: 4
     pub fn get_relative(&mut self, delta: u16) -> u8 {
         let cp = self.get_pc();
-        self.get_memory(cp + delta)
+        let addr = self.addr_add(cp, delta);
+        self.get_memory(addr)
     }
 
-    #[cfg(test)]
-    pub fn opcode_filled() -> Self {
-        let mut cpu = Cpu::default();
-        for i in 0..=0xFF {
-            let addr: u16 = i.into();
-            cpu.memory.put(i.into(), i);
-            let prefixed_addr = addr * 2 + 0x0100;
-            cpu.memory.put(prefixed_addr, 0xCB);
-            cpu.memory.put(prefixed_addr + 1, i);
+    pub fn new(bus: B) -> Self
+    where
+        B: Default,
+    {
+        Cpu {
+            bus,
+            ..Default::default()
         }
-        let stop_addr = 0x0300;
-        cpu.memory.put(stop_addr, 0x10);
-        cpu.memory.put(stop_addr + 1, 0x00);
-        cpu
     }
 
     pub fn advance_by(&mut self, delta: u16) {
-        let pc = self.get_pc_mut();
-        *pc = pc.wrapping_add(delta);
+        let pc = self.addr_add(self.get_pc(), delta);
+        self.set_pc(pc);
     }
 
     pub fn move_by(&mut self, delta: i16) {
-        // well technically addition of signed and unsigned is the same because of the way 2's complement works,
-        // so addition of a signed delta is juste add the bits together and let it wrapp overflow, discarding the carry.
-        // (when mixed_integrer_ops stable ?)
-        let bytes = i16::to_ne_bytes(delta);
-        let delta = u16::from_ne_bytes(bytes);
-        self.advance_by(delta);
+        let pc = self.addr_add_signed(self.get_pc(), delta);
+        self.set_pc(pc);
+    }
+
+    /// Mark the start of a new instruction at the current PC.
+    pub fn begin_instruction(&mut self) {
+        let pc = self.get_pc();
+        self.instruction_pc = pc;
+        self.instruction = None;
+        // peek without cycling, the fetch will do the actual read
+        self.history.push(pc, self.peek_memory(pc));
+    }
+
+    pub fn set_current_instruction(&mut self, instruction: Option<Instruction>) {
+        self.instruction = instruction;
+    }
+
+    /// Address of the instruction being executed.
+    pub fn get_instruction_pc(&self) -> u16 {
+        self.instruction_pc
+    }
+
+    pub fn get_current_instruction(&self) -> Option<Instruction> {
+        self.instruction
+    }
+
+    pub fn get_pc_history(&self) -> &PcHistory {
+        &self.history
+    }
+
+    // Arithmetic on emulated addresses wraps around like the hardware does:
+    // PC running past 0xFFFF, SP going under 0x0000, HL+ and HL- are all defined.
+
+    pub fn addr_add(&self, addr: u16, delta: u16) -> u16 {
+        addr.wrapping_add(delta)
+    }
+
+    pub fn addr_sub(&self, addr: u16, delta: u16) -> u16 {
+        addr.wrapping_sub(delta)
+    }
+
+    pub fn addr_add_signed(&self, addr: u16, delta: i16) -> u16 {
+        addr.wrapping_add_signed(delta)
+    }
+
+    /// Execute `instruction`, a panic of the emulator's own arithmetic (an overflow
+    /// in a helper, a bad index) is raised again with the instruction and the history,
+    /// so it can be tracked down to the emulated code.
+    #[cfg(all(debug_assertions, feature = "checked-arithmetic"))]
+    pub(crate) fn audit(&mut self, instruction: Instruction, run: impl FnOnce(&mut Self)) {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| run(self)));
+        if let Err(payload) = result {
+            panic!(
+                "{} (PC: {:#06X}, instruction: {})\n{}",
+                crate::emulator::panic_message(&*payload),
+                self.instruction_pc,
+                instruction,
+                self.history
+            );
+        }
     }
 
     pub fn get_pc_mut(&mut self) -> &mut u16 {
@@ -125,14 +220,27 @@ impl Cpu {
     pub fn get_memory(&mut self, addr: u16) -> u8 {
         // memory read is 1 cycle
         self.cycle();
-        self.memory.get(addr)
+        self.bus.read(addr)
+    }
+
+    pub fn get_bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn get_bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    /// Read memory without taking any cycle, for diagnostics.
+    pub fn peek_memory(&self, addr: u16) -> u8 {
+        self.bus.peek(addr)
     }
 
     /// Cycles: 4
     pub fn put_memory(&mut self, addr: u16, value: u8) {
         // memory write is 1 cycle
         self.cycle();
-        self.memory.put(addr, value);
+        self.bus.write(addr, value);
     }
 
     pub fn get_flags(&self) -> SetFlags {
@@ -162,7 +270,7 @@ impl Cpu {
     /// Cycles: 8
     pub fn get_long_at(&mut self, addr: u16) -> u16 {
         let lsb = self.get_memory(addr);
-        let msb = self.get_memory(addr + 1);
+        let msb = self.get_memory(self.addr_add(addr, 1));
         u16::from_be_bytes([msb, lsb])
     }
 
@@ -170,47 +278,451 @@ impl Cpu {
     pub fn put_long_at(&mut self, addr: u16, value: u16) {
         let [msb, lsb] = u16::to_be_bytes(value);
         self.put_memory(addr, lsb);
-        self.put_memory(addr + 1, msb);
+        self.put_memory(self.addr_add(addr, 1), msb);
     }
 
     /// Cycles: 8
     pub fn get_next_long(&mut self) -> u16 {
-        self.get_long_at(self.get_pc() + 1)
+        self.get_long_at(self.addr_add(self.get_pc(), 1))
     }
 
     /// Cycles: 8
     pub fn advance_long(&mut self) -> u16 {
-        let msb = self.advance();
+        // little endian, like everything in memory
         let lsb = self.advance();
+        let msb = self.advance();
         u16::from_be_bytes([msb, lsb])
     }
 
     /// Cycles: 8
     pub fn push_stack(&mut self, value: u16) {
+        // the hardware pushes the msb first, going down
+        let [msb, lsb] = u16::to_be_bytes(value);
         let sp = self.get_long_reg(LongRegister::SP);
-        let addr = sp - 2;
-        self.put_long_at(addr, value);
-        self.put_long_reg(LongRegister::SP, addr);
+        let sp = self.addr_sub(sp, 1);
+        self.put_memory(sp, msb);
+        let sp = self.addr_sub(sp, 1);
+        self.put_memory(sp, lsb);
+        self.put_long_reg(LongRegister::SP, sp);
     }
 
     /// Cycles: 8
     pub fn pop_stack(&mut self) -> u16 {
         let sp = self.get_long_reg(LongRegister::SP);
         let value = self.get_long_at(sp);
-        self.put_long_reg(LongRegister::SP, sp + 2);
+        self.put_long_reg(LongRegister::SP, self.addr_add(sp, 2));
         value
     }
 
+    /// Elapsed clock cycles, at the normal speed, so in real time even in CGB double speed.
+    pub fn get_cycles(&self) -> u64 {
+        self.cyclic.get_cycles()
+    }
+
+    /// Elapsed cycles of the CPU clock, twice as fast in CGB double speed.
+    pub fn get_cpu_cycles(&self) -> u64 {
+        self.cyclic.get_cpu_cycles()
+    }
+
+    pub fn get_state(&self) -> CpuState {
+        self.state
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.state == CpuState::Locked
+    }
+
+    pub fn lock(&mut self) {
+        self.state = CpuState::Locked;
+    }
+
+    /// Power cycle the core, the bus is left untouched.
+    ///
+    /// The cycle counter keeps going, so it stays a timeline of the whole session.
+    pub fn reset(&mut self) {
+        self.state = CpuState::Running;
+        self.registers = Registers::default();
+        self.ime = false;
+        self.ime_delay = 0;
+        self.halt_bug = false;
+        self.instruction_pc = 0;
+        self.instruction = None;
+        self.history.clear();
+    }
+
+    /// State of the core only, the bus saves its own.
+    ///
+    /// Like `reset`, the cycle counter is not part of it.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.put_u8(self.state as u8);
+        self.registers.save_state(state);
+        state.put_bool(self.ime);
+        state.put_u8(self.ime_delay);
+        state.put_bool(self.halt_bug);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.state = match state.get_u8()? {
+            0 => CpuState::Running,
+            1 => CpuState::Halted,
+            2 => CpuState::Stopped,
+            3 => CpuState::Locked,
+            value => return Err(SaveStateError::InvalidValue(value)),
+        };
+        self.registers.load_state(state)?;
+        self.ime = state.get_bool()?;
+        self.ime_delay = state.get_u8()?;
+        self.halt_bug = state.get_bool()?;
+        self.history.clear();
+        Ok(())
+    }
+
     /// Cycle: 4
     pub fn cycle(&mut self) {
-        self.cyclic.cycle()
+        self.cyclic.cycle(self.bus.is_double_speed());
+        self.bus.tick();
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.state == CpuState::Halted
+    }
+
+    /// HALT, wait for an interrupt.
+    ///
+    /// If IME is off and an interrupt is already pending, the CPU doesn't halt
+    /// but fails to increment PC on the next fetch (the HALT bug).
+    pub fn halt(&mut self) {
+        if !self.ime && self.bus.get_pending_interrupts() != 0 {
+            self.halt_bug = true;
+        } else {
+            self.state = CpuState::Halted;
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.state == CpuState::Stopped
+    }
+
+    /// STOP, enter the low power state until a joypad press,
+    /// or switch the CGB speed if KEY1 armed it.
+    pub fn stop(&mut self) {
+        if !self.bus.stop() {
+            self.state = CpuState::Stopped;
+        }
+    }
+
+    /// Let time pass without clocking the rest of the machine, like in STOP.
+    ///
+    /// Cycle: 4
+    pub fn idle(&mut self) {
+        self.cyclic.cycle(self.bus.is_double_speed());
+    }
+
+    pub fn get_ime(&self) -> bool {
+        self.ime
     }
 
+    /// Enable interrupts right away, like RETI.
     pub fn enable_interrupts(&mut self) {
-        todo!()
+        self.ime = true;
+        self.ime_delay = 0;
+    }
+
+    /// Enable interrupts after the next instruction, like EI.
+    pub fn schedule_enable_interrupts(&mut self) {
+        if !self.ime && self.ime_delay == 0 {
+            // counts this instruction boundary and the one after the next instruction
+            self.ime_delay = 2;
+        }
     }
 
     pub fn disable_interrupts(&mut self) {
-        todo!()
+        self.ime = false;
+        self.ime_delay = 0;
+    }
+
+    /// To call between instructions, wakes the CPU up from HALT or STOP
+    /// and services the highest priority pending interrupt if IME is set.
+    ///
+    /// Returns the serviced interrupt.
+    ///
+    /// Cycles: 20 when servicing, +4 when waking up from HALT.
+    pub fn handle_interrupts(&mut self) -> Option<Interrupt> {
+        if self.is_stopped() {
+            // a joypad press is the only way out, it also sets the joypad IF bit
+            if self.bus.peek(Self::INTERRUPT_FLAG_REGISTER) & Interrupt::Joypad.get_mask() == 0 {
+                return None;
+            }
+            self.state = CpuState::Running;
+        }
+        if self.ime_delay > 0 {
+            self.ime_delay -= 1;
+            if self.ime_delay == 0 {
+                self.ime = true;
+            }
+        }
+        if self.bus.get_pending_interrupts() == 0 {
+            return None;
+        }
+        // a pending interrupt ends HALT even with IME off
+        if self.is_halted() {
+            self.state = CpuState::Running;
+            if self.ime {
+                self.cycle();
+            }
+        }
+        if !self.ime {
+            return None;
+        }
+        self.service_interrupt()
+    }
+
+    /// Cycles: 20
+    fn service_interrupt(&mut self) -> Option<Interrupt> {
+        self.ime = false;
+        // 2 wait states
+        self.cycle();
+        self.cycle();
+        let [msb, lsb] = u16::to_be_bytes(self.get_pc());
+        let sp = self.get_long_reg(LongRegister::SP);
+        let sp = self.addr_sub(sp, 1);
+        self.put_memory(sp, msb);
+        // the interrupt is only chosen now, so pushing the msb onto IE can cancel it
+        let interrupt = Interrupt::highest_priority(self.bus.get_pending_interrupts());
+        let sp = self.addr_sub(sp, 1);
+        self.put_memory(sp, lsb);
+        self.put_long_reg(LongRegister::SP, sp);
+        self.cycle();
+        match interrupt {
+            Some(interrupt) => {
+                self.bus.acknowledge_interrupt(interrupt);
+                self.set_pc(interrupt.get_vector());
+            }
+            // cancelled, ends up at 0x0000
+            None => self.set_pc(0x0000),
+        }
+        interrupt
+    }
+}
+
+impl Cpu<Memory> {
+    #[cfg(test)]
+    pub fn opcode_filled() -> Self {
+        let mut rom = vec![0; 0x8000];
+        for i in 0..=0xFF {
+            rom[i] = i as u8;
+            let prefixed_addr = i * 2 + 0x0100;
+            rom[prefixed_addr] = 0xCB;
+            rom[prefixed_addr + 1] = i as u8;
+        }
+        let stop_addr = 0x0300;
+        rom[stop_addr] = 0x10;
+        rom[stop_addr + 1] = 0x00;
+        let mbc = RomOnly::new(rom, 0);
+        Cpu::new(Memory::new(Box::new(mbc)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{registers::LongRegister, Cpu};
+    use crate::{
+        instructions::Instruction,
+        memory::{
+            bus::{Bus, BusAccess, FlatBus},
+            interrupts::Interrupt,
+        },
+    };
+
+    fn step(cpu: &mut Cpu<FlatBus>) -> Option<Interrupt> {
+        let interrupt = cpu.handle_interrupts();
+        if !cpu.is_halted() {
+            Instruction::fetch(cpu).unwrap().execute(cpu);
+        }
+        interrupt
+    }
+
+    fn request_vblank(cpu: &mut Cpu<FlatBus>) {
+        let bus = cpu.get_bus_mut();
+        bus.memory[0xFFFF] = Interrupt::VBlank.get_mask();
+        bus.request_interrupt(Interrupt::VBlank);
+    }
+
+    #[test]
+    fn stack_wraps_around() {
+        let mut cpu: Cpu = Cpu::default();
+        cpu.push_stack(0x1234);
+        assert_eq!(cpu.get_long_reg(LongRegister::SP), 0xFFFE);
+        assert_eq!(cpu.pop_stack(), 0x1234);
+        assert_eq!(cpu.get_long_reg(LongRegister::SP), 0x0000);
+    }
+
+    /// A bus whose reads above 0xFF00 hit a bug of the emulator.
+    #[cfg(all(debug_assertions, feature = "checked-arithmetic"))]
+    #[derive(Default)]
+    struct BuggyBus(FlatBus);
+
+    #[cfg(all(debug_assertions, feature = "checked-arithmetic"))]
+    impl Bus for BuggyBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            // an offset into the I/O registers computed the wrong way around
+            let _offset = std::hint::black_box(0xFF00u16) - addr;
+            self.0.read(addr)
+        }
+
+        fn peek(&self, addr: u16) -> u8 {
+            self.0.peek(addr)
+        }
+
+        fn write(&mut self, addr: u16, value: u8) {
+            self.0.write(addr, value);
+        }
+
+        fn tick(&mut self) {
+            self.0.tick();
+        }
+
+        fn request_interrupt(&mut self, interrupt: Interrupt) {
+            self.0.request_interrupt(interrupt);
+        }
+
+        fn stop(&mut self) -> bool {
+            self.0.stop()
+        }
+
+        fn is_double_speed(&self) -> bool {
+            self.0.is_double_speed()
+        }
+
+        fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+            self.0.acknowledge_interrupt(interrupt);
+        }
+
+        fn get_pending_interrupts(&self) -> u8 {
+            self.0.get_pending_interrupts()
+        }
+    }
+
+    #[test]
+    #[cfg(all(debug_assertions, feature = "checked-arithmetic"))]
+    #[should_panic(expected = "subtract with overflow (PC: 0x0001, instruction: ld a, [$FF80])")]
+    fn checked_arithmetic_names_the_instruction() {
+        // NOP; LD A, (0xFF80)
+        let bus = BuggyBus(FlatBus::with_program(0x0000, &[0x00, 0xFA, 0x80, 0xFF]));
+        let mut cpu = Cpu::new(bus);
+        for _ in 0..2 {
+            let instruction = Instruction::fetch(&mut cpu).unwrap();
+            instruction.execute(&mut cpu);
+        }
+    }
+
+    #[test]
+    fn push_bus_activity() {
+        // PUSH BC
+        let mut cpu = Cpu::new(FlatBus::with_program(0x0000, &[0xC5]));
+        cpu.put_long_reg(LongRegister::SP, 0xFFFE);
+        cpu.put_long_reg(LongRegister::BC, 0x1234);
+        let instruction = Instruction::fetch(&mut cpu).unwrap();
+        instruction.execute(&mut cpu);
+
+        assert_eq!(
+            cpu.get_bus().accesses,
+            [
+                BusAccess::Read {
+                    cycle: 4,
+                    addr: 0x0000,
+                    value: 0xC5
+                },
+                BusAccess::Write {
+                    cycle: 12,
+                    addr: 0xFFFD,
+                    value: 0x12
+                },
+                BusAccess::Write {
+                    cycle: 16,
+                    addr: 0xFFFC,
+                    value: 0x34
+                },
+            ]
+        );
+        assert_eq!(cpu.get_long_reg(LongRegister::SP), 0xFFFC);
+        assert_eq!(cpu.get_cycles(), 16);
+    }
+
+    #[test]
+    fn ei_takes_effect_after_the_next_instruction() {
+        // EI, NOP, NOP
+        let mut cpu = Cpu::new(FlatBus::with_program(0x0000, &[0xFB, 0x00, 0x00]));
+        cpu.put_long_reg(LongRegister::SP, 0xFFFE);
+        request_vblank(&mut cpu);
+
+        assert_eq!(step(&mut cpu), None);
+        assert_eq!(step(&mut cpu), None);
+        assert_eq!(cpu.get_pc(), 0x0002);
+        assert_eq!(step(&mut cpu), Some(Interrupt::VBlank));
+        // serviced in 20 cycles, then the NOP at the vector
+        assert_eq!(cpu.get_cycles(), 4 + 4 + 20 + 4);
+        assert_eq!(cpu.get_pc(), 0x0041);
+        assert_eq!(cpu.pop_stack(), 0x0002);
+        assert!(!cpu.get_ime());
+        assert_eq!(cpu.get_bus().get_pending_interrupts(), 0);
+    }
+
+    #[test]
+    fn halt_bug() {
+        // HALT, INC A
+        let mut cpu = Cpu::new(FlatBus::with_program(0x0000, &[0x76, 0x3C]));
+        request_vblank(&mut cpu);
+        step(&mut cpu);
+        assert!(!cpu.is_halted());
+        step(&mut cpu);
+        step(&mut cpu);
+        assert_eq!(cpu.get_reg_a(), 2);
+        assert_eq!(cpu.get_pc(), 0x0002);
+    }
+
+    #[test]
+    fn halt_wakes_up_without_ime() {
+        // HALT, INC A
+        let mut cpu = Cpu::new(FlatBus::with_program(0x0000, &[0x76, 0x3C]));
+        step(&mut cpu);
+        assert!(cpu.is_halted());
+        step(&mut cpu);
+        assert!(cpu.is_halted());
+        request_vblank(&mut cpu);
+        assert_eq!(step(&mut cpu), None);
+        assert_eq!(cpu.get_reg_a(), 1);
+    }
+
+    #[test]
+    fn pushing_onto_ie_cancels_the_interrupt() {
+        let mut cpu = Cpu::new(FlatBus::with_program(0x0000, &[0x00]));
+        cpu.put_long_reg(LongRegister::SP, 0x0000);
+        cpu.set_pc(0x0200);
+        cpu.enable_interrupts();
+        request_vblank(&mut cpu);
+        // the msb of PC (0x02) lands on IE, disabling VBlank
+        assert_eq!(cpu.handle_interrupts(), None);
+        assert_eq!(cpu.get_pc(), 0x0000);
+        assert_eq!(cpu.get_bus().memory[0xFFFF], 0x02);
+    }
+
+    #[test]
+    fn stop_until_joypad_press() {
+        // STOP, INC A
+        let mut cpu = Cpu::new(FlatBus::with_program(0x0000, &[0x10, 0x00, 0x3C]));
+        cpu.get_bus_mut().memory[0xFF04] = 0x42;
+        step(&mut cpu);
+        assert!(cpu.is_stopped());
+        assert_eq!(cpu.get_bus().memory[0xFF04], 0);
+        // other interrupts don't wake it up
+        request_vblank(&mut cpu);
+        assert_eq!(cpu.handle_interrupts(), None);
+        assert!(cpu.is_stopped());
+
+        cpu.get_bus_mut().request_interrupt(Interrupt::Joypad);
+        step(&mut cpu);
+        assert!(!cpu.is_stopped());
+        assert_eq!(cpu.get_reg_a(), 1);
     }
 }
@@ -1,8 +1,12 @@
 use std::ops::BitOr;
 
-use crate::help_traits::AccesBigEndianBytesU16;
+use crate::{
+    help_traits::AccesBigEndianBytesU16,
+    savestate::{SaveStateError, StateReader, StateWriter},
+};
 
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Registers {
     af: u16,
     bc: u16,
@@ -13,6 +17,7 @@ pub struct Registers {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Register {
     A,
     B,
@@ -25,6 +30,7 @@ pub enum Register {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LongRegister {
     AF,
     BC,
@@ -64,7 +70,7 @@ pub enum Flags {
     Carry,
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct SetFlags {
     pub zero: bool,
     pub substract: bool,
@@ -96,9 +102,9 @@ impl Flags {
     const CARRY_MASK: u8 = 0x10;
 }
 
-impl Into<u8> for Flags {
-    fn into(self) -> u8 {
-        match self {
+impl From<Flags> for u8 {
+    fn from(flag: Flags) -> Self {
+        match flag {
             Flags::Zero => Flags::ZERO_MASK,
             Flags::Substract => Flags::SUBSTRACT_MASK,
             Flags::HalfCarry => Flags::HALF_CARRY_MASK,
@@ -122,19 +128,19 @@ impl From<u8> for SetFlags {
     }
 }
 
-impl Into<u8> for SetFlags {
-    fn into(self) -> u8 {
+impl From<SetFlags> for u8 {
+    fn from(set_flags: SetFlags) -> Self {
         let mut flags = 0;
-        if self.zero {
+        if set_flags.zero {
             flags |= Flags::ZERO_MASK;
         }
-        if self.substract {
+        if set_flags.substract {
             flags |= Flags::SUBSTRACT_MASK;
         }
-        if self.half_carry {
+        if set_flags.half_carry {
             flags |= Flags::HALF_CARRY_MASK;
         }
-        if self.carry {
+        if set_flags.carry {
             flags |= Flags::CARRY_MASK;
         }
         flags
@@ -218,6 +224,26 @@ impl Registers {
         *register = value;
     }
 
+    pub fn save_state(&self, state: &mut StateWriter) {
+        for reg in [self.af, self.bc, self.de, self.hl, self.sp, self.pc] {
+            state.put_u16(reg);
+        }
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        for reg in [
+            &mut self.af,
+            &mut self.bc,
+            &mut self.de,
+            &mut self.hl,
+            &mut self.sp,
+            &mut self.pc,
+        ] {
+            *reg = state.get_u16()?;
+        }
+        Ok(())
+    }
+
     pub fn get_flags(&self) -> SetFlags {
         let flags = self.get(Register::F);
         flags.into()
@@ -0,0 +1,161 @@
+use std::fmt::Display;
+
+use crate::memory::interrupts::Interrupt;
+
+/// A call the CPU is in, from a `CALL`, a `RST` or an interrupt dispatch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallFrame {
+    /// Address of the `CALL` or `RST`, or of the instruction the interrupt came before.
+    pub call_pc: u16,
+    /// What was pushed on the stack.
+    pub return_addr: u16,
+    /// The function called.
+    pub target: u16,
+    /// Where the return address is on the stack.
+    pub stack_addr: u16,
+    /// Set for interrupt dispatches.
+    pub interrupt: Option<Interrupt>,
+}
+
+impl Display for CallFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#06X}", self.target)?;
+        if let Some(interrupt) = self.interrupt {
+            write!(f, " ({:?} interrupt)", interrupt)?;
+        }
+        write!(
+            f,
+            " called from {:#06X}, returns to {:#06X}",
+            self.call_pc, self.return_addr
+        )
+    }
+}
+
+/// Shadow of the calls on the stack, the hardware has no frame pointers to walk.
+///
+/// Frames are matched to returns by where their return address is on the stack,
+/// so returning from a frame drops the frames above it, which were left
+/// without a return (by popping their return address or reloading SP).
+/// A call over stale frames drops them too.
+#[derive(Debug, Clone, Default)]
+pub struct CallStack {
+    frames: Vec<CallFrame>,
+}
+
+impl CallStack {
+    /// Outermost call first.
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    /// The innermost call.
+    pub fn last(&self) -> Option<&CallFrame> {
+        self.frames.last()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    pub(crate) fn push(&mut self, frame: CallFrame) {
+        self.drop_from(frame.stack_addr);
+        self.frames.push(frame);
+    }
+
+    /// A return popped its address from `stack_addr`.
+    pub(crate) fn pop(&mut self, stack_addr: u16) {
+        self.drop_from(stack_addr);
+    }
+
+    /// The stack grows down, the frames at or below `stack_addr` are gone.
+    fn drop_from(&mut self, stack_addr: u16) {
+        let kept = self
+            .frames
+            .iter()
+            .position(|frame| frame.stack_addr <= stack_addr)
+            .unwrap_or(self.frames.len());
+        self.frames.truncate(kept);
+    }
+}
+
+/// One frame per line, innermost first like a backtrace.
+impl Display for CallStack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, frame) in self.frames.iter().rev().enumerate() {
+            writeln!(f, "#{} {}", i, frame)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{emulator::Emulator, memory::interrupts::Interrupt};
+
+    #[test]
+    fn call_stack() {
+        let mut program = [0; 0x52];
+        // EI; CALL 0x0010; JR -2
+        program[..6].copy_from_slice(&[0xFB, 0xCD, 0x10, 0x00, 0x18, 0xFE]);
+        // RST 0x18; JR -2
+        program[0x10..0x13].copy_from_slice(&[0xDF, 0x18, 0xFE]);
+        // INC SP; INC SP; RET, drops the RST frame and returns from the CALL
+        program[0x18..0x1B].copy_from_slice(&[0x33, 0x33, 0xC9]);
+        // timer handler: NOP; RETI
+        program[0x50..].copy_from_slice(&[0x00, 0xD9]);
+        let mut emulator = Emulator::from_program(&program);
+        let cpu = emulator.get_cpu_mut();
+        cpu.put_memory(0xFFFF, 0x00);
+
+        emulator.step();
+        emulator.step();
+        emulator.step();
+        let stack = emulator.get_debugger().call_stack();
+        let frames: Vec<_> = stack
+            .frames()
+            .iter()
+            .map(|frame| (frame.call_pc, frame.return_addr, frame.target))
+            .collect();
+        assert_eq!(frames, [(0x0001, 0x0004, 0x0010), (0x0010, 0x0011, 0x0018)]);
+        assert_eq!(stack.frames()[1].stack_addr, 0xFFFA);
+        assert_eq!(
+            stack.to_string(),
+            "#0 0x0018 called from 0x0010, returns to 0x0011\n\
+             #1 0x0010 called from 0x0001, returns to 0x0004\n"
+        );
+
+        emulator.step();
+        emulator.step();
+        emulator.step();
+        assert_eq!(emulator.get_cpu().get_pc(), 0x0004);
+        assert!(emulator.get_debugger().call_stack().is_empty());
+
+        let cpu = emulator.get_cpu_mut();
+        cpu.put_memory(0xFFFF, Interrupt::Timer.get_mask());
+        cpu.put_memory(0xFF0F, Interrupt::Timer.get_mask());
+        assert_eq!(emulator.step().interrupt, Some(Interrupt::Timer));
+        let frame = *emulator.get_debugger().call_stack().last().unwrap();
+        assert_eq!(
+            (frame.call_pc, frame.return_addr, frame.target),
+            (0x0004, 0x0004, 0x0050)
+        );
+        assert_eq!(frame.interrupt, Some(Interrupt::Timer));
+        emulator.step();
+        assert!(emulator.get_debugger().call_stack().is_empty());
+
+        emulator.get_cpu_mut().set_pc(0x0000);
+        emulator.step();
+        emulator.step();
+        assert_eq!(emulator.get_debugger().call_stack().len(), 1);
+        emulator.reset();
+        assert!(emulator.get_debugger().call_stack().is_empty());
+    }
+}
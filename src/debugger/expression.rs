@@ -0,0 +1,418 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::{
+    cpu::{
+        registers::{Flags, LongRegister, Register},
+        Cpu,
+    },
+    memory::bus::Bus,
+};
+
+/// A condition over the registers, the flags and the memory, like `A == 0x3E && [HL] != 0`.
+///
+/// The language is a subset of C expressions on integers:
+/// - numbers in decimal, hexadecimal (`0x3E` or `$3E`) or binary (`0b101` or `%101`)
+/// - the registers `A`, `B`, `C`, `D`, `E`, `F`, `H`, `L`, `AF`, `BC`, `DE`, `HL`, `SP` and `PC`
+/// - the flags `ZF`, `NF`, `HF` and `CF`, 1 when set
+/// - the byte at an address with `[addr]`, read without side effects
+/// - the operators, by increasing precedence: `||`, `&&`, `|`, `^`, `&`, `==` `!=`,
+///   `<` `<=` `>` `>=`, `<<` `>>`, `+` `-`, and the unary `!` `-` `~`, with parentheses
+///
+/// Names are case insensitive. Comparisons and logical operators give 1 or 0,
+/// and the condition is met when the result isn't 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConditionError {
+    /// A character that isn't part of the language, at the given byte offset.
+    UnexpectedChar(usize, char),
+    InvalidNumber(usize),
+    /// Not a register nor a flag.
+    UnknownName(usize, String),
+    /// A token where it doesn't fit, at its byte offset.
+    UnexpectedToken(usize),
+    UnexpectedEnd,
+}
+
+impl Display for ConditionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConditionError::UnexpectedChar(offset, c) => {
+                write!(f, "unexpected character {:?} at {}", c, offset)
+            }
+            ConditionError::InvalidNumber(offset) => write!(f, "invalid number at {}", offset),
+            ConditionError::UnknownName(offset, name) => {
+                write!(f, "unknown register or flag {:?} at {}", name, offset)
+            }
+            ConditionError::UnexpectedToken(offset) => write!(f, "unexpected token at {}", offset),
+            ConditionError::UnexpectedEnd => write!(f, "unexpected end of condition"),
+        }
+    }
+}
+
+impl std::error::Error for ConditionError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnaryOp {
+    Not,
+    Neg,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+}
+
+impl BinaryOp {
+    /// By increasing precedence, the operators of each level are left associative.
+    const LEVELS: [&'static [(&'static str, BinaryOp)]; 9] = [
+        &[("||", BinaryOp::Or)],
+        &[("&&", BinaryOp::And)],
+        &[("|", BinaryOp::BitOr)],
+        &[("^", BinaryOp::BitXor)],
+        &[("&", BinaryOp::BitAnd)],
+        &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+        &[
+            ("<", BinaryOp::Lt),
+            ("<=", BinaryOp::Le),
+            (">", BinaryOp::Gt),
+            (">=", BinaryOp::Ge),
+        ],
+        &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+        &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    ];
+
+    fn apply(self, lhs: i64, rhs: i64) -> i64 {
+        match self {
+            BinaryOp::Or => i64::from(lhs != 0 || rhs != 0),
+            BinaryOp::And => i64::from(lhs != 0 && rhs != 0),
+            BinaryOp::BitOr => lhs | rhs,
+            BinaryOp::BitXor => lhs ^ rhs,
+            BinaryOp::BitAnd => lhs & rhs,
+            BinaryOp::Eq => i64::from(lhs == rhs),
+            BinaryOp::Ne => i64::from(lhs != rhs),
+            BinaryOp::Lt => i64::from(lhs < rhs),
+            BinaryOp::Le => i64::from(lhs <= rhs),
+            BinaryOp::Gt => i64::from(lhs > rhs),
+            BinaryOp::Ge => i64::from(lhs >= rhs),
+            // out of range shifts give 0 rather than wrapping the amount
+            BinaryOp::Shl => u32::try_from(rhs)
+                .ok()
+                .and_then(|rhs| lhs.checked_shl(rhs))
+                .unwrap_or(0),
+            BinaryOp::Shr => u32::try_from(rhs)
+                .ok()
+                .and_then(|rhs| lhs.checked_shr(rhs))
+                .unwrap_or(0),
+            BinaryOp::Add => lhs.wrapping_add(rhs),
+            BinaryOp::Sub => lhs.wrapping_sub(rhs),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Number(i64),
+    Register(Register),
+    LongRegister(LongRegister),
+    Flag(Flags),
+    Memory(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn evaluate<B: Bus>(&self, cpu: &Cpu<B>) -> i64 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Register(reg) => cpu.get_reg(*reg).into(),
+            Expr::LongRegister(reg) => cpu.get_long_reg(*reg).into(),
+            Expr::Flag(flag) => cpu.get_flag(*flag).into(),
+            Expr::Memory(addr) => cpu.peek_memory(addr.evaluate(cpu) as u16).into(),
+            Expr::Unary(op, expr) => {
+                let value = expr.evaluate(cpu);
+                match op {
+                    UnaryOp::Not => i64::from(value == 0),
+                    UnaryOp::Neg => value.wrapping_neg(),
+                    UnaryOp::BitNot => !value,
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => op.apply(lhs.evaluate(cpu), rhs.evaluate(cpu)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    /// Operators and brackets.
+    Symbol(&'static str),
+}
+
+/// Longest first, so `<=` isn't read as `<` then `=`.
+const SYMBOLS: [&str; 21] = [
+    "&&", "||", "==", "!=", "<=", ">=", "<<", ">>", "<", ">", "+", "-", "&", "|", "^", "!", "~",
+    "(", ")", "[", "]",
+];
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, ConditionError> {
+    let mut tokens = Vec::new();
+    let mut rest = source.char_indices().peekable();
+    while let Some(&(offset, c)) = rest.peek() {
+        if c.is_whitespace() {
+            rest.next();
+        } else if c.is_ascii_alphanumeric() || c == '$' || c == '%' || c == '_' {
+            let mut end = offset;
+            while let Some(&(i, c)) = rest.peek() {
+                if i != offset && !(c.is_ascii_alphanumeric() || c == '_') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                rest.next();
+            }
+            tokens.push((offset, parse_word(offset, &source[offset..end])?));
+        } else {
+            let symbol = SYMBOLS
+                .iter()
+                .find(|symbol| source[offset..].starts_with(*symbol))
+                .ok_or(ConditionError::UnexpectedChar(offset, c))?;
+            for _ in 0..symbol.len() {
+                rest.next();
+            }
+            tokens.push((offset, Token::Symbol(symbol)));
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_word(offset: usize, word: &str) -> Result<Token, ConditionError> {
+    let lower = word.to_ascii_lowercase();
+    let number = if let Some(hex) = lower.strip_prefix("0x").or(lower.strip_prefix('$')) {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b").or(lower.strip_prefix('%')) {
+        i64::from_str_radix(bin, 2)
+    } else if lower.starts_with(|c: char| c.is_ascii_digit()) {
+        lower.parse()
+    } else {
+        return Ok(Token::Name(lower));
+    };
+    number
+        .map(Token::Number)
+        .map_err(|_| ConditionError::InvalidNumber(offset))
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&(usize, Token)> {
+        self.tokens.get(self.next)
+    }
+
+    fn advance(&mut self) -> Result<(usize, Token), ConditionError> {
+        let token = self
+            .tokens
+            .get(self.next)
+            .cloned()
+            .ok_or(ConditionError::UnexpectedEnd)?;
+        self.next += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some((_, Token::Symbol(s))) if *s == symbol);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), ConditionError> {
+        if self.eat(symbol) {
+            return Ok(());
+        }
+        match self.peek() {
+            Some((offset, _)) => Err(ConditionError::UnexpectedToken(*offset)),
+            None => Err(ConditionError::UnexpectedEnd),
+        }
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Result<Expr, ConditionError> {
+        let Some(operators) = BinaryOp::LEVELS.get(level) else {
+            return self.parse_unary();
+        };
+        let mut lhs = self.parse_binary(level + 1)?;
+        'outer: loop {
+            for (symbol, op) in operators.iter() {
+                if self.eat(symbol) {
+                    let rhs = self.parse_binary(level + 1)?;
+                    lhs = Expr::Binary(*op, Box::new(lhs), Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ConditionError> {
+        for (symbol, op) in [
+            ("!", UnaryOp::Not),
+            ("-", UnaryOp::Neg),
+            ("~", UnaryOp::BitNot),
+        ] {
+            if self.eat(symbol) {
+                return Ok(Expr::Unary(op, Box::new(self.parse_unary()?)));
+            }
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ConditionError> {
+        let (offset, token) = self.advance()?;
+        match token {
+            Token::Number(value) => Ok(Expr::Number(value)),
+            Token::Name(name) => parse_name(offset, name),
+            Token::Symbol("(") => {
+                let expr = self.parse_binary(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Token::Symbol("[") => {
+                let addr = self.parse_binary(0)?;
+                self.expect("]")?;
+                Ok(Expr::Memory(Box::new(addr)))
+            }
+            Token::Symbol(_) => Err(ConditionError::UnexpectedToken(offset)),
+        }
+    }
+}
+
+fn parse_name(offset: usize, name: String) -> Result<Expr, ConditionError> {
+    let expr = match name.as_str() {
+        "a" => Expr::Register(Register::A),
+        "b" => Expr::Register(Register::B),
+        "c" => Expr::Register(Register::C),
+        "d" => Expr::Register(Register::D),
+        "e" => Expr::Register(Register::E),
+        "f" => Expr::Register(Register::F),
+        "h" => Expr::Register(Register::H),
+        "l" => Expr::Register(Register::L),
+        "af" => Expr::LongRegister(LongRegister::AF),
+        "bc" => Expr::LongRegister(LongRegister::BC),
+        "de" => Expr::LongRegister(LongRegister::DE),
+        "hl" => Expr::LongRegister(LongRegister::HL),
+        "sp" => Expr::LongRegister(LongRegister::SP),
+        "pc" => Expr::LongRegister(LongRegister::PC),
+        "zf" => Expr::Flag(Flags::Zero),
+        "nf" => Expr::Flag(Flags::Substract),
+        "hf" => Expr::Flag(Flags::HalfCarry),
+        "cf" => Expr::Flag(Flags::Carry),
+        _ => return Err(ConditionError::UnknownName(offset, name)),
+    };
+    Ok(expr)
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Self, ConditionError> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            next: 0,
+        };
+        let expr = parser.parse_binary(0)?;
+        if let Some((offset, _)) = parser.peek() {
+            return Err(ConditionError::UnexpectedToken(*offset));
+        }
+        Ok(Condition {
+            source: source.trim().to_string(),
+            expr,
+        })
+    }
+
+    pub fn evaluate<B: Bus>(&self, cpu: &Cpu<B>) -> i64 {
+        self.expr.evaluate(cpu)
+    }
+
+    pub fn is_met<B: Bus>(&self, cpu: &Cpu<B>) -> bool {
+        self.evaluate(cpu) != 0
+    }
+}
+
+impl FromStr for Condition {
+    type Err = ConditionError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        Self::parse(source)
+    }
+}
+
+/// The source it was parsed from.
+impl Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Condition, ConditionError};
+    use crate::{
+        cpu::{
+            registers::{Flags, LongRegister, Register},
+            Cpu,
+        },
+        memory::bus::FlatBus,
+    };
+
+    #[test]
+    fn parse_and_evaluate() {
+        let mut cpu = Cpu::new(FlatBus::default());
+        cpu.put_reg(Register::A, 0x3E);
+        cpu.put_long_reg(LongRegister::HL, 0xC000);
+        cpu.put_memory(0xC000, 0x12);
+        cpu.set_flag(Flags::Carry);
+        let eval = |source: &str| Condition::parse(source).unwrap().evaluate(&cpu);
+
+        assert_eq!(eval("A == 0x3E && [HL] != 0"), 1);
+        assert_eq!(eval("a == $3f || [hl + 1] != 0"), 0);
+        assert_eq!(eval("1 + 2 << 1"), 6);
+        assert_eq!(eval("1 | 6 & 3 ^ 1"), 1 | ((6 & 3) ^ 1));
+        assert_eq!(eval("-(%101 - 0b1) + ~0"), -5);
+        assert_eq!(eval("!ZF && CF && H == 0xC0 && HL >= 49152"), 1);
+        assert_eq!(eval("10 - 3 - 2"), 5);
+        assert_eq!(eval("1 << 64"), 0);
+
+        let condition: Condition = " [0xC000] < 0x20 ".parse().unwrap();
+        assert!(condition.is_met(&cpu));
+        assert_eq!(condition.to_string(), "[0xC000] < 0x20");
+
+        let error = |source| Condition::parse(source).unwrap_err();
+        assert_eq!(error("A = 1"), ConditionError::UnexpectedChar(2, '='));
+        assert_eq!(error("A == 0xZZ"), ConditionError::InvalidNumber(5));
+        assert_eq!(error("X == 1"), ConditionError::UnknownName(0, "x".into()));
+        assert_eq!(error("(A == 1"), ConditionError::UnexpectedEnd);
+        assert_eq!(error("A 1"), ConditionError::UnexpectedToken(2));
+        assert_eq!(error("A == )"), ConditionError::UnexpectedToken(5));
+    }
+}
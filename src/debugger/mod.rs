@@ -0,0 +1,440 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    cpu::{registers::LongRegister, Cpu},
+    emulator::StopReason,
+    instructions::{control_flow::ControlFlowInstruction, Instruction},
+    memory::{bus::Bus, interrupts::Interrupt},
+};
+
+pub use self::call_stack::{CallFrame, CallStack};
+pub use self::expression::{Condition, ConditionError};
+pub use self::trace::{TraceFormat, Tracer};
+pub use self::watchpoint::{WatchHit, WatchKind, Watchpoint, Watchpoints};
+
+pub mod call_stack;
+pub mod expression;
+pub mod trace;
+pub mod watchpoint;
+
+/// A breakpoint on the address of an instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    /// Disabled breakpoints are kept but never hit.
+    pub enabled: bool,
+    /// Only stop when it is met, checked each time the address is reached.
+    pub condition: Option<Condition>,
+    /// Times execution stopped on it.
+    pub hit_count: u64,
+}
+
+impl Breakpoint {
+    pub fn new(addr: u16) -> Self {
+        Breakpoint {
+            addr,
+            enabled: true,
+            condition: None,
+            hit_count: 0,
+        }
+    }
+}
+
+/// Where a `step_over`, `step_out` or `run_to` of the emulator stops,
+/// a one shot breakpoint checked like the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepTarget {
+    /// The first instruction reached at most this many calls deep, see `Debugger::get_call_depth`.
+    Depth(isize),
+    Addr(u16),
+}
+
+/// Debugging state attached to the emulator, see `Emulator::get_debugger_mut`.
+///
+/// Breakpoints are checked on the PC before each instruction is fetched, whatever bank
+/// is mapped there. When one is hit the run returns `StopReason::Breakpoint` before
+/// executing the instruction, running again executes it: the first instruction of a run
+/// never stops on the breakpoint at its address.
+///
+/// The step target works the same way and stays armed across runs until it is reached,
+/// so a step out of a long function can take several frames. Any other stop than a
+/// completed frame (a breakpoint, a watchpoint, a pause...) cancels it.
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: BTreeMap<u16, Breakpoint>,
+    /// PC the run started from, its breakpoint is skipped once.
+    resume_pc: Option<u16>,
+    /// Access of the last `StopReason::Watchpoint`.
+    watch_hit: Option<WatchHit>,
+    step_target: Option<StepTarget>,
+    call_depth: isize,
+    call_stack: CallStack,
+    break_on_ld_b_b: bool,
+    break_requested: bool,
+}
+
+impl Debugger {
+    /// Opcode of `LD B, B`, the software breakpoint of the test ROMs and other debuggers.
+    pub const MAGIC_BREAKPOINT: u8 = 0x40;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns false if there was already one at that address, it is then left as is.
+    pub fn add_breakpoint(&mut self, addr: u16) -> bool {
+        if self.breakpoints.contains_key(&addr) {
+            return false;
+        }
+        self.breakpoints.insert(addr, Breakpoint::new(addr));
+        true
+    }
+
+    /// Add a breakpoint only stopping when `condition` is met, see `Condition` for the syntax.
+    /// An existing breakpoint at that address gets the condition.
+    pub fn add_conditional_breakpoint(
+        &mut self,
+        addr: u16,
+        condition: &str,
+    ) -> Result<&mut Breakpoint, ConditionError> {
+        let condition = Condition::parse(condition)?;
+        let breakpoint = self
+            .breakpoints
+            .entry(addr)
+            .or_insert_with(|| Breakpoint::new(addr));
+        breakpoint.condition = Some(condition);
+        Ok(breakpoint)
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> Option<Breakpoint> {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn get_breakpoint(&self, addr: u16) -> Option<&Breakpoint> {
+        self.breakpoints.get(&addr)
+    }
+
+    pub fn get_breakpoint_mut(&mut self, addr: u16) -> Option<&mut Breakpoint> {
+        self.breakpoints.get_mut(&addr)
+    }
+
+    /// Sorted by address.
+    pub fn breakpoints(&self) -> impl Iterator<Item = &Breakpoint> + '_ {
+        self.breakpoints.values()
+    }
+
+    /// Stop on every `LD B, B` like on a breakpoint, the test ROMs (Mooneye's especially)
+    /// use it to tell they are done.
+    pub fn set_break_on_ld_b_b(&mut self, enabled: bool) {
+        self.break_on_ld_b_b = enabled;
+    }
+
+    pub fn get_break_on_ld_b_b(&self) -> bool {
+        self.break_on_ld_b_b
+    }
+
+    /// Stop the run before its next instruction with `StopReason::DebuggerRequest`,
+    /// whether the CPU is running or halted. The request is cleared once it stopped a run.
+    pub fn request_break(&mut self) {
+        self.break_requested = true;
+    }
+
+    pub fn is_break_requested(&self) -> bool {
+        self.break_requested
+    }
+
+    pub(crate) fn take_break_request(&mut self) -> bool {
+        std::mem::take(&mut self.break_requested)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// What the access that stopped on a watchpoint was,
+    /// the watchpoints themselves are on the bus, see `Memory::get_watchpoints_mut`.
+    pub fn get_watch_hit(&self) -> Option<&WatchHit> {
+        self.watch_hit.as_ref()
+    }
+
+    pub(crate) fn set_watch_hit(&mut self, hit: WatchHit) {
+        self.watch_hit = Some(hit);
+    }
+
+    pub fn get_step_target(&self) -> Option<StepTarget> {
+        self.step_target
+    }
+
+    /// Replaces the previous target, `None` cancels it.
+    pub fn set_step_target(&mut self, target: Option<StepTarget>) {
+        self.step_target = target;
+    }
+
+    /// Calls, restarts and interrupts entered minus the returns since the last reset.
+    /// It goes below 0 when returning from calls made before.
+    pub fn get_call_depth(&self) -> isize {
+        self.call_depth
+    }
+
+    /// The calls the CPU is in since the last reset, for backtraces.
+    pub fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    /// Forget the calls, on a reset or a state load.
+    pub(crate) fn reset_calls(&mut self) {
+        self.call_depth = 0;
+        self.call_stack.clear();
+    }
+
+    /// Called when an interrupt is dispatched, the PC is in the handler.
+    pub(crate) fn enter_interrupt<B: Bus>(&mut self, interrupt: Interrupt, cpu: &Cpu<B>) {
+        self.call_depth += 1;
+        let return_addr = Self::pushed_addr(cpu);
+        self.call_stack.push(CallFrame {
+            call_pc: return_addr,
+            return_addr,
+            target: cpu.get_pc(),
+            stack_addr: cpu.get_long_reg(LongRegister::SP),
+            interrupt: Some(interrupt),
+        });
+    }
+
+    /// The return address on top of the stack.
+    fn pushed_addr<B: Bus>(cpu: &Cpu<B>) -> u16 {
+        let sp = cpu.get_long_reg(LongRegister::SP);
+        u16::from_le_bytes([cpu.peek_memory(sp), cpu.peek_memory(sp.wrapping_add(1))])
+    }
+
+    /// Called after each executed instruction, `sp` is the stack pointer before it.
+    /// Only the calls and returns taken move the stack pointer.
+    pub(crate) fn track_instruction<B: Bus>(
+        &mut self,
+        instruction: Instruction,
+        sp: u16,
+        cpu: &Cpu<B>,
+    ) {
+        use ControlFlowInstruction::*;
+        let Instruction::ControlFlow(instruction) = instruction else {
+            return;
+        };
+        if cpu.get_long_reg(LongRegister::SP) == sp {
+            return;
+        }
+        match instruction {
+            CallImmediate(_) | CallImmediateCondition(..) | Reset(_) => {
+                self.call_depth += 1;
+                self.call_stack.push(CallFrame {
+                    call_pc: cpu.get_instruction_pc(),
+                    return_addr: Self::pushed_addr(cpu),
+                    target: cpu.get_pc(),
+                    stack_addr: cpu.get_long_reg(LongRegister::SP),
+                    interrupt: None,
+                });
+            }
+            Return | ReturnCondition(_) | ReturnEnableInterrupt => {
+                self.call_depth -= 1;
+                self.call_stack.pop(sp);
+            }
+            _ => {}
+        }
+    }
+
+    /// Called when a run starts.
+    pub(crate) fn resume(&mut self, pc: u16) {
+        self.resume_pc = Some(pc);
+    }
+
+    /// Called before fetching the instruction at the PC, returns why to stop on it.
+    pub(crate) fn check_breakpoint<B: Bus>(&mut self, cpu: &Cpu<B>) -> Option<StopReason> {
+        let pc = cpu.get_pc();
+        if self.resume_pc.take() == Some(pc) {
+            return None;
+        }
+        let reached = match self.step_target {
+            Some(StepTarget::Depth(depth)) => self.call_depth <= depth,
+            Some(StepTarget::Addr(addr)) => pc == addr,
+            None => false,
+        };
+        if reached {
+            self.step_target = None;
+            return Some(StopReason::StepComplete);
+        }
+        if self.break_on_ld_b_b && cpu.peek_memory(pc) == Self::MAGIC_BREAKPOINT {
+            return Some(StopReason::Breakpoint(pc));
+        }
+        match self.breakpoints.get_mut(&pc) {
+            Some(breakpoint)
+                if breakpoint.enabled
+                    && breakpoint
+                        .condition
+                        .as_ref()
+                        .is_none_or(|condition| condition.is_met(cpu)) =>
+            {
+                breakpoint.hit_count += 1;
+                Some(StopReason::Breakpoint(pc))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StepTarget;
+    use crate::{
+        cpu::registers::{LongRegister, Register},
+        emulator::{Emulator, StopReason},
+    };
+
+    #[test]
+    fn breakpoints() {
+        // INC A; INC B; JR -4
+        let mut emulator = Emulator::from_program(&[0x3C, 0x04, 0x18, 0xFC]);
+
+        let debugger = emulator.get_debugger_mut();
+        assert!(debugger.add_breakpoint(0x0001));
+        assert!(!debugger.add_breakpoint(0x0001));
+        assert!(debugger.add_breakpoint(0x0002));
+        assert_eq!(
+            debugger.breakpoints().map(|b| b.addr).collect::<Vec<_>>(),
+            [0x0001, 0x0002]
+        );
+
+        assert_eq!(emulator.run_frame(), StopReason::Breakpoint(0x0001));
+        assert_eq!(emulator.get_cpu().get_pc(), 0x0001);
+        assert_eq!(emulator.get_cpu().get_reg(Register::B), 0);
+        // running again executes the instruction under the breakpoint
+        assert_eq!(emulator.run_frame(), StopReason::Breakpoint(0x0002));
+        assert_eq!(emulator.get_cpu().get_reg(Register::B), 1);
+        assert_eq!(emulator.step().reason, StopReason::InstructionComplete);
+
+        emulator.get_debugger_mut().remove_breakpoint(0x0002);
+        emulator
+            .get_debugger_mut()
+            .get_breakpoint_mut(0x0001)
+            .unwrap()
+            .enabled = false;
+        assert_eq!(emulator.run_frame(), StopReason::FrameComplete);
+        assert_eq!(
+            emulator
+                .get_debugger()
+                .get_breakpoint(0x0001)
+                .unwrap()
+                .hit_count,
+            1
+        );
+    }
+
+    #[test]
+    fn break_request() {
+        // INC A; HALT
+        let mut emulator = Emulator::from_program(&[0x3C, 0x76]);
+        emulator.get_debugger_mut().request_break();
+        assert_eq!(emulator.run_frame(), StopReason::DebuggerRequest);
+        assert_eq!(emulator.get_cpu().get_reg(Register::A), 0);
+        assert!(!emulator.get_debugger().is_break_requested());
+        assert_eq!(emulator.run_frame(), StopReason::FrameComplete);
+        assert_eq!(emulator.get_cpu().get_reg(Register::A), 1);
+
+        // also stops a halted CPU
+        emulator.get_debugger_mut().request_break();
+        assert_eq!(emulator.step().reason, StopReason::DebuggerRequest);
+        assert_eq!(emulator.run_frame(), StopReason::FrameComplete);
+    }
+
+    #[test]
+    fn conditional_breakpoints() {
+        // INC A; LD (HL), A; JR -3
+        let mut emulator = Emulator::from_program(&[0x3C, 0x77, 0x18, 0xFC]);
+        emulator.get_cpu_mut().put_reg(Register::A, 0);
+        emulator
+            .get_cpu_mut()
+            .put_long_reg(LongRegister::HL, 0xC000);
+
+        let debugger = emulator.get_debugger_mut();
+        assert!(debugger
+            .add_conditional_breakpoint(0x0002, "A = 3")
+            .is_err());
+        debugger
+            .add_conditional_breakpoint(0x0002, "A == 0x3 && [HL] != 0")
+            .unwrap();
+        assert_eq!(emulator.run_frame(), StopReason::Breakpoint(0x0002));
+        assert_eq!(emulator.get_cpu().get_reg(Register::A), 3);
+        assert_eq!(emulator.get_cpu().peek_memory(0xC000), 3);
+
+        // INC A only sets the zero flag when A wraps to 0
+        emulator
+            .get_debugger_mut()
+            .add_conditional_breakpoint(0x0002, "[$C000] == 3 && ZF")
+            .unwrap();
+        assert_eq!(emulator.run_frame(), StopReason::FrameComplete);
+        assert_eq!(
+            emulator
+                .get_debugger()
+                .get_breakpoint(0x0002)
+                .unwrap()
+                .hit_count,
+            1
+        );
+    }
+
+    #[test]
+    fn stepping() {
+        let mut program = [0; 0x22];
+        // CALL 0x0010; INC B; JR -2
+        program[..6].copy_from_slice(&[0xCD, 0x10, 0x00, 0x04, 0x18, 0xFE]);
+        // INC A; CALL 0x0020; RET
+        program[0x10..0x15].copy_from_slice(&[0x3C, 0xCD, 0x20, 0x00, 0xC9]);
+        // INC C; RET
+        program[0x20..].copy_from_slice(&[0x0C, 0xC9]);
+        let mut emulator = Emulator::from_program(&program);
+        emulator.get_cpu_mut().put_reg(Register::A, 0);
+        emulator.get_cpu_mut().put_reg(Register::C, 0);
+
+        assert_eq!(emulator.step_over(), StopReason::StepComplete);
+        assert_eq!(emulator.get_cpu().get_pc(), 0x0003);
+        assert_eq!(emulator.get_cpu().get_reg(Register::A), 1);
+        assert_eq!(emulator.get_cpu().get_reg(Register::C), 1);
+        assert_eq!(emulator.get_debugger().get_call_depth(), 0);
+
+        emulator.get_cpu_mut().set_pc(0x0000);
+        emulator.step();
+        assert_eq!(emulator.get_debugger().get_call_depth(), 1);
+        assert_eq!(emulator.step_over(), StopReason::StepComplete);
+        assert_eq!(emulator.get_cpu().get_pc(), 0x0011);
+        assert_eq!(emulator.step_out(), StopReason::StepComplete);
+        assert_eq!(emulator.get_cpu().get_pc(), 0x0003);
+        assert_eq!(emulator.get_debugger().get_call_depth(), 0);
+
+        // never reached from the loop, the target stays armed
+        assert_eq!(emulator.run_to(0x0021), StopReason::FrameComplete);
+        assert_eq!(
+            emulator.get_debugger().get_step_target(),
+            Some(StepTarget::Addr(0x0021))
+        );
+        emulator.get_cpu_mut().set_pc(0x0000);
+        assert_eq!(emulator.run_frame(), StopReason::StepComplete);
+        assert_eq!(emulator.get_cpu().get_pc(), 0x0021);
+        assert_eq!(emulator.get_debugger().get_call_depth(), 2);
+        assert_eq!(emulator.step_out(), StopReason::StepComplete);
+        assert_eq!(emulator.get_cpu().get_pc(), 0x0014);
+
+        // a breakpoint in the function stepped over stops first, and cancels the step
+        emulator.get_cpu_mut().set_pc(0x0000);
+        emulator.get_debugger_mut().add_breakpoint(0x0020);
+        assert_eq!(emulator.step_over(), StopReason::Breakpoint(0x0020));
+        assert_eq!(emulator.get_debugger().get_step_target(), None);
+        assert_eq!(emulator.run_frame(), StopReason::FrameComplete);
+
+        // same for a step out, the next run doesn't complete it
+        emulator.get_cpu_mut().set_pc(0x0000);
+        emulator.get_debugger_mut().clear_breakpoints();
+        assert_eq!(emulator.run_to(0x0010), StopReason::StepComplete);
+        emulator.get_debugger_mut().add_breakpoint(0x0021);
+        assert_eq!(emulator.step_out(), StopReason::Breakpoint(0x0021));
+        assert_eq!(emulator.get_debugger().get_step_target(), None);
+        emulator.get_debugger_mut().clear_breakpoints();
+        assert_eq!(emulator.run_frame(), StopReason::FrameComplete);
+    }
+}
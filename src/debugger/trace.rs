@@ -0,0 +1,315 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+};
+
+use crate::{
+    cpu::{
+        registers::{LongRegister, Register},
+        Cpu,
+    },
+    instructions::{
+        disassembler::{self, Syntax},
+        Instruction,
+    },
+    memory::bus::Bus,
+};
+
+/// How the trace lines are written, the state is the one before the instruction.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// The format of gameboy-doctor and of the logs of many other emulators:
+    /// `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+    #[default]
+    Doctor,
+    /// With the instruction and the clock cycles since power on:
+    /// `0100: 00       nop              A:01 F:Z-HC BC:0013 DE:00D8 HL:014D SP:FFFE CY:0`
+    Full,
+}
+
+/// The CPU before an instruction, captured before it is fetched.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TraceState {
+    pc: u16,
+    sp: u16,
+    /// A, F, B, C, D, E, H, L.
+    regs: [u8; 8],
+    /// The 4 bytes from the PC.
+    pcmem: [u8; 4],
+    cycles: u64,
+}
+
+impl TraceState {
+    const REGS: [Register; 8] = [
+        Register::A,
+        Register::F,
+        Register::B,
+        Register::C,
+        Register::D,
+        Register::E,
+        Register::H,
+        Register::L,
+    ];
+
+    pub(crate) fn capture<B: Bus>(cpu: &Cpu<B>) -> Self {
+        let pc = cpu.get_pc();
+        TraceState {
+            pc,
+            sp: cpu.get_long_reg(LongRegister::SP),
+            regs: Self::REGS.map(|reg| cpu.get_reg(reg)),
+            pcmem: [0, 1, 2, 3].map(|i| cpu.peek_memory(pc.wrapping_add(i))),
+            cycles: cpu.get_cycles(),
+        }
+    }
+
+    fn format(&self, instruction: Instruction, format: TraceFormat) -> String {
+        let [a, f, b, c, d, e, h, l] = self.regs;
+        match format {
+            TraceFormat::Doctor => {
+                let [m0, m1, m2, m3] = self.pcmem;
+                format!(
+                    "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} \
+                     SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+                    a, f, b, c, d, e, h, l, self.sp, self.pc, m0, m1, m2, m3
+                )
+            }
+            TraceFormat::Full => {
+                let length = usize::from(instruction.length());
+                let bytes: Vec<_> = self.pcmem[..length]
+                    .iter()
+                    .map(|byte| format!("{:02X}", byte))
+                    .collect();
+                let flags: String = ['Z', 'N', 'H', 'C']
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, flag)| if f & (0x80 >> i) != 0 { flag } else { '-' })
+                    .collect();
+                format!(
+                    "{:04X}: {:<8} {:<16} A:{:02X} F:{} BC:{:02X}{:02X} DE:{:02X}{:02X} \
+                     HL:{:02X}{:02X} SP:{:04X} CY:{}",
+                    self.pc,
+                    bytes.join(" "),
+                    disassembler::format_instruction(instruction, self.pc, Syntax::Rgbds),
+                    a,
+                    flags,
+                    b,
+                    c,
+                    d,
+                    e,
+                    h,
+                    l,
+                    self.sp,
+                    self.cycles
+                )
+            }
+        }
+    }
+}
+
+enum TraceSink {
+    Writer(Box<dyn Write>),
+    Ring {
+        lines: VecDeque<String>,
+        capacity: usize,
+    },
+}
+
+/// Logs a line per executed instruction, see `Emulator::set_tracer`.
+///
+/// Interrupt dispatches, halted cycles and illegal opcodes have no line,
+/// as in the logs of the other emulators.
+pub struct Tracer {
+    format: TraceFormat,
+    sink: TraceSink,
+    lines: u64,
+    /// The first write error, nothing is written after it.
+    error: Option<io::Error>,
+}
+
+impl std::fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tracer")
+            .field("format", &self.format)
+            .field("lines", &self.lines)
+            .field("error", &self.error)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Tracer {
+    /// Write each line to `writer`, it is better buffered.
+    pub fn to_writer(writer: Box<dyn Write>, format: TraceFormat) -> Self {
+        Self::with_sink(TraceSink::Writer(writer), format)
+    }
+
+    /// Keep the last `capacity` lines in memory, to look at what led to a crash.
+    pub fn to_ring(capacity: usize, format: TraceFormat) -> Self {
+        let lines = VecDeque::with_capacity(capacity);
+        Self::with_sink(TraceSink::Ring { lines, capacity }, format)
+    }
+
+    fn with_sink(sink: TraceSink, format: TraceFormat) -> Self {
+        Tracer {
+            format,
+            sink,
+            lines: 0,
+            error: None,
+        }
+    }
+
+    pub fn get_format(&self) -> TraceFormat {
+        self.format
+    }
+
+    /// Instructions traced since the tracer was made.
+    pub fn get_line_count(&self) -> u64 {
+        self.lines
+    }
+
+    /// The lines kept by a ring, from the oldest, nothing when writing to a writer.
+    pub fn lines(&self) -> impl Iterator<Item = &str> + '_ {
+        let lines = match &self.sink {
+            TraceSink::Ring { lines, .. } => Some(lines.iter().map(String::as_str)),
+            TraceSink::Writer(_) => None,
+        };
+        lines.into_iter().flatten()
+    }
+
+    /// The error that stopped the writes, if any.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.sink {
+            TraceSink::Writer(writer) => writer.flush(),
+            TraceSink::Ring { .. } => Ok(()),
+        }
+    }
+
+    /// Called once the instruction executed, with the state captured before.
+    pub(crate) fn trace(&mut self, state: &TraceState, instruction: Instruction) {
+        if self.error.is_some() {
+            return;
+        }
+        self.lines += 1;
+        let line = state.format(instruction, self.format);
+        match &mut self.sink {
+            TraceSink::Writer(writer) => {
+                if let Err(err) = writeln!(writer, "{}", line) {
+                    self.error = Some(err);
+                }
+            }
+            TraceSink::Ring { lines, capacity } => {
+                if *capacity == 0 {
+                    return;
+                }
+                if lines.len() == *capacity {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, io::Write, rc::Rc};
+
+    use super::{TraceFormat, Tracer};
+    use crate::{
+        config::EmuConfig,
+        cpu::registers::{LongRegister, Register},
+        emulator::Emulator,
+    };
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn trace() {
+        // LD A, 0x3E; CP 0x3E; JR -6
+        let mut program = [0; 0x106];
+        program[0x100..].copy_from_slice(&[0x3E, 0x3E, 0xFE, 0x3E, 0x18, 0xFA]);
+        let mut emulator = Emulator::from_program(&program);
+        let cpu = emulator.get_cpu_mut();
+        cpu.set_pc(0x0100);
+        cpu.put_long_reg(LongRegister::AF, 0x01B0);
+        cpu.put_long_reg(LongRegister::BC, 0x0013);
+        cpu.put_long_reg(LongRegister::DE, 0x00D8);
+        cpu.put_long_reg(LongRegister::HL, 0x014D);
+
+        let buffer = SharedBuffer::default();
+        let tracer = Tracer::to_writer(Box::new(buffer.clone()), TraceFormat::Doctor);
+        emulator.set_tracer(Some(tracer));
+        let cycles = emulator.get_cycles();
+        emulator.step();
+        emulator.step();
+        let tracer = emulator.set_tracer(Some(Tracer::to_ring(2, TraceFormat::Full)));
+        assert_eq!(tracer.unwrap().get_line_count(), 2);
+        assert_eq!(
+            String::from_utf8(buffer.0.take()).unwrap(),
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:3E,3E,FE,3E\n\
+             A:3E F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0102 PCMEM:FE,3E,18,FA\n"
+        );
+
+        emulator.get_cpu_mut().put_reg(Register::B, 0xAB);
+        for _ in 0..3 {
+            emulator.step();
+        }
+        let tracer = emulator.get_tracer().unwrap();
+        let lines: Vec<_> = tracer.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                format!(
+                    "0100: 3E 3E    ld a, $3E        A:3E F:ZN-- BC:AB13 DE:00D8 HL:014D SP:FFFE CY:{}",
+                    cycles + 28
+                ),
+                format!(
+                    "0102: FE 3E    cp a, $3E        A:3E F:ZN-- BC:AB13 DE:00D8 HL:014D SP:FFFE CY:{}",
+                    cycles + 36
+                ),
+            ]
+        );
+        assert_eq!(tracer.get_line_count(), 3);
+    }
+
+    #[test]
+    fn gameboy_doctor() {
+        // LDH A, (LY); JR -4
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[0xF0, 0x44, 0x18, 0xFC]);
+        rom[0x14D] = 0x42;
+        let mut emulator =
+            Emulator::from_rom_with_config(rom, EmuConfig::gameboy_doctor()).unwrap();
+        emulator.set_tracer(Some(Tracer::to_ring(2, TraceFormat::Doctor)));
+        emulator.step();
+        emulator.step();
+        let lines: Vec<_> = emulator.get_tracer().unwrap().lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:F0,44,18,FC",
+                "A:90 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0102 PCMEM:18,FC,00,00",
+            ]
+        );
+        // whatever the PPU is doing
+        for _ in 0..1000 {
+            emulator.step();
+            assert_eq!(emulator.get_cpu().get_reg(Register::A), 0x90);
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use std::{collections::BTreeMap, ops::RangeInclusive};
+
+/// Which accesses trigger a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    /// Writes of a value different from the one in memory.
+    Change,
+}
+
+/// Watched addresses, on accesses made by the CPU.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub kind: WatchKind,
+    /// Disabled watchpoints are kept but never hit.
+    pub enabled: bool,
+}
+
+impl Watchpoint {
+    fn matches(&self, addr: u16, kind: WatchKind, old: u8, new: u8) -> bool {
+        let kind_matches = match self.kind {
+            WatchKind::Change => kind == WatchKind::Write && old != new,
+            watched => watched == kind,
+        };
+        self.enabled && kind_matches && self.range.contains(&addr)
+    }
+}
+
+/// An access that hit a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    /// Address of the instruction doing the access.
+    pub pc: u16,
+    pub addr: u16,
+    /// `Read` or `Write`, a change is a write.
+    pub kind: WatchKind,
+    /// What was in memory, the value read for reads.
+    pub old: u8,
+    /// The value written, the value read for reads.
+    pub new: u8,
+}
+
+/// The watchpoints of the bus, see `Memory::get_watchpoints_mut`.
+///
+/// The first access hitting one is kept until the emulator takes it after the instruction,
+/// stops with `StopReason::Watchpoint` and hands it to `Debugger::get_watch_hit`.
+/// The instruction is always completed, the stop happens right after it.
+#[derive(Debug, Clone, Default)]
+pub struct Watchpoints {
+    /// By id, in the order they were added.
+    watchpoints: BTreeMap<usize, Watchpoint>,
+    next_id: usize,
+    hit: Option<WatchHit>,
+}
+
+impl Watchpoints {
+    /// Returns the id of the watchpoint, it stays valid until it is removed.
+    pub fn add(&mut self, range: RangeInclusive<u16>, kind: WatchKind) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.watchpoints.insert(
+            id,
+            Watchpoint {
+                range,
+                kind,
+                enabled: true,
+            },
+        );
+        id
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Watchpoint> {
+        self.watchpoints.remove(&id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Watchpoint> {
+        self.watchpoints.get_mut(&id)
+    }
+
+    /// The watchpoints with their id.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Watchpoint)> + '_ {
+        self.watchpoints
+            .iter()
+            .map(|(&id, watchpoint)| (id, watchpoint))
+    }
+
+    pub fn len(&self) -> usize {
+        self.watchpoints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watchpoints.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.watchpoints.clear();
+        self.hit = None;
+    }
+
+    /// Called by the bus on each access, `old` and `new` are the same for reads.
+    pub(crate) fn check(&mut self, addr: u16, kind: WatchKind, old: u8, new: u8) {
+        if self.hit.is_some() {
+            return;
+        }
+        if self
+            .watchpoints
+            .values()
+            .any(|watchpoint| watchpoint.matches(addr, kind, old, new))
+        {
+            self.hit = Some(WatchHit {
+                pc: 0,
+                addr,
+                kind,
+                old,
+                new,
+            });
+        }
+    }
+
+    /// The PC is filled by the caller, the bus doesn't know it.
+    pub(crate) fn take_hit(&mut self) -> Option<WatchHit> {
+        self.hit.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WatchKind;
+    use crate::emulator::{Emulator, StopReason};
+
+    #[test]
+    fn watchpoints() {
+        // LD A, 5; LD (0xC000), A; LD (0xC000), A; LD A, (0xC000); JR -2
+        let program = [
+            0x3E, 0x05, 0xEA, 0x00, 0xC0, 0xEA, 0x00, 0xC0, 0xFA, 0x00, 0xC0, 0x18, 0xFE,
+        ];
+        let mut emulator = Emulator::from_program(&program);
+        emulator.get_cpu_mut().get_bus_mut().put(0xC000, 0x00);
+        let restart = |emulator: &mut Emulator| emulator.get_cpu_mut().set_pc(0x0000);
+
+        let watchpoints = emulator.get_cpu_mut().get_bus_mut().get_watchpoints_mut();
+        let change = watchpoints.add(0xC000..=0xC0FF, WatchKind::Change);
+        let read = watchpoints.add(0xC000..=0xC000, WatchKind::Read);
+        // removing one doesn't change the id of the others
+        watchpoints.remove(change);
+        assert!(watchpoints.get_mut(change).is_none());
+        watchpoints.get_mut(read).unwrap().enabled = false;
+        let change = watchpoints.add(0xC000..=0xC0FF, WatchKind::Change);
+        assert_eq!(
+            watchpoints.iter().map(|(id, _)| id).collect::<Vec<_>>(),
+            [read, change]
+        );
+        assert_eq!(emulator.run_frame(), StopReason::Watchpoint(0xC000));
+        let hit = *emulator.get_debugger().get_watch_hit().unwrap();
+        assert_eq!(
+            (hit.pc, hit.kind, hit.old, hit.new),
+            (0x0002, WatchKind::Write, 0, 5)
+        );
+        assert_eq!(emulator.get_cpu().get_pc(), 0x0005);
+        // same value written again, and the read isn't watched
+        assert_eq!(emulator.run_frame(), StopReason::FrameComplete);
+
+        let watchpoints = emulator.get_cpu_mut().get_bus_mut().get_watchpoints_mut();
+        watchpoints.remove(change);
+        watchpoints.get_mut(read).unwrap().enabled = true;
+        watchpoints.add(0xBFFF..=0xBFFF, WatchKind::Write);
+        restart(&mut emulator);
+        assert_eq!(emulator.run_frame(), StopReason::Watchpoint(0xC000));
+        let hit = *emulator.get_debugger().get_watch_hit().unwrap();
+        assert_eq!(
+            (hit.pc, hit.kind, hit.old, hit.new),
+            (0x0008, WatchKind::Read, 5, 5)
+        );
+
+        let watchpoints = emulator.get_cpu_mut().get_bus_mut().get_watchpoints_mut();
+        watchpoints.clear();
+        watchpoints.add(0xC000..=0xC000, WatchKind::Write);
+        restart(&mut emulator);
+        assert_eq!(emulator.run_frame(), StopReason::Watchpoint(0xC000));
+        assert_eq!(emulator.run_frame(), StopReason::Watchpoint(0xC000));
+        assert_eq!(emulator.get_debugger().get_watch_hit().unwrap().pc, 0x0005);
+
+        // the step reports the instruction that made the access
+        restart(&mut emulator);
+        emulator.step();
+        let event = emulator.step();
+        assert_eq!(event.reason, StopReason::Watchpoint(0xC000));
+        assert_eq!(event.instruction.unwrap().to_string(), "ld [$C000], a");
+        assert_eq!(event.pc, 0x0002);
+        assert_eq!(emulator.get_cpu().get_pc(), 0x0005);
+    }
+}
@@ -0,0 +1,220 @@
+use crate::{
+    config::{BootMode, EmuConfig, Model},
+    cpu::Cpu,
+    debugger::Tracer,
+    memory::{
+        boot_rom::BootRom,
+        cartridge::{self, CartridgeError},
+        mbc::{rom_only::RomOnly, Mbc},
+        Memory,
+    },
+    ppu::CompatPalette,
+    savestate::rewind::RewindBuffer,
+    serial::SerialDevice,
+};
+
+use super::Emulator;
+
+/// Everything to set before the console is turned on.
+///
+/// ```ignore
+/// let emulator = EmulatorBuilder::new()
+///     .with_rom(rom)
+///     .with_save_data(save)
+///     .with_audio_sample_rate(48000)
+///     .build()?;
+/// ```
+#[derive(Debug, Default)]
+pub struct EmulatorBuilder {
+    rom: Option<Vec<u8>>,
+    cartridge: Option<Box<dyn Mbc>>,
+    boot_rom: Option<BootRom>,
+    config: EmuConfig,
+    save_data: Option<Vec<u8>>,
+    audio_sample_rate: Option<u32>,
+    palette: Option<CompatPalette>,
+    serial_device: Option<Box<dyn SerialDevice>>,
+    rewind: Option<RewindBuffer>,
+    tracer: Option<Tracer>,
+}
+
+impl EmulatorBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The cartridge is made from the header of `rom` when building.
+    /// Without a ROM or a cartridge, the console boots with an empty slot.
+    pub fn with_rom(mut self, rom: Vec<u8>) -> Self {
+        self.rom = Some(rom);
+        self.cartridge = None;
+        self
+    }
+
+    /// Insert an already made cartridge, replaces `with_rom`.
+    pub fn with_cartridge(mut self, cartridge: Box<dyn Mbc>) -> Self {
+        self.cartridge = Some(cartridge);
+        self.rom = None;
+        self
+    }
+
+    /// Boot from a dump of the boot ROM instead of the `BootMode` of the config.
+    pub fn with_boot_rom(mut self, boot_rom: BootRom) -> Self {
+        self.boot_rom = Some(boot_rom);
+        self
+    }
+
+    /// Replaces the whole config, the other config setters should be called after this one.
+    pub fn with_config(mut self, config: EmuConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn with_model(mut self, model: Model) -> Self {
+        self.config.model = model;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.config.seed = seed;
+        self
+    }
+
+    pub fn with_boot(mut self, boot: BootMode) -> Self {
+        self.config.boot = boot;
+        self
+    }
+
+    /// Content of the `.sav` file, loaded in the cartridge before the first instruction.
+    pub fn with_save_data(mut self, data: Vec<u8>) -> Self {
+        self.save_data = Some(data);
+        self
+    }
+
+    /// See `Emulator::set_audio_sample_rate`.
+    pub fn with_audio_sample_rate(mut self, rate: u32) -> Self {
+        self.audio_sample_rate = Some(rate);
+        self
+    }
+
+    /// Colors to use instead of the ones picked from the cartridge title.
+    pub fn with_palette(mut self, palette: CompatPalette) -> Self {
+        self.palette = Some(palette);
+        self
+    }
+
+    /// Device plugged in the link port from the start.
+    pub fn with_serial_device(mut self, device: Box<dyn SerialDevice>) -> Self {
+        self.serial_device = Some(device);
+        self
+    }
+
+    /// Record snapshots from the start, see `Emulator::set_rewind`.
+    pub fn with_rewind(mut self, rewind: RewindBuffer) -> Self {
+        self.rewind = Some(rewind);
+        self
+    }
+
+    /// Trace the instructions from the start, the boot ROM included, see `Emulator::set_tracer`.
+    pub fn with_tracer(mut self, tracer: Tracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Fails only when the ROM given to `with_rom` isn't a valid cartridge.
+    pub fn build(self) -> Result<Emulator, CartridgeError> {
+        let mut mbc = match (self.rom, self.cartridge) {
+            (Some(rom), _) => cartridge::load(rom)?,
+            (None, Some(cartridge)) => cartridge,
+            (None, None) => Box::<RomOnly>::default(),
+        };
+        if let Some(data) = self.save_data {
+            mbc.load_save_data(&data);
+        }
+        let mut memory = Memory::with_config(mbc, self.config);
+        memory.set_boot_rom(self.boot_rom);
+        if let Some(device) = self.serial_device {
+            memory.set_serial_device(device);
+        }
+        if let Some(rate) = self.audio_sample_rate {
+            memory.get_apu_mut().set_output_rate(rate);
+        }
+        let mut emulator = Emulator::new(Cpu::new(memory));
+        emulator.palette = self.palette;
+        emulator.rewind = self.rewind;
+        emulator.tracer = self.tracer;
+        Ok(emulator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::Color;
+
+    #[test]
+    fn build() {
+        let mut rom = vec![0; 0x8000];
+        // MBC1 with 8KB of battery backed RAM
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        let colors = [Color::BLACK; 4];
+        let palette = CompatPalette {
+            bg: colors,
+            obj0: colors,
+            obj1: colors,
+        };
+        let emulator = EmulatorBuilder::new()
+            .with_rom(rom)
+            .with_seed(42)
+            .with_save_data(vec![0xAB; 0x2000])
+            .with_audio_sample_rate(48000)
+            .with_palette(palette)
+            .build()
+            .unwrap();
+
+        assert_eq!(emulator.get_config().seed, 42);
+        assert_eq!(emulator.get_save_data(), vec![0xAB; 0x2000]);
+        assert_eq!(
+            emulator.get_cpu().get_bus().get_apu().get_output_rate(),
+            48000
+        );
+        assert_eq!(emulator.get_compat_palette(), palette);
+
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0xEE;
+        assert!(EmulatorBuilder::new().with_rom(rom).build().is_err());
+    }
+
+    #[test]
+    fn boot_rom() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100] = 0x42;
+        let mut boot = vec![0; BootRom::DMG_SIZE];
+        // LD A, 1; LDH (0x50), A
+        boot[..4].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+        let mut emulator = EmulatorBuilder::new()
+            .with_rom(rom)
+            .with_boot_rom(BootRom::new(boot).unwrap())
+            .build()
+            .unwrap();
+
+        let peek = |emulator: &Emulator, addr| emulator.get_cpu().get_bus().get(addr);
+        assert!(emulator.is_booting());
+        assert_eq!(emulator.get_cpu().get_pc(), 0);
+        assert_eq!(peek(&emulator, 0x0000), 0x3E);
+        // the header is always the cartridge one
+        assert_eq!(peek(&emulator, 0x0100), 0x42);
+        emulator.step();
+        emulator.step();
+        assert!(!emulator.is_booting());
+        assert_eq!(peek(&emulator, 0x0000), 0x00);
+        assert_eq!(emulator.get_cpu().get_pc(), 4);
+
+        emulator.reset();
+        assert!(emulator.is_booting());
+        assert_eq!(peek(&emulator, 0x0000), 0x3E);
+
+        assert!(BootRom::new(vec![0; 0x200]).is_err());
+    }
+}
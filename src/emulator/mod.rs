@@ -0,0 +1,1243 @@
+use std::{
+    any::Any,
+    fmt::Display,
+    panic::{self, AssertUnwindSafe},
+};
+
+pub use self::builder::EmulatorBuilder;
+
+pub mod builder;
+
+#[cfg(feature = "metrics")]
+use crate::metrics::{Metrics, Stopwatch};
+use crate::{
+    boot::{apply_post_boot_state, HeaderError, HleBoot},
+    config::{BootMode, EmuConfig},
+    cpu::{history::PcHistory, registers::LongRegister, Cpu},
+    debugger::{trace::TraceState, Debugger, StepTarget, Tracer},
+    extensions::{ExtensionError, IllegalOpcodePolicy, OpcodeExtensions, OpcodeHandler},
+    instructions::Instruction,
+    memory::{
+        cartridge::{self, CartridgeError},
+        interrupts::Interrupt,
+        joypad::Button,
+        mbc::{mbc3::Rtc, Mbc},
+        Memory,
+    },
+    ppu::{Color, CompatPalette, DisplayGeometry, Ppu},
+    savestate::{
+        rewind::RewindBuffer, SaveStateError, StateReader, StateWriter, FORMAT_VERSION,
+        HEADERLESS_VERSION,
+    },
+    schedule::{ControlAction, Schedule, ScheduledAt},
+    serial::{SerialCallback, SerialDevice},
+};
+
+/// Why a run method returned control to the caller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// A whole frame has been emulated.
+    FrameComplete,
+    /// `step` ran its instruction.
+    InstructionComplete,
+    /// `run_cycles` ran its cycles.
+    CyclesElapsed,
+    /// The condition given to `run_until` is met.
+    ConditionMet,
+    /// Execution reached a breakpoint at the given address.
+    Breakpoint(u16),
+    /// A watched memory address has been accessed.
+    Watchpoint(u16),
+    /// An opcode that doesn't exist has been fetched, the CPU is now locked.
+    IllegalOpcode { pc: u16, opcode: u8 },
+    /// The CPU is locked and won't execute anything until reset.
+    CpuLocked,
+    /// The cycle budget ran out before the stop condition was met.
+    Timeout,
+    /// The debugger asked to pause the emulation, see `Debugger::request_break`.
+    DebuggerRequest,
+    /// A `step_over`, `step_out` or `run_to` got where it was going.
+    StepComplete,
+    /// A scheduled state failed to load, the machine is left as it was.
+    InvalidState,
+    /// The emulator is paused, nothing is run until `set_paused(false)`.
+    Paused,
+}
+
+/// What happened during a `step`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepEvent {
+    /// `StopReason::InstructionComplete`, or why nothing was run,
+    /// `StopReason::IllegalOpcode` when the CPU just locked up.
+    pub reason: StopReason,
+    /// `None` while halted, stopped, booting, waiting for the VRAM DMA,
+    /// or when an opcode handler ran.
+    /// On a `StopReason::Watchpoint` it is the instruction that made the access.
+    pub instruction: Option<Instruction>,
+    /// Where the instruction is, or the PC when none was executed.
+    pub pc: u16,
+    /// Clock cycles the step took.
+    pub cycles: u64,
+    /// Interrupt the CPU jumped to before the instruction.
+    pub interrupt: Option<Interrupt>,
+    /// The PPU completed a frame.
+    pub frame_complete: bool,
+    /// Byte sent through the link port, when a transfer completed.
+    pub serial_byte: Option<u8>,
+}
+
+/// What `execute_step` did, `step` turns it into a `StepEvent`.
+#[derive(Debug, Default)]
+struct Executed {
+    instruction: Option<Instruction>,
+    interrupt: Option<Interrupt>,
+}
+
+/// A failure of the emulator itself, not of the emulated program.
+#[derive(Debug, Clone)]
+pub enum EmuError {
+    /// A panic was caught while running, the CPU is locked until a reset or a state load.
+    InternalPanic {
+        message: String,
+        /// The instructions executed right before the panic.
+        history: PcHistory,
+    },
+}
+
+impl Display for EmuError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EmuError::InternalPanic { message, history } => {
+                write!(f, "emulator panicked: {}\n{}", message, history)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EmuError {}
+
+/// The message given to `panic!`, from the payload of a caught panic.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Emulator {
+    cpu: Cpu,
+    schedule: Schedule,
+    extensions: OpcodeExtensions,
+    /// Running boot animation, when booting with `BootMode::Hle`.
+    boot: Option<HleBoot>,
+    paused: bool,
+    /// Overrides the palette picked from the cartridge title.
+    palette: Option<CompatPalette>,
+    rewind: Option<RewindBuffer>,
+    debugger: Debugger,
+    tracer: Option<Tracer>,
+    /// Time spent running the frame being emulated.
+    #[cfg(feature = "metrics")]
+    frame_time: std::time::Duration,
+    #[cfg(feature = "metrics")]
+    metrics_frame: u64,
+    #[cfg(feature = "metrics")]
+    last_metrics: Metrics,
+}
+
+impl Emulator {
+    /// Clock cycles in a frame, 154 scanlines of 456 cycles.
+    pub const CYCLES_PER_FRAME: u64 = 70224;
+
+    pub fn new(mut cpu: Cpu) -> Self {
+        let boot = Self::start_boot(&mut cpu);
+        Emulator {
+            cpu,
+            schedule: Schedule::default(),
+            extensions: OpcodeExtensions::default(),
+            boot,
+            paused: false,
+            palette: None,
+            rewind: None,
+            debugger: Debugger::default(),
+            tracer: None,
+            #[cfg(feature = "metrics")]
+            frame_time: Default::default(),
+            #[cfg(feature = "metrics")]
+            metrics_frame: 0,
+            #[cfg(feature = "metrics")]
+            last_metrics: Metrics::default(),
+        }
+    }
+
+    fn start_boot(cpu: &mut Cpu) -> Option<HleBoot> {
+        let memory = cpu.get_bus_mut();
+        if memory.is_boot_rom_active() {
+            // a real boot, the dump takes care of everything
+            cpu.set_pc(0x0000);
+            return None;
+        }
+        match memory.get_config().boot {
+            BootMode::Cold => None,
+            BootMode::Hle => Some(HleBoot::start(memory)),
+            BootMode::Skip => {
+                // the VRAM keeps the logo, like after a real boot
+                HleBoot::start(memory);
+                apply_post_boot_state(cpu);
+                None
+            }
+        }
+    }
+
+    /// Whether the boot animation or the boot ROM is still running, the cartridge isn't executed yet.
+    pub fn is_booting(&self) -> bool {
+        self.boot.is_some() || self.cpu.get_bus().is_boot_rom_active()
+    }
+
+    /// Why the boot is stuck and will never start the cartridge, see `EmuConfig::check_header`.
+    pub fn get_boot_hang_reason(&self) -> Option<HeaderError> {
+        self.boot.as_ref()?.get_hang_reason()
+    }
+
+    pub fn from_rom(rom: Vec<u8>) -> Result<Self, CartridgeError> {
+        Self::from_rom_with_config(rom, EmuConfig::default())
+    }
+
+    pub fn from_rom_with_config(rom: Vec<u8>, config: EmuConfig) -> Result<Self, CartridgeError> {
+        let mbc = cartridge::load(rom)?;
+        let memory = Memory::with_config(mbc, config);
+        Ok(Self::new(Cpu::new(memory)))
+    }
+
+    /// `program` at 0x0000 of a 32KB ROM without mapper, with the stack pointer the boot ROM leaves.
+    #[cfg(test)]
+    pub fn from_program(program: &[u8]) -> Self {
+        Self::from_program_with_config(program, EmuConfig::default())
+    }
+
+    #[cfg(test)]
+    pub fn from_program_with_config(program: &[u8], config: EmuConfig) -> Self {
+        let mut rom = vec![0; 0x8000];
+        rom[..program.len()].copy_from_slice(program);
+        let memory =
+            Memory::with_config(Box::new(crate::memory::mbc::RomOnly::new(rom, 0)), config);
+        let mut cpu = Cpu::new(memory);
+        // so interrupt pushes and calls land in HRAM
+        cpu.put_long_reg(LongRegister::SP, 0xFFFE);
+        Self::new(cpu)
+    }
+
+    pub fn get_config(&self) -> &EmuConfig {
+        self.cpu.get_bus().get_config()
+    }
+
+    pub fn get_cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn get_cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    pub fn get_cycles(&self) -> u64 {
+        self.cpu.get_cycles()
+    }
+
+    /// Feed the accelerometer of MBC7 cartridges, in g.
+    /// Does nothing if the cartridge has no accelerometer.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        let mbc = self.cpu.get_bus_mut().get_mbc_mut();
+        if let Some(accelerometer) = mbc.accelerometer() {
+            accelerometer.set_tilt(x, y);
+        }
+    }
+
+    /// The clock of MBC3 cartridges, to set the time or change its speed.
+    pub fn get_rtc_mut(&mut self) -> Option<&mut Rtc> {
+        self.cpu.get_bus_mut().get_mbc_mut().rtc()
+    }
+
+    /// Feed the state of a button from the frontend, pressing one wakes the CPU up from STOP.
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.cpu.get_bus_mut().set_button(button, pressed);
+    }
+
+    /// Release every button held, for when the frontend stops receiving the key releases.
+    pub fn release_all_buttons(&mut self) {
+        self.cpu.get_bus_mut().release_all_buttons();
+    }
+
+    /// To call when the window of the frontend loses the focus, the keys held would be stuck otherwise.
+    ///
+    /// Every button is released, and the emulator is paused if `pause` is set.
+    pub fn focus_lost(&mut self, pause: bool) {
+        self.release_all_buttons();
+        if pause {
+            self.paused = true;
+        }
+    }
+
+    /// While paused, running returns `StopReason::Paused` right away.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Plug a device in the link port, like a link cable to another emulator.
+    pub fn set_serial_device(&mut self, device: Box<dyn SerialDevice>) -> Box<dyn SerialDevice> {
+        self.cpu.get_bus_mut().set_serial_device(device)
+    }
+
+    /// Call `callback` with each byte the game sends through the link port,
+    /// replacing the plugged device.
+    pub fn set_serial_callback(&mut self, callback: impl FnMut(u8) + 'static) {
+        self.set_serial_device(Box::new(SerialCallback::new(callback)));
+    }
+
+    /// Content of the `.sav` file, the whole battery backed RAM of the cartridge.
+    pub fn get_save_data(&self) -> Vec<u8> {
+        self.cpu.get_bus().get_mbc().get_save_data()
+    }
+
+    /// Load a `.sav` file, should be done before running the game.
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        self.cpu.get_bus_mut().get_mbc_mut().load_save_data(data);
+    }
+
+    /// The last frame drawn, one shade per pixel from 0 (white) to 3 (black).
+    pub fn get_framebuffer(&self) -> &[u8; Ppu::WIDTH * Ppu::HEIGHT] {
+        self.cpu.get_bus().get_ppu().get_framebuffer()
+    }
+
+    /// The last frame drawn in RGB, the CGB colors or the DMG shades depending on the model.
+    /// Use `get_compat_palette` to colorize the DMG shades instead.
+    pub fn get_pixels(&self) -> Vec<Color> {
+        self.cpu.get_bus().get_ppu().get_pixels()
+    }
+
+    /// How the frontend should show the frames.
+    ///
+    /// Super Game Boy borders aren't emulated, so for now it's always the native 160x144.
+    pub fn get_display_geometry(&self) -> DisplayGeometry {
+        DisplayGeometry::NATIVE
+    }
+
+    /// Interleaved stereo samples produced since the last call, at the rate set by `set_audio_sample_rate`.
+    ///
+    /// About one second of samples is kept, the frontend should take them every frame.
+    pub fn take_audio_samples(&mut self) -> Vec<i16> {
+        self.cpu.get_bus_mut().get_apu_mut().take_samples()
+    }
+
+    /// Rate of the samples given by `take_audio_samples`, the native `Apu::SAMPLE_RATE` (about 1MHz) by default.
+    pub fn set_audio_sample_rate(&mut self, rate: u32) {
+        self.cpu.get_bus_mut().get_apu_mut().set_output_rate(rate);
+    }
+
+    /// The colors a CGB would give to this game, from the title in its header,
+    /// unless they were set with `set_compat_palette`.
+    pub fn get_compat_palette(&self) -> CompatPalette {
+        self.palette
+            .unwrap_or_else(|| CompatPalette::for_cartridge(self.cpu.get_bus()))
+    }
+
+    /// Force the colors given by `get_compat_palette`, `None` goes back to the cartridge ones.
+    pub fn set_compat_palette(&mut self, palette: Option<CompatPalette>) {
+        self.palette = palette;
+    }
+
+    /// Power cycle the console, the cartridge and the link port device stay plugged.
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.cpu.get_bus_mut().reset();
+        self.boot = Self::start_boot(&mut self.cpu);
+        self.debugger.reset_calls();
+    }
+
+    /// Swap the cartridge without turning the console off, returning the previous one.
+    pub fn swap_cartridge(&mut self, mbc: Box<dyn Mbc>) -> Box<dyn Mbc> {
+        self.cpu.get_bus_mut().replace_mbc(mbc)
+    }
+
+    /// Snapshot of the whole machine, the cartridge included.
+    ///
+    /// Only the emulated hardware is saved. What the host attaches to it is not:
+    /// opcode handlers, scheduled actions, the link port device, the pause, and the
+    /// debugger's breakpoints, step target and watchpoints (those are kept on the bus
+    /// but aren't part of it) are left as they are when a state is loaded, so a state
+    /// never brings back or drops anything the frontend set up, whenever the state was made.
+    ///
+    /// The buttons held are saved, they are the level of the joypad lines:
+    /// loading a state brings back the input it was made with, until the frontend
+    /// sets the buttons again.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::with_header();
+        self.cpu.save_state(&mut state);
+        self.cpu.get_bus().save_state(&mut state);
+        state.into_inner()
+    }
+
+    /// Restore a snapshot made by `save_state`, with the same cartridge inserted.
+    /// The states start with a format version, those of older versions still load,
+    /// as do the ones made before the states had a header.
+    ///
+    /// On error the machine is left as it was.
+    /// The boot animation is not part of the snapshot, loading one skips it.
+    /// The host side (see `save_state`) is untouched.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        // roll back rather than leaving a half loaded machine
+        let backup = self.save_state();
+        if let Err(err) = self.decode_state(data) {
+            self.decode_state(&backup)
+                .expect("a fresh state should always load");
+            return Err(err);
+        }
+        self.boot = None;
+        self.debugger.reset_calls();
+        Ok(())
+    }
+
+    fn decode_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut state = StateReader::new(data);
+        match state.read_header() {
+            Ok(()) => {}
+            Err(SaveStateError::InvalidHeader) => {
+                state = StateReader::with_version(data, HEADERLESS_VERSION);
+            }
+            Err(err) => return Err(err),
+        }
+        match state.get_version() {
+            // the layout hasn't changed since the headerless states
+            HEADERLESS_VERSION..=FORMAT_VERSION => {
+                self.cpu.load_state(&mut state)?;
+                self.cpu.get_bus_mut().load_state(&mut state)
+            }
+            version => Err(SaveStateError::UnsupportedVersion(version)),
+        }
+    }
+
+    /// Snapshot the machine into `rewind` as frames complete, `None` turns rewinding off.
+    /// Returns the previous buffer.
+    pub fn set_rewind(&mut self, rewind: Option<RewindBuffer>) -> Option<RewindBuffer> {
+        std::mem::replace(&mut self.rewind, rewind)
+    }
+
+    pub fn get_rewind(&self) -> Option<&RewindBuffer> {
+        self.rewind.as_ref()
+    }
+
+    /// Step back to the newest snapshot at least `frames` frames ago,
+    /// or as far as the rewind buffer goes.
+    ///
+    /// Returns the frames actually gone back, 0 when rewinding is off or nothing was recorded.
+    /// Like `load_state`, the host side is untouched, and the frame count keeps going.
+    pub fn rewind(&mut self, frames: u64) -> u64 {
+        let Some((gone, state)) = self
+            .rewind
+            .as_mut()
+            .and_then(|rewind| rewind.rewind(frames))
+        else {
+            return 0;
+        };
+        self.load_state(&state)
+            .expect("a fresh state should always load");
+        gone
+    }
+
+    fn update_rewind(&mut self) {
+        let frame = self.get_frame_count();
+        let due = match &mut self.rewind {
+            Some(rewind) => rewind.update_frame(frame),
+            None => false,
+        };
+        if due {
+            let state = self.save_state();
+            if let Some(rewind) = &mut self.rewind {
+                rewind.push(state);
+            }
+        }
+    }
+
+    /// Log the executed instructions to `tracer`, `None` turns tracing off.
+    /// Returns the previous tracer.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) -> Option<Tracer> {
+        std::mem::replace(&mut self.tracer, tracer)
+    }
+
+    pub fn get_tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    pub fn get_tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    pub fn get_debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub fn get_debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /// Queue an action to run at an exact frame or cycle, for scripted reproductions.
+    pub fn schedule(&mut self, at: ScheduledAt, action: ControlAction) {
+        self.schedule.add(at, action);
+    }
+
+    pub fn get_schedule_mut(&mut self) -> &mut Schedule {
+        &mut self.schedule
+    }
+
+    /// Give an illegal opcode a custom behavior instead of locking the CPU.
+    ///
+    /// This is a research hook, games never rely on it.
+    pub fn register_opcode_handler(
+        &mut self,
+        opcode: u8,
+        handler: Box<dyn OpcodeHandler>,
+    ) -> Result<(), ExtensionError> {
+        self.extensions.register(opcode, handler)
+    }
+
+    /// What illegal opcodes without a handler do, they lock the CPU by default.
+    pub fn set_illegal_opcode_policy(&mut self, policy: IllegalOpcodePolicy) {
+        self.extensions.set_policy(policy);
+    }
+
+    pub fn get_opcode_extensions_mut(&mut self) -> &mut OpcodeExtensions {
+        &mut self.extensions
+    }
+
+    fn run_scheduled_actions(&mut self) -> Result<(), StopReason> {
+        while let Some(action) = self.schedule.pop_due(self.get_cycles()) {
+            match action {
+                ControlAction::Reset => self.reset(),
+                ControlAction::LoadState(data) => {
+                    if self.load_state(&data).is_err() {
+                        return Err(StopReason::InvalidState);
+                    }
+                }
+                ControlAction::SwapCartridge(mbc) => {
+                    self.swap_cartridge(mbc);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run until the PPU completes a frame (enters VBlank), the framebuffer is then ready to be presented.
+    ///
+    /// With the LCD off there are no frames, so it runs until the next multiple of `CYCLES_PER_FRAME`
+    /// to keep the frontend going.
+    pub fn run_frame(&mut self) -> StopReason {
+        let frame_end = (self.get_cycles() / Self::CYCLES_PER_FRAME + 1) * Self::CYCLES_PER_FRAME;
+        // a frame completed by a previous run doesn't count
+        self.cpu.get_bus_mut().get_ppu_mut().take_frame_complete();
+        self.debugger.resume(self.cpu.get_pc());
+        loop {
+            if let Err(reason) = self.step_instruction() {
+                return reason;
+            }
+            let ppu = self.cpu.get_bus_mut().get_ppu_mut();
+            if ppu.take_frame_complete() {
+                return StopReason::FrameComplete;
+            }
+            if !ppu.is_enabled() && self.get_cycles() >= frame_end {
+                return StopReason::FrameComplete;
+            }
+        }
+    }
+
+    /// `run_frame`, but a panic in the emulator is caught and returned instead of unwinding,
+    /// so a frontend or a server can survive a bug of the emulator.
+    pub fn run_frame_guarded(&mut self) -> Result<StopReason, EmuError> {
+        self.run_guarded(Self::run_frame)
+    }
+
+    /// Any of the run methods with the guard of `run_frame_guarded`,
+    /// e.g. `emulator.run_guarded(|emu| emu.step())`.
+    pub fn run_guarded<T>(&mut self, run: impl FnOnce(&mut Self) -> T) -> Result<T, EmuError> {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| run(self)));
+        result.map_err(|payload| {
+            let message = panic_message(&*payload);
+            // the machine may be in any state, don't let it go on
+            self.cpu.lock();
+            EmuError::InternalPanic {
+                message,
+                history: self.cpu.get_pc_history().clone(),
+            }
+        })
+    }
+
+    /// The last executed instructions, for crash reports and backtraces.
+    pub fn get_pc_history(&self) -> &PcHistory {
+        self.cpu.get_pc_history()
+    }
+
+    /// Frames completed by the PPU since power on.
+    pub fn get_frame_count(&self) -> u64 {
+        self.cpu.get_bus().get_ppu().get_frame_count()
+    }
+
+    /// Run until `predicate` returns true, it is checked between each instruction.
+    /// This is the loop the other ways of running build on, so the debugger, the tests
+    /// and the scripts get the same stops (pause, breakpoints, locked CPU...).
+    ///
+    /// Returns `StopReason::Timeout` if the condition is not met after `cycle_budget` clock cycles.
+    pub fn run_until<F>(&mut self, mut predicate: F, cycle_budget: u64) -> StopReason
+    where
+        F: FnMut(&Emulator) -> bool,
+    {
+        let deadline = self.get_cycles().saturating_add(cycle_budget);
+        self.debugger.resume(self.cpu.get_pc());
+        loop {
+            if predicate(self) {
+                return StopReason::ConditionMet;
+            }
+            if self.get_cycles() >= deadline {
+                return StopReason::Timeout;
+            }
+            if let Err(reason) = self.step_instruction() {
+                return reason;
+            }
+        }
+    }
+
+    /// Run one instruction, or one M-cycle while the CPU is halted or stopped.
+    pub fn step(&mut self) -> StepEvent {
+        let start_pc = self.cpu.get_pc();
+        let start_cycles = self.get_cycles();
+        let start_frame = self.get_frame_count();
+        // a byte sent outside of `step` isn't this step's
+        self.cpu
+            .get_bus_mut()
+            .get_serial_port_mut()
+            .take_sent_byte();
+        self.debugger.resume(start_pc);
+        // a watchpoint stops after its instruction, which is still reported
+        let (executed, result) = self.run_step();
+        let reason = result.err().unwrap_or(StopReason::InstructionComplete);
+        let pc = if executed.instruction.is_some() {
+            self.cpu.get_instruction_pc()
+        } else if let StopReason::IllegalOpcode { pc, .. } = reason {
+            pc
+        } else {
+            start_pc
+        };
+        StepEvent {
+            reason,
+            instruction: executed.instruction,
+            pc,
+            cycles: self.get_cycles() - start_cycles,
+            interrupt: executed.interrupt,
+            frame_complete: self.get_frame_count() != start_frame,
+            serial_byte: self
+                .cpu
+                .get_bus_mut()
+                .get_serial_port_mut()
+                .take_sent_byte(),
+        }
+    }
+
+    /// Run one instruction, a call or a restart is run until it returns.
+    ///
+    /// Like `run_frame` it also stops on breakpoints and at the end of the frame,
+    /// the rest of the call is then run by the next runs, see `Debugger::get_step_target`.
+    /// An interrupt dispatched by the step is run until it returns as well,
+    /// the instruction it interrupted is then still to run.
+    pub fn step_over(&mut self) -> StopReason {
+        let depth = self.debugger.get_call_depth();
+        let event = self.step();
+        if event.reason != StopReason::InstructionComplete {
+            return event.reason;
+        }
+        if self.debugger.get_call_depth() <= depth {
+            return StopReason::StepComplete;
+        }
+        self.run_to_target(StepTarget::Depth(depth))
+    }
+
+    /// Run until the current function returns, stopping after its `RET`,
+    /// `run_frame` stops apply as for `step_over`.
+    pub fn step_out(&mut self) -> StopReason {
+        let depth = self.debugger.get_call_depth();
+        self.run_to_target(StepTarget::Depth(depth - 1))
+    }
+
+    /// Run until the instruction at `addr`, `run_frame` stops apply as for `step_over`.
+    pub fn run_to(&mut self, addr: u16) -> StopReason {
+        self.run_to_target(StepTarget::Addr(addr))
+    }
+
+    fn run_to_target(&mut self, target: StepTarget) -> StopReason {
+        self.debugger.set_step_target(Some(target));
+        self.run_frame()
+    }
+
+    /// Run at least `cycles` clock cycles, instructions aren't interrupted
+    /// so it can run a few more.
+    pub fn run_cycles(&mut self, cycles: u64) -> StopReason {
+        let end = self.get_cycles().saturating_add(cycles);
+        match self.run_until(|emu| emu.get_cycles() >= end, u64::MAX) {
+            StopReason::ConditionMet => StopReason::CyclesElapsed,
+            reason => reason,
+        }
+    }
+
+    fn step_instruction(&mut self) -> Result<Executed, StopReason> {
+        let (executed, result) = self.run_step();
+        result.map(|()| executed)
+    }
+
+    /// What was executed is also given when stopping on a watchpoint, the access is already done.
+    fn run_step(&mut self) -> (Executed, Result<(), StopReason>) {
+        #[cfg(feature = "metrics")]
+        let stopwatch = Stopwatch::start();
+        let (executed, mut result) = match self.execute_step() {
+            Ok(executed) => (executed, Ok(())),
+            Err(reason) => (Executed::default(), Err(reason)),
+        };
+        match self.cpu.get_bus_mut().get_watchpoints_mut().take_hit() {
+            Some(mut hit) if result.is_ok() => {
+                hit.pc = self.cpu.get_instruction_pc();
+                self.debugger.set_watch_hit(hit);
+                result = Err(StopReason::Watchpoint(hit.addr));
+            }
+            _ => {}
+        }
+        if result.is_err_and(|reason| reason != StopReason::StepComplete) {
+            // an interrupted step doesn't complete in a later run
+            self.debugger.set_step_target(None);
+        }
+        #[cfg(feature = "metrics")]
+        {
+            stopwatch.stop(&mut self.frame_time);
+            self.update_metrics();
+        }
+        if result.is_ok() {
+            self.update_rewind();
+        }
+        (executed, result)
+    }
+
+    /// Once a frame is over, split its time between the CPU and the rest.
+    #[cfg(feature = "metrics")]
+    fn update_metrics(&mut self) {
+        let frame = self.get_frame_count();
+        if frame == self.metrics_frame {
+            return;
+        }
+        self.metrics_frame = frame;
+        let mut metrics = self.cpu.get_bus_mut().take_metrics();
+        let frame_time = std::mem::take(&mut self.frame_time);
+        metrics.cpu = frame_time.saturating_sub(metrics.get_total());
+        self.last_metrics = metrics;
+    }
+
+    /// Where the host time went during the last frame, needs the `metrics` feature.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> Metrics {
+        self.last_metrics
+    }
+
+    fn execute_step(&mut self) -> Result<Executed, StopReason> {
+        if self.paused {
+            return Err(StopReason::Paused);
+        }
+        if self.debugger.take_break_request() {
+            return Err(StopReason::DebuggerRequest);
+        }
+        self.run_scheduled_actions()?;
+        if self.cpu.is_locked() {
+            return Err(StopReason::CpuLocked);
+        }
+        if let Some(boot) = &mut self.boot {
+            if boot.step(&mut self.cpu) {
+                self.boot = None;
+            }
+            return Ok(Executed::default());
+        }
+        if self.cpu.get_bus().is_hdma_copying() {
+            // the VRAM DMA has the bus, the CPU waits for it to be done
+            self.cpu.cycle();
+            return Ok(Executed::default());
+        }
+        let interrupt = self.cpu.handle_interrupts();
+        if let Some(interrupt) = interrupt {
+            self.debugger.enter_interrupt(interrupt, &self.cpu);
+        }
+        let idle = Executed {
+            instruction: None,
+            interrupt,
+        };
+        if self.cpu.is_stopped() {
+            // the clock is stopped, but the frontend still needs its frames
+            self.cpu.idle();
+            return Ok(idle);
+        }
+        if self.cpu.is_halted() {
+            // nothing to do but let the rest of the machine run
+            self.cpu.cycle();
+            return Ok(idle);
+        }
+        if let Some(reason) = self.debugger.check_breakpoint(&self.cpu) {
+            return Err(reason);
+        }
+        let sp = self.cpu.get_long_reg(LongRegister::SP);
+        let trace = self.tracer.as_ref().map(|_| TraceState::capture(&self.cpu));
+        match Instruction::fetch(&mut self.cpu) {
+            Some(instruction) => {
+                instruction.execute(&mut self.cpu);
+                if let (Some(tracer), Some(state)) = (&mut self.tracer, &trace) {
+                    tracer.trace(state, instruction);
+                }
+                self.debugger.track_instruction(instruction, sp, &self.cpu);
+                Ok(Executed {
+                    instruction: Some(instruction),
+                    interrupt,
+                })
+            }
+            None => {
+                let pc = self.cpu.get_instruction_pc();
+                let opcode = self.cpu.peek_memory(pc);
+                if self.extensions.execute(&mut self.cpu, opcode) {
+                    return Ok(idle);
+                }
+                if let IllegalOpcodePolicy::Error = self.extensions.get_policy() {
+                    // back on the opcode, the caller decides what to do
+                    self.cpu.set_pc(pc);
+                } else {
+                    // the hardware just hangs on illegal opcodes
+                    self.cpu.lock();
+                }
+                Err(StopReason::IllegalOpcode { pc, opcode })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{
+        config::{EmuConfig, Model},
+        cpu::{
+            registers::{LongRegister, Register},
+            Cpu,
+        },
+        debugger::WatchKind,
+        memory::{interrupts::Interrupt, joypad::Button},
+        savestate::{rewind::RewindBuffer, SaveStateError},
+        schedule::{ControlAction, ScheduledAt},
+    };
+
+    use super::{EmuError, Emulator, IllegalOpcodePolicy, StopReason};
+
+    #[test]
+    fn run_frame() {
+        // JR -2
+        let mut emulator = Emulator::from_program(&[0x18, 0xFE]);
+        assert_eq!(emulator.run_frame(), StopReason::FrameComplete);
+        assert!(emulator.get_cycles() >= Emulator::CYCLES_PER_FRAME);
+        assert_eq!(emulator.run_frame(), StopReason::FrameComplete);
+        assert!(emulator.get_cycles() >= 2 * Emulator::CYCLES_PER_FRAME);
+    }
+
+    #[test]
+    fn run_frame_stops_at_vblank() {
+        // LD A,0x91, LDH (0x40),A, EI, JR -2 with the VBlank interrupt enabled
+        let mut program = vec![
+            0x3E, 0x91, 0xE0, 0x40, 0x3E, 0x01, 0xE0, 0xFF, 0xFB, 0x18, 0xFE,
+        ];
+        program.resize(0x40, 0x00);
+        // VBlank handler: INC B, RETI
+        program.extend([0x04, 0xD9]);
+        let mut emulator = Emulator::from_program(&program);
+        assert_eq!(emulator.run_frame(), StopReason::FrameComplete);
+        assert_eq!(emulator.get_frame_count(), 1);
+        let cycles = emulator.get_cycles();
+        assert_eq!(emulator.run_frame(), StopReason::FrameComplete);
+        // give or take the instruction that was running
+        let elapsed = emulator.get_cycles() - cycles;
+        assert!(elapsed.abs_diff(Emulator::CYCLES_PER_FRAME) <= 24);
+        // the handler ran once per frame
+        emulator.run_until(|emu| emu.get_cpu().get_reg(Register::B) == 2, 100);
+        assert_eq!(emulator.get_frame_count(), 2);
+    }
+
+    #[test]
+    fn double_speed() {
+        // LD A,1; LDH (0x4D),A; STOP; NOP
+        let program = [0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00, 0x00];
+        let config = EmuConfig {
+            model: Model::Cgb,
+            ..Default::default()
+        };
+        let mut cgb = Emulator::from_program_with_config(&program, config);
+        cgb.step();
+        cgb.step();
+        assert_eq!(cgb.get_cpu().get_bus().get(0xFF4D), 0x7F);
+        cgb.step();
+        assert!(!cgb.get_cpu().is_stopped());
+        assert_eq!(cgb.get_cpu().get_bus().get(0xFF4D), 0xFE);
+        // the NOP is 4 cycles of the CPU, but only 2 of the rest
+        let (cycles, cpu_cycles) = (cgb.get_cycles(), cgb.get_cpu().get_cpu_cycles());
+        cgb.step();
+        assert_eq!(cgb.get_cycles() - cycles, 2);
+        assert_eq!(cgb.get_cpu().get_cpu_cycles() - cpu_cycles, 4);
+
+        // no switch on DMG, STOP stops
+        let mut dmg = Emulator::from_program(&program);
+        for _ in 0..3 {
+            dmg.step();
+        }
+        assert!(dmg.get_cpu().is_stopped());
+        assert_eq!(dmg.get_cpu().get_bus().get(0xFF4D), 0xFF);
+    }
+
+    #[test]
+    fn run_until() {
+        let mut emulator = Emulator::from_program(&[0x18, 0xFE]);
+        let reason = emulator.run_until(|emu| emu.get_cycles() >= 1000, 2000);
+        assert_eq!(reason, StopReason::ConditionMet);
+        let reason = emulator.run_until(|_| false, 2000);
+        assert_eq!(reason, StopReason::Timeout);
+        // the budget is checked after the condition, between instructions
+        let cycles = emulator.get_cycles();
+        let reason = emulator.run_until(|_| true, 0);
+        assert_eq!(reason, StopReason::ConditionMet);
+        assert_eq!(emulator.get_cycles(), cycles);
+        emulator.set_paused(true);
+        assert_eq!(emulator.run_until(|_| false, 2000), StopReason::Paused);
+    }
+
+    #[test]
+    fn scheduled_actions() {
+        // INC A, JR -3
+        let mut emulator = Emulator::from_program(&[0x3C, 0x18, 0xFD]);
+        emulator.run_frame();
+        let state = emulator.save_state();
+        let a = emulator.get_cpu().get_reg_a();
+        assert_ne!(a, 0);
+
+        emulator.schedule(ScheduledAt::Frame(2), ControlAction::Reset);
+        emulator.schedule(ScheduledAt::Cycle(100), ControlAction::LoadState(state));
+        emulator.schedule(ScheduledAt::Frame(3), ControlAction::LoadState(vec![0xFF]));
+
+        // the state load is overdue, it happens right away
+        emulator.run_until(|_| true, 0);
+        assert_eq!(emulator.get_cpu().get_reg_a(), a);
+
+        emulator.run_frame();
+        // reset at the start of frame 2, then a single INC A
+        emulator.run_until(|_| false, 1);
+        assert_eq!(emulator.get_cpu().get_pc(), 1);
+        assert_eq!(emulator.get_cpu().get_reg_a(), 1);
+
+        assert_eq!(emulator.run_frame(), StopReason::FrameComplete);
+        assert_eq!(emulator.run_frame(), StopReason::InvalidState);
+        assert!(emulator.get_schedule_mut().is_empty());
+    }
+
+    #[test]
+    fn illegal_opcode_handler() {
+        // LD A,0x10, custom 0xFC n (A += n), HALT
+        let mut emulator = Emulator::from_program(&[0x3E, 0x10, 0xFC, 0x05, 0x76]);
+        assert!(emulator
+            .register_opcode_handler(0x00, Box::new(|_: &mut Cpu, _| {}))
+            .is_err());
+        let handler = |cpu: &mut Cpu, _opcode| {
+            let n = cpu.advance();
+            let a = cpu.get_reg_a();
+            cpu.put_reg_a(a + n);
+        };
+        emulator
+            .register_opcode_handler(0xFC, Box::new(handler))
+            .unwrap();
+        emulator.run_until(|emu| emu.get_cpu().is_halted(), 1000);
+        assert_eq!(emulator.get_cpu().get_reg_a(), 0x15);
+        assert!(!emulator.get_cpu().is_locked());
+    }
+
+    #[test]
+    fn illegal_opcode_locks_cpu() {
+        // NOP, NOP, illegal
+        let mut emulator = Emulator::from_program(&[0x00, 0x00, 0xD3]);
+        let reason = emulator.run_frame();
+        assert_eq!(
+            reason,
+            StopReason::IllegalOpcode {
+                pc: 0x0002,
+                opcode: 0xD3
+            }
+        );
+        assert_eq!(emulator.run_frame(), StopReason::CpuLocked);
+    }
+
+    #[test]
+    fn panic_guard() {
+        // NOP, NOP, illegal opcode with a buggy handler
+        let mut emulator = Emulator::from_program(&[0x00, 0x00, 0xFD]);
+        let handler = |_: &mut Cpu, _opcode| panic!("buggy handler");
+        emulator
+            .register_opcode_handler(0xFD, Box::new(handler))
+            .unwrap();
+        let Err(EmuError::InternalPanic { message, history }) = emulator.run_frame_guarded() else {
+            panic!("the panic should be caught");
+        };
+        assert_eq!(message, "buggy handler");
+        let pcs: Vec<u16> = history.iter().map(|entry| entry.pc).collect();
+        assert_eq!(pcs, [0, 1, 2]);
+        assert_eq!(emulator.run_frame_guarded().unwrap(), StopReason::CpuLocked);
+
+        // the other run methods get the same guard
+        let mut emulator = Emulator::from_program(&[0x00, 0x00, 0xFD]);
+        emulator
+            .register_opcode_handler(0xFD, Box::new(handler))
+            .unwrap();
+        emulator.step();
+        emulator.step();
+        let Err(EmuError::InternalPanic { history, .. }) = emulator.run_guarded(|emu| emu.step())
+        else {
+            panic!("the panic should be caught");
+        };
+        assert_eq!(history.last().unwrap().pc, 2);
+        assert_eq!(emulator.get_pc_history().len(), history.len());
+        assert_eq!(
+            emulator.run_guarded(|emu| emu.run_cycles(100)).unwrap(),
+            StopReason::CpuLocked
+        );
+    }
+
+    #[test]
+    fn serial_callback() {
+        // LD A,'H', LDH (SB),A, LD A,0x81, LDH (SC),A, HALT
+        let mut emulator =
+            Emulator::from_program(&[0x3E, b'H', 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0x76]);
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let sent = output.clone();
+        emulator.set_serial_callback(move |byte| sent.borrow_mut().push(byte));
+        emulator.run_until(|_| false, 5000);
+        assert_eq!(*output.borrow(), b"H");
+    }
+
+    #[test]
+    fn step_events() {
+        // LD A,0x08, LDH (IE),A, LD A,'H', LDH (SB),A, LD A,0x81, LDH (SC),A, EI, JR -2
+        let mut program = vec![
+            0x3E, 0x08, 0xE0, 0xFF, 0x3E, b'H', 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0xFB, 0x18,
+            0xFE,
+        ];
+        program.resize(0x58, 0x00);
+        // serial handler: RETI
+        program.push(0xD9);
+        let mut emulator = Emulator::from_program(&program);
+
+        let event = emulator.step();
+        assert_eq!(event.reason, StopReason::InstructionComplete);
+        assert_eq!(event.instruction.unwrap().to_string(), "ld a, $08");
+        assert_eq!((event.pc, event.cycles), (0x0000, 8));
+
+        let mut sent = None;
+        let mut interrupt = None;
+        for _ in 0..2000 {
+            let event = emulator.step();
+            sent = sent.or(event.serial_byte);
+            if event.interrupt.is_some() {
+                interrupt = Some(event);
+                break;
+            }
+        }
+        assert_eq!(sent, Some(b'H'));
+        let event = interrupt.unwrap();
+        assert_eq!(event.interrupt, Some(Interrupt::Serial));
+        assert_eq!(
+            (event.pc, event.instruction.unwrap().to_string()),
+            (0x58, "reti".into())
+        );
+        // RETI popped the pushed return address back off the stack
+        assert_eq!(emulator.get_cpu().get_long_reg(LongRegister::SP), 0xFFFE);
+
+        let mut emulator = Emulator::from_program(&[0xFD]);
+        let event = emulator.step();
+        let illegal = StopReason::IllegalOpcode {
+            pc: 0x0000,
+            opcode: 0xFD,
+        };
+        assert_eq!(
+            (event.reason, event.instruction, event.pc),
+            (illegal, None, 0)
+        );
+    }
+
+    #[test]
+    fn illegal_opcode_policy() {
+        let illegal = StopReason::IllegalOpcode {
+            pc: 0x0001,
+            opcode: 0xE3,
+        };
+        // NOP, illegal opcode, INC A
+        let program = [0x00, 0xE3, 0x3C];
+
+        let mut emulator = Emulator::from_program(&program);
+        assert_eq!(emulator.run_frame(), illegal);
+        assert_eq!(emulator.run_frame(), StopReason::CpuLocked);
+
+        let mut emulator = Emulator::from_program(&program);
+        emulator.set_illegal_opcode_policy(IllegalOpcodePolicy::Error);
+        assert_eq!(emulator.run_frame(), illegal);
+        assert_eq!(emulator.get_cpu().get_pc(), 0x0001);
+        assert_eq!(emulator.run_frame(), illegal);
+
+        let mut emulator = Emulator::from_program(&program);
+        let hits = Rc::new(RefCell::new(Vec::new()));
+        let seen = hits.clone();
+        let callback = move |cpu: &mut Cpu, opcode| seen.borrow_mut().push((cpu.get_pc(), opcode));
+        emulator.set_illegal_opcode_policy(IllegalOpcodePolicy::Callback(Box::new(callback)));
+        emulator.step();
+        emulator.step();
+        emulator.step();
+        assert_eq!(*hits.borrow(), [(0x0002, 0xE3)]);
+        assert_eq!(emulator.get_cpu().get_reg(Register::A), 0x01);
+    }
+
+    #[test]
+    fn focus_lost() {
+        // JR -2
+        let mut emulator = Emulator::from_program(&[0x18, 0xFE]);
+        emulator.set_button(Button::A, true);
+        emulator.focus_lost(true);
+        assert!(!emulator
+            .get_cpu()
+            .get_bus()
+            .get_joypad()
+            .is_pressed(Button::A));
+        let cycles = emulator.get_cycles();
+        assert_eq!(emulator.run_frame(), StopReason::Paused);
+        assert_eq!(emulator.get_cycles(), cycles);
+        emulator.set_paused(false);
+        assert_eq!(emulator.run_frame(), StopReason::FrameComplete);
+    }
+
+    #[test]
+    fn load_state_is_all_or_nothing() {
+        // INC B; JR -3
+        let mut emulator = Emulator::from_program(&[0x04, 0x18, 0xFD]);
+        emulator.run_frame();
+        let state = emulator.save_state();
+        emulator.run_frame();
+        let before = emulator.save_state();
+        // cut in the middle of the memory
+        assert_eq!(
+            emulator.load_state(&state[..state.len() / 2]),
+            Err(SaveStateError::UnexpectedEnd)
+        );
+        assert_eq!(emulator.save_state(), before);
+    }
+
+    /// Made by the emulator before the states had a header, 3 frames into the program below.
+    #[test]
+    fn load_headerless_state() {
+        let fixture = include_bytes!("../../tests/fixtures/headerless.state");
+        // LD A, 0x91; LDH (0x40), A; INC B; JR -3
+        let mut emulator = Emulator::from_program(&[0x3E, 0x91, 0xE0, 0x40, 0x04, 0x18, 0xFD]);
+        emulator.load_state(fixture).unwrap();
+        let cpu = emulator.get_cpu();
+        assert_eq!(cpu.get_reg(Register::B), 82);
+        assert_eq!(cpu.get_pc(), 0x0004);
+        // saved again, only the header is new
+        assert_eq!(&emulator.save_state()[6..], fixture);
+    }
+
+    #[test]
+    fn load_state_keeps_host_side() {
+        // JR -2
+        let mut emulator = Emulator::from_program(&[0x18, 0xFE]);
+        let state = emulator.save_state();
+        emulator
+            .register_opcode_handler(0xD3, Box::new(|_: &mut Cpu, _| {}))
+            .unwrap();
+        emulator.schedule(ScheduledAt::Frame(100), ControlAction::Reset);
+        emulator.set_paused(true);
+        emulator.get_debugger_mut().add_breakpoint(0x0000);
+        let watchpoint = emulator
+            .get_cpu_mut()
+            .get_bus_mut()
+            .get_watchpoints_mut()
+            .add(0xC000..=0xC000, WatchKind::Write);
+        emulator.set_button(Button::A, true);
+        emulator.load_state(&state).unwrap();
+        assert!(!emulator.get_opcode_extensions_mut().is_empty());
+        assert_eq!(emulator.get_schedule_mut().len(), 1);
+        assert!(emulator.is_paused());
+        assert!(emulator.get_debugger().get_breakpoint(0x0000).is_some());
+        let watchpoints = emulator.get_cpu_mut().get_bus_mut().get_watchpoints_mut();
+        assert!(watchpoints.get_mut(watchpoint).is_some());
+        // the input is the one of the state
+        assert_eq!(emulator.get_cpu().get_bus().get_joypad().get_pressed(), 0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn metrics() {
+        // LD A, 0x91; LDH (0x40), A; JR -2
+        let mut emulator = Emulator::from_program(&[0x3E, 0x91, 0xE0, 0x40, 0x18, 0xFE]);
+        assert_eq!(emulator.metrics().get_total(), std::time::Duration::ZERO);
+        emulator.run_frame();
+        emulator.run_frame();
+        let metrics = emulator.metrics();
+        assert!(metrics.cpu > std::time::Duration::ZERO);
+        assert!(metrics.ppu > std::time::Duration::ZERO);
+        assert!(metrics.apu > std::time::Duration::ZERO);
+        assert!(metrics.bus > std::time::Duration::ZERO);
+    }
+
+    #[test]
+    fn step_and_run_cycles() {
+        // NOP, JR -2
+        let mut emulator = Emulator::from_program(&[0x00, 0x18, 0xFE]);
+        let cycles = emulator.get_cycles();
+        assert_eq!(emulator.step().reason, StopReason::InstructionComplete);
+        assert_eq!(emulator.get_cycles(), cycles + 4);
+        assert_eq!(emulator.step().reason, StopReason::InstructionComplete);
+        assert_eq!(emulator.get_cycles(), cycles + 16);
+
+        // JR takes 12 cycles, 100 is reached after 9 of them
+        assert_eq!(emulator.run_cycles(100), StopReason::CyclesElapsed);
+        assert_eq!(emulator.get_cycles(), cycles + 16 + 108);
+    }
+
+    #[test]
+    fn rewind() {
+        // LD A, 0x91; LDH (0x40), A; INC B; JR -3
+        let mut emulator = Emulator::from_program(&[0x3E, 0x91, 0xE0, 0x40, 0x04, 0x18, 0xFD]);
+        assert_eq!(emulator.rewind(10), 0);
+        emulator.set_rewind(Some(RewindBuffer::new(2, usize::MAX)));
+        for _ in 0..4 {
+            emulator.run_frame();
+        }
+        let state = emulator.save_state();
+        let b = emulator.get_cpu().get_reg(Register::B);
+        for _ in 0..4 {
+            emulator.run_frame();
+        }
+        assert_ne!(emulator.get_cpu().get_reg(Register::B), b);
+
+        // snapshots at each even frame
+        assert_eq!(emulator.rewind(3), 4);
+        assert_eq!(emulator.save_state(), state);
+        assert_eq!(emulator.get_cpu().get_reg(Register::B), b);
+        assert_eq!(emulator.get_rewind().unwrap().len(), 3);
+        emulator.run_frame();
+        assert_eq!(emulator.rewind(100), 5);
+    }
+}
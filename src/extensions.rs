@@ -0,0 +1,139 @@
+use std::{collections::HashMap, fmt::Display};
+
+use crate::cpu::Cpu;
+
+/// Custom behavior of an illegal opcode.
+///
+/// When called, PC points after the opcode, operands can be read with `Cpu::advance`.
+pub trait OpcodeHandler {
+    fn execute(&mut self, cpu: &mut Cpu, opcode: u8);
+}
+
+impl<F> OpcodeHandler for F
+where
+    F: FnMut(&mut Cpu, u8),
+{
+    fn execute(&mut self, cpu: &mut Cpu, opcode: u8) {
+        self(cpu, opcode)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtensionError {
+    /// The opcode exists on the hardware and can't be overridden.
+    LegalOpcode(u8),
+}
+
+impl Display for ExtensionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtensionError::LegalOpcode(opcode) => {
+                write!(f, "opcode {:#04X} is a legal instruction", opcode)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExtensionError {}
+
+/// What happens on an illegal opcode that has no handler registered.
+#[derive(Default)]
+pub enum IllegalOpcodePolicy {
+    /// The CPU locks up until a reset, like the hardware.
+    #[default]
+    Lock,
+    /// The run stops with `StopReason::IllegalOpcode` without locking the CPU,
+    /// PC is left on the opcode so running again stops on it again.
+    Error,
+    /// The handler is called for every illegal opcode, then the execution goes on.
+    Callback(Box<dyn OpcodeHandler>),
+}
+
+impl std::fmt::Debug for IllegalOpcodePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IllegalOpcodePolicy::Lock => f.write_str("Lock"),
+            IllegalOpcodePolicy::Error => f.write_str("Error"),
+            IllegalOpcodePolicy::Callback(_) => f.write_str("Callback"),
+        }
+    }
+}
+
+/// Handlers registered for illegal opcodes, a research hook that isn't part of the hardware.
+///
+/// The SM83 has 11 opcodes that don't exist and lock the CPU when fetched.
+/// Handlers can be registered for them, to experiment with custom instructions
+/// (homebrew VMs, emulator traps, ...). It is empty by default,
+/// and legal opcodes can't be overridden, so accurate emulation is never affected.
+#[derive(Default)]
+pub struct OpcodeExtensions {
+    handlers: HashMap<u8, Box<dyn OpcodeHandler>>,
+    /// For the opcodes without a handler.
+    policy: IllegalOpcodePolicy,
+}
+
+impl std::fmt::Debug for OpcodeExtensions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut opcodes: Vec<_> = self.handlers.keys().collect();
+        opcodes.sort();
+        f.debug_struct("OpcodeExtensions")
+            .field("opcodes", &opcodes)
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+impl OpcodeExtensions {
+    pub const ILLEGAL_OPCODES: [u8; 11] = [
+        0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+    ];
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_illegal(opcode: u8) -> bool {
+        Self::ILLEGAL_OPCODES.contains(&opcode)
+    }
+
+    pub fn register(
+        &mut self,
+        opcode: u8,
+        handler: Box<dyn OpcodeHandler>,
+    ) -> Result<(), ExtensionError> {
+        if !Self::is_illegal(opcode) {
+            return Err(ExtensionError::LegalOpcode(opcode));
+        }
+        self.handlers.insert(opcode, handler);
+        Ok(())
+    }
+
+    /// Back to the hardware behavior for this opcode.
+    pub fn unregister(&mut self, opcode: u8) {
+        self.handlers.remove(&opcode);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+
+    pub fn get_policy(&self) -> &IllegalOpcodePolicy {
+        &self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: IllegalOpcodePolicy) {
+        self.policy = policy;
+    }
+
+    /// Run the handler of `opcode`, or the callback of the policy,
+    /// returns false if there is none.
+    pub fn execute(&mut self, cpu: &mut Cpu, opcode: u8) -> bool {
+        let handler = match (self.handlers.get_mut(&opcode), &mut self.policy) {
+            (Some(handler), _) => handler,
+            (None, IllegalOpcodePolicy::Callback(handler)) => handler,
+            (None, _) => return false,
+        };
+        handler.execute(cpu, opcode);
+        true
+    }
+}
@@ -9,9 +9,6 @@ pub trait AccesBigEndianBytesU16: Into<u16> {
         let [_high, low] = u16::to_be_bytes(self.into());
         low
     }
-    fn set_high(&mut self, byte: u8) {
-        *self.get_high_mut() = byte;
-    }
     fn set_low(&mut self, byte: u8) {
         *self.get_low_mut() = byte;
     }
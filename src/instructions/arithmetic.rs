@@ -1,11 +1,15 @@
-use std::ops::{AddAssign, SubAssign};
-
-use crate::cpu::{
-    registers::{Flags, LongRegister, Register, Registers, SetFlags},
-    Cpu,
+use crate::{
+    cpu::{
+        registers::{Flags, LongRegister, Register, Registers, SetFlags},
+        Cpu,
+    },
+    memory::bus::Bus,
 };
 
+use super::{get_long_register_bits, get_register_bits, FetchRegister};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ArithmeticInstruction {
     // 8-bits arithmetic
     /// ADD A, n
@@ -182,14 +186,26 @@ pub enum ArithmeticInstruction {
     ///
     /// Add to the 16-bit register HL, the value from the 16-bit register lr.
     ///
+    /// Flags:
+    /// Z - Not affected.
+    /// N - Reset.
+    /// H - Set if carry from bit 11.
+    /// C - Set if carry from bit 15.
+    ///
     /// Cycles: 8
     AddHL(LongRegister),
     /// ADD SP, n
     ///
-    /// Add to the 16-bit register SP, the immediate 8-bit value n.
+    /// Add to the 16-bit register SP, the immediate signed 8-bit value n.
+    ///
+    /// Flags:
+    /// Z - Reset.
+    /// N - Reset.
+    /// H - Set if carry from bit 3.
+    /// C - Set if carry from bit 7.
     ///
     /// Cycles: 16
-    AddSPImmediate(u8),
+    AddSPImmediate(i8),
     /// INC lr
     ///
     /// Increment register lr.
@@ -247,7 +263,7 @@ impl ArithmeticInstruction {
 
     fn fetch_inc_dec(opcode: u8) -> Self {
         let dec = opcode & 0x01 == 0x01;
-        let i = (opcode & 0b00011100) >> 2;
+        let i = (opcode & 0b00111000) >> 3;
         let reg = Registers::REGISTERS[i as usize];
         match (dec, reg) {
             (true, Register::F) => ArithmeticInstruction::DecAddrHL,
@@ -274,7 +290,7 @@ impl ArithmeticInstruction {
         ArithmeticInstruction::AddHL(reg)
     }
 
-    pub fn fetch(cpu: &mut Cpu, opcode: u8) -> Option<Self> {
+    pub fn fetch<B: Bus>(cpu: &mut Cpu<B>, opcode: u8) -> Option<Self> {
         use ArithmeticInstruction::*;
         match opcode {
             0x80..=0x8F => Some(Self::fetch_add(opcode)),
@@ -284,7 +300,7 @@ impl ArithmeticInstruction {
             0xD6 => Some(SubImmediate(cpu.advance())),
             0xDE => Some(SubCarryImmediate(cpu.advance())),
             0xE6 => Some(AndImmediate(cpu.advance())),
-            0xE8 => Some(AddSPImmediate(cpu.advance())),
+            0xE8 => Some(AddSPImmediate(i8::from_be_bytes([cpu.advance()]))),
             0xEE => Some(XorImmediate(cpu.advance())),
             0xF6 => Some(OrImmediate(cpu.advance())),
             0xFE => Some(CmpImmediate(cpu.advance())),
@@ -296,7 +312,51 @@ impl ArithmeticInstruction {
         }
     }
 
-    pub fn execute(self, cpu: &mut Cpu) {
+    pub fn get_opcode(self) -> u8 {
+        use ArithmeticInstruction::*;
+        // ADD, ADC, SUB, SBC, AND, XOR, OR and CP follow each other, with the operand in the low bits
+        let alu = |base: u8, reg: FetchRegister| {
+            base | reg.map(get_register_bits, get_register_bits(Register::F))
+        };
+        let hl = FetchRegister::AddrHL;
+        let long = get_long_register_bits;
+        match self {
+            AddImmediate(_) => 0xC6,
+            AddCarryImmediate(_) => 0xCE,
+            SubImmediate(_) => 0xD6,
+            SubCarryImmediate(_) => 0xDE,
+            AndImmediate(_) => 0xE6,
+            XorImmediate(_) => 0xEE,
+            OrImmediate(_) => 0xF6,
+            CmpImmediate(_) => 0xFE,
+            AddRegister(r) => alu(0x80, FetchRegister::Register(r)),
+            AddAddrHL => alu(0x80, hl),
+            AddCarryRegister(r) => alu(0x88, FetchRegister::Register(r)),
+            AddCarryAddrHL => alu(0x88, hl),
+            SubRegister(r) => alu(0x90, FetchRegister::Register(r)),
+            SubAddrHL => alu(0x90, hl),
+            SubCarryRegister(r) => alu(0x98, FetchRegister::Register(r)),
+            SubCarryAddrHL => alu(0x98, hl),
+            AndRegister(r) => alu(0xA0, FetchRegister::Register(r)),
+            AndAddrHL => alu(0xA0, hl),
+            XorRegister(r) => alu(0xA8, FetchRegister::Register(r)),
+            XorAddrHL => alu(0xA8, hl),
+            OrRegister(r) => alu(0xB0, FetchRegister::Register(r)),
+            OrAddrHL => alu(0xB0, hl),
+            CmpRegister(r) => alu(0xB8, FetchRegister::Register(r)),
+            CmpAddrHL => alu(0xB8, hl),
+            IncRegister(r) => 0x04 | (get_register_bits(r) << 3),
+            IncAddrHL => 0x34,
+            DecRegister(r) => 0x05 | (get_register_bits(r) << 3),
+            DecAddrHL => 0x35,
+            AddHL(lr) => 0x09 | (long(lr) << 4),
+            AddSPImmediate(_) => 0xE8,
+            IncLongRegister(lr) => 0x03 | (long(lr) << 4),
+            DecLongRegister(lr) => 0x0B | (long(lr) << 4),
+        }
+    }
+
+    pub fn execute<B: Bus>(self, cpu: &mut Cpu<B>) {
         match self {
             ArithmeticInstruction::AddImmediate(n) => {
                 let a = cpu.get_reg_a();
@@ -454,22 +514,41 @@ impl ArithmeticInstruction {
                 cpu.put_at_hl(value);
             }
             ArithmeticInstruction::AddHL(lr) => {
-                todo!()
+                // 2 machine cycle but only one W/R, so need to explicitly cycle
+                cpu.cycle();
+                let hl = cpu.get_long_reg(LongRegister::HL);
+                let value = cpu.get_long_reg(lr);
+                let (result, mut flags) = Self::add_long(hl, value);
+                flags.zero = cpu.get_flag(Flags::Zero);
+                cpu.set_flags(flags);
+                cpu.put_long_reg(LongRegister::HL, result);
             }
             ArithmeticInstruction::AddSPImmediate(n) => {
-                todo!()
+                // 4 machine cycle but only 2 W/R, so need to explicitly cycle twice
+                cpu.cycle();
+                cpu.cycle();
+                let sp = cpu.get_long_reg(LongRegister::SP);
+                let (result, flags) = Self::add_sp_delta(sp, n);
+                cpu.set_flags(flags);
+                cpu.put_long_reg(LongRegister::SP, result);
             }
             ArithmeticInstruction::IncLongRegister(reg) => {
-                cpu.get_long_reg(reg).add_assign(1);
+                // 2 machine cycle but only the opcode read, the 16-bit increment takes one
+                cpu.cycle();
+                let value = cpu.get_long_reg(reg);
+                // INC rr wraps on hardware too, it is not address math
+                cpu.put_long_reg(reg, value.wrapping_add(1));
             }
             ArithmeticInstruction::DecLongRegister(reg) => {
-                cpu.get_long_reg(reg).sub_assign(1);
+                cpu.cycle();
+                let value = cpu.get_long_reg(reg);
+                cpu.put_long_reg(reg, value.wrapping_sub(1));
             }
         }
     }
 
     fn add(a: u8, b: u8) -> (u8, SetFlags) {
-        let half_carry = a & 0x0F + b & 0x0F > 0x0F;
+        let half_carry = (a & 0x0F) + (b & 0x0F) > 0x0F;
         let (value, carry) = a.overflowing_add(b);
         let zero = value == 0;
         let flags = SetFlags {
@@ -481,31 +560,66 @@ impl ArithmeticInstruction {
         (value, flags)
     }
 
+    /// Flags are from bit 11 and 15, zero is left reset.
+    fn add_long(a: u16, b: u16) -> (u16, SetFlags) {
+        let half_carry = (a & 0x0FFF) + (b & 0x0FFF) > 0x0FFF;
+        let (value, carry) = a.overflowing_add(b);
+        let flags = SetFlags {
+            half_carry,
+            carry,
+            ..Default::default()
+        };
+        (value, flags)
+    }
+
+    /// SP + e, shared by `ADD SP, e` and `LD HL, SP+e`. The flags are from the unsigned add
+    /// of the low byte of SP and e, bits 3 and 7, zero and substract are reset.
+    /// The result wraps around, a stack near 0x0000 going down is fine.
+    pub(super) fn add_sp_delta(sp: u16, delta: i8) -> (u16, SetFlags) {
+        let [low, _] = sp.to_le_bytes();
+        let [delta_byte] = delta.to_le_bytes();
+        let (_, flags) = Self::add(low, delta_byte);
+        let flags = SetFlags {
+            half_carry: flags.half_carry,
+            carry: flags.carry,
+            ..Default::default()
+        };
+        (sp.wrapping_add_signed(delta.into()), flags)
+    }
+
+    /// The carry is added to both the low nibble and the whole byte before comparing,
+    /// adding it to one of the operands first would lose the carry out of 0x0F or 0xFF.
     fn add_carry(a: u8, b: u8, carry: bool) -> (u8, SetFlags) {
-        if carry {
-            match (a, b) {
-                (0xFF, 0xFF) => (
-                    0xFF,
-                    SetFlags {
-                        carry: true,
-                        half_carry: true,
-                        ..Default::default()
-                    },
-                ),
-                (0xFF, x) | (x, 0xFF) => Self::add(x + 1, 0xFF),
-                _ => Self::add(a + 1, b),
-            }
-        } else {
-            Self::add(a, b)
-        }
+        let carry = u8::from(carry);
+        let half_carry = (a & 0x0F) + (b & 0x0F) + carry > 0x0F;
+        let result = u16::from(a) + u16::from(b) + u16::from(carry);
+        let [value, high] = result.to_le_bytes();
+        let flags = SetFlags {
+            half_carry,
+            carry: high != 0,
+            zero: value == 0,
+            substract: false,
+        };
+        (value, flags)
     }
 
+    /// Carry and half carry are set on a borrow out of bit 7 and bit 3.
     fn sub(a: u8, b: u8) -> (u8, SetFlags) {
-        todo!()
+        Self::sub_carry(a, b, false)
     }
 
     fn sub_carry(a: u8, b: u8, carry: bool) -> (u8, SetFlags) {
-        todo!()
+        let carry = u8::from(carry);
+        let half_carry = (a & 0x0F) < (b & 0x0F) + carry;
+        let borrow = u16::from(a) < u16::from(b) + u16::from(carry);
+        let value = a.wrapping_sub(b).wrapping_sub(carry);
+        let flags = SetFlags {
+            half_carry,
+            carry: borrow,
+            zero: value == 0,
+            substract: true,
+        };
+        (value, flags)
     }
 
     fn inc(a: u8, carry: bool) -> (u8, SetFlags) {
@@ -520,3 +634,152 @@ impl ArithmeticInstruction {
         (value, flags)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cpu::{
+            registers::{Flags, LongRegister, SetFlags},
+            Cpu,
+        },
+        instructions::Instruction,
+        memory::bus::FlatBus,
+    };
+
+    use super::ArithmeticInstruction;
+
+    fn execute(cpu: &mut Cpu<FlatBus>) {
+        Instruction::fetch(cpu).unwrap().execute(cpu);
+    }
+
+    #[test]
+    fn add_hl() {
+        // ADD HL, BC
+        let mut cpu = Cpu::new(FlatBus::with_program(0x0000, &[0x09, 0x09]));
+        cpu.set_flag(Flags::Zero);
+        cpu.put_long_reg(LongRegister::HL, 0x0FFF);
+        cpu.put_long_reg(LongRegister::BC, 0x0001);
+        execute(&mut cpu);
+        assert_eq!(cpu.get_long_reg(LongRegister::HL), 0x1000);
+        assert_eq!(
+            cpu.get_flags(),
+            SetFlags {
+                zero: true,
+                half_carry: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(cpu.get_cycles(), 8);
+
+        cpu.put_long_reg(LongRegister::BC, 0xF000);
+        execute(&mut cpu);
+        assert_eq!(cpu.get_long_reg(LongRegister::HL), 0x0000);
+        assert_eq!(
+            cpu.get_flags(),
+            SetFlags {
+                zero: true,
+                carry: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn add_sp() {
+        // ADD SP, -1
+        let mut cpu = Cpu::new(FlatBus::with_program(0x0000, &[0xE8, 0xFF]));
+        cpu.set_flag(Flags::Zero);
+        cpu.put_long_reg(LongRegister::SP, 0x00FF);
+        execute(&mut cpu);
+        assert_eq!(cpu.get_long_reg(LongRegister::SP), 0x00FE);
+        // flags come from the low byte, as an unsigned add of 0xFF
+        assert_eq!(
+            cpu.get_flags(),
+            SetFlags {
+                half_carry: true,
+                carry: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(cpu.get_cycles(), 16);
+    }
+
+    #[test]
+    fn sp_plus_delta_flags() {
+        for sp in [
+            0x0000, 0x0001, 0x000F, 0x00F0, 0x00FF, 0x0100, 0x7FFF, 0x8000, 0xFF00, 0xFFFF,
+        ] {
+            for delta in -128i8..=127 {
+                let [low, _] = u16::to_le_bytes(sp);
+                let byte = delta as u8;
+                let expected_flags = SetFlags {
+                    half_carry: (low & 0x0F) + (byte & 0x0F) > 0x0F,
+                    carry: u16::from(low) + u16::from(byte) > 0xFF,
+                    ..Default::default()
+                };
+                let expected = (i32::from(sp) + i32::from(delta)).rem_euclid(0x10000) as u16;
+
+                // ADD SP, e; LD HL, SP+e
+                for (opcode, result_reg, cycles) in
+                    [(0xE8, LongRegister::SP, 16), (0xF8, LongRegister::HL, 12)]
+                {
+                    let mut cpu = Cpu::new(FlatBus::with_program(0x0000, &[opcode, byte]));
+                    cpu.set_flag(Flags::Zero);
+                    cpu.set_flag(Flags::Substract);
+                    cpu.put_long_reg(LongRegister::SP, sp);
+                    execute(&mut cpu);
+                    let context = format!("{:#04X} with SP {:#06X} and e {}", opcode, sp, delta);
+                    assert_eq!(cpu.get_long_reg(result_reg), expected, "{}", context);
+                    assert_eq!(cpu.get_flags(), expected_flags, "{}", context);
+                    assert_eq!(cpu.get_cycles(), cycles, "{}", context);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sub_flags() {
+        let sub = ArithmeticInstruction::sub;
+        let sbc = ArithmeticInstruction::sub_carry;
+        let flags = |zero, half_carry, carry| SetFlags {
+            zero,
+            substract: true,
+            half_carry,
+            carry,
+        };
+        assert_eq!(sub(0x00, 0x01), (0xFF, flags(false, true, true)));
+        assert_eq!(sub(0x10, 0x01), (0x0F, flags(false, true, false)));
+        assert_eq!(sub(0x42, 0x42), (0x00, flags(true, false, false)));
+        assert_eq!(sub(0x3E, 0x40), (0xFE, flags(false, false, true)));
+        assert_eq!(sbc(0xFF, 0xFF, true), (0xFF, flags(false, true, true)));
+        assert_eq!(sbc(0x00, 0xFF, true), (0x00, flags(true, true, true)));
+        assert_eq!(sbc(0x10, 0x0F, true), (0x00, flags(true, true, false)));
+        assert_eq!(sbc(0x01, 0x00, true), (0x00, flags(true, false, false)));
+
+        for a in 0..=0xFFu8 {
+            for b in 0..=0xFFu8 {
+                for carry in [false, true] {
+                    let c = i32::from(carry);
+                    let wide = i32::from(a) - i32::from(b) - c;
+                    let nibble = i32::from(a & 0x0F) - i32::from(b & 0x0F) - c;
+                    let expected = flags(wide.rem_euclid(0x100) == 0, nibble < 0, wide < 0);
+                    assert_eq!(sbc(a, b, carry), (wide as u8, expected), "{a} - {b} - {c}");
+
+                    let wide = i32::from(a) + i32::from(b) + c;
+                    let nibble = i32::from(a & 0x0F) + i32::from(b & 0x0F) + c;
+                    let expected = SetFlags {
+                        zero: wide.rem_euclid(0x100) == 0,
+                        substract: false,
+                        half_carry: nibble > 0x0F,
+                        carry: wide > 0xFF,
+                    };
+                    assert_eq!(
+                        ArithmeticInstruction::add_carry(a, b, carry),
+                        (wide as u8, expected),
+                        "{a} + {b} + {c}"
+                    );
+                }
+            }
+        }
+    }
+}
@@ -1,11 +1,15 @@
-use crate::cpu::{
-    registers::{Flags, Register, SetFlags},
-    Cpu,
+use crate::{
+    cpu::{
+        registers::{Flags, Register, SetFlags},
+        Cpu,
+    },
+    memory::bus::Bus,
 };
 
-use super::FetchRegister;
+use super::{get_register_bits, FetchRegister};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TargetBit {
     First,
     Second,
@@ -49,6 +53,7 @@ impl TargetBit {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BitInstruction {
     /// BIT b, r
     ///
@@ -89,7 +94,7 @@ pub enum BitInstruction {
 }
 
 impl BitInstruction {
-    pub fn fetch_prefixed(_: &Cpu, opcode_id: u8, reg: FetchRegister) -> Option<Self> {
+    pub fn fetch_prefixed<B: Bus>(_: &Cpu<B>, opcode_id: u8, reg: FetchRegister) -> Option<Self> {
         use BitInstruction::*;
 
         let bit = opcode_id >> 3;
@@ -114,13 +119,28 @@ impl BitInstruction {
         }
     }
 
-    pub fn execute(self, cpu: &mut Cpu) {
-        // every bit instructions are 1 byte instruction and don't access memory,
-        // but they are all either 2 / 4 cycles
-        // 1 cycle already happened at fetch, so add another so it remains 0 / 2 cycles.
-        // Opcodes that take 2 more cycles are memory fetch which are read then write,
+    /// The byte after the 0xCB prefix.
+    pub fn get_opcode(self) -> u8 {
+        use BitInstruction::*;
+        let opcode = |base: u8, reg: Register, bit: TargetBit| {
+            let bit = bit.get_mask().trailing_zeros() as u8;
+            base | (bit << 3) | get_register_bits(reg)
+        };
+        let hl = Register::F;
+        match self {
+            BitRegister(reg, bit) => opcode(0x40, reg, bit),
+            BitAddrHL(bit) => opcode(0x40, hl, bit),
+            ResRegister(reg, bit) => opcode(0x80, reg, bit),
+            ResAddrHL(bit) => opcode(0x80, hl, bit),
+            SetRegister(reg, bit) => opcode(0xC0, reg, bit),
+            SetAddrHL(bit) => opcode(0xC0, hl, bit),
+        }
+    }
+
+    pub fn execute<B: Bus>(self, cpu: &mut Cpu<B>) {
+        // the prefix and the opcode reads are the 2 cycles of the register ones,
+        // the (HL) ones add their read, and their write for RES and SET,
         // so the cycle count is good !
-        cpu.cycle();
         match self {
             BitInstruction::BitRegister(reg, bit) => {
                 let value = cpu.get_reg(reg);
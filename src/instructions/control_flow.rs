@@ -1,9 +1,13 @@
-use crate::cpu::{
-    registers::{Flags, LongRegister, Registers, SetFlags},
-    Cpu,
+use crate::{
+    cpu::{
+        registers::{LongRegister, SetFlags},
+        Cpu,
+    },
+    memory::bus::Bus,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ControlFlowCondition {
     NotZero,
     Zero,
@@ -24,6 +28,17 @@ impl From<u8> for ControlFlowCondition {
 }
 
 impl ControlFlowCondition {
+    /// The condition field of the opcodes, bits 3 and 4.
+    fn get_bits(self) -> u8 {
+        let cc = match self {
+            ControlFlowCondition::NotZero => 0,
+            ControlFlowCondition::Zero => 1,
+            ControlFlowCondition::NoCarry => 2,
+            ControlFlowCondition::Carry => 3,
+        };
+        cc << 3
+    }
+
     fn check_condition(self, flags: SetFlags) -> bool {
         match self {
             ControlFlowCondition::NotZero => !flags.zero,
@@ -35,6 +50,7 @@ impl ControlFlowCondition {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ControlFlowInstruction {
     // Jumps
     /// JP nn
@@ -112,7 +128,7 @@ pub enum ControlFlowInstruction {
 }
 
 impl ControlFlowInstruction {
-    pub fn fetch(cpu: &mut Cpu, opcode: u8) -> Option<Self> {
+    pub fn fetch<B: Bus>(cpu: &mut Cpu<B>, opcode: u8) -> Option<Self> {
         use ControlFlowInstruction::*;
         let cc = ((opcode & 0b00011000) >> 3).into();
         match opcode {
@@ -134,7 +150,7 @@ impl ControlFlowInstruction {
         }
     }
 
-    fn exec_cc(this: Self, cc: ControlFlowCondition, cpu: &mut Cpu) -> bool {
+    fn exec_cc<B: Bus>(this: Self, cc: ControlFlowCondition, cpu: &mut Cpu<B>) -> bool {
         let flags = cpu.get_flags();
         let jump = cc.check_condition(flags);
         if jump {
@@ -143,7 +159,24 @@ impl ControlFlowInstruction {
         jump
     }
 
-    pub fn execute(self, cpu: &mut Cpu) {
+    pub fn get_opcode(self) -> u8 {
+        use ControlFlowInstruction::*;
+        match self {
+            JumpImmediate(_) => 0xC3,
+            JumpImmediateCondition(cc, _) => 0xC2 | cc.get_bits(),
+            JumpAddrHL => 0xE9,
+            JumpImmediateRelative(_) => 0x18,
+            JumpRelativeCondition(cc, _) => 0x20 | cc.get_bits(),
+            CallImmediate(_) => 0xCD,
+            CallImmediateCondition(cc, _) => 0xC4 | cc.get_bits(),
+            Reset(addr) => 0xC7 | addr,
+            Return => 0xC9,
+            ReturnCondition(cc) => 0xC0 | cc.get_bits(),
+            ReturnEnableInterrupt => 0xD9,
+        }
+    }
+
+    pub fn execute<B: Bus>(self, cpu: &mut Cpu<B>) {
         match self {
             ControlFlowInstruction::JumpImmediate(addr) => {
                 cpu.set_pc(addr);
@@ -188,12 +221,10 @@ impl ControlFlowInstruction {
                 ControlFlowInstruction::JumpImmediate(addr).execute(cpu);
             }
             ControlFlowInstruction::ReturnCondition(cc) => {
-                // weird, but if condition not met take 2 cycles, but opcode is 1 wide
-                // cycle count is good on jump, but on NOP still lack 1 cycle
-                let jumped = Self::exec_cc(ControlFlowInstruction::Return, cc, cpu);
-                if !jumped {
-                    cpu.cycle();
-                }
+                // the condition is checked in a cycle of its own, met or not,
+                // so 2 cycles when not met and one more than RET when met
+                cpu.cycle();
+                Self::exec_cc(ControlFlowInstruction::Return, cc, cpu);
             }
             ControlFlowInstruction::ReturnEnableInterrupt => {
                 ControlFlowInstruction::Return.execute(cpu);
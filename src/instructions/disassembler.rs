@@ -0,0 +1,452 @@
+use std::fmt::Display;
+
+use crate::{
+    cpu::{
+        registers::{LongRegister, Register},
+        Cpu,
+    },
+    memory::{bus::Bus, interrupts::Interrupt},
+};
+
+use super::{
+    arithmetic::ArithmeticInstruction, bit::BitInstruction, control_flow::ControlFlowCondition,
+    control_flow::ControlFlowInstruction, load::LoadInstruction, miscellaneous::MiscInstruction,
+    rotate_shift::RotateShiftInstruction, Instruction,
+};
+
+/// How the disassembly is written, to compare it with the output of other tools.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// What the RGBDS assembler takes, `ld a, [hl+]`.
+    #[default]
+    Rgbds,
+    /// The one of the Nintendo datasheets, `LDI A,(HL)`.
+    Classic,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Reg(Register),
+    Long(LongRegister),
+    Imm8(u8),
+    Imm16(u16),
+    /// Signed offset, of ADD SP, e.
+    Offset(i8),
+    /// SP plus a signed offset, of LD HL, SP+e.
+    SPOffset(i8),
+    /// Memory at an absolute address.
+    Addr(u16),
+    /// Memory at the address in a register.
+    AddrLong(LongRegister),
+    AddrHLInc,
+    AddrHLDec,
+    /// Memory at 0xFF00 + n.
+    HighAddr(u8),
+    /// Memory at 0xFF00 + C.
+    HighC,
+    Cond(ControlFlowCondition),
+    Bit(u8),
+}
+
+impl Operand {
+    fn format(self, syntax: Syntax) -> String {
+        let (open, close) = match syntax {
+            Syntax::Rgbds => ("[", "]"),
+            Syntax::Classic => ("(", ")"),
+        };
+        // the names follow the case of the mnemonics, the numbers are always in uppercase
+        let name = |name: String| match syntax {
+            Syntax::Rgbds => name.to_lowercase(),
+            Syntax::Classic => name,
+        };
+        match self {
+            Operand::Reg(reg) => name(format!("{:?}", reg)),
+            Operand::Long(reg) => name(format!("{:?}", reg)),
+            Operand::Imm8(value) => format!("${:02X}", value),
+            Operand::Imm16(value) => format!("${:04X}", value),
+            Operand::Offset(offset) => offset.to_string(),
+            Operand::SPOffset(offset) => format!("{}{:+}", name("SP".into()), offset),
+            Operand::Addr(addr) => format!("{}${:04X}{}", open, addr, close),
+            Operand::AddrLong(reg) => format!("{}{}{}", open, name(format!("{:?}", reg)), close),
+            Operand::AddrHLInc => format!("{}{}+{}", open, name("HL".into()), close),
+            Operand::AddrHLDec => format!("{}{}-{}", open, name("HL".into()), close),
+            Operand::HighAddr(offset) => match syntax {
+                Syntax::Rgbds => format!("[$FF{:02X}]", offset),
+                Syntax::Classic => format!("($FF00+${:02X})", offset),
+            },
+            Operand::HighC => match syntax {
+                Syntax::Rgbds => "[c]".to_string(),
+                Syntax::Classic => "($FF00+C)".to_string(),
+            },
+            Operand::Cond(cond) => name(
+                match cond {
+                    ControlFlowCondition::NotZero => "NZ",
+                    ControlFlowCondition::Zero => "Z",
+                    ControlFlowCondition::NoCarry => "NC",
+                    ControlFlowCondition::Carry => "C",
+                }
+                .to_string(),
+            ),
+            Operand::Bit(bit) => bit.to_string(),
+        }
+    }
+}
+
+/// Write `instruction` in `syntax`, `addr` is where it is to resolve the relative jumps.
+pub fn format_instruction(instruction: Instruction, addr: u16, syntax: Syntax) -> String {
+    format_parts(instruction, Some(addr), syntax)
+}
+
+/// Without `addr` the relative jumps are written with their offset.
+fn format_parts(instruction: Instruction, addr: Option<u16>, syntax: Syntax) -> String {
+    let (mnemonic, operands) = get_parts(instruction, addr, syntax);
+    let operands: Vec<_> = operands.iter().map(|op| op.format(syntax)).collect();
+    let (mnemonic, separator) = match syntax {
+        Syntax::Rgbds => (mnemonic.to_lowercase(), ", "),
+        Syntax::Classic => (mnemonic.to_string(), ","),
+    };
+    if operands.is_empty() {
+        mnemonic
+    } else {
+        format!("{} {}", mnemonic, operands.join(separator))
+    }
+}
+
+/// Decode the instruction at the start of `bytes`, read from `addr`.
+///
+/// Returns the text and the length of the instruction,
+/// opcodes that don't exist are written as a single data byte.
+pub fn disassemble_bytes(bytes: &[u8], addr: u16, syntax: Syntax) -> (String, u16) {
+    match decode(bytes, addr) {
+        Some((instruction, length)) => (format_instruction(instruction, addr, syntax), length),
+        None => {
+            let byte = bytes.first().copied().unwrap_or(0xFF);
+            let text = match syntax {
+                Syntax::Rgbds => format!("db ${:02X}", byte),
+                Syntax::Classic => format!("DB ${:02X}", byte),
+            };
+            (text, 1)
+        }
+    }
+}
+
+/// Decode the instruction at `addr` on `bus`, without side effects.
+///
+/// Returns the text in the RGBDS syntax and the length of the instruction.
+pub fn disassemble<B: Bus>(addr: u16, bus: &B) -> (String, u16) {
+    let bytes: Vec<_> = (0..3).map(|i| bus.peek(addr.wrapping_add(i))).collect();
+    disassemble_bytes(&bytes, addr, Syntax::Rgbds)
+}
+
+/// Decode the instruction at the start of `bytes`, read from `addr`, with its length.
+///
+/// `None` for the opcodes that don't exist.
+pub fn decode(bytes: &[u8], addr: u16) -> Option<(Instruction, u16)> {
+    let mut cpu = Cpu::new(DecodeBus::new(bytes, addr));
+    cpu.set_pc(addr);
+    let instruction = Instruction::fetch(&mut cpu)?;
+    Some((instruction, cpu.get_pc().wrapping_sub(addr)))
+}
+
+/// Where a JR at `addr` goes, the offset is from the end of the 2 bytes instruction.
+pub fn get_relative_target(addr: u16, offset: i8) -> u16 {
+    addr.wrapping_add(2).wrapping_add_signed(offset.into())
+}
+
+fn relative_target(addr: Option<u16>, offset: i8) -> Operand {
+    match addr {
+        Some(addr) => Operand::Imm16(get_relative_target(addr, offset)),
+        None => Operand::Offset(offset),
+    }
+}
+
+/// 8-bit ALU operations with A, RGBDS always writes A but the datasheets only do for ADD, ADC and SBC.
+fn alu(mnemonic: &'static str, operand: Operand, syntax: Syntax) -> (&'static str, Vec<Operand>) {
+    let implicit_a = matches!(mnemonic, "SUB" | "AND" | "OR" | "XOR" | "CP");
+    if implicit_a && syntax == Syntax::Classic {
+        (mnemonic, vec![operand])
+    } else {
+        (mnemonic, vec![Operand::Reg(Register::A), operand])
+    }
+}
+
+fn get_parts(
+    instruction: Instruction,
+    addr: Option<u16>,
+    syntax: Syntax,
+) -> (&'static str, Vec<Operand>) {
+    use Operand::*;
+    let hl = AddrLong(LongRegister::HL);
+    let a = Reg(Register::A);
+    let classic = syntax == Syntax::Classic;
+    match instruction {
+        Instruction::Load(instruction) => match instruction {
+            LoadInstruction::LoadImmediate(reg, value) => ("LD", vec![Reg(reg), Imm8(value)]),
+            LoadInstruction::LoadRegister(dst, src) => ("LD", vec![Reg(dst), Reg(src)]),
+            LoadInstruction::LoadFromHLAddr(reg) => ("LD", vec![Reg(reg), hl]),
+            LoadInstruction::LoadIntoHLAddr(reg) => ("LD", vec![hl, Reg(reg)]),
+            LoadInstruction::LoadIntoHLAddrn(value) => ("LD", vec![hl, Imm8(value)]),
+            LoadInstruction::LoadIntoAFromAddr(reg) => ("LD", vec![a, AddrLong(reg)]),
+            LoadInstruction::LoadIntoAFromAddrnn(addr) => ("LD", vec![a, Addr(addr)]),
+            LoadInstruction::LoadIntoAddrFromA(reg) => ("LD", vec![AddrLong(reg), a]),
+            LoadInstruction::LoadIntoAddrnnFromA(addr) => ("LD", vec![Addr(addr), a]),
+            LoadInstruction::LoadFromAddrCIntoA if classic => ("LD", vec![a, HighC]),
+            LoadInstruction::LoadFromAddrCIntoA => ("LDH", vec![a, HighC]),
+            LoadInstruction::LoadIntoAddrCFromA if classic => ("LD", vec![HighC, a]),
+            LoadInstruction::LoadIntoAddrCFromA => ("LDH", vec![HighC, a]),
+            LoadInstruction::LoadFromAddrHLIntoADec if classic => ("LDD", vec![a, hl]),
+            LoadInstruction::LoadFromAddrHLIntoADec => ("LD", vec![a, AddrHLDec]),
+            LoadInstruction::LoadFromAIntoAddrHLDec if classic => ("LDD", vec![hl, a]),
+            LoadInstruction::LoadFromAIntoAddrHLDec => ("LD", vec![AddrHLDec, a]),
+            LoadInstruction::LoadFromAddrHLIntoAInc if classic => ("LDI", vec![a, hl]),
+            LoadInstruction::LoadFromAddrHLIntoAInc => ("LD", vec![a, AddrHLInc]),
+            LoadInstruction::LoadFromAIntoAddrHLInc if classic => ("LDI", vec![hl, a]),
+            LoadInstruction::LoadFromAIntoAddrHLInc => ("LD", vec![AddrHLInc, a]),
+            LoadInstruction::LoadFromAIntoAddrn(offset) if classic => {
+                ("LD", vec![HighAddr(offset), a])
+            }
+            LoadInstruction::LoadFromAIntoAddrn(offset) => ("LDH", vec![HighAddr(offset), a]),
+            LoadInstruction::LoadFromAddrnIntoA(offset) if classic => {
+                ("LD", vec![a, HighAddr(offset)])
+            }
+            LoadInstruction::LoadFromAddrnIntoA(offset) => ("LDH", vec![a, HighAddr(offset)]),
+            LoadInstruction::LoadImmediateLong(reg, value) => ("LD", vec![Long(reg), Imm16(value)]),
+            LoadInstruction::LoadFromHLIntoSP => {
+                ("LD", vec![Long(LongRegister::SP), Long(LongRegister::HL)])
+            }
+            LoadInstruction::LoadFromSPPlusnIntoHL(offset) if classic => {
+                ("LDHL", vec![Long(LongRegister::SP), Offset(offset)])
+            }
+            LoadInstruction::LoadFromSPPlusnIntoHL(offset) => {
+                ("LD", vec![Long(LongRegister::HL), SPOffset(offset)])
+            }
+            LoadInstruction::LoadSPIntoAddrnn(addr) => {
+                ("LD", vec![Addr(addr), Long(LongRegister::SP)])
+            }
+            LoadInstruction::Push(reg) => ("PUSH", vec![Long(reg)]),
+            LoadInstruction::Pop(reg) => ("POP", vec![Long(reg)]),
+        },
+        Instruction::Arithmetic(instruction) => match instruction {
+            ArithmeticInstruction::AddImmediate(value) => alu("ADD", Imm8(value), syntax),
+            ArithmeticInstruction::AddRegister(reg) => alu("ADD", Reg(reg), syntax),
+            ArithmeticInstruction::AddAddrHL => alu("ADD", hl, syntax),
+            ArithmeticInstruction::SubImmediate(value) => alu("SUB", Imm8(value), syntax),
+            ArithmeticInstruction::SubRegister(reg) => alu("SUB", Reg(reg), syntax),
+            ArithmeticInstruction::SubAddrHL => alu("SUB", hl, syntax),
+            ArithmeticInstruction::AddCarryImmediate(value) => alu("ADC", Imm8(value), syntax),
+            ArithmeticInstruction::AddCarryRegister(reg) => alu("ADC", Reg(reg), syntax),
+            ArithmeticInstruction::AddCarryAddrHL => alu("ADC", hl, syntax),
+            ArithmeticInstruction::SubCarryImmediate(value) => alu("SBC", Imm8(value), syntax),
+            ArithmeticInstruction::SubCarryRegister(reg) => alu("SBC", Reg(reg), syntax),
+            ArithmeticInstruction::SubCarryAddrHL => alu("SBC", hl, syntax),
+            ArithmeticInstruction::AndImmediate(value) => alu("AND", Imm8(value), syntax),
+            ArithmeticInstruction::AndRegister(reg) => alu("AND", Reg(reg), syntax),
+            ArithmeticInstruction::AndAddrHL => alu("AND", hl, syntax),
+            ArithmeticInstruction::OrImmediate(value) => alu("OR", Imm8(value), syntax),
+            ArithmeticInstruction::OrRegister(reg) => alu("OR", Reg(reg), syntax),
+            ArithmeticInstruction::OrAddrHL => alu("OR", hl, syntax),
+            ArithmeticInstruction::XorImmediate(value) => alu("XOR", Imm8(value), syntax),
+            ArithmeticInstruction::XorRegister(reg) => alu("XOR", Reg(reg), syntax),
+            ArithmeticInstruction::XorAddrHL => alu("XOR", hl, syntax),
+            ArithmeticInstruction::CmpImmediate(value) => alu("CP", Imm8(value), syntax),
+            ArithmeticInstruction::CmpRegister(reg) => alu("CP", Reg(reg), syntax),
+            ArithmeticInstruction::CmpAddrHL => alu("CP", hl, syntax),
+            ArithmeticInstruction::IncRegister(reg) => ("INC", vec![Reg(reg)]),
+            ArithmeticInstruction::IncAddrHL => ("INC", vec![hl]),
+            ArithmeticInstruction::DecRegister(reg) => ("DEC", vec![Reg(reg)]),
+            ArithmeticInstruction::DecAddrHL => ("DEC", vec![hl]),
+            ArithmeticInstruction::AddHL(reg) => ("ADD", vec![Long(LongRegister::HL), Long(reg)]),
+            ArithmeticInstruction::AddSPImmediate(offset) => {
+                ("ADD", vec![Long(LongRegister::SP), Offset(offset)])
+            }
+            ArithmeticInstruction::IncLongRegister(reg) => ("INC", vec![Long(reg)]),
+            ArithmeticInstruction::DecLongRegister(reg) => ("DEC", vec![Long(reg)]),
+        },
+        Instruction::Misc(instruction) => match instruction {
+            MiscInstruction::SwapRegister(reg) => ("SWAP", vec![Reg(reg)]),
+            MiscInstruction::SwapAddrHL => ("SWAP", vec![hl]),
+            MiscInstruction::DecimalAdjustA => ("DAA", vec![]),
+            MiscInstruction::ComplementA => ("CPL", vec![]),
+            MiscInstruction::ComplementCarry => ("CCF", vec![]),
+            MiscInstruction::SetCarry => ("SCF", vec![]),
+            MiscInstruction::Nop => ("NOP", vec![]),
+            MiscInstruction::Halt => ("HALT", vec![]),
+            MiscInstruction::Stop => ("STOP", vec![]),
+            MiscInstruction::DisableInterrupt => ("DI", vec![]),
+            MiscInstruction::EnableInterrupt => ("EI", vec![]),
+        },
+        Instruction::RotateShift(instruction) => match instruction {
+            RotateShiftInstruction::RotateLeftCarryA => ("RLCA", vec![]),
+            RotateShiftInstruction::RotateLeftA => ("RLA", vec![]),
+            RotateShiftInstruction::RotateRightCarryA => ("RRCA", vec![]),
+            RotateShiftInstruction::RotateRightA => ("RRA", vec![]),
+            RotateShiftInstruction::RotateLeftCarryRegister(reg) => ("RLC", vec![Reg(reg)]),
+            RotateShiftInstruction::RotateLeftCarryAddrHL => ("RLC", vec![hl]),
+            RotateShiftInstruction::RotateLeftRegister(reg) => ("RL", vec![Reg(reg)]),
+            RotateShiftInstruction::RotateLeftAddrHL => ("RL", vec![hl]),
+            RotateShiftInstruction::RotateRightCarryRegister(reg) => ("RRC", vec![Reg(reg)]),
+            RotateShiftInstruction::RotateRightCarryAddrHL => ("RRC", vec![hl]),
+            RotateShiftInstruction::RotateRightRegister(reg) => ("RR", vec![Reg(reg)]),
+            RotateShiftInstruction::RotateRightAddrHL => ("RR", vec![hl]),
+            RotateShiftInstruction::ShiftLeftRegister(reg) => ("SLA", vec![Reg(reg)]),
+            RotateShiftInstruction::ShiftLeftAddrHL => ("SLA", vec![hl]),
+            RotateShiftInstruction::ShiftRightRegisterSigned(reg) => ("SRA", vec![Reg(reg)]),
+            RotateShiftInstruction::ShiftRightAddrHLSigned => ("SRA", vec![hl]),
+            RotateShiftInstruction::ShiftRightRegister(reg) => ("SRL", vec![Reg(reg)]),
+            RotateShiftInstruction::ShiftRightAddrHL => ("SRL", vec![hl]),
+        },
+        Instruction::Bit(instruction) => {
+            let bit = |target: super::bit::TargetBit| Bit(target.get_mask().trailing_zeros() as u8);
+            match instruction {
+                BitInstruction::BitRegister(reg, target) => ("BIT", vec![bit(target), Reg(reg)]),
+                BitInstruction::BitAddrHL(target) => ("BIT", vec![bit(target), hl]),
+                BitInstruction::SetRegister(reg, target) => ("SET", vec![bit(target), Reg(reg)]),
+                BitInstruction::SetAddrHL(target) => ("SET", vec![bit(target), hl]),
+                BitInstruction::ResRegister(reg, target) => ("RES", vec![bit(target), Reg(reg)]),
+                BitInstruction::ResAddrHL(target) => ("RES", vec![bit(target), hl]),
+            }
+        }
+        Instruction::ControlFlow(instruction) => match instruction {
+            ControlFlowInstruction::JumpImmediate(addr) => ("JP", vec![Imm16(addr)]),
+            ControlFlowInstruction::JumpImmediateCondition(cond, addr) => {
+                ("JP", vec![Cond(cond), Imm16(addr)])
+            }
+            ControlFlowInstruction::JumpAddrHL if classic => ("JP", vec![hl]),
+            ControlFlowInstruction::JumpAddrHL => ("JP", vec![Long(LongRegister::HL)]),
+            ControlFlowInstruction::JumpImmediateRelative(offset) => {
+                ("JR", vec![relative_target(addr, offset)])
+            }
+            ControlFlowInstruction::JumpRelativeCondition(cond, offset) => {
+                ("JR", vec![Cond(cond), relative_target(addr, offset)])
+            }
+            ControlFlowInstruction::CallImmediate(addr) => ("CALL", vec![Imm16(addr)]),
+            ControlFlowInstruction::CallImmediateCondition(cond, addr) => {
+                ("CALL", vec![Cond(cond), Imm16(addr)])
+            }
+            ControlFlowInstruction::Reset(addr) => ("RST", vec![Imm8(addr)]),
+            ControlFlowInstruction::Return => ("RET", vec![]),
+            ControlFlowInstruction::ReturnCondition(cond) => ("RET", vec![Cond(cond)]),
+            ControlFlowInstruction::ReturnEnableInterrupt => ("RETI", vec![]),
+        },
+    }
+}
+
+/// RGBDS syntax, or the classic one with `{:#}`.
+///
+/// Where the instruction is isn't known, so the relative jumps show their offset.
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let syntax = if f.alternate() {
+            Syntax::Classic
+        } else {
+            Syntax::Rgbds
+        };
+        f.write_str(&format_parts(*self, None, syntax))
+    }
+}
+
+/// Gives the CPU the bytes to decode, without touching the machine.
+#[derive(Debug, Default)]
+struct DecodeBus {
+    /// The longest instructions are 3 bytes.
+    bytes: [u8; 3],
+    addr: u16,
+}
+
+impl DecodeBus {
+    fn new(bytes: &[u8], addr: u16) -> Self {
+        let mut bus = DecodeBus {
+            bytes: [0xFF; 3],
+            addr,
+        };
+        let len = bytes.len().min(bus.bytes.len());
+        bus.bytes[..len].copy_from_slice(&bytes[..len]);
+        bus
+    }
+}
+
+impl Bus for DecodeBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        let offset = usize::from(addr.wrapping_sub(self.addr));
+        self.bytes.get(offset).copied().unwrap_or(0xFF)
+    }
+
+    fn write(&mut self, _addr: u16, _value: u8) {}
+
+    fn tick(&mut self) {}
+
+    fn request_interrupt(&mut self, _interrupt: Interrupt) {}
+
+    fn stop(&mut self) -> bool {
+        false
+    }
+
+    fn is_double_speed(&self) -> bool {
+        false
+    }
+
+    fn acknowledge_interrupt(&mut self, _interrupt: Interrupt) {}
+
+    fn get_pending_interrupts(&self) -> u8 {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::bus::FlatBus;
+
+    fn both(bytes: &[u8], addr: u16) -> (String, String) {
+        let (rgbds, len) = disassemble_bytes(bytes, addr, Syntax::Rgbds);
+        let (classic, classic_len) = disassemble_bytes(bytes, addr, Syntax::Classic);
+        assert_eq!(len, classic_len);
+        assert_eq!(usize::from(len), bytes.len(), "{}", rgbds);
+        (rgbds, classic)
+    }
+
+    #[test]
+    fn syntaxes() {
+        let cases: &[(&[u8], &str, &str)] = &[
+            (&[0x2A], "ld a, [hl+]", "LDI A,(HL)"),
+            (&[0x32], "ld [hl-], a", "LDD (HL),A"),
+            (&[0xF0, 0x44], "ldh a, [$FF44]", "LD A,($FF00+$44)"),
+            (&[0xE2], "ldh [c], a", "LD ($FF00+C),A"),
+            (&[0xF8, 0xFE], "ld hl, sp-2", "LDHL SP,-2"),
+            (&[0x90], "sub a, b", "SUB B"),
+            (&[0xCE, 0x12], "adc a, $12", "ADC A,$12"),
+            (&[0xC3, 0x50, 0x01], "jp $0150", "JP $0150"),
+            (&[0xE9], "jp hl", "JP (HL)"),
+            (&[0x20, 0xFE], "jr nz, $0200", "JR NZ,$0200"),
+            (&[0xEA, 0x00, 0xC0], "ld [$C000], a", "LD ($C000),A"),
+            (&[0xCB, 0x7E], "bit 7, [hl]", "BIT 7,(HL)"),
+            (&[0xFF], "rst $38", "RST $38"),
+            (&[0x10, 0x00], "stop", "STOP"),
+            (&[0xD3], "db $D3", "DB $D3"),
+        ];
+        for (bytes, rgbds, classic) in cases {
+            assert_eq!(
+                both(bytes, 0x0200),
+                (rgbds.to_string(), classic.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn display_and_bus() {
+        let (instruction, _) = decode(&[0xF0, 0x44], 0x0000).unwrap();
+        assert_eq!(instruction.to_string(), "ldh a, [$FF44]");
+        assert_eq!(format!("{:#}", instruction), "LD A,($FF00+$44)");
+        let (instruction, _) = decode(&[0x38, 0xFC], 0x0000).unwrap();
+        assert_eq!(instruction.to_string(), "jr c, -4");
+
+        // JR -2 at 0xC000, then an illegal opcode
+        let bus = FlatBus::with_program(0xC000, &[0x18, 0xFE, 0xFD]);
+        assert_eq!(disassemble(0xC000, &bus), ("jr $C000".to_string(), 2));
+        assert_eq!(disassemble(0xC002, &bus), ("db $FD".to_string(), 1));
+    }
+}
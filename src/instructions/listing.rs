@@ -0,0 +1,286 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, Write},
+};
+
+use crate::cpu::registers::Register;
+
+use super::{
+    control_flow::ControlFlowInstruction,
+    disassembler::{self, Syntax},
+    load::LoadInstruction,
+    Instruction,
+};
+
+/// Where code is in the ROM, the bank is always 0 below 0x4000.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Location {
+    pub bank: usize,
+    pub addr: u16,
+}
+
+impl Location {
+    pub fn new(bank: usize, addr: u16) -> Self {
+        let bank = if addr < Self::SWITCHABLE_START {
+            0
+        } else {
+            bank
+        };
+        Location { bank, addr }
+    }
+
+    const SWITCHABLE_START: u16 = 0x4000;
+    const ROM_END: u16 = 0x7FFF;
+    const BANK_SIZE: usize = 0x4000;
+
+    fn get_offset(self) -> usize {
+        self.bank * Self::BANK_SIZE + usize::from(self.addr) % Self::BANK_SIZE
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Traced {
+    instruction: Instruction,
+    bytes: Vec<u8>,
+    /// Where it jumps or calls to.
+    target: Option<Location>,
+    /// The switchable bank it selects.
+    bank_switch: Option<usize>,
+}
+
+/// The code reachable from the entry point and the interrupt vectors,
+/// a starting point for disassembling a game.
+///
+/// The ROM is walked like the CPU would, following jumps and calls.
+/// Jumps through HL and code copied to RAM are lost, and the bank switches are
+/// only followed for the usual `ld a, n` then `ld [$2000-$3FFF], a`.
+#[derive(Debug, Clone, Default)]
+pub struct Listing {
+    instructions: BTreeMap<Location, Traced>,
+    labels: BTreeMap<Location, String>,
+    bank_count: usize,
+}
+
+impl Listing {
+    /// Vectors and the names of their labels.
+    const ENTRIES: [(u16, &'static str); 6] = [
+        (0x0100, "Entry"),
+        (0x0040, "VBlankInterrupt"),
+        (0x0048, "StatInterrupt"),
+        (0x0050, "TimerInterrupt"),
+        (0x0058, "SerialInterrupt"),
+        (0x0060, "JoypadInterrupt"),
+    ];
+    const BANK_REGISTER_START: u16 = 0x2000;
+    const BANK_REGISTER_END: u16 = 0x3FFF;
+
+    pub fn trace(rom: &[u8]) -> Self {
+        let mut listing = Listing {
+            bank_count: rom.len().div_ceil(Location::BANK_SIZE).max(1),
+            ..Default::default()
+        };
+        // the switchable bank is 1 at power on
+        let mut pending: Vec<Location> = Vec::new();
+        for (addr, name) in Self::ENTRIES {
+            let location = Location::new(1, addr);
+            listing.labels.insert(location, name.to_string());
+            pending.push(location);
+        }
+        while let Some(start) = pending.pop() {
+            listing.trace_from(rom, start, &mut pending);
+        }
+        listing
+    }
+
+    /// Follow the code from `start` until it stops going forward, the branches are added to `pending`.
+    fn trace_from(&mut self, rom: &[u8], start: Location, pending: &mut Vec<Location>) {
+        let mut bank = start.bank.max(1);
+        let mut location = start;
+        let mut a_value = None;
+        while location.addr <= Location::ROM_END && !self.instructions.contains_key(&location) {
+            let offset = location.get_offset();
+            let bytes = rom.get(offset..).unwrap_or_default();
+            let Some((instruction, length)) = disassembler::decode(bytes, location.addr) else {
+                return;
+            };
+            let bytes = (0..usize::from(length))
+                .map(|i| bytes.get(i).copied().unwrap_or(0xFF))
+                .collect();
+            let mut traced = Traced {
+                instruction,
+                bytes,
+                target: None,
+                bank_switch: None,
+            };
+            let (target, goes_on) = Self::get_flow(instruction, location.addr);
+            if let Some(target) = target {
+                let target = Location::new(bank, target);
+                traced.target = Some(target);
+                if target.addr <= Location::ROM_END {
+                    self.labels.entry(target).or_insert_with(|| {
+                        format!("label_{:02X}_{:04X}", target.bank, target.addr)
+                    });
+                    pending.push(target);
+                }
+            }
+            a_value = match instruction {
+                Instruction::Load(LoadInstruction::LoadImmediate(Register::A, value)) => {
+                    Some(value)
+                }
+                Instruction::Load(LoadInstruction::LoadIntoAddrnnFromA(addr))
+                    if (Self::BANK_REGISTER_START..=Self::BANK_REGISTER_END).contains(&addr) =>
+                {
+                    if let Some(value) = a_value {
+                        // most mappers turn bank 0 into 1
+                        bank = usize::from(value).max(1) % self.bank_count;
+                        traced.bank_switch = Some(bank);
+                    }
+                    a_value
+                }
+                _ => None,
+            };
+            self.instructions.insert(location, traced);
+            if !goes_on {
+                return;
+            }
+            location = Location::new(bank, location.addr.wrapping_add(length));
+        }
+    }
+
+    /// Where `instruction` at `addr` branches to, and whether the execution can go on after it.
+    fn get_flow(instruction: Instruction, addr: u16) -> (Option<u16>, bool) {
+        let Instruction::ControlFlow(instruction) = instruction else {
+            return (None, true);
+        };
+        match instruction {
+            ControlFlowInstruction::JumpImmediate(target) => (Some(target), false),
+            ControlFlowInstruction::JumpImmediateCondition(_, target) => (Some(target), true),
+            ControlFlowInstruction::JumpAddrHL => (None, false),
+            ControlFlowInstruction::JumpImmediateRelative(offset) => {
+                (Some(disassembler::get_relative_target(addr, offset)), false)
+            }
+            ControlFlowInstruction::JumpRelativeCondition(_, offset) => {
+                (Some(disassembler::get_relative_target(addr, offset)), true)
+            }
+            ControlFlowInstruction::CallImmediate(target)
+            | ControlFlowInstruction::CallImmediateCondition(_, target) => (Some(target), true),
+            ControlFlowInstruction::Reset(target) => (Some(target.into()), true),
+            ControlFlowInstruction::Return | ControlFlowInstruction::ReturnEnableInterrupt => {
+                (None, false)
+            }
+            ControlFlowInstruction::ReturnCondition(_) => (None, true),
+        }
+    }
+
+    pub fn get_instruction_count(&self) -> usize {
+        self.instructions.len()
+    }
+
+    /// Whether an instruction starts at this location.
+    pub fn is_code(&self, bank: usize, addr: u16) -> bool {
+        self.instructions.contains_key(&Location::new(bank, addr))
+    }
+
+    pub fn get_label(&self, bank: usize, addr: u16) -> Option<&str> {
+        self.labels
+            .get(&Location::new(bank, addr))
+            .map(String::as_str)
+    }
+
+    /// Write the listing, one line per instruction with its location and bytes,
+    /// the labels, the branch targets and the bytes that were never reached.
+    pub fn write<W: Write>(&self, mut writer: W, syntax: Syntax) -> io::Result<()> {
+        writeln!(
+            writer,
+            "; {} instructions reached in {} banks",
+            self.instructions.len(),
+            self.bank_count
+        )?;
+        let banks: BTreeSet<usize> = self.instructions.keys().map(|loc| loc.bank).collect();
+        for bank in banks {
+            writeln!(writer, "\n; bank ${:02X}", bank)?;
+            let mut next = None;
+            for (location, traced) in self.instructions.range(
+                Location { bank, addr: 0 }..=Location {
+                    bank,
+                    addr: u16::MAX,
+                },
+            ) {
+                if let Some(next) = next.filter(|&next| next < location.addr) {
+                    writeln!(
+                        writer,
+                        "; ${:04X}-${:04X} not reached",
+                        next,
+                        location.addr - 1
+                    )?;
+                }
+                next = Some(location.addr.wrapping_add(traced.bytes.len() as u16));
+                self.write_instruction(&mut writer, *location, traced, syntax)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_instruction<W: Write>(
+        &self,
+        writer: &mut W,
+        location: Location,
+        traced: &Traced,
+        syntax: Syntax,
+    ) -> io::Result<()> {
+        if let Some(label) = self.labels.get(&location) {
+            writeln!(writer, "{}:", label)?;
+        }
+        let bytes: Vec<_> = traced.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let text = disassembler::format_instruction(traced.instruction, location.addr, syntax);
+        let mut line = format!(
+            "{:02X}:{:04X}  {:<9} {}",
+            location.bank,
+            location.addr,
+            bytes.join(" "),
+            text
+        );
+        if let Some(label) = traced.target.and_then(|target| self.labels.get(&target)) {
+            line += &format!(" ; -> {}", label);
+        }
+        if let Some(bank) = traced.bank_switch {
+            line += &format!(" ; bank ${:02X}", bank);
+        }
+        writeln!(writer, "{}", line.trim_end())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace() {
+        // ret everywhere else
+        let mut rom = vec![0xC9; 0x4000 * 4];
+        // entry: ld a, 2; ld [$2000], a; call $4000; jr -2
+        rom[0x0100..0x0109]
+            .copy_from_slice(&[0x3E, 0x02, 0xEA, 0x00, 0x20, 0xCD, 0x00, 0x40, 0x18]);
+        rom[0x0109] = 0xFE;
+        // vblank: reti
+        rom[0x0040] = 0xD9;
+        // bank 2 at 0x4000: nop; ret
+        rom[0x8000..0x8002].copy_from_slice(&[0x00, 0xC9]);
+
+        let listing = Listing::trace(&rom);
+        assert!(listing.is_code(0, 0x0108));
+        assert!(listing.is_code(2, 0x4001));
+        assert!(!listing.is_code(1, 0x4000));
+        assert_eq!(listing.get_label(2, 0x4000), Some("label_02_4000"));
+        assert_eq!(listing.get_instruction_count(), 11);
+
+        let mut text = Vec::new();
+        listing.write(&mut text, Syntax::Rgbds).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("00:0102  EA 00 20  ld [$2000], a ; bank $02"));
+        assert!(text.contains("00:0105  CD 00 40  call $4000 ; -> label_02_4000"));
+        assert!(text.contains("label_00_0108:\n00:0108  18 FE     jr $0108"));
+        assert!(text.contains("; $0041-$0047 not reached"));
+        assert!(text.contains("\n; bank $02\nlabel_02_4000:\n02:4000  00        nop"));
+    }
+}
@@ -1,9 +1,16 @@
-use crate::cpu::{
-    registers::{LongRegister, Register, Registers, SetFlags},
-    Cpu,
+use crate::{
+    cpu::{
+        registers::{LongRegister, Register, Registers},
+        Cpu,
+    },
+    instructions::arithmetic::ArithmeticInstruction,
+    memory::bus::Bus,
 };
 
+use super::{get_long_register_bits, get_register_bits};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LoadInstruction {
     // 8-bits loads
     /// LD r, n: Load register (immediate)
@@ -216,27 +223,7 @@ impl LoadInstruction {
         lr
     }
 
-    fn add_delta_to_addr(addr: u16, delta: i8) -> (u16, SetFlags) {
-        let neg = delta.is_negative();
-        let [delta_byte] = i8::to_be_bytes(delta);
-        let delta_byte: u16 = delta_byte.into();
-        let [delta] = i8::to_be_bytes(delta.abs());
-        let delta: u16 = delta.into();
-        let result = if neg { addr - delta } else { addr + delta };
-
-        let carry = (addr ^ delta_byte ^ result) & 0x0100 == 0x0100;
-        let half_carry = (addr ^ delta_byte ^ result) & 0x0010 == 0x0010;
-
-        let flags = SetFlags {
-            carry,
-            half_carry,
-            ..Default::default()
-        };
-
-        (result, flags)
-    }
-
-    pub fn fetch(cpu: &mut Cpu, opcode: u8) -> Option<Self> {
+    pub fn fetch<B: Bus>(cpu: &mut Cpu<B>, opcode: u8) -> Option<Self> {
         use LoadInstruction::*;
 
         match opcode {
@@ -260,17 +247,47 @@ impl LoadInstruction {
             x if x & 0b11001111 == 0x0A => Some(LoadIntoAFromAddr(Self::fetch_long_register(x))),
             x if x & 0b11001111 == 0x02 => Some(LoadIntoAddrFromA(Self::fetch_long_register(x))),
             x if x & 0b11000111 == 0x06 => Some(Self::fetch_load_immediate(x, cpu.advance())),
-            x if x & 0b11001111 == 0x01 => Some(LoadImmediateLong(
-                Self::fetch_long_register(x),
-                cpu.advance_long(),
-            )),
+            x if x & 0b11001111 == 0x01 => {
+                Some(Self::fetch_load_immediate_long(x, cpu.advance_long()))
+            }
             x if x & 0b11001111 == 0xC5 => Some(Push(Self::fetch_long_register(x))),
             x if x & 0b11001111 == 0xC1 => Some(Pop(Self::fetch_long_register(x))),
             _ => None,
         }
     }
 
-    pub fn execute(self, cpu: &mut Cpu) {
+    pub fn get_opcode(self) -> u8 {
+        use LoadInstruction::*;
+        let reg = get_register_bits;
+        let long = get_long_register_bits;
+        match self {
+            LoadImmediate(r, _) => 0x06 | (reg(r) << 3),
+            LoadRegister(r1, r2) => 0x40 | (reg(r1) << 3) | reg(r2),
+            LoadFromHLAddr(r) => 0x46 | (reg(r) << 3),
+            LoadIntoHLAddr(r) => 0x70 | reg(r),
+            LoadIntoHLAddrn(_) => 0x36,
+            LoadIntoAFromAddr(lr) => 0x0A | (long(lr) << 4),
+            LoadIntoAFromAddrnn(_) => 0xFA,
+            LoadIntoAddrFromA(lr) => 0x02 | (long(lr) << 4),
+            LoadIntoAddrnnFromA(_) => 0xEA,
+            LoadFromAddrCIntoA => 0xF2,
+            LoadIntoAddrCFromA => 0xE2,
+            LoadFromAddrHLIntoADec => 0x3A,
+            LoadFromAIntoAddrHLDec => 0x32,
+            LoadFromAddrHLIntoAInc => 0x2A,
+            LoadFromAIntoAddrHLInc => 0x22,
+            LoadFromAIntoAddrn(_) => 0xE0,
+            LoadFromAddrnIntoA(_) => 0xF0,
+            LoadImmediateLong(lr, _) => 0x01 | (long(lr) << 4),
+            LoadFromHLIntoSP => 0xF9,
+            LoadFromSPPlusnIntoHL(_) => 0xF8,
+            LoadSPIntoAddrnn(_) => 0x08,
+            Push(lr) => 0xC5 | (long(lr) << 4),
+            Pop(lr) => 0xC1 | (long(lr) << 4),
+        }
+    }
+
+    pub fn execute<B: Bus>(self, cpu: &mut Cpu<B>) {
         match self {
             LoadInstruction::LoadImmediate(reg, n) => {
                 cpu.put_reg(reg, n);
@@ -323,25 +340,25 @@ impl LoadInstruction {
                 let addr = cpu.get_long_reg(LongRegister::HL);
                 let value = cpu.get_memory(addr);
                 cpu.put_reg_a(value);
-                cpu.put_long_reg(LongRegister::HL, addr - 1);
+                cpu.put_long_reg(LongRegister::HL, cpu.addr_sub(addr, 1));
             }
             LoadInstruction::LoadFromAIntoAddrHLDec => {
                 let addr = cpu.get_long_reg(LongRegister::HL);
                 let value = cpu.get_reg_a();
                 cpu.put_memory(addr, value);
-                cpu.put_long_reg(LongRegister::HL, addr - 1);
+                cpu.put_long_reg(LongRegister::HL, cpu.addr_sub(addr, 1));
             }
             LoadInstruction::LoadFromAddrHLIntoAInc => {
                 let addr = cpu.get_long_reg(LongRegister::HL);
                 let value = cpu.get_memory(addr);
                 cpu.put_reg_a(value);
-                cpu.put_long_reg(LongRegister::HL, addr + 1);
+                cpu.put_long_reg(LongRegister::HL, cpu.addr_add(addr, 1));
             }
             LoadInstruction::LoadFromAIntoAddrHLInc => {
                 let addr = cpu.get_long_reg(LongRegister::HL);
                 let value = cpu.get_reg_a();
                 cpu.put_memory(addr, value);
-                cpu.put_long_reg(LongRegister::HL, addr + 1);
+                cpu.put_long_reg(LongRegister::HL, cpu.addr_add(addr, 1));
             }
             LoadInstruction::LoadFromAIntoAddrn(n) => {
                 let addr = u16::from_be_bytes([0xFF, n]);
@@ -363,8 +380,10 @@ impl LoadInstruction {
                 cpu.put_long_reg(LongRegister::SP, value);
             }
             LoadInstruction::LoadFromSPPlusnIntoHL(delta) => {
+                // 3 machine cycle but only 2 W/R, so need to explicitly cycle
+                cpu.cycle();
                 let sp = cpu.get_long_reg(LongRegister::SP);
-                let (value, flags) = Self::add_delta_to_addr(sp, delta);
+                let (value, flags) = ArithmeticInstruction::add_sp_delta(sp, delta);
                 cpu.set_flags(flags);
                 cpu.put_long_reg(LongRegister::HL, value);
             }
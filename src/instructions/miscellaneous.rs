@@ -1,14 +1,16 @@
 use crate::{
     cpu::{
-        registers::{Flags, Register},
+        registers::{Flags, Register, SetFlags},
         Cpu,
     },
     map_fetch_register,
+    memory::bus::Bus,
 };
 
-use super::FetchRegister;
+use super::{get_register_bits, opcodes::Opcode, FetchRegister};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MiscInstruction {
     /// SWAP r
     ///
@@ -62,18 +64,22 @@ pub enum MiscInstruction {
     /// Power down CPU until an interrupt occurs.
     /// Use this when ever possible to reduce energy consumption.
     ///
+    /// If interrupts are disabled and one is already pending, the CPU doesn't halt
+    /// and the next byte is read twice (HALT bug).
+    ///
     /// Cycles: 4
     Halt,
     /// STOP
     ///
     /// Halt CPU & LCD display until button pressed.
+    /// Also resets DIV.
     ///
     /// Cycles: 4
     Stop,
     /// DI
     ///
-    /// This instruction disables interrupts but not immediately.
-    /// Interrupts are disabled after instruction after DI is executed.
+    /// This instruction disables interrupts immediately,
+    /// and cancels a pending EI.
     ///
     /// Cycles: 4
     DisableInterrupt,
@@ -87,12 +93,12 @@ pub enum MiscInstruction {
 }
 
 impl MiscInstruction {
-    pub fn fetch_prefixed(_: &Cpu, opcode_id: u8, reg: FetchRegister) -> Option<Self> {
+    pub fn fetch_prefixed<B: Bus>(_: &Cpu<B>, opcode_id: u8, reg: FetchRegister) -> Option<Self> {
         use MiscInstruction::*;
-        (opcode_id == 0x30).then(|| map_fetch_register!(reg, SwapRegister, SwapAddrHL))
+        (opcode_id == 0x30).then_some(map_fetch_register!(reg, SwapRegister, SwapAddrHL))
     }
 
-    pub fn fetch(cpu: &mut Cpu, opcode: u8) -> Option<Self> {
+    pub fn fetch<B: Bus>(cpu: &mut Cpu<B>, opcode: u8) -> Option<Self> {
         use MiscInstruction::*;
         match opcode {
             0x27 => Some(DecimalAdjustA),
@@ -114,25 +120,68 @@ impl MiscInstruction {
         lower << 4 | upper >> 4
     }
 
-    pub fn execute(self, cpu: &mut Cpu) {
+    /// BCD correction of A after an addition or a substraction,
+    /// N is kept and H is always reset.
+    fn decimal_adjust(a: u8, flags: SetFlags) -> (u8, SetFlags) {
+        let mut correction = 0;
+        let mut carry = flags.carry;
+        if flags.half_carry || (!flags.substract && a & 0x0F > 0x09) {
+            correction |= 0x06;
+        }
+        if flags.carry || (!flags.substract && a > 0x99) {
+            correction |= 0x60;
+            carry = true;
+        }
+        let value = if flags.substract {
+            a.wrapping_sub(correction)
+        } else {
+            a.wrapping_add(correction)
+        };
+        let flags = SetFlags {
+            zero: value == 0,
+            substract: flags.substract,
+            half_carry: false,
+            carry,
+        };
+        (value, flags)
+    }
+
+    pub fn get_opcode(self) -> Opcode {
+        use MiscInstruction::*;
+        match self {
+            SwapRegister(reg) => Opcode::Prefixed(0x30 | get_register_bits(reg)),
+            SwapAddrHL => Opcode::Prefixed(0x36),
+            DecimalAdjustA => Opcode::Unprefixed(0x27),
+            ComplementA => Opcode::Unprefixed(0x2F),
+            ComplementCarry => Opcode::Unprefixed(0x3F),
+            SetCarry => Opcode::Unprefixed(0x37),
+            Nop => Opcode::Unprefixed(0x00),
+            Halt => Opcode::Unprefixed(0x76),
+            Stop => Opcode::Unprefixed(0x10),
+            DisableInterrupt => Opcode::Unprefixed(0xF3),
+            EnableInterrupt => Opcode::Unprefixed(0xFB),
+        }
+    }
+
+    pub fn execute<B: Bus>(self, cpu: &mut Cpu<B>) {
         match self {
             MiscInstruction::SwapRegister(reg) => {
-                // 1 wide opcode and no memory access, but 2 cycles
-                // so need to put one there
-                cpu.cycle();
+                // the prefix and the opcode reads are the 2 cycles
                 let value = cpu.get_reg(reg);
                 let value = Self::swap(value);
                 cpu.put_reg(reg, value);
             }
             MiscInstruction::SwapAddrHL => {
-                // 1 wide opcode and 2 memory access, but 4 cycles
-                // so need to put one there
-                cpu.cycle();
+                // the prefix, the opcode, the read and the write are the 4 cycles
                 let value = cpu.get_at_hl();
                 let value = Self::swap(value);
                 cpu.put_at_hl(value);
             }
-            MiscInstruction::DecimalAdjustA => todo!(),
+            MiscInstruction::DecimalAdjustA => {
+                let (value, flags) = Self::decimal_adjust(cpu.get_reg_a(), cpu.get_flags());
+                cpu.put_reg_a(value);
+                cpu.set_flags(flags);
+            }
             MiscInstruction::ComplementA => {
                 let value = cpu.get_reg_a();
                 cpu.put_reg_a(!value);
@@ -147,14 +196,109 @@ impl MiscInstruction {
             MiscInstruction::Nop => {
                 // litteraly do nothing
             }
-            MiscInstruction::Halt => todo!(),
-            MiscInstruction::Stop => todo!(),
+            MiscInstruction::Halt => {
+                cpu.halt();
+            }
+            MiscInstruction::Stop => {
+                // on CGB this is also where the speed switch happens
+                cpu.stop();
+            }
             MiscInstruction::DisableInterrupt => {
                 cpu.disable_interrupts();
             }
             MiscInstruction::EnableInterrupt => {
-                cpu.enable_interrupts();
+                cpu.schedule_enable_interrupts();
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cpu::{
+            registers::{Register, SetFlags},
+            Cpu,
+        },
+        instructions::Instruction,
+        memory::bus::FlatBus,
+    };
+
+    #[test]
+    fn decimal_adjust() {
+        // LD A, x; ADD/SUB y; DAA
+        let run = |a: u8, op: u8, b: u8| {
+            let program = [0x3E, a, op, b, 0x27];
+            let mut cpu = Cpu::new(FlatBus::with_program(0x0000, &program));
+            for _ in 0..3 {
+                Instruction::fetch(&mut cpu).unwrap().execute(&mut cpu);
+            }
+            (cpu.get_reg(Register::A), cpu.get_flags())
+        };
+        const ADD: u8 = 0xC6;
+        const SUB: u8 = 0xD6;
+
+        // 15 + 27 = 42, low nibble overflows past 9
+        assert_eq!(run(0x15, ADD, 0x27), (0x42, SetFlags::default()));
+        // 09 + 09 = 18, half carry out of the low nibble
+        assert_eq!(run(0x09, ADD, 0x09), (0x18, SetFlags::default()));
+        // 99 + 01 = 100
+        assert_eq!(
+            run(0x99, ADD, 0x01),
+            (
+                0x00,
+                SetFlags {
+                    zero: true,
+                    carry: true,
+                    ..Default::default()
+                }
+            )
+        );
+        // 90 + 90 = 180, binary carry
+        assert_eq!(
+            run(0x90, ADD, 0x90),
+            (
+                0x80,
+                SetFlags {
+                    carry: true,
+                    ..Default::default()
+                }
+            )
+        );
+        // 42 - 15 = 27, half borrow
+        assert_eq!(
+            run(0x42, SUB, 0x15),
+            (
+                0x27,
+                SetFlags {
+                    substract: true,
+                    ..Default::default()
+                }
+            )
+        );
+        // 10 - 20 = -10, borrow is kept
+        assert_eq!(
+            run(0x10, SUB, 0x20),
+            (
+                0x90,
+                SetFlags {
+                    substract: true,
+                    carry: true,
+                    ..Default::default()
+                }
+            )
+        );
+        // 05 - 05 = 0
+        assert_eq!(
+            run(0x05, SUB, 0x05),
+            (
+                0x00,
+                SetFlags {
+                    zero: true,
+                    substract: true,
+                    ..Default::default()
+                }
+            )
+        );
+    }
+}
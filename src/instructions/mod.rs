@@ -1,21 +1,33 @@
-use crate::cpu::{
-    registers::{LongRegister, Register, Registers},
-    Cpu,
+use crate::{
+    cpu::{
+        registers::{LongRegister, Register, Registers},
+        Cpu,
+    },
+    memory::bus::Bus,
 };
 
 use self::{
-    arithmetic::ArithmeticInstruction, bit::BitInstruction, control_flow::ControlFlowInstruction,
-    load::LoadInstruction, miscellaneous::MiscInstruction, rotate_shift::RotateShiftInstruction,
+    arithmetic::ArithmeticInstruction,
+    bit::BitInstruction,
+    control_flow::ControlFlowInstruction,
+    load::LoadInstruction,
+    miscellaneous::MiscInstruction,
+    opcodes::{Opcode, OpcodeGroup, OpcodeInfo},
+    rotate_shift::RotateShiftInstruction,
 };
 
 pub mod arithmetic;
 pub mod bit;
 pub mod control_flow;
+pub mod disassembler;
+pub mod listing;
 pub mod load;
 pub mod miscellaneous;
+pub mod opcodes;
 pub mod rotate_shift;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Instruction {
     Load(LoadInstruction),
     Arithmetic(ArithmeticInstruction),
@@ -26,6 +38,7 @@ pub enum Instruction {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FetchRegister {
     Register(Register),
     AddrHL,
@@ -67,37 +80,175 @@ impl FetchRegister {
     }
 }
 
+/// The 3 bits selecting `reg` in an opcode, (HL) is in the slot of F.
+fn get_register_bits(reg: Register) -> u8 {
+    match reg {
+        Register::B => 0,
+        Register::C => 1,
+        Register::D => 2,
+        Register::E => 3,
+        Register::H => 4,
+        Register::L => 5,
+        Register::F => 6,
+        Register::A => 7,
+    }
+}
+
+/// The 2 bits selecting `reg` in an opcode, AF is in the slot of SP for PUSH and POP.
+fn get_long_register_bits(reg: LongRegister) -> u8 {
+    match reg {
+        LongRegister::BC => 0,
+        LongRegister::DE => 1,
+        LongRegister::HL => 2,
+        LongRegister::SP | LongRegister::AF => 3,
+        LongRegister::PC => unreachable!("no opcode takes PC"),
+    }
+}
+
 impl Instruction {
-    pub fn fetch(cpu: &mut Cpu) -> Option<Self> {
+    pub fn fetch<B: Bus>(cpu: &mut Cpu<B>) -> Option<Self> {
+        cpu.begin_instruction();
+        let instruction = Self::decode(cpu);
+        cpu.set_current_instruction(instruction);
+        instruction
+    }
+
+    /// The group of the opcode is looked up in the opcode tables,
+    /// only the fetch of that group runs.
+    fn decode<B: Bus>(cpu: &mut Cpu<B>) -> Option<Self> {
         let opcode = cpu.advance();
-        if opcode == 0xCB {
-            let opcode = cpu.advance();
-            let reg = (opcode & 0b00000111).into();
-            let opcode_id = opcode & 0b11111000;
-            MiscInstruction::fetch_prefixed(cpu, opcode_id, reg)
-                .map(Instruction::Misc)
-                .or_else(|| {
-                    RotateShiftInstruction::fetch_prefixed(cpu, opcode_id, reg)
-                        .map(Instruction::RotateShift)
-                })
-                .or_else(|| {
-                    BitInstruction::fetch_prefixed(cpu, opcode_id, reg).map(Instruction::Bit)
-                })
-        } else {
-            LoadInstruction::fetch(cpu, opcode)
-                .map(Instruction::Load)
-                .or_else(|| ArithmeticInstruction::fetch(cpu, opcode).map(Instruction::Arithmetic))
-                .or_else(|| MiscInstruction::fetch(cpu, opcode).map(Instruction::Misc))
-                .or_else(|| {
-                    RotateShiftInstruction::fetch(cpu, opcode).map(Instruction::RotateShift)
-                })
-                .or_else(|| {
-                    ControlFlowInstruction::fetch(cpu, opcode).map(Instruction::ControlFlow)
-                })
+        match opcodes::OPCODES[usize::from(opcode)].group {
+            OpcodeGroup::Load => LoadInstruction::fetch(cpu, opcode).map(Instruction::Load),
+            OpcodeGroup::Arithmetic => {
+                ArithmeticInstruction::fetch(cpu, opcode).map(Instruction::Arithmetic)
+            }
+            OpcodeGroup::Misc => MiscInstruction::fetch(cpu, opcode).map(Instruction::Misc),
+            OpcodeGroup::RotateShift => {
+                RotateShiftInstruction::fetch(cpu, opcode).map(Instruction::RotateShift)
+            }
+            OpcodeGroup::ControlFlow => {
+                ControlFlowInstruction::fetch(cpu, opcode).map(Instruction::ControlFlow)
+            }
+            OpcodeGroup::Prefix => Self::decode_prefixed(cpu),
+            OpcodeGroup::Bit | OpcodeGroup::Illegal => None,
         }
     }
 
-    pub fn execute(self, cpu: &mut Cpu) {
+    fn decode_prefixed<B: Bus>(cpu: &mut Cpu<B>) -> Option<Self> {
+        let opcode = cpu.advance();
+        let reg = (opcode & 0b00000111).into();
+        let opcode_id = opcode & 0b11111000;
+        match opcodes::PREFIXED_OPCODES[usize::from(opcode)].group {
+            OpcodeGroup::Misc => {
+                MiscInstruction::fetch_prefixed(cpu, opcode_id, reg).map(Instruction::Misc)
+            }
+            OpcodeGroup::RotateShift => RotateShiftInstruction::fetch_prefixed(cpu, opcode_id, reg)
+                .map(Instruction::RotateShift),
+            OpcodeGroup::Bit => {
+                BitInstruction::fetch_prefixed(cpu, opcode_id, reg).map(Instruction::Bit)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn get_opcode(self) -> Opcode {
+        match self {
+            Instruction::Load(instruction) => Opcode::Unprefixed(instruction.get_opcode()),
+            Instruction::Arithmetic(instruction) => Opcode::Unprefixed(instruction.get_opcode()),
+            Instruction::Misc(instruction) => instruction.get_opcode(),
+            Instruction::RotateShift(instruction) => instruction.get_opcode(),
+            Instruction::Bit(instruction) => Opcode::Prefixed(instruction.get_opcode()),
+            Instruction::ControlFlow(instruction) => Opcode::Unprefixed(instruction.get_opcode()),
+        }
+    }
+
+    /// Append the bytes of the instruction to `out`, what `fetch` reads back.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self.get_opcode() {
+            Opcode::Unprefixed(opcode) => out.push(opcode),
+            Opcode::Prefixed(opcode) => out.extend([0xCB, opcode]),
+        }
+        let (byte, long) = self.get_operands();
+        out.extend(byte);
+        out.extend(long.map(u16::to_le_bytes).into_iter().flatten());
+    }
+
+    /// The immediate data after the opcode, a byte or a little endian word.
+    fn get_operands(self) -> (Option<u8>, Option<u16>) {
+        use ArithmeticInstruction as Arith;
+        use ControlFlowInstruction as Flow;
+        use LoadInstruction as Ld;
+        let [byte] = match self {
+            Instruction::Load(
+                Ld::LoadImmediate(_, n)
+                | Ld::LoadIntoHLAddrn(n)
+                | Ld::LoadFromAIntoAddrn(n)
+                | Ld::LoadFromAddrnIntoA(n),
+            )
+            | Instruction::Arithmetic(
+                Arith::AddImmediate(n)
+                | Arith::AddCarryImmediate(n)
+                | Arith::SubImmediate(n)
+                | Arith::SubCarryImmediate(n)
+                | Arith::AndImmediate(n)
+                | Arith::OrImmediate(n)
+                | Arith::XorImmediate(n)
+                | Arith::CmpImmediate(n),
+            ) => [n],
+            Instruction::Load(Ld::LoadFromSPPlusnIntoHL(e))
+            | Instruction::Arithmetic(Arith::AddSPImmediate(e))
+            | Instruction::ControlFlow(
+                Flow::JumpImmediateRelative(e) | Flow::JumpRelativeCondition(_, e),
+            ) => e.to_le_bytes(),
+            // STOP is followed by a 0x00 the CPU skips
+            Instruction::Misc(MiscInstruction::Stop) => [0x00],
+            Instruction::Load(
+                Ld::LoadIntoAFromAddrnn(nn)
+                | Ld::LoadIntoAddrnnFromA(nn)
+                | Ld::LoadImmediateLong(_, nn)
+                | Ld::LoadSPIntoAddrnn(nn),
+            )
+            | Instruction::ControlFlow(
+                Flow::JumpImmediate(nn)
+                | Flow::JumpImmediateCondition(_, nn)
+                | Flow::CallImmediate(nn)
+                | Flow::CallImmediateCondition(_, nn),
+            ) => return (None, Some(nn)),
+            _ => return (None, None),
+        };
+        (Some(byte), None)
+    }
+
+    pub fn get_info(self) -> OpcodeInfo {
+        self.get_opcode().get_info()
+    }
+
+    /// Clock cycles, when the condition isn't met for the conditional jumps, calls and returns.
+    pub fn cycles(self) -> u8 {
+        self.get_info().cycles
+    }
+
+    /// Clock cycles added when the condition is met, 0 for the unconditional instructions.
+    pub fn extra_cycles(self) -> u8 {
+        let info = self.get_info();
+        info.cycles_taken - info.cycles
+    }
+
+    /// Bytes taken by the instruction, with its operands and the 0xCB prefix.
+    pub fn length(self) -> u8 {
+        self.get_info().length
+    }
+
+    /// With the `checked-arithmetic` feature in a debug build,
+    /// a panic while executing tells which instruction it was, see `Cpu::audit`.
+    pub fn execute<B: Bus>(self, cpu: &mut Cpu<B>) {
+        #[cfg(all(debug_assertions, feature = "checked-arithmetic"))]
+        cpu.audit(self, |cpu| self.dispatch(cpu));
+        #[cfg(not(all(debug_assertions, feature = "checked-arithmetic")))]
+        self.dispatch(cpu);
+    }
+
+    fn dispatch<B: Bus>(self, cpu: &mut Cpu<B>) {
         match self {
             Instruction::Load(instruction) => instruction.execute(cpu),
             Instruction::Arithmetic(instruction) => instruction.execute(cpu),
@@ -111,9 +262,108 @@ impl Instruction {
 
 #[cfg(test)]
 mod tests {
-    use crate::cpu::Cpu;
+    use crate::{cpu::Cpu, memory::bus::FlatBus};
+
+    use super::{
+        arithmetic::ArithmeticInstruction,
+        disassembler,
+        load::LoadInstruction,
+        opcodes::{Opcode, OpcodeGroup, OPCODES},
+        Instruction, Register,
+    };
+
+    /// Every opcode that decodes, prefixed ones with their prefix, and zeroed operands.
+    fn all_opcodes() -> Vec<(Opcode, Vec<u8>)> {
+        let mut opcodes = Vec::new();
+        for opcode in 0..=0xFF {
+            match OPCODES[usize::from(opcode)].group {
+                OpcodeGroup::Illegal => (),
+                OpcodeGroup::Prefix => opcodes.extend(
+                    (0..=0xFF).map(|prefixed| (Opcode::Prefixed(prefixed), vec![0xCB, prefixed])),
+                ),
+                _ => opcodes.push((Opcode::Unprefixed(opcode), vec![opcode, 0x00, 0x00])),
+            }
+        }
+        opcodes
+    }
+
+    #[test]
+    fn opcode_round_trip() {
+        for (opcode, bytes) in all_opcodes() {
+            let (instruction, length) = disassembler::decode(&bytes, 0x0000).unwrap();
+            assert_eq!(instruction.get_opcode(), opcode, "{:?}", instruction);
+            assert_eq!(u16::from(instruction.length()), length, "{:?}", instruction);
+        }
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        for operands in [[0x00, 0x00], [0x12, 0x34], [0xFF, 0x80], [0x7F, 0xFF]] {
+            for (_, mut bytes) in all_opcodes() {
+                // keep the 0x00 after STOP
+                if bytes[0] != 0x10 && bytes[0] != 0xCB {
+                    bytes[1..].copy_from_slice(&operands);
+                }
+                let (instruction, length) = disassembler::decode(&bytes, 0x0000).unwrap();
+                let mut encoded = Vec::new();
+                instruction.encode(&mut encoded);
+                assert_eq!(encoded, bytes[..usize::from(length)], "{:?}", instruction);
+            }
+        }
+
+        // LD A, 0x42; LD [0xC000], A; INC A
+        let mut program = Vec::new();
+        for instruction in [
+            Instruction::Load(LoadInstruction::LoadImmediate(Register::A, 0x42)),
+            Instruction::Load(LoadInstruction::LoadIntoAddrnnFromA(0xC000)),
+            Instruction::Arithmetic(ArithmeticInstruction::IncRegister(Register::A)),
+        ] {
+            instruction.encode(&mut program);
+        }
+        assert_eq!(program, [0x3E, 0x42, 0xEA, 0x00, 0xC0, 0x3C]);
+        let mut cpu = Cpu::new(FlatBus::with_program(0x0000, &program));
+        for _ in 0..3 {
+            Instruction::fetch(&mut cpu).unwrap().execute(&mut cpu);
+        }
+        assert_eq!(cpu.get_bus().memory[0xC000], 0x42);
+        assert_eq!(cpu.get_reg_a(), 0x43);
+    }
 
-    use super::Instruction;
+    #[test]
+    fn cycles_match_execution() {
+        for (opcode, bytes) in all_opcodes() {
+            // HALT and STOP wait for something else
+            if matches!(opcode, Opcode::Unprefixed(0x76 | 0x10)) {
+                continue;
+            }
+            // run with every flag reset then set, so both outcomes of the conditions are seen
+            let mut elapsed: Vec<_> = [0x00, 0xF0]
+                .into_iter()
+                .map(|flags| {
+                    let mut cpu = Cpu::new(FlatBus::with_program(0x0000, &bytes));
+                    cpu.set_flags(flags.into());
+                    cpu.put_long_reg(crate::cpu::registers::LongRegister::SP, 0xFFF0);
+                    // keep LD [HL-] and LD [HL+] away from the address space ends
+                    cpu.put_long_reg(crate::cpu::registers::LongRegister::HL, 0xC000);
+                    let instruction = Instruction::fetch(&mut cpu).unwrap();
+                    instruction.execute(&mut cpu);
+                    (instruction, cpu.get_cycles())
+                })
+                .collect();
+            elapsed.sort_by_key(|(_, cycles)| *cycles);
+            let [(instruction, fewest), (_, most)] = elapsed[..] else {
+                unreachable!()
+            };
+            let cycles = u64::from(instruction.cycles());
+            let extra = u64::from(instruction.extra_cycles());
+            assert_eq!(
+                (fewest, most - fewest),
+                (cycles, extra),
+                "{:?}",
+                instruction
+            );
+        }
+    }
 
     #[test]
     fn display_instruction() {
@@ -127,7 +377,7 @@ mod tests {
             }
             let instruction = Instruction::fetch(&mut cpu);
             if let Some(inst) = instruction {
-                println!("{:?}", inst,);
+                println!("{}", inst);
             } else {
                 println!("Unknown");
             }
@@ -137,7 +387,7 @@ mod tests {
             print!("CB {:#X} : ", cpu.get_relative(1));
             let instruction = Instruction::fetch(&mut cpu);
             if let Some(inst) = instruction {
-                println!("{:?}", inst,);
+                println!("{}", inst);
             } else {
                 println!("Unknown");
             }
@@ -145,7 +395,7 @@ mod tests {
 
         print!("0x10 0x00 : ");
         if let Some(inst) = Instruction::fetch(&mut cpu) {
-            println!("{:?}", inst,);
+            println!("{}", inst);
         } else {
             println!("Unknown");
         }
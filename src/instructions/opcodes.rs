@@ -0,0 +1,223 @@
+/// Which instruction family decodes an opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpcodeGroup {
+    Load,
+    Arithmetic,
+    Misc,
+    RotateShift,
+    Bit,
+    ControlFlow,
+    /// 0xCB, the next byte is looked up in `PREFIXED_OPCODES`.
+    Prefix,
+    /// Locks up the CPU on hardware.
+    Illegal,
+}
+
+/// What is known of an opcode before reading its operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodeInfo {
+    pub group: OpcodeGroup,
+    /// Uppercase, in the RGBDS syntax.
+    pub mnemonic: &'static str,
+    /// Bytes with the operands, and the 0xCB prefix for the prefixed opcodes.
+    pub length: u8,
+    /// Clock cycles, when the condition isn't met for the conditional ones.
+    pub cycles: u8,
+    /// Clock cycles when the condition is met, same as `cycles` for the others.
+    pub cycles_taken: u8,
+}
+
+impl OpcodeInfo {
+    const fn new(group: OpcodeGroup, mnemonic: &'static str, length: u8, cycles: u8) -> Self {
+        OpcodeInfo {
+            group,
+            mnemonic,
+            length,
+            cycles,
+            cycles_taken: cycles,
+        }
+    }
+
+    const fn conditional(mnemonic: &'static str, length: u8, cycles: u8, taken: u8) -> Self {
+        OpcodeInfo {
+            cycles_taken: taken,
+            ..Self::new(OpcodeGroup::ControlFlow, mnemonic, length, cycles)
+        }
+    }
+
+    const ILLEGAL: Self = Self::new(OpcodeGroup::Illegal, "ILLEGAL", 1, 4);
+}
+
+/// The byte selecting an instruction, and whether it follows the 0xCB prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Unprefixed(u8),
+    Prefixed(u8),
+}
+
+impl Opcode {
+    pub fn get_info(self) -> OpcodeInfo {
+        match self {
+            Opcode::Unprefixed(opcode) => OPCODES[usize::from(opcode)],
+            Opcode::Prefixed(opcode) => PREFIXED_OPCODES[usize::from(opcode)],
+        }
+    }
+}
+
+/// Every opcode, indexed by its first byte.
+pub const OPCODES: [OpcodeInfo; 256] = build_table(false);
+
+/// The opcodes following 0xCB, indexed by their second byte.
+pub const PREFIXED_OPCODES: [OpcodeInfo; 256] = build_table(true);
+
+const fn build_table(prefixed: bool) -> [OpcodeInfo; 256] {
+    let mut table = [OpcodeInfo::ILLEGAL; 256];
+    let mut opcode = 0;
+    while opcode < table.len() {
+        table[opcode] = if prefixed {
+            get_prefixed_info(opcode as u8)
+        } else {
+            get_info(opcode as u8)
+        };
+        opcode += 1;
+    }
+    table
+}
+
+/// The operand in the low 3 bits is (HL).
+const fn is_hl_operand(opcode: u8) -> bool {
+    opcode & 0b00000111 == 0b110
+}
+
+const ALU_MNEMONICS: [&str; 8] = ["ADD", "ADC", "SUB", "SBC", "AND", "XOR", "OR", "CP"];
+
+const fn get_info(opcode: u8) -> OpcodeInfo {
+    use OpcodeGroup::*;
+    let new = OpcodeInfo::new;
+    let conditional = OpcodeInfo::conditional;
+    match opcode {
+        0x00 => new(Misc, "NOP", 1, 4),
+        0x10 => new(Misc, "STOP", 2, 4),
+        0x76 => new(Misc, "HALT", 1, 4),
+        0x27 => new(Misc, "DAA", 1, 4),
+        0x2F => new(Misc, "CPL", 1, 4),
+        0x37 => new(Misc, "SCF", 1, 4),
+        0x3F => new(Misc, "CCF", 1, 4),
+        0xF3 => new(Misc, "DI", 1, 4),
+        0xFB => new(Misc, "EI", 1, 4),
+        0x07 => new(RotateShift, "RLCA", 1, 4),
+        0x0F => new(RotateShift, "RRCA", 1, 4),
+        0x17 => new(RotateShift, "RLA", 1, 4),
+        0x1F => new(RotateShift, "RRA", 1, 4),
+        0x08 => new(Load, "LD", 3, 20),
+        0x36 => new(Load, "LD", 2, 12),
+        0xE0 | 0xF0 => new(Load, "LDH", 2, 12),
+        0xE2 | 0xF2 => new(Load, "LDH", 1, 8),
+        0xEA | 0xFA => new(Load, "LD", 3, 16),
+        0xF8 => new(Load, "LD", 2, 12),
+        0xF9 => new(Load, "LD", 1, 8),
+        0x34 => new(Arithmetic, "INC", 1, 12),
+        0x35 => new(Arithmetic, "DEC", 1, 12),
+        0xE8 => new(Arithmetic, "ADD", 2, 16),
+        0x18 => new(ControlFlow, "JR", 2, 12),
+        0xC3 => new(ControlFlow, "JP", 3, 16),
+        0xE9 => new(ControlFlow, "JP", 1, 4),
+        0xCD => new(ControlFlow, "CALL", 3, 24),
+        0xC9 => new(ControlFlow, "RET", 1, 16),
+        0xD9 => new(ControlFlow, "RETI", 1, 16),
+        0xCB => new(Prefix, "PREFIX", 2, 8),
+        0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => {
+            OpcodeInfo::ILLEGAL
+        }
+        0x40..=0x7F if is_hl_operand(opcode) || (opcode & 0b00111000) == 0b00110000 => {
+            new(Load, "LD", 1, 8)
+        }
+        0x40..=0x7F => new(Load, "LD", 1, 4),
+        0x80..=0xBF => {
+            let mnemonic = ALU_MNEMONICS[((opcode >> 3) & 0b111) as usize];
+            let cycles = if is_hl_operand(opcode) { 8 } else { 4 };
+            new(Arithmetic, mnemonic, 1, cycles)
+        }
+        x if x & 0b11000111 == 0b11000110 => {
+            new(Arithmetic, ALU_MNEMONICS[((x >> 3) & 0b111) as usize], 2, 8)
+        }
+        x if x & 0b11000111 == 0b11000111 => new(ControlFlow, "RST", 1, 16),
+        x if x & 0b11001111 == 0x01 => new(Load, "LD", 3, 12),
+        x if x & 0b11001111 == 0x02 || x & 0b11001111 == 0x0A => new(Load, "LD", 1, 8),
+        x if x & 0b11001111 == 0x03 => new(Arithmetic, "INC", 1, 8),
+        x if x & 0b11001111 == 0x0B => new(Arithmetic, "DEC", 1, 8),
+        x if x & 0b11000111 == 0x04 => new(Arithmetic, "INC", 1, 4),
+        x if x & 0b11000111 == 0x05 => new(Arithmetic, "DEC", 1, 4),
+        x if x & 0b11000111 == 0x06 => new(Load, "LD", 2, 8),
+        x if x & 0b11001111 == 0x09 => new(Arithmetic, "ADD", 1, 8),
+        x if x & 0b11001111 == 0xC1 => new(Load, "POP", 1, 12),
+        x if x & 0b11001111 == 0xC5 => new(Load, "PUSH", 1, 16),
+        x if x & 0b11100111 == 0x20 => conditional("JR", 2, 8, 12),
+        x if x & 0b11100111 == 0xC0 => conditional("RET", 1, 8, 20),
+        x if x & 0b11100111 == 0xC2 => conditional("JP", 3, 12, 16),
+        x if x & 0b11100111 == 0xC4 => conditional("CALL", 3, 12, 24),
+        _ => OpcodeInfo::ILLEGAL,
+    }
+}
+
+const fn get_prefixed_info(opcode: u8) -> OpcodeInfo {
+    use OpcodeGroup::*;
+    const SHIFTS: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+    let hl = is_hl_operand(opcode);
+    let (group, mnemonic) = match opcode >> 6 {
+        0 if opcode >> 3 == 6 => (Misc, "SWAP"),
+        0 => (RotateShift, SHIFTS[(opcode >> 3) as usize]),
+        1 => (Bit, "BIT"),
+        2 => (Bit, "RES"),
+        _ => (Bit, "SET"),
+    };
+    // BIT only reads (HL), the others read then write it back
+    let cycles = match (hl, opcode >> 6) {
+        (false, _) => 8,
+        (true, 1) => 12,
+        (true, _) => 16,
+    };
+    OpcodeInfo::new(group, mnemonic, 2, cycles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::{disassembler, Instruction};
+
+    fn get_group(instruction: Instruction) -> OpcodeGroup {
+        match instruction {
+            Instruction::Load(_) => OpcodeGroup::Load,
+            Instruction::Arithmetic(_) => OpcodeGroup::Arithmetic,
+            Instruction::Misc(_) => OpcodeGroup::Misc,
+            Instruction::RotateShift(_) => OpcodeGroup::RotateShift,
+            Instruction::Bit(_) => OpcodeGroup::Bit,
+            Instruction::ControlFlow(_) => OpcodeGroup::ControlFlow,
+        }
+    }
+
+    #[test]
+    fn tables_match_decoder() {
+        for opcode in 0..=0xFF {
+            let info = OPCODES[usize::from(opcode)];
+            let decoded = disassembler::decode(&[opcode, 0x00, 0x00], 0x0000);
+            match info.group {
+                OpcodeGroup::Illegal => assert_eq!(decoded, None, "{:#04X}", opcode),
+                OpcodeGroup::Prefix => {
+                    for prefixed in 0..=0xFF {
+                        let info = PREFIXED_OPCODES[usize::from(prefixed)];
+                        let (instruction, length) =
+                            disassembler::decode(&[opcode, prefixed], 0x0000).unwrap();
+                        assert_eq!(get_group(instruction), info.group, "CB {:#04X}", prefixed);
+                        assert_eq!(length, u16::from(info.length), "CB {:#04X}", prefixed);
+                    }
+                }
+                group => {
+                    let (instruction, length) = decoded.unwrap();
+                    assert_eq!(get_group(instruction), group, "{:#04X}", opcode);
+                    assert_eq!(length, u16::from(info.length), "{:#04X}", opcode);
+                }
+            }
+        }
+    }
+}
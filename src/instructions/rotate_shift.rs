@@ -4,11 +4,13 @@ use crate::{
         Cpu,
     },
     map_fetch_register,
+    memory::bus::Bus,
 };
 
-use super::FetchRegister;
+use super::{get_register_bits, opcodes::Opcode, FetchRegister};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RotateShiftInstruction {
     /// RLCA
     ///
@@ -121,7 +123,11 @@ pub enum RotateShiftInstruction {
 }
 
 impl RotateShiftInstruction {
-    pub const fn fetch_prefixed(_: &Cpu, opcode_id: u8, reg: FetchRegister) -> Option<Self> {
+    pub const fn fetch_prefixed<B: Bus>(
+        _: &Cpu<B>,
+        opcode_id: u8,
+        reg: FetchRegister,
+    ) -> Option<Self> {
         use RotateShiftInstruction::*;
         match opcode_id {
             // Rotate left
@@ -166,7 +172,7 @@ impl RotateShiftInstruction {
         }
     }
 
-    pub const fn fetch(_: &Cpu, opcode: u8) -> Option<Self> {
+    pub const fn fetch<B: Bus>(_: &Cpu<B>, opcode: u8) -> Option<Self> {
         use RotateShiftInstruction::*;
 
         match opcode {
@@ -178,7 +184,33 @@ impl RotateShiftInstruction {
         }
     }
 
-    pub fn execute(self, cpu: &mut Cpu) {
+    pub fn get_opcode(self) -> Opcode {
+        use RotateShiftInstruction::*;
+        let prefixed = |base: u8, reg: Register| Opcode::Prefixed(base | get_register_bits(reg));
+        let hl = Register::F;
+        match self {
+            RotateLeftCarryA => Opcode::Unprefixed(0x07),
+            RotateLeftA => Opcode::Unprefixed(0x17),
+            RotateRightCarryA => Opcode::Unprefixed(0x0F),
+            RotateRightA => Opcode::Unprefixed(0x1F),
+            RotateLeftCarryRegister(reg) => prefixed(0x00, reg),
+            RotateLeftCarryAddrHL => prefixed(0x00, hl),
+            RotateRightCarryRegister(reg) => prefixed(0x08, reg),
+            RotateRightCarryAddrHL => prefixed(0x08, hl),
+            RotateLeftRegister(reg) => prefixed(0x10, reg),
+            RotateLeftAddrHL => prefixed(0x10, hl),
+            RotateRightRegister(reg) => prefixed(0x18, reg),
+            RotateRightAddrHL => prefixed(0x18, hl),
+            ShiftLeftRegister(reg) => prefixed(0x20, reg),
+            ShiftLeftAddrHL => prefixed(0x20, hl),
+            ShiftRightRegisterSigned(reg) => prefixed(0x28, reg),
+            ShiftRightAddrHLSigned => prefixed(0x28, hl),
+            ShiftRightRegister(reg) => prefixed(0x38, reg),
+            ShiftRightAddrHL => prefixed(0x38, hl),
+        }
+    }
+
+    pub fn execute<B: Bus>(self, cpu: &mut Cpu<B>) {
         // all opcodes are either not prefixed and just operate on A and take 4 cycles
         // or are prefixed and take 8 / 16 cycles
         // so no cycle adjust needed
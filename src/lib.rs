@@ -0,0 +1,20 @@
+pub mod apu;
+pub mod boot;
+pub mod config;
+pub mod cpu;
+pub mod debugger;
+pub mod emulator;
+pub mod extensions;
+mod help_traits;
+pub mod instructions;
+pub mod memory;
+pub mod metrics;
+pub mod movie;
+pub mod ppu;
+pub mod recording;
+pub mod savestate;
+pub mod schedule;
+#[cfg(feature = "serde")]
+mod serde_helpers;
+pub mod serial;
+pub mod testing;
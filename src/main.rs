@@ -1,8 +1,3 @@
-mod cpu;
-mod help_traits;
-mod instructions;
-mod memory;
-
 fn main() {
     println!("Hello, world!");
 }
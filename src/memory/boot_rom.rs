@@ -0,0 +1,87 @@
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootRomError {
+    /// Neither a DMG (256 bytes) nor a CGB (2KB or 2.25KB) boot ROM.
+    InvalidSize(usize),
+}
+
+impl Display for BootRomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BootRomError::InvalidSize(size) => {
+                write!(f, "boot ROM of {} bytes, expected 256, 2048 or 2304", size)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BootRomError {}
+
+/// A dump of the boot ROM, mapped over the cartridge until the boot writes to 0xFF50.
+///
+/// The DMG one covers 0x0000-0x00FF, the CGB one also 0x0200-0x08FF,
+/// 0x0100-0x01FF always shows the cartridge header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BootRom {
+    /// Laid out like the address space, the CGB header gap included.
+    rom: Vec<u8>,
+    mapped: bool,
+}
+
+impl BootRom {
+    pub const REGISTER: u16 = 0xFF50;
+    pub const DMG_SIZE: usize = 0x100;
+    /// The CGB dumps come with or without the 256 bytes gap of the header.
+    pub const CGB_SIZE: usize = 0x800;
+    pub const CGB_SIZE_WITH_GAP: usize = 0x900;
+
+    const HEADER_START: u16 = 0x0100;
+    const HEADER_END: u16 = 0x01FF;
+
+    pub fn new(rom: Vec<u8>) -> Result<Self, BootRomError> {
+        let rom = match rom.len() {
+            Self::DMG_SIZE | Self::CGB_SIZE_WITH_GAP => rom,
+            Self::CGB_SIZE => {
+                let mut with_gap = rom[..Self::DMG_SIZE].to_vec();
+                with_gap.resize(Self::DMG_SIZE * 2, 0xFF);
+                with_gap.extend_from_slice(&rom[Self::DMG_SIZE..]);
+                with_gap
+            }
+            size => return Err(BootRomError::InvalidSize(size)),
+        };
+        Ok(BootRom { rom, mapped: true })
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        self.rom.len() != Self::DMG_SIZE
+    }
+
+    /// Whether a read of `addr` gives the boot ROM instead of the cartridge.
+    pub fn is_mapped(&self, addr: u16) -> bool {
+        self.mapped
+            && usize::from(addr) < self.rom.len()
+            && !(Self::HEADER_START..=Self::HEADER_END).contains(&addr)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.mapped
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        self.rom[usize::from(addr)]
+    }
+
+    /// Any non zero value written to 0xFF50 unmaps the boot ROM, there is no way back until a reset.
+    pub fn set_register(&mut self, value: u8) {
+        if value != 0 {
+            self.mapped = false;
+        }
+    }
+
+    /// Map it back for a power cycle, or restore it from a savestate.
+    pub fn set_active(&mut self, active: bool) {
+        self.mapped = active;
+    }
+}
@@ -0,0 +1,264 @@
+use crate::metrics::Stopwatch;
+
+use super::{interrupts::Interrupt, Memory};
+
+/// What the CPU sees of the rest of the machine.
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    /// Read without any side effect, for diagnostics.
+    fn peek(&self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, value: u8);
+    /// Advance the rest of the machine by one machine cycle (4 clock cycles).
+    fn tick(&mut self);
+    /// Set the interrupt bit in the IF register.
+    ///
+    /// This is what the PPU, timer, serial port and joypad use to raise an interrupt,
+    /// tests can also use it instead of writing IF directly.
+    fn request_interrupt(&mut self, interrupt: Interrupt);
+    /// The CPU executed STOP, DIV is reset.
+    ///
+    /// Returns true if a CGB speed switch was armed, the speed is switched
+    /// and the CPU goes on instead of stopping.
+    fn stop(&mut self) -> bool;
+    /// CGB double speed, the CPU, the timer and the serial port run twice as fast
+    /// as the rest of the machine.
+    fn is_double_speed(&self) -> bool;
+    /// Clear the IF bit of the interrupt the CPU is servicing.
+    fn acknowledge_interrupt(&mut self, interrupt: Interrupt);
+    /// Interrupts both requested and enabled (IF & IE), in the IF bit layout.
+    fn get_pending_interrupts(&self) -> u8;
+}
+
+impl Bus for Memory {
+    fn read(&mut self, addr: u16) -> u8 {
+        let stopwatch = Stopwatch::start();
+        let value = Memory::read(self, addr);
+        stopwatch.stop(&mut self.metrics.bus);
+        value
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.get(addr)
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        let stopwatch = Stopwatch::start();
+        Memory::write(self, addr, value);
+        stopwatch.stop(&mut self.metrics.bus);
+    }
+
+    fn tick(&mut self) {
+        Memory::tick(self);
+    }
+
+    fn request_interrupt(&mut self, interrupt: Interrupt) {
+        Memory::request_interrupt(self, interrupt);
+    }
+
+    fn stop(&mut self) -> bool {
+        self.reset_div();
+        self.switch_speed()
+    }
+
+    fn is_double_speed(&self) -> bool {
+        Memory::is_double_speed(self)
+    }
+
+    fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        Memory::acknowledge_interrupt(self, interrupt);
+    }
+
+    fn get_pending_interrupts(&self) -> u8 {
+        Memory::get_pending_interrupts(self)
+    }
+}
+
+/// Flat 64KB of RAM that records every access, nothing else of the machine.
+///
+/// Enough to run the CPU alone, for per-instruction tests like the SM83 JSON tests.
+#[derive(Debug)]
+pub struct FlatBus {
+    pub memory: Vec<u8>,
+    /// Clock cycles elapsed, stamped on each access.
+    pub cycles: u64,
+    pub accesses: Vec<BusAccess>,
+    pub interrupts: Vec<Interrupt>,
+}
+
+/// An access seen on the bus, `cycle` is the clock cycle it happened at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusAccess {
+    Read { cycle: u64, addr: u16, value: u8 },
+    Write { cycle: u64, addr: u16, value: u8 },
+}
+
+impl Default for FlatBus {
+    fn default() -> Self {
+        FlatBus {
+            memory: vec![0; 0x10000],
+            cycles: 0,
+            accesses: Vec::new(),
+            interrupts: Vec::new(),
+        }
+    }
+}
+
+impl FlatBus {
+    /// `program` copied at `addr`, the rest of the memory is zeroed.
+    pub fn with_program(addr: u16, program: &[u8]) -> Self {
+        let mut bus = FlatBus::default();
+        let start = addr as usize;
+        bus.memory[start..start + program.len()].copy_from_slice(program);
+        bus
+    }
+}
+
+impl Bus for FlatBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        let value = self.memory[addr as usize];
+        self.accesses.push(BusAccess::Read {
+            cycle: self.cycles,
+            addr,
+            value,
+        });
+        value
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.memory[addr as usize] = value;
+        self.accesses.push(BusAccess::Write {
+            cycle: self.cycles,
+            addr,
+            value,
+        });
+    }
+
+    fn tick(&mut self) {
+        self.cycles += 4;
+    }
+
+    fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupts.push(interrupt);
+        self.memory[0xFF0F] |= interrupt.get_mask();
+    }
+
+    fn stop(&mut self) -> bool {
+        self.memory[0xFF04] = 0;
+        false
+    }
+
+    fn is_double_speed(&self) -> bool {
+        false
+    }
+
+    fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.memory[0xFF0F] &= !interrupt.get_mask();
+    }
+
+    fn get_pending_interrupts(&self) -> u8 {
+        self.memory[0xFF0F] & self.memory[0xFFFF] & 0b00011111
+    }
+}
+
+/// Wraps another bus and records every access going through it,
+/// to compare two implementations access by access.
+#[derive(Debug, Default)]
+pub struct RecordingBus<B: Bus> {
+    pub inner: B,
+    /// Clock cycles elapsed, stamped on each access.
+    pub cycles: u64,
+    pub accesses: Vec<BusAccess>,
+}
+
+impl<B: Bus> RecordingBus<B> {
+    pub fn new(inner: B) -> Self {
+        RecordingBus {
+            inner,
+            cycles: 0,
+            accesses: Vec::new(),
+        }
+    }
+
+    /// Accesses recorded since the last call.
+    pub fn take_accesses(&mut self) -> Vec<BusAccess> {
+        std::mem::take(&mut self.accesses)
+    }
+}
+
+impl<B: Bus> Bus for RecordingBus<B> {
+    fn read(&mut self, addr: u16) -> u8 {
+        let value = self.inner.read(addr);
+        self.accesses.push(BusAccess::Read {
+            cycle: self.cycles,
+            addr,
+            value,
+        });
+        value
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.inner.peek(addr)
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.inner.write(addr, value);
+        self.accesses.push(BusAccess::Write {
+            cycle: self.cycles,
+            addr,
+            value,
+        });
+    }
+
+    fn tick(&mut self) {
+        self.inner.tick();
+        self.cycles += 4;
+    }
+
+    fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.inner.request_interrupt(interrupt);
+    }
+
+    fn stop(&mut self) -> bool {
+        self.inner.stop()
+    }
+
+    fn is_double_speed(&self) -> bool {
+        self.inner.is_double_speed()
+    }
+
+    fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.inner.acknowledge_interrupt(interrupt);
+    }
+
+    fn get_pending_interrupts(&self) -> u8 {
+        self.inner.get_pending_interrupts()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::Cpu, instructions::Instruction};
+
+    #[test]
+    fn recording_matches_flat() {
+        // LD A, 0x42; LD (0xC000), A; LD B, (HL)
+        let program = [0x3E, 0x42, 0xEA, 0x00, 0xC0, 0x46];
+        let mut flat = Cpu::new(FlatBus::with_program(0x0000, &program));
+        let mut recorded = Cpu::new(RecordingBus::new(FlatBus::with_program(0x0000, &program)));
+        for _ in 0..3 {
+            Instruction::fetch(&mut flat).unwrap().execute(&mut flat);
+            Instruction::fetch(&mut recorded)
+                .unwrap()
+                .execute(&mut recorded);
+        }
+        assert_eq!(flat.get_bus().accesses, recorded.get_bus().accesses);
+        assert_eq!(recorded.get_bus().inner.memory[0xC000], 0x42);
+        assert_eq!(recorded.get_bus_mut().take_accesses().len(), 8);
+        assert!(recorded.get_bus().accesses.is_empty());
+    }
+}
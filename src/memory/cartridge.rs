@@ -0,0 +1,132 @@
+use std::fmt::Display;
+
+use super::mbc::{Mbc, Mbc1, Mbc2, Mbc3, Mbc5, Mbc7, Mmm01, RomOnly, WisdomTree};
+
+/// The cartridge header, located at 0x0100-0x014F of the ROM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub cartridge_type: u8,
+    pub rom_size: usize,
+    pub ram_size: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CartridgeError {
+    /// The ROM is too small to contain a header.
+    TooSmall(usize),
+    UnsupportedCartridgeType(u8),
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+}
+
+impl Display for CartridgeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CartridgeError::TooSmall(size) => {
+                write!(f, "ROM of {} bytes is too small to contain a header", size)
+            }
+            CartridgeError::UnsupportedCartridgeType(kind) => {
+                write!(f, "unsupported cartridge type {:#04X}", kind)
+            }
+            CartridgeError::UnknownRomSize(code) => {
+                write!(f, "unknown ROM size code {:#04X}", code)
+            }
+            CartridgeError::UnknownRamSize(code) => {
+                write!(f, "unknown RAM size code {:#04X}", code)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+impl CartridgeHeader {
+    const TITLE_START: usize = 0x0134;
+    const TITLE_END: usize = 0x0143;
+    const CARTRIDGE_TYPE: usize = 0x0147;
+    const ROM_SIZE: usize = 0x0148;
+    const RAM_SIZE: usize = 0x0149;
+    const HEADER_END: usize = 0x0150;
+
+    pub fn parse(rom: &[u8]) -> Result<Self, CartridgeError> {
+        if rom.len() < Self::HEADER_END {
+            return Err(CartridgeError::TooSmall(rom.len()));
+        }
+        let title = rom[Self::TITLE_START..=Self::TITLE_END]
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| char::from(c))
+            .collect();
+        let cartridge_type = rom[Self::CARTRIDGE_TYPE];
+        let rom_size = match rom[Self::ROM_SIZE] {
+            code @ 0x00..=0x08 => (32 * 1024) << code,
+            code => return Err(CartridgeError::UnknownRomSize(code)),
+        };
+        let ram_size = match rom[Self::RAM_SIZE] {
+            0x00 => 0,
+            // unused, but some homebrews use it
+            0x01 => 2 * 1024,
+            0x02 => 8 * 1024,
+            0x03 => 32 * 1024,
+            0x04 => 128 * 1024,
+            0x05 => 64 * 1024,
+            code => return Err(CartridgeError::UnknownRamSize(code)),
+        };
+        Ok(CartridgeHeader {
+            title,
+            cartridge_type,
+            rom_size,
+            ram_size,
+        })
+    }
+
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self.cartridge_type,
+            0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFF
+        )
+    }
+}
+
+/// Build the mapper described by the header of the ROM.
+pub fn load(rom: Vec<u8>) -> Result<Box<dyn Mbc>, CartridgeError> {
+    let header = match mmm01_header(&rom) {
+        Some(header) => header,
+        None => CartridgeHeader::parse(&rom)?,
+    };
+    let ram_size = header.ram_size;
+    let mbc: Box<dyn Mbc> = match header.cartridge_type {
+        0x00 if is_wisdom_tree(&rom) => Box::new(WisdomTree::new(rom)),
+        0x00 | 0x08 | 0x09 => Box::new(RomOnly::new(rom, ram_size)),
+        0x01..=0x03 => Box::new(Mbc1::new(rom, ram_size)),
+        0x05 | 0x06 => Box::new(Mbc2::new(rom)),
+        0x0B..=0x0D => Box::new(Mmm01::new(rom, ram_size)),
+        0x0F | 0x10 => Box::new(Mbc3::new(rom, ram_size, true)),
+        0x11..=0x13 => Box::new(Mbc3::new(rom, ram_size, false)),
+        0x19..=0x1B => Box::new(Mbc5::new(rom, ram_size, false)),
+        0x1C..=0x1E => Box::new(Mbc5::new(rom, ram_size, true)),
+        0x22 => Box::new(Mbc7::new(rom)),
+        0xC0 => Box::new(WisdomTree::new(rom)),
+        kind => return Err(CartridgeError::UnsupportedCartridgeType(kind)),
+    };
+    Ok(mbc)
+}
+
+/// MMM01 compilations boot from the last 32KB of the ROM,
+/// so the header at the start is the one of the first game, the real one is with the menu.
+fn mmm01_header(rom: &[u8]) -> Option<CartridgeHeader> {
+    let menu = rom.len().checked_sub(0x8000).filter(|&start| start > 0)?;
+    CartridgeHeader::parse(&rom[menu..])
+        .ok()
+        .filter(|header| matches!(header.cartridge_type, 0x0B..=0x0D))
+}
+
+/// Wisdom Tree carts mostly use the ROM only type, but are bigger than 32KB
+/// and have their name in the header.
+fn is_wisdom_tree(rom: &[u8]) -> bool {
+    rom.len() > 0x8000
+        && rom[CartridgeHeader::TITLE_START..CartridgeHeader::HEADER_END]
+            .windows(6)
+            .any(|window| window == b"WISDOM")
+}
@@ -0,0 +1,101 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+/// The OAM DMA, started by writing the high byte of the source to 0xFF46.
+///
+/// After a 1 M-cycle delay, it copies 160 bytes to the OAM, one per M-cycle.
+/// Meanwhile it owns the bus it reads from and the OAM, the CPU only has HRAM and the IO registers
+/// (and the other bus, VRAM if the source is external or the opposite).
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OamDma {
+    register: u8,
+    /// M-cycles before a requested transfer starts, 0 if none is requested.
+    start_delay: u8,
+    /// Bytes copied, the transfer is running while below 160.
+    index: u8,
+    source: u16,
+    active: bool,
+    /// Last byte copied, what the CPU sees when reading from the bus of the DMA.
+    current: u8,
+}
+
+impl OamDma {
+    pub const REGISTER: u16 = 0xFF46;
+    pub const LENGTH: u8 = 0xA0;
+
+    pub fn get_register(&self) -> u8 {
+        self.register
+    }
+
+    /// Request a transfer, a running one goes on until the new one starts.
+    pub fn start(&mut self, value: u8) {
+        self.register = value;
+        self.start_delay = 1;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Address the transfer reads from, the range 0xE000-0xFFFF reads the WRAM.
+    pub fn get_source(&self) -> u16 {
+        self.source
+    }
+
+    pub fn get_current(&self) -> u8 {
+        self.current
+    }
+
+    /// Advance one M-cycle, returns the address to copy from and the OAM offset to copy to.
+    ///
+    /// Cycles: 4
+    pub fn tick(&mut self) -> Option<(u16, u16)> {
+        let transfer = if self.active {
+            let offset = u16::from(self.index);
+            self.index += 1;
+            self.active = self.index < Self::LENGTH;
+            Some((self.source + offset, offset))
+        } else {
+            None
+        };
+        if self.start_delay > 0 {
+            self.start_delay -= 1;
+            if self.start_delay == 0 {
+                let source = u16::from(self.register) << 8;
+                // above 0xDF the external bus mirrors the WRAM
+                self.source = if source >= 0xE000 {
+                    source - 0x2000
+                } else {
+                    source
+                };
+                self.index = 0;
+                self.active = true;
+            }
+        }
+        transfer
+    }
+
+    /// Called with the byte copied by the transfer returned by `tick`.
+    pub fn set_current(&mut self, value: u8) {
+        self.current = value;
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.put_u8(self.register);
+        state.put_u8(self.start_delay);
+        state.put_u8(self.index);
+        state.put_u16(self.source);
+        state.put_bool(self.active);
+        state.put_u8(self.current);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.register = state.get_u8()?;
+        self.start_delay = state.get_u8()?;
+        self.index = state.get_u8()?;
+        self.source = state.get_u16()?;
+        self.active = state.get_bool()?;
+        self.current = state.get_u8()?;
+        Ok(())
+    }
+}
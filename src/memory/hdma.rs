@@ -0,0 +1,155 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+/// The VRAM DMA of the CGB, copies blocks of 16 bytes from the ROM or the RAM to the VRAM.
+///
+/// Writing HDMA5 starts it, either all at once (general purpose DMA) or one block per HBlank
+/// (HBlank DMA). The CPU is stalled while a copy is in progress, 2 bytes are copied per M-cycle
+/// (1 in double speed).
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Hdma {
+    source: u16,
+    /// Offset in the VRAM, relative to 0x8000.
+    dest: u16,
+    /// Blocks left minus 1, what HDMA5 reads while active.
+    blocks: u8,
+    /// A transfer is started and not done.
+    active: bool,
+    /// Copying one block per HBlank instead of everything at once.
+    hblank: bool,
+    /// Bytes left to copy before the CPU gets the bus back.
+    burst: u16,
+}
+
+impl Default for Hdma {
+    fn default() -> Self {
+        Hdma {
+            source: 0,
+            dest: 0,
+            // reads 0xFF until a transfer is started
+            blocks: 0x7F,
+            active: false,
+            hblank: false,
+            burst: 0,
+        }
+    }
+}
+
+impl Hdma {
+    pub const HDMA1_REGISTER: u16 = 0xFF51;
+    pub const HDMA2_REGISTER: u16 = 0xFF52;
+    pub const HDMA3_REGISTER: u16 = 0xFF53;
+    pub const HDMA4_REGISTER: u16 = 0xFF54;
+    pub const HDMA5_REGISTER: u16 = 0xFF55;
+    pub const BLOCK_SIZE: u16 = 0x10;
+    /// Bytes copied per M-cycle.
+    pub const BYTES_PER_CYCLE: u8 = 2;
+
+    const HBLANK_MODE: u8 = 1 << 7;
+    const VRAM_MASK: u16 = 0x1FF0;
+
+    pub const fn is_register(addr: u16) -> bool {
+        matches!(addr, Self::HDMA1_REGISTER..=Self::HDMA5_REGISTER)
+    }
+
+    /// HDMA1-4 are write only, HDMA5 gives the blocks left, bit 7 set once done or cancelled.
+    pub fn get_register(&self, addr: u16) -> u8 {
+        match addr {
+            Self::HDMA5_REGISTER if self.active => self.blocks,
+            Self::HDMA5_REGISTER => Self::HBLANK_MODE | self.blocks,
+            _ => 0xFF,
+        }
+    }
+
+    /// `lcd_enabled` is whether the PPU runs, with the LCD off an HBlank DMA copies its first block right away.
+    pub fn set_register(&mut self, addr: u16, value: u8, lcd_enabled: bool) {
+        match addr {
+            Self::HDMA1_REGISTER => self.source = (self.source & 0x00FF) | (u16::from(value) << 8),
+            // the low 4 bits are ignored, the blocks are aligned
+            Self::HDMA2_REGISTER => self.source = (self.source & 0xFF00) | u16::from(value & 0xF0),
+            Self::HDMA3_REGISTER => {
+                self.dest = ((self.dest & 0x00FF) | (u16::from(value) << 8)) & Self::VRAM_MASK;
+            }
+            Self::HDMA4_REGISTER => {
+                self.dest = ((self.dest & 0xFF00) | u16::from(value)) & Self::VRAM_MASK;
+            }
+            Self::HDMA5_REGISTER => self.start(value, lcd_enabled),
+            _ => {}
+        }
+    }
+
+    fn start(&mut self, value: u8, lcd_enabled: bool) {
+        if self.active && self.hblank && value & Self::HBLANK_MODE == 0 {
+            // cancels the HBlank DMA, the block in progress is still copied
+            self.active = false;
+            return;
+        }
+        self.blocks = value & !Self::HBLANK_MODE;
+        self.active = true;
+        self.hblank = value & Self::HBLANK_MODE != 0;
+        if !self.hblank {
+            self.burst = (u16::from(self.blocks) + 1) * Self::BLOCK_SIZE;
+        } else if !lcd_enabled {
+            self.burst = Self::BLOCK_SIZE;
+        }
+    }
+
+    /// The PPU entered HBlank, an HBlank DMA copies its next block.
+    pub fn start_hblank(&mut self) {
+        if self.active && self.hblank && self.burst == 0 {
+            self.burst = Self::BLOCK_SIZE;
+        }
+    }
+
+    /// Whether the CPU is stalled by a copy.
+    pub fn is_copying(&self) -> bool {
+        self.burst > 0
+    }
+
+    /// Address to copy from and VRAM offset to copy to of the next byte, if copying.
+    pub fn next_byte(&mut self) -> Option<(u16, u16)> {
+        if self.burst == 0 {
+            return None;
+        }
+        // the upper half of the RAM echoes the WRAM
+        let source = if self.source >= 0xE000 {
+            self.source - 0x2000
+        } else {
+            self.source
+        };
+        let transfer = (source, self.dest);
+        self.source = self.source.wrapping_add(1);
+        // the destination wraps in the VRAM
+        self.dest = (self.dest + 1) & 0x1FFF;
+        self.burst -= 1;
+        if self.burst.is_multiple_of(Self::BLOCK_SIZE) {
+            // a block is done
+            if self.blocks == 0 {
+                self.blocks = 0x7F;
+                self.active = false;
+            } else {
+                self.blocks -= 1;
+            }
+        }
+        Some(transfer)
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.put_u16(self.source);
+        state.put_u16(self.dest);
+        state.put_u8(self.blocks);
+        state.put_bool(self.active);
+        state.put_bool(self.hblank);
+        state.put_u16(self.burst);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.source = state.get_u16()?;
+        self.dest = state.get_u16()?;
+        self.blocks = state.get_u8()?;
+        self.active = state.get_bool()?;
+        self.hblank = state.get_bool()?;
+        self.burst = state.get_u16()?;
+        Ok(())
+    }
+}
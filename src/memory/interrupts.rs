@@ -0,0 +1,79 @@
+/// Interrupt sources, in priority order.
+///
+/// Each one has a bit in the IF (0xFF0F) and IE (0xFFFF) registers:
+///
+/// |7|6|5|4|3|2|1|0|
+/// |-|-|-|-|-|-|-|-|
+/// |1|1|1|Joypad|Serial|Timer|LCD STAT|VBlank|
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Interrupt {
+    /// Requested by the PPU when entering the vertical blank.
+    VBlank,
+    /// Requested by the PPU on the sources selected in the STAT register.
+    LcdStat,
+    /// Requested by the timer when TIMA overflows.
+    Timer,
+    /// Requested by the serial port when a transfer completes.
+    Serial,
+    /// Requested by the joypad when a selected button is pressed.
+    Joypad,
+}
+
+impl Interrupt {
+    /// Sorted by priority, highest first.
+    pub const ALL: [Interrupt; 5] = [
+        Interrupt::VBlank,
+        Interrupt::LcdStat,
+        Interrupt::Timer,
+        Interrupt::Serial,
+        Interrupt::Joypad,
+    ];
+
+    pub const fn get_mask(self) -> u8 {
+        match self {
+            Interrupt::VBlank => 1 << 0,
+            Interrupt::LcdStat => 1 << 1,
+            Interrupt::Timer => 1 << 2,
+            Interrupt::Serial => 1 << 3,
+            Interrupt::Joypad => 1 << 4,
+        }
+    }
+
+    /// Address the CPU jumps to when servicing the interrupt.
+    pub const fn get_vector(self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x0040,
+            Interrupt::LcdStat => 0x0048,
+            Interrupt::Timer => 0x0050,
+            Interrupt::Serial => 0x0058,
+            Interrupt::Joypad => 0x0060,
+        }
+    }
+
+    /// The interrupts set in `bits`, as in IF or IE, by priority.
+    pub fn from_bits(bits: u8) -> Interrupts {
+        Interrupts(bits)
+    }
+
+    /// The interrupt with the highest priority set in `bits`, as in IF or IE.
+    pub fn highest_priority(bits: u8) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|interrupt| bits & interrupt.get_mask() != 0)
+    }
+}
+
+/// Iterator over the interrupts set in IF bits, see `Interrupt::from_bits`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupts(u8);
+
+impl Iterator for Interrupts {
+    type Item = Interrupt;
+
+    fn next(&mut self) -> Option<Interrupt> {
+        let interrupt = Interrupt::highest_priority(self.0)?;
+        self.0 &= !interrupt.get_mask();
+        Some(interrupt)
+    }
+}
@@ -0,0 +1,113 @@
+use crate::{apu::Apu, config::Model, ppu::Ppu, serial::SerialPort};
+
+use super::{
+    boot_rom::BootRom, dma::OamDma, hdma::Hdma, joypad::Joypad, timer::Timer, work_ram::WorkRam,
+    Memory,
+};
+
+/// What is behind an address of the IO area (0xFF00-0xFF7F).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IoRegister {
+    Joypad,
+    SerialData,
+    SerialControl,
+    Div,
+    Tima,
+    Tma,
+    Tac,
+    InterruptFlag,
+    /// The sound registers and the wave RAM.
+    Apu,
+    /// 0xFF40-0xFF4B but DMA.
+    Lcd,
+    OamDma,
+    /// Unmaps the boot ROM, write only.
+    BootRom,
+    /// CGB speed switch.
+    Key1,
+    /// CGB registers of the PPU, VBK and the palettes.
+    CgbPpu,
+    /// CGB VRAM DMA.
+    Hdma,
+    /// CGB WRAM bank.
+    Svbk,
+    /// Nothing is there, reads 0xFF and writes are ignored.
+    Unused,
+}
+
+/// Which component handles each IO register, the CGB ones are unused on the other models.
+///
+/// The components handle the side effects and the unused bits of their registers themselves.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IoRegisters {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::array"))]
+    map: [IoRegister; Self::SIZE],
+}
+
+impl IoRegisters {
+    pub const START: u16 = 0xFF00;
+    pub const END: u16 = 0xFF7F;
+    const SIZE: usize = (Self::END - Self::START + 1) as usize;
+
+    pub fn new(model: Model) -> Self {
+        let mut map = [IoRegister::Unused; Self::SIZE];
+        for (offset, register) in map.iter_mut().enumerate() {
+            let addr = Self::START + offset as u16;
+            *register = Self::get_register(addr, model.is_cgb());
+        }
+        IoRegisters { map }
+    }
+
+    fn get_register(addr: u16, cgb: bool) -> IoRegister {
+        match addr {
+            Joypad::REGISTER => IoRegister::Joypad,
+            SerialPort::DATA_REGISTER => IoRegister::SerialData,
+            SerialPort::CONTROL_REGISTER => IoRegister::SerialControl,
+            Timer::DIV_REGISTER => IoRegister::Div,
+            Timer::TIMA_REGISTER => IoRegister::Tima,
+            Timer::TMA_REGISTER => IoRegister::Tma,
+            Timer::TAC_REGISTER => IoRegister::Tac,
+            Memory::INTERRUPT_FLAG_REGISTER => IoRegister::InterruptFlag,
+            OamDma::REGISTER => IoRegister::OamDma,
+            Ppu::LCDC_REGISTER..=Ppu::WX_REGISTER => IoRegister::Lcd,
+            BootRom::REGISTER => IoRegister::BootRom,
+            _ if Apu::is_register(addr) => IoRegister::Apu,
+            _ if !cgb => IoRegister::Unused,
+            Memory::KEY1_REGISTER => IoRegister::Key1,
+            Ppu::VBK_REGISTER | Ppu::BCPS_REGISTER..=Ppu::OCPD_REGISTER => IoRegister::CgbPpu,
+            _ if Hdma::is_register(addr) => IoRegister::Hdma,
+            WorkRam::SVBK_REGISTER => IoRegister::Svbk,
+            _ => IoRegister::Unused,
+        }
+    }
+
+    /// `addr` must be in 0xFF00-0xFF7F.
+    pub fn get(&self, addr: u16) -> IoRegister {
+        self.map[usize::from(addr - Self::START)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Model;
+
+    use super::{IoRegister, IoRegisters};
+
+    #[test]
+    fn model_registers() {
+        let dmg = IoRegisters::new(Model::Dmg);
+        let cgb = IoRegisters::new(Model::Cgb);
+        assert_eq!(dmg.get(0xFF00), IoRegister::Joypad);
+        assert_eq!(dmg.get(0xFF46), IoRegister::OamDma);
+        assert_eq!(dmg.get(0xFF45), IoRegister::Lcd);
+        assert_eq!(dmg.get(0xFF26), IoRegister::Apu);
+        assert_eq!(dmg.get(0xFF03), IoRegister::Unused);
+        assert_eq!(dmg.get(0xFF4F), IoRegister::Unused);
+        assert_eq!(cgb.get(0xFF4F), IoRegister::CgbPpu);
+        assert_eq!(cgb.get(0xFF55), IoRegister::Hdma);
+        assert_eq!(cgb.get(0xFF70), IoRegister::Svbk);
+        assert_eq!(cgb.get(0xFF7F), IoRegister::Unused);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Right,
+        Button::Left,
+        Button::Up,
+        Button::Down,
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+    ];
+
+    /// Bit in the pressed buttons, directions in the low nibble, in P1 order.
+    pub const fn get_mask(self) -> u8 {
+        match self {
+            Button::Right => 1 << 0,
+            Button::Left => 1 << 1,
+            Button::Up => 1 << 2,
+            Button::Down => 1 << 3,
+            Button::A => 1 << 4,
+            Button::B => 1 << 5,
+            Button::Select => 1 << 6,
+            Button::Start => 1 << 7,
+        }
+    }
+}
+
+/// The P1/JOYP register (0xFF00).
+///
+/// The buttons are a 2x4 matrix, bits 4 and 5 select the directions or the action buttons
+/// (0 selects), and the low nibble reads the selected lines, 0 when pressed.
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Joypad {
+    /// Bits 4-5 of P1.
+    select: u8,
+    pressed: u8,
+}
+
+impl Joypad {
+    pub const REGISTER: u16 = 0xFF00;
+
+    const SELECT_DIRECTIONS: u8 = 1 << 4;
+    const SELECT_BUTTONS: u8 = 1 << 5;
+    const SELECT_MASK: u8 = Self::SELECT_DIRECTIONS | Self::SELECT_BUTTONS;
+
+    pub fn get_register(&self) -> u8 {
+        // unused bits read as 1
+        0xC0 | self.select | self.get_lines()
+    }
+
+    /// Returns true to request the joypad interrupt.
+    pub fn set_register(&mut self, value: u8) -> bool {
+        let lines = self.get_lines();
+        self.select = value & Self::SELECT_MASK;
+        self.is_falling_edge(lines)
+    }
+
+    /// Every pressed button, as `Button::get_mask` bits.
+    pub fn get_pressed(&self) -> u8 {
+        self.pressed
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.pressed & button.get_mask() != 0
+    }
+
+    /// Returns true to request the joypad interrupt.
+    pub fn set_button(&mut self, button: Button, pressed: bool) -> bool {
+        let lines = self.get_lines();
+        if pressed {
+            self.pressed |= button.get_mask();
+        } else {
+            self.pressed &= !button.get_mask();
+        }
+        self.is_falling_edge(lines)
+    }
+
+    /// Release every button, releasing never requests the interrupt.
+    pub fn release_all(&mut self) {
+        self.pressed = 0;
+    }
+
+    /// The low nibble of P1, a line is low if a button of a selected row is pressed on it.
+    fn get_lines(&self) -> u8 {
+        let mut pressed = 0;
+        if self.select & Self::SELECT_DIRECTIONS == 0 {
+            pressed |= self.pressed & 0x0F;
+        }
+        if self.select & Self::SELECT_BUTTONS == 0 {
+            pressed |= self.pressed >> 4;
+        }
+        !pressed & 0x0F
+    }
+
+    /// The interrupt is requested when any line goes from high to low.
+    fn is_falling_edge(&self, old_lines: u8) -> bool {
+        old_lines & !self.get_lines() != 0
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.put_u8(self.select);
+        state.put_u8(self.pressed);
+    }
+
+    /// The buttons held come back with the state, see `Emulator::save_state`.
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.select = state.get_u8()? & Self::SELECT_MASK;
+        self.pressed = state.get_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Button, Joypad};
+
+    #[test]
+    fn matrix() {
+        let mut joypad = Joypad::default();
+        // nothing selected, nothing pressed, no interrupt
+        joypad.set_register(0x30);
+        assert!(!joypad.set_button(Button::A, true));
+        assert_eq!(joypad.get_register(), 0xFF);
+
+        // selecting the buttons with A held is a falling edge
+        assert!(joypad.set_register(0x10));
+        assert_eq!(joypad.get_register(), 0xDE);
+        assert!(!joypad.set_button(Button::Up, true));
+        assert!(joypad.set_button(Button::Start, true));
+        assert_eq!(joypad.get_register(), 0xD6);
+
+        joypad.set_register(0x20);
+        assert_eq!(joypad.get_register(), 0xEB);
+        joypad.set_button(Button::Up, false);
+        assert_eq!(joypad.get_register(), 0xEF);
+    }
+}
@@ -0,0 +1,207 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+use super::{load_save_ram, read_ram_bank, read_rom_bank, write_ram_bank, Mbc, ROM_BANK_SIZE};
+
+/// MBC1, up to 2MB of ROM (128 banks) and 32KB of RAM (4 banks).
+///
+/// The 2 bits register is either the upper bits of the ROM bank or the RAM bank,
+/// in mode 1 it also applies to the 0x0000-0x3FFF area.
+///
+/// MBC1M multicarts (collection cartridges) wire the 2 bits register one bit lower,
+/// so each game sees 16 banks, the 0x0000-0x3FFF area in mode 1 is used to boot them.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mbc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    /// 5 bits
+    rom_bank: u8,
+    /// 2 bits
+    upper_bank: u8,
+    /// Advanced banking mode
+    mode: bool,
+    multicart: bool,
+}
+
+impl Mbc1 {
+    /// The Nintendo logo of the header, checked by the boot ROM.
+    const LOGO_START: usize = 0x0104;
+    const LOGO_END: usize = 0x0134;
+
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        let multicart = Self::is_multicart(&rom);
+        Mbc1 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            upper_bank: 0,
+            mode: false,
+            multicart,
+        }
+    }
+
+    /// Multicarts are 1MB ROMs where each game, 256KB apart, has its own header.
+    ///
+    /// The menu is the first game, so the logo is also searched in the other ones.
+    pub fn is_multicart(rom: &[u8]) -> bool {
+        if rom.len() != 64 * ROM_BANK_SIZE {
+            return false;
+        }
+        let logo = &rom[Self::LOGO_START..Self::LOGO_END];
+        let games = (1..4)
+            .map(|game| game * 0x10 * ROM_BANK_SIZE)
+            .filter(|start| &rom[start + Self::LOGO_START..start + Self::LOGO_END] == logo)
+            .count();
+        games >= 2
+    }
+
+    pub fn is_multicart_wiring(&self) -> bool {
+        self.multicart
+    }
+
+    fn upper_shift(&self) -> u8 {
+        if self.multicart {
+            4
+        } else {
+            5
+        }
+    }
+
+    fn get_rom_bank(&self, addr: u16) -> usize {
+        let upper = self.upper_bank << self.upper_shift();
+        let bank = if addr < 0x4000 {
+            if self.mode {
+                upper
+            } else {
+                0
+            }
+        } else if self.multicart {
+            // bit 4 of the bank register is not connected
+            upper | (self.rom_bank & 0x0F)
+        } else {
+            upper | self.rom_bank
+        };
+        bank.into()
+    }
+
+    fn get_ram_bank(&self) -> usize {
+        if self.mode {
+            self.upper_bank.into()
+        } else {
+            0
+        }
+    }
+}
+
+impl Mbc for Mbc1 {
+    #[cfg(feature = "serde")]
+    fn as_builtin(&self) -> Option<super::BuiltinMbc<'_>> {
+        Some(super::BuiltinMbc::Mbc1(self))
+    }
+
+    fn read_rom(&self, addr: u16) -> u8 {
+        read_rom_bank(&self.rom, self.get_rom_bank(addr), addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                // the check is on the 5 bits, so bank 0x20 maps 0x21
+                let bank = value & 0x1F;
+                self.rom_bank = if bank == 0 { 1 } else { bank };
+            }
+            0x4000..=0x5FFF => self.upper_bank = value & 0b11,
+            0x6000..=0x7FFF => self.mode = value & 1 != 0,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if self.ram_enabled {
+            read_ram_bank(&self.ram, self.get_ram_bank(), addr)
+        } else {
+            0xFF
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if self.ram_enabled {
+            let bank = self.get_ram_bank();
+            write_ram_bank(&mut self.ram, bank, addr, value);
+        }
+    }
+
+    fn get_save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_save_ram(&mut self.ram, data);
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.put_bool(self.ram_enabled);
+        state.put_u8(self.rom_bank);
+        state.put_u8(self.upper_bank);
+        state.put_bool(self.mode);
+        state.put_bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.ram_enabled = state.get_bool()?;
+        self.rom_bank = state.get_u8()?;
+        self.upper_bank = state.get_u8()?;
+        self.mode = state.get_bool()?;
+        state.get_bytes_into(&mut self.ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::mbc::{Mbc, ROM_BANK_SIZE};
+
+    use super::Mbc1;
+
+    // 64 banks, each filled with its number, with a fake logo at the start of each game
+    fn rom(games: &[usize]) -> Vec<u8> {
+        let mut rom: Vec<u8> = (0..64)
+            .flat_map(|bank| vec![bank as u8; ROM_BANK_SIZE])
+            .collect();
+        for game in games {
+            let start = game * 0x10 * ROM_BANK_SIZE;
+            rom[start + Mbc1::LOGO_START..start + Mbc1::LOGO_END].fill(0xCE);
+        }
+        rom
+    }
+
+    #[test]
+    fn rom_banking() {
+        let mut mbc = Mbc1::new(rom(&[0]), 0);
+        assert!(!mbc.is_multicart_wiring());
+        mbc.write_rom(0x2000, 0);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_rom(0x2000, 0x05);
+        mbc.write_rom(0x4000, 1);
+        assert_eq!(mbc.read_rom(0x4000), 0x25);
+        assert_eq!(mbc.read_rom(0x0000), 0);
+        mbc.write_rom(0x6000, 1);
+        assert_eq!(mbc.read_rom(0x0000), 0x20);
+    }
+
+    #[test]
+    fn multicart_banking() {
+        let mut mbc = Mbc1::new(rom(&[0, 1, 2, 3]), 0);
+        assert!(mbc.is_multicart_wiring());
+        // boot the third game
+        mbc.write_rom(0x6000, 1);
+        mbc.write_rom(0x4000, 2);
+        assert_eq!(mbc.read_rom(0x0000), 0x20);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+        // bit 4 is ignored
+        mbc.write_rom(0x2000, 0x13);
+        assert_eq!(mbc.read_rom(0x4000), 0x23);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+use super::{load_save_ram, read_rom_bank, Mbc};
+
+/// MBC2, up to 256KB of ROM (16 banks) and a built-in RAM of 512 half-bytes.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mbc2 {
+    rom: Vec<u8>,
+    /// Only the lower nibble of each byte is stored.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::array"))]
+    ram: [u8; Self::RAM_SIZE],
+    ram_enabled: bool,
+    /// 4 bits
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    pub const RAM_SIZE: usize = 512;
+
+    pub fn new(rom: Vec<u8>) -> Self {
+        Mbc2 {
+            rom,
+            ram: [0; Self::RAM_SIZE],
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+
+    /// The RAM only has 9 address lines, it is repeated over the whole 0xA000-0xBFFF area.
+    fn ram_index(addr: u16) -> usize {
+        addr as usize & (Self::RAM_SIZE - 1)
+    }
+}
+
+impl Mbc for Mbc2 {
+    #[cfg(feature = "serde")]
+    fn as_builtin(&self) -> Option<super::BuiltinMbc<'_>> {
+        Some(super::BuiltinMbc::Mbc2(self))
+    }
+
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = if addr < 0x4000 { 0 } else { self.rom_bank };
+        read_rom_bank(&self.rom, bank.into(), addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        if addr >= 0x4000 {
+            return;
+        }
+        // both registers share the same area, bit 8 of the address selects which one is written
+        if addr & 0x0100 == 0 {
+            self.ram_enabled = value & 0x0F == 0x0A;
+        } else {
+            self.rom_bank = match value & 0x0F {
+                0 => 1,
+                bank => bank,
+            };
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if self.ram_enabled {
+            // the upper nibble is not connected and reads as 1s
+            self.ram[Self::ram_index(addr)] | 0xF0
+        } else {
+            0xFF
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if self.ram_enabled {
+            self.ram[Self::ram_index(addr)] = value & 0x0F;
+        }
+    }
+
+    fn get_save_data(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_save_ram(&mut self.ram, data);
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.put_bool(self.ram_enabled);
+        state.put_u8(self.rom_bank);
+        state.put_bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.ram_enabled = state.get_bool()?;
+        self.rom_bank = state.get_u8()?;
+        state.get_bytes_into(&mut self.ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::mbc::{Mbc, ROM_BANK_SIZE};
+
+    use super::Mbc2;
+
+    #[test]
+    fn registers_selected_by_address_bit_8() {
+        let rom = (0..16).flat_map(|bank| vec![bank; ROM_BANK_SIZE]).collect();
+        let mut mbc = Mbc2::new(rom);
+
+        // bit 8 clear: RAM enable, the ROM bank doesn't change
+        mbc.write_rom(0x0000, 0x0A);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_ram(0xA000, 0x05);
+        assert_eq!(mbc.read_ram(0xA000), 0xF5);
+
+        // bit 8 set: ROM bank
+        mbc.write_rom(0x2100, 0x0A);
+        assert_eq!(mbc.read_rom(0x4000), 0x0A);
+        assert_eq!(mbc.read_ram(0xA000), 0xF5);
+        mbc.write_rom(0x3F00, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+
+        mbc.write_rom(0x1000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn half_byte_ram_is_mirrored() {
+        let mut mbc = Mbc2::new(vec![0; 2 * ROM_BANK_SIZE]);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA1FF, 0xAB);
+        assert_eq!(mbc.read_ram(0xA1FF), 0xFB);
+        assert_eq!(mbc.read_ram(0xA3FF), 0xFB);
+        assert_eq!(mbc.read_ram(0xBFFF), 0xFB);
+    }
+}
@@ -0,0 +1,568 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+use super::{load_save_ram, read_ram_bank, read_rom_bank, write_ram_bank, Mbc};
+
+/// Time of the RTC, as in its registers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RtcTime {
+    /// 9 bits
+    pub days: u16,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+}
+
+/// Where the time of the RTC comes from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RtcMode {
+    /// Only emulated cycles make the clock advance,
+    /// the time spent with the emulator closed doesn't count.
+    #[default]
+    Emulated,
+    /// Follow the host clock: the time spent since the save was written is caught up on load,
+    /// or the time of day is taken from the host if there is no save.
+    /// It then advances with emulated cycles.
+    HostClock,
+}
+
+/// Real Time Clock of the MBC3, clocked by its own 32768Hz crystal.
+///
+/// Besides what the game sees, it can be driven from outside:
+/// set the time, freeze it, or make it run faster,
+/// to trigger time based events or test the day rollover.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rtc {
+    time: RtcTime,
+    /// Set when the day counter overflows, until the game clears it.
+    day_carry: bool,
+    /// Halt bit of the registers, controlled by the game.
+    halted: bool,
+    /// Copy of the registers read by the game, updated by the latch.
+    latched: [u8; 5],
+    /// Clock cycles since the last second.
+    cycles: u64,
+    /// Stopped from outside, unlike `halted` the game doesn't see it.
+    frozen: bool,
+    /// Clock seconds per emulated second.
+    speed: u32,
+    mode: RtcMode,
+}
+
+impl Default for Rtc {
+    fn default() -> Self {
+        Rtc {
+            time: RtcTime::default(),
+            day_carry: false,
+            halted: false,
+            latched: [0; 5],
+            cycles: 0,
+            frozen: false,
+            speed: 1,
+            mode: RtcMode::default(),
+        }
+    }
+}
+
+impl Rtc {
+    const CYCLES_PER_SECOND: u64 = 4_194_304;
+    const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+    /// Size of the RTC footer of `.sav` files, in the format used by most emulators:
+    /// the 5 registers then the 5 latched ones, each as a 32-bit little endian value,
+    /// and the 64-bit UNIX timestamp of the save.
+    pub const SAVE_SIZE: usize = 48;
+    const TIMESTAMP_SIZE: usize = 8;
+
+    const SECONDS: u8 = 0x08;
+    const MINUTES: u8 = 0x09;
+    const HOURS: u8 = 0x0A;
+    const DAYS_LOW: u8 = 0x0B;
+    const DAYS_HIGH: u8 = 0x0C;
+
+    const DAY_HIGH_MASK: u8 = 0x01;
+    const HALT_MASK: u8 = 0x40;
+    const DAY_CARRY_MASK: u8 = 0x80;
+
+    pub fn get_time(&self) -> RtcTime {
+        self.time
+    }
+
+    /// Out of range values are masked to the size of the registers, like if the game wrote them.
+    pub fn set_time(&mut self, time: RtcTime) {
+        self.time = RtcTime {
+            days: time.days & 0x1FF,
+            hours: time.hours & 0x1F,
+            minutes: time.minutes & 0x3F,
+            seconds: time.seconds & 0x3F,
+        };
+        self.cycles = 0;
+    }
+
+    pub fn get_day_carry(&self) -> bool {
+        self.day_carry
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Stop the clock, the game can still read and write it.
+    pub fn set_frozen(&mut self, frozen: bool) {
+        self.frozen = frozen;
+    }
+
+    pub fn get_speed(&self) -> u32 {
+        self.speed
+    }
+
+    /// Run the clock `speed` times faster than the emulation, 1 is the normal speed.
+    pub fn set_speed(&mut self, speed: u32) {
+        self.speed = speed;
+    }
+
+    pub fn get_mode(&self) -> RtcMode {
+        self.mode
+    }
+
+    /// Switching to `RtcMode::HostClock` takes the time of day from the host (UTC), keeping the days.
+    pub fn set_mode(&mut self, mode: RtcMode) {
+        self.mode = mode;
+        if mode == RtcMode::HostClock {
+            let time_of_day = unix_time() % Self::SECONDS_PER_DAY;
+            self.time.hours = (time_of_day / 3600) as u8;
+            self.time.minutes = (time_of_day / 60 % 60) as u8;
+            self.time.seconds = (time_of_day % 60) as u8;
+            self.cycles = 0;
+        }
+    }
+
+    /// Move the clock forward, even if halted or frozen.
+    pub fn advance(&mut self, mut seconds: u64) {
+        // out of range values must tick up to their wrap
+        while seconds > 0 && !self.is_in_range() {
+            self.tick_second();
+            seconds -= 1;
+        }
+        let time = &mut self.time;
+        let total = u64::from(time.seconds)
+            + u64::from(time.minutes) * 60
+            + u64::from(time.hours) * 3600
+            + u64::from(time.days) * Self::SECONDS_PER_DAY
+            + seconds;
+        let days = total / Self::SECONDS_PER_DAY;
+        if days > 0x1FF {
+            self.day_carry = true;
+        }
+        time.days = (days & 0x1FF) as u16;
+        time.hours = (total / 3600 % 24) as u8;
+        time.minutes = (total / 60 % 60) as u8;
+        time.seconds = (total % 60) as u8;
+    }
+
+    fn is_in_range(&self) -> bool {
+        self.time.seconds < 60 && self.time.minutes < 60 && self.time.hours < 24
+    }
+
+    fn step(&mut self, cycles: u32) {
+        if self.halted || self.frozen {
+            return;
+        }
+        self.cycles += u64::from(cycles) * u64::from(self.speed);
+        while self.cycles >= Self::CYCLES_PER_SECOND {
+            self.cycles -= Self::CYCLES_PER_SECOND;
+            self.tick_second();
+        }
+    }
+
+    /// Each counter wraps at the size of its register,
+    /// so out of range values count up to the wrap without carrying.
+    fn tick_second(&mut self) {
+        let time = &mut self.time;
+        time.seconds = (time.seconds + 1) & 0x3F;
+        if time.seconds != 60 {
+            return;
+        }
+        time.seconds = 0;
+        time.minutes = (time.minutes + 1) & 0x3F;
+        if time.minutes != 60 {
+            return;
+        }
+        time.minutes = 0;
+        time.hours = (time.hours + 1) & 0x1F;
+        if time.hours != 24 {
+            return;
+        }
+        time.hours = 0;
+        time.days = (time.days + 1) & 0x1FF;
+        if time.days == 0 {
+            self.day_carry = true;
+        }
+    }
+
+    fn get_registers(&self) -> [u8; 5] {
+        let [days_high, days_low] = self.time.days.to_be_bytes();
+        let mut flags = days_high & Self::DAY_HIGH_MASK;
+        if self.halted {
+            flags |= Self::HALT_MASK;
+        }
+        if self.day_carry {
+            flags |= Self::DAY_CARRY_MASK;
+        }
+        [
+            self.time.seconds,
+            self.time.minutes,
+            self.time.hours,
+            days_low,
+            flags,
+        ]
+    }
+
+    fn latch(&mut self) {
+        self.latched = self.get_registers();
+    }
+
+    fn read(&self, register: u8) -> u8 {
+        let value = self.latched[usize::from(register - Self::SECONDS)];
+        // unused bits read as 1
+        match register {
+            Self::SECONDS | Self::MINUTES => value | 0xC0,
+            Self::HOURS => value | 0xE0,
+            Self::DAYS_HIGH => value | 0x3E,
+            _ => value,
+        }
+    }
+
+    fn write(&mut self, register: u8, value: u8) {
+        match register {
+            Self::SECONDS => {
+                self.time.seconds = value & 0x3F;
+                // writing the seconds resets the sub-second counter
+                self.cycles = 0;
+            }
+            Self::MINUTES => self.time.minutes = value & 0x3F,
+            Self::HOURS => self.time.hours = value & 0x1F,
+            Self::DAYS_LOW => self.time.days = (self.time.days & 0x100) | u16::from(value),
+            Self::DAYS_HIGH => {
+                let high = u16::from(value & Self::DAY_HIGH_MASK) << 8;
+                self.time.days = (self.time.days & 0xFF) | high;
+                self.halted = value & Self::HALT_MASK != 0;
+                self.day_carry = value & Self::DAY_CARRY_MASK != 0;
+            }
+            _ => {}
+        }
+        // the registers the game reads are updated too
+        self.latched[usize::from(register - Self::SECONDS)] =
+            self.get_registers()[usize::from(register - Self::SECONDS)];
+    }
+
+    /// `.sav` footer, with the timestamp left at 0 so the data only changes with the clock,
+    /// `stamp_save_data` sets it when the file is written.
+    fn get_save_data(&self) -> Vec<u8> {
+        let registers = self.get_registers().into_iter();
+        let latched = self.latched.into_iter();
+        let mut data: Vec<u8> = registers
+            .chain(latched)
+            .flat_map(|value| u32::from(value).to_le_bytes())
+            .collect();
+        data.extend_from_slice(&[0; Self::TIMESTAMP_SIZE]);
+        data
+    }
+
+    /// Set the timestamp of the RTC footer ending `data` to now,
+    /// for `RtcMode::HostClock` to catch up from there on load.
+    pub fn stamp_save_data(data: &mut [u8]) {
+        if let Some(start) = data.len().checked_sub(Self::TIMESTAMP_SIZE) {
+            data[start..].copy_from_slice(&unix_time().to_le_bytes());
+        }
+    }
+
+    /// Reset the timestamp of the RTC footer ending `data`, as in `get_save_data`.
+    pub fn clear_save_stamp(data: &mut [u8]) {
+        if let Some(start) = data.len().checked_sub(Self::TIMESTAMP_SIZE) {
+            data[start..].fill(0);
+        }
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        let value = |i: usize| data[i * 4];
+        for (i, register) in (Self::SECONDS..=Self::DAYS_HIGH).enumerate() {
+            self.write(register, value(i));
+        }
+        for (i, latched) in self.latched.iter_mut().enumerate() {
+            *latched = value(i + 5);
+        }
+        let saved_at = u64::from_le_bytes(data[40..48].try_into().unwrap());
+        // a halted clock doesn't count the time spent closed either,
+        // and unstamped saves don't say when they were made
+        if self.mode == RtcMode::HostClock && !self.halted && saved_at != 0 {
+            self.advance(unix_time().saturating_sub(saved_at));
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        for value in self.get_registers() {
+            state.put_u8(value);
+        }
+        for value in self.latched {
+            state.put_u8(value);
+        }
+        state.put_u64(self.cycles);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        for register in Self::SECONDS..=Self::DAYS_HIGH {
+            self.write(register, state.get_u8()?);
+        }
+        for value in self.latched.iter_mut() {
+            *value = state.get_u8()?;
+        }
+        self.cycles = state.get_u64()?;
+        Ok(())
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+/// MBC3, up to 2MB of ROM (128 banks), 32KB of RAM (4 banks) and an optional RTC.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mbc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    /// 7 bits
+    rom_bank: u8,
+    /// RAM bank 0-3 or RTC register 0x08-0x0C
+    ram_bank: u8,
+    rtc: Option<Rtc>,
+    /// The latch is done by writing 0x00 then 0x01.
+    last_latch_write: u8,
+}
+
+impl Mbc3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rtc: bool) -> Self {
+        Mbc3 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rtc: has_rtc.then(Rtc::default),
+            last_latch_write: 0xFF,
+        }
+    }
+
+    fn selected_rtc_register(&self) -> Option<u8> {
+        matches!(self.ram_bank, Rtc::SECONDS..=Rtc::DAYS_HIGH).then_some(self.ram_bank)
+    }
+}
+
+impl Mbc for Mbc3 {
+    #[cfg(feature = "serde")]
+    fn as_builtin(&self) -> Option<super::BuiltinMbc<'_>> {
+        Some(super::BuiltinMbc::Mbc3(self))
+    }
+
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = if addr < 0x4000 { 0 } else { self.rom_bank };
+        read_rom_bank(&self.rom, bank.into(), addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                let bank = value & 0x7F;
+                self.rom_bank = if bank == 0 { 1 } else { bank };
+            }
+            0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+            0x6000..=0x7FFF => {
+                if self.last_latch_write == 0x00 && value == 0x01 {
+                    if let Some(rtc) = &mut self.rtc {
+                        rtc.latch();
+                    }
+                }
+                self.last_latch_write = value;
+            }
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match (self.selected_rtc_register(), &self.rtc) {
+            (Some(register), Some(rtc)) => rtc.read(register),
+            (Some(_), None) => 0xFF,
+            (None, _) => read_ram_bank(&self.ram, usize::from(self.ram_bank & 0b11), addr),
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        match (self.selected_rtc_register(), &mut self.rtc) {
+            (Some(register), Some(rtc)) => rtc.write(register, value),
+            (Some(_), None) => {}
+            (None, _) => {
+                let bank = usize::from(self.ram_bank & 0b11);
+                write_ram_bank(&mut self.ram, bank, addr, value);
+            }
+        }
+    }
+
+    fn step(&mut self, cycles: u32) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.step(cycles);
+        }
+    }
+
+    fn rtc(&mut self) -> Option<&mut Rtc> {
+        self.rtc.as_mut()
+    }
+
+    /// The RAM, followed by the RTC if there is one.
+    fn get_save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        if let Some(rtc) = &self.rtc {
+            data.extend(rtc.get_save_data());
+        }
+        data
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_save_ram(&mut self.ram, data);
+        if let (Some(rtc), Some(footer)) = (&mut self.rtc, data.get(self.ram.len()..)) {
+            // saves without the RTC just start from where it is
+            if footer.len() == Rtc::SAVE_SIZE {
+                rtc.load_save_data(footer);
+            }
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.put_bool(self.ram_enabled);
+        state.put_u8(self.rom_bank);
+        state.put_u8(self.ram_bank);
+        state.put_u8(self.last_latch_write);
+        state.put_bytes(&self.ram);
+        if let Some(rtc) = &self.rtc {
+            rtc.save_state(state);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.ram_enabled = state.get_bool()?;
+        self.rom_bank = state.get_u8()?;
+        self.ram_bank = state.get_u8()?;
+        self.last_latch_write = state.get_u8()?;
+        state.get_bytes_into(&mut self.ram)?;
+        if let Some(rtc) = &mut self.rtc {
+            rtc.load_state(state)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::mbc::Mbc;
+
+    use super::{Mbc3, Rtc, RtcMode, RtcTime};
+
+    fn read_rtc(mbc: &mut Mbc3, register: u8) -> u8 {
+        mbc.write_rom(0x4000, register);
+        mbc.read_ram(0xA000)
+    }
+
+    #[test]
+    fn latch_and_read() {
+        let mut mbc = Mbc3::new(vec![0; 0x8000], 0, true);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.step(4 * Rtc::CYCLES_PER_SECOND as u32 - 4);
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 0xC0 | 3);
+
+        // the latched value doesn't move
+        mbc.step(4);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 0xC0 | 3);
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 0xC0 | 4);
+    }
+
+    #[test]
+    fn external_controls() {
+        let mut mbc = Mbc3::new(vec![0; 0x8000], 0, true);
+        let rtc = mbc.rtc().unwrap();
+        rtc.set_time(RtcTime {
+            days: 0x1FF,
+            hours: 23,
+            minutes: 59,
+            seconds: 59,
+        });
+        rtc.advance(1);
+        assert_eq!(rtc.get_time(), RtcTime::default());
+        assert!(rtc.get_day_carry());
+
+        rtc.set_frozen(true);
+        mbc.step(Rtc::CYCLES_PER_SECOND as u32);
+        assert_eq!(mbc.rtc().unwrap().get_time().seconds, 0);
+
+        let rtc = mbc.rtc().unwrap();
+        rtc.set_frozen(false);
+        rtc.set_speed(60);
+        mbc.step(Rtc::CYCLES_PER_SECOND as u32);
+        assert_eq!(mbc.rtc().unwrap().get_time().minutes, 1);
+    }
+
+    #[test]
+    fn host_clock_catches_up_on_load() {
+        let mut mbc = Mbc3::new(vec![0; 0x8000], 0x2000, true);
+        mbc.rtc().unwrap().set_time(RtcTime {
+            days: 1,
+            hours: 12,
+            minutes: 0,
+            seconds: 0,
+        });
+        let mut save = mbc.get_save_data();
+        assert_eq!(save.len(), 0x2000 + Rtc::SAVE_SIZE);
+        // the data doesn't move with the host clock, only the written file is stamped
+        assert_eq!(save, mbc.get_save_data());
+        Rtc::stamp_save_data(&mut save);
+        // saved a day and an hour ago
+        let timestamp = save.len() - 8;
+        let saved_at = u64::from_le_bytes(save[timestamp..].try_into().unwrap());
+        save[timestamp..].copy_from_slice(&(saved_at - 25 * 3600).to_le_bytes());
+
+        let mut emulated = Mbc3::new(vec![0; 0x8000], 0x2000, true);
+        emulated.load_save_data(&save);
+        assert_eq!(emulated.rtc().unwrap().get_time().days, 1);
+
+        let mut synced = Mbc3::new(vec![0; 0x8000], 0x2000, true);
+        synced.rtc().unwrap().set_mode(RtcMode::HostClock);
+        synced.load_save_data(&save);
+        let time = synced.rtc().unwrap().get_time();
+        assert_eq!((time.days, time.hours), (2, 13));
+
+        // halted by the game when saved
+        let mut halted = Mbc3::new(vec![0; 0x8000], 0x2000, true);
+        halted.rtc().unwrap().set_mode(RtcMode::HostClock);
+        let days_high = 0x2000 + 4 * 4;
+        save[days_high] |= Rtc::HALT_MASK;
+        halted.load_save_data(&save);
+        let time = halted.rtc().unwrap().get_time();
+        assert_eq!((time.days, time.hours), (1, 12));
+    }
+}
@@ -0,0 +1,204 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+use super::{load_save_ram, read_ram_bank, read_rom_bank, write_ram_bank, Mbc};
+
+/// MBC5, up to 8MB of ROM (512 banks) and 128KB of RAM (16 banks).
+///
+/// Unlike MBC1 and MBC3 the ROM bank 0 can be mapped in the switchable area.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mbc5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    /// 9 bits
+    rom_bank: u16,
+    /// 4 bits
+    ram_bank: u8,
+    /// Rumble cartridges use bit 3 of the RAM bank register to drive the motor.
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl Mbc5 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rumble: bool) -> Self {
+        Mbc5 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble: false,
+        }
+    }
+
+    pub fn is_rumbling(&self) -> bool {
+        self.rumble
+    }
+}
+
+impl Mbc for Mbc5 {
+    #[cfg(feature = "serde")]
+    fn as_builtin(&self) -> Option<super::BuiltinMbc<'_>> {
+        Some(super::BuiltinMbc::Mbc5(self))
+    }
+
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = if addr < 0x4000 { 0 } else { self.rom_bank };
+        read_rom_bank(&self.rom, bank.into(), addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = value == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | u16::from(value),
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | (u16::from(value & 1) << 8),
+            0x4000..=0x5FFF => {
+                if self.has_rumble {
+                    self.rumble = value & 0b1000 != 0;
+                    self.ram_bank = value & 0b0111;
+                } else {
+                    self.ram_bank = value & 0b1111;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if self.ram_enabled {
+            read_ram_bank(&self.ram, self.ram_bank.into(), addr)
+        } else {
+            0xFF
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if self.ram_enabled {
+            write_ram_bank(&mut self.ram, self.ram_bank.into(), addr, value);
+        }
+    }
+
+    fn get_save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_save_ram(&mut self.ram, data);
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.put_bool(self.ram_enabled);
+        state.put_u16(self.rom_bank);
+        state.put_u8(self.ram_bank);
+        state.put_bool(self.rumble);
+        state.put_bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.ram_enabled = state.get_bool()?;
+        self.rom_bank = state.get_u16()?;
+        self.ram_bank = state.get_u8()?;
+        self.rumble = state.get_bool()?;
+        state.get_bytes_into(&mut self.ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        memory::mbc::{Mbc, RAM_BANK_SIZE, ROM_BANK_SIZE},
+        savestate::{StateReader, StateWriter},
+    };
+
+    use super::Mbc5;
+
+    fn mbc() -> Mbc5 {
+        // 512 banks, each filled with the low byte of its number, except the first byte with the high bit
+        let rom = (0..512)
+            .flat_map(|bank: usize| {
+                let mut data = vec![bank as u8; ROM_BANK_SIZE];
+                data[0] = (bank >> 8) as u8;
+                data
+            })
+            .collect();
+        Mbc5::new(rom, RAM_BANK_SIZE * 16, false)
+    }
+
+    #[test]
+    fn rom_banking() {
+        let mut mbc = mbc();
+        assert_eq!(mbc.read_rom(0x4001), 1);
+
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4001), 0);
+
+        mbc.write_rom(0x2000, 0x42);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        assert_eq!(mbc.read_rom(0x4001), 0x42);
+
+        mbc.write_rom(0x3000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0);
+        assert_eq!(mbc.read_rom(0x7FFF), 0x42);
+        assert_eq!(mbc.read_rom(0x0001), 0);
+    }
+
+    #[test]
+    fn ram_banking() {
+        let mut mbc = mbc();
+        mbc.write_ram(0xA000, 0x12);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+        mbc.write_rom(0x0000, 0x0A);
+        for bank in 0..16 {
+            mbc.write_rom(0x4000, bank);
+            mbc.write_ram(0xA123, bank + 1);
+        }
+        for bank in 0..16 {
+            mbc.write_rom(0x4000, bank);
+            assert_eq!(mbc.read_ram(0xA123), bank + 1);
+        }
+
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0xA123), 0xFF);
+    }
+
+    #[test]
+    fn every_ram_bank_is_saved() {
+        let mut mbc = mbc();
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x11);
+        mbc.write_rom(0x4000, 0x03);
+        mbc.write_ram(0xA000, 0x33);
+        mbc.write_rom(0x4000, 0x0F);
+        mbc.write_ram(0xBFFF, 0xFF);
+
+        let check = |mbc: &mut Mbc5| {
+            mbc.write_rom(0x0000, 0x0A);
+            mbc.write_rom(0x4000, 0x00);
+            assert_eq!(mbc.read_ram(0xA000), 0x11);
+            mbc.write_rom(0x4000, 0x03);
+            assert_eq!(mbc.read_ram(0xA000), 0x33);
+            mbc.write_rom(0x4000, 0x0F);
+            assert_eq!(mbc.read_ram(0xBFFF), 0xFF);
+        };
+
+        // .sav
+        let save = mbc.get_save_data();
+        assert_eq!(save.len(), RAM_BANK_SIZE * 16);
+        let mut loaded = self::mbc();
+        loaded.load_save_data(&save);
+        check(&mut loaded);
+
+        // savestate, the mapped bank is restored too
+        let mut state = StateWriter::new();
+        mbc.save_state(&mut state);
+        let state = state.into_inner();
+        let mut loaded = self::mbc();
+        loaded.load_state(&mut StateReader::new(&state)).unwrap();
+        assert_eq!(loaded.read_ram(0xBFFF), 0xFF);
+        check(&mut loaded);
+    }
+}
@@ -0,0 +1,541 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+use super::{read_rom_bank, Mbc};
+
+/// Two-axis accelerometer of the MBC7.
+///
+/// Tilt is given in g, each axis reads as `0x81D0 + tilt * 0x70` once latched.
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Accelerometer {
+    x: f32,
+    y: f32,
+    latched_x: u16,
+    latched_y: u16,
+    /// The latch must be erased before a new value can be latched.
+    erased: bool,
+}
+
+impl Accelerometer {
+    const CENTER: f32 = 0x81D0 as f32;
+    const ONE_G: f32 = 0x70 as f32;
+    const ERASED: u16 = 0x8000;
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.x = x;
+        self.y = y;
+    }
+
+    pub fn get_tilt(&self) -> (f32, f32) {
+        (self.x, self.y)
+    }
+
+    fn to_register(tilt: f32) -> u16 {
+        // float to int casts saturate
+        (Self::CENTER + tilt * Self::ONE_G) as u16
+    }
+
+    fn erase(&mut self) {
+        self.latched_x = Self::ERASED;
+        self.latched_y = Self::ERASED;
+        self.erased = true;
+    }
+
+    fn latch(&mut self) {
+        if self.erased {
+            self.latched_x = Self::to_register(self.x);
+            self.latched_y = Self::to_register(self.y);
+            self.erased = false;
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum EepromState {
+    /// Waiting for the start bit.
+    #[default]
+    Idle,
+    /// Shifting in the 2 bits opcode and 8 bits address.
+    Command { bits: u16, count: u8 },
+    /// Shifting out words, starting at `addr`.
+    Read { addr: u8, value: u16, count: u8 },
+    /// Shifting in the word to write, `addr` is `None` for WRAL.
+    Write {
+        addr: Option<u8>,
+        value: u16,
+        count: u8,
+    },
+    /// Command done, waiting for the chip to be deselected.
+    Done,
+}
+
+/// 93LC56 serial EEPROM, 128 words of 16 bits, bit-banged through the 0xA080 register.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Eeprom {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::array"))]
+    data: [u16; Self::WORDS],
+    state: EepromState,
+    write_enabled: bool,
+    chip_select: bool,
+    clock: bool,
+    data_in: bool,
+    data_out: bool,
+}
+
+impl Default for Eeprom {
+    fn default() -> Self {
+        Eeprom {
+            data: [0xFFFF; Self::WORDS],
+            state: EepromState::Idle,
+            write_enabled: false,
+            chip_select: false,
+            clock: false,
+            data_in: false,
+            data_out: true,
+        }
+    }
+}
+
+impl Eeprom {
+    const WORDS: usize = 128;
+    const CHIP_SELECT_MASK: u8 = 0x80;
+    const CLOCK_MASK: u8 = 0x40;
+    const DATA_IN_MASK: u8 = 0x02;
+    const DATA_OUT_MASK: u8 = 0x01;
+
+    pub fn get_word(&self, addr: u8) -> u16 {
+        self.data[Self::index(addr)]
+    }
+
+    fn index(addr: u8) -> usize {
+        addr as usize % Self::WORDS
+    }
+
+    fn read_register(&self) -> u8 {
+        let mut value = 0;
+        if self.chip_select {
+            value |= Self::CHIP_SELECT_MASK;
+        }
+        if self.clock {
+            value |= Self::CLOCK_MASK;
+        }
+        if self.data_in {
+            value |= Self::DATA_IN_MASK;
+        }
+        if self.data_out {
+            value |= Self::DATA_OUT_MASK;
+        }
+        value
+    }
+
+    /// The words as big endian bytes, the order they are shifted out.
+    fn get_bytes(&self) -> Vec<u8> {
+        self.data
+            .iter()
+            .flat_map(|word| word.to_be_bytes())
+            .collect()
+    }
+
+    fn load_bytes(&mut self, bytes: &[u8]) {
+        for (word, bytes) in self.data.iter_mut().zip(bytes.chunks_exact(2)) {
+            *word = u16::from_be_bytes([bytes[0], bytes[1]]);
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.put_bytes(&self.get_bytes());
+        match self.state {
+            EepromState::Idle => state.put_u8(0),
+            EepromState::Command { bits, count } => {
+                state.put_u8(1);
+                state.put_u16(bits);
+                state.put_u8(count);
+            }
+            EepromState::Read { addr, value, count } => {
+                state.put_u8(2);
+                state.put_u8(addr);
+                state.put_u16(value);
+                state.put_u8(count);
+            }
+            EepromState::Write { addr, value, count } => {
+                state.put_u8(3);
+                state.put_bool(addr.is_some());
+                state.put_u8(addr.unwrap_or(0));
+                state.put_u16(value);
+                state.put_u8(count);
+            }
+            EepromState::Done => state.put_u8(4),
+        }
+        state.put_bool(self.write_enabled);
+        state.put_bool(self.chip_select);
+        state.put_bool(self.clock);
+        state.put_bool(self.data_in);
+        state.put_bool(self.data_out);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        let bytes = state.get_bytes()?;
+        if bytes.len() != Self::WORDS * 2 {
+            return Err(SaveStateError::SizeMismatch {
+                expected: Self::WORDS * 2,
+                found: bytes.len(),
+            });
+        }
+        self.load_bytes(bytes);
+        self.state = match state.get_u8()? {
+            0 => EepromState::Idle,
+            1 => EepromState::Command {
+                bits: state.get_u16()?,
+                count: state.get_u8()?,
+            },
+            2 => EepromState::Read {
+                addr: state.get_u8()?,
+                value: state.get_u16()?,
+                count: state.get_u8()?,
+            },
+            3 => {
+                let has_addr = state.get_bool()?;
+                let addr = state.get_u8()?;
+                EepromState::Write {
+                    addr: has_addr.then_some(addr),
+                    value: state.get_u16()?,
+                    count: state.get_u8()?,
+                }
+            }
+            4 => EepromState::Done,
+            tag => return Err(SaveStateError::InvalidValue(tag)),
+        };
+        self.write_enabled = state.get_bool()?;
+        self.chip_select = state.get_bool()?;
+        self.clock = state.get_bool()?;
+        self.data_in = state.get_bool()?;
+        self.data_out = state.get_bool()?;
+        Ok(())
+    }
+
+    fn write_register(&mut self, value: u8) {
+        let chip_select = value & Self::CHIP_SELECT_MASK != 0;
+        let clock = value & Self::CLOCK_MASK != 0;
+        self.data_in = value & Self::DATA_IN_MASK != 0;
+
+        if !chip_select {
+            self.state = EepromState::Idle;
+        } else if clock && !self.clock {
+            // bits are shifted on the rising edge of the clock
+            self.shift(self.data_in);
+        }
+        self.chip_select = chip_select;
+        self.clock = clock;
+    }
+
+    fn shift(&mut self, bit: bool) {
+        let bit = u16::from(bit);
+        self.state = match self.state {
+            EepromState::Idle if bit == 1 => EepromState::Command { bits: 0, count: 0 },
+            EepromState::Idle => EepromState::Idle,
+            EepromState::Command { bits, count } => {
+                let bits = bits << 1 | bit;
+                if count + 1 == 10 {
+                    self.execute(bits)
+                } else {
+                    EepromState::Command {
+                        bits,
+                        count: count + 1,
+                    }
+                }
+            }
+            EepromState::Read { addr, value, count } => {
+                self.data_out = value & 0x8000 != 0;
+                if count + 1 == 16 {
+                    // sequential read continues with the next word
+                    let addr = addr.wrapping_add(1);
+                    EepromState::Read {
+                        addr,
+                        value: self.get_word(addr),
+                        count: 0,
+                    }
+                } else {
+                    EepromState::Read {
+                        addr,
+                        value: value << 1,
+                        count: count + 1,
+                    }
+                }
+            }
+            EepromState::Write { addr, value, count } => {
+                let value = value << 1 | bit;
+                if count + 1 == 16 {
+                    if self.write_enabled {
+                        match addr {
+                            Some(addr) => self.data[Self::index(addr)] = value,
+                            None => self.data.fill(value),
+                        }
+                    }
+                    // ready
+                    self.data_out = true;
+                    EepromState::Done
+                } else {
+                    EepromState::Write {
+                        addr,
+                        value,
+                        count: count + 1,
+                    }
+                }
+            }
+            EepromState::Done => EepromState::Done,
+        }
+    }
+
+    fn execute(&mut self, command: u16) -> EepromState {
+        let [opcode, addr] = command.to_be_bytes();
+        match opcode & 0b11 {
+            // READ, starts with a dummy 0
+            0b10 => {
+                self.data_out = false;
+                EepromState::Read {
+                    addr,
+                    value: self.get_word(addr),
+                    count: 0,
+                }
+            }
+            // WRITE
+            0b01 => EepromState::Write {
+                addr: Some(addr),
+                value: 0,
+                count: 0,
+            },
+            // ERASE
+            0b11 => {
+                if self.write_enabled {
+                    self.data[Self::index(addr)] = 0xFFFF;
+                }
+                self.data_out = true;
+                EepromState::Done
+            }
+            _ => match addr >> 6 {
+                // EWDS
+                0b00 => {
+                    self.write_enabled = false;
+                    EepromState::Done
+                }
+                // WRAL
+                0b01 => EepromState::Write {
+                    addr: None,
+                    value: 0,
+                    count: 0,
+                },
+                // ERAL
+                0b10 => {
+                    if self.write_enabled {
+                        self.data.fill(0xFFFF);
+                    }
+                    self.data_out = true;
+                    EepromState::Done
+                }
+                // EWEN
+                _ => {
+                    self.write_enabled = true;
+                    EepromState::Done
+                }
+            },
+        }
+    }
+}
+
+/// MBC7, up to 2MB of ROM, with an accelerometer and an EEPROM instead of RAM.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mbc7 {
+    rom: Vec<u8>,
+    rom_bank: u8,
+    /// The register area is only accessible when both enables are set.
+    ram_enabled: bool,
+    ram_enabled_two: bool,
+    accelerometer: Accelerometer,
+    eeprom: Eeprom,
+}
+
+impl Mbc7 {
+    pub fn new(rom: Vec<u8>) -> Self {
+        Mbc7 {
+            rom,
+            rom_bank: 1,
+            ram_enabled: false,
+            ram_enabled_two: false,
+            accelerometer: Accelerometer::default(),
+            eeprom: Eeprom::default(),
+        }
+    }
+
+    pub fn get_eeprom(&self) -> &Eeprom {
+        &self.eeprom
+    }
+
+    fn registers_enabled(&self) -> bool {
+        self.ram_enabled && self.ram_enabled_two
+    }
+}
+
+impl Mbc for Mbc7 {
+    #[cfg(feature = "serde")]
+    fn as_builtin(&self) -> Option<super::BuiltinMbc<'_>> {
+        Some(super::BuiltinMbc::Mbc7(self))
+    }
+
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = if addr < 0x4000 { 0 } else { self.rom_bank };
+        read_rom_bank(&self.rom, bank.into(), addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = value == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x7F,
+            0x4000..=0x5FFF => self.ram_enabled_two = value == 0x40,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.registers_enabled() || addr >= 0xB000 {
+            return 0xFF;
+        }
+        let [x_high, x_low] = self.accelerometer.latched_x.to_be_bytes();
+        let [y_high, y_low] = self.accelerometer.latched_y.to_be_bytes();
+        match (addr >> 4) & 0x0F {
+            0x2 => x_low,
+            0x3 => x_high,
+            0x4 => y_low,
+            0x5 => y_high,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read_register(),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if !self.registers_enabled() || addr >= 0xB000 {
+            return;
+        }
+        match (addr >> 4) & 0x0F {
+            0x0 if value == 0x55 => self.accelerometer.erase(),
+            0x1 if value == 0xAA => self.accelerometer.latch(),
+            0x8 => self.eeprom.write_register(value),
+            _ => {}
+        }
+    }
+
+    fn accelerometer(&mut self) -> Option<&mut Accelerometer> {
+        Some(&mut self.accelerometer)
+    }
+
+    fn get_save_data(&self) -> Vec<u8> {
+        self.eeprom.get_bytes()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        self.eeprom.load_bytes(data);
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.put_u8(self.rom_bank);
+        state.put_bool(self.ram_enabled);
+        state.put_bool(self.ram_enabled_two);
+        // the tilt is an input, only the latch is part of the state
+        state.put_u16(self.accelerometer.latched_x);
+        state.put_u16(self.accelerometer.latched_y);
+        state.put_bool(self.accelerometer.erased);
+        self.eeprom.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.rom_bank = state.get_u8()?;
+        self.ram_enabled = state.get_bool()?;
+        self.ram_enabled_two = state.get_bool()?;
+        self.accelerometer.latched_x = state.get_u16()?;
+        self.accelerometer.latched_y = state.get_u16()?;
+        self.accelerometer.erased = state.get_bool()?;
+        self.eeprom.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::mbc::Mbc;
+
+    use super::Mbc7;
+
+    fn enabled_mbc() -> Mbc7 {
+        let mut mbc = Mbc7::new(vec![0; 0x8000]);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x40);
+        mbc
+    }
+
+    /// Start bit, 2 bits opcode and 8 bits address.
+    fn send_command(mbc: &mut Mbc7, opcode: u32, addr: u32) {
+        send_bits(mbc, 1 << 10 | opcode << 8 | addr, 11);
+    }
+
+    fn send_bits(mbc: &mut Mbc7, bits: u32, count: u32) {
+        for i in (0..count).rev() {
+            let di = ((bits >> i) as u8 & 1) << 1;
+            mbc.write_ram(0xA080, 0x80 | di);
+            mbc.write_ram(0xA080, 0x80 | 0x40 | di);
+        }
+    }
+
+    fn receive_word(mbc: &mut Mbc7) -> u16 {
+        let mut word = 0;
+        for _ in 0..16 {
+            mbc.write_ram(0xA080, 0x80);
+            mbc.write_ram(0xA080, 0x80 | 0x40);
+            word = word << 1 | u16::from(mbc.read_ram(0xA080) & 1);
+        }
+        word
+    }
+
+    fn deselect(mbc: &mut Mbc7) {
+        mbc.write_ram(0xA080, 0x00);
+    }
+
+    #[test]
+    fn accelerometer_latch() {
+        let mut mbc = enabled_mbc();
+        mbc.accelerometer().unwrap().set_tilt(1.0, -1.0);
+
+        // latching without erasing first does nothing
+        mbc.write_ram(0xA010, 0xAA);
+        assert_eq!(mbc.read_ram(0xA020), 0x00);
+
+        mbc.write_ram(0xA000, 0x55);
+        assert_eq!(mbc.read_ram(0xA030), 0x80);
+        mbc.write_ram(0xA010, 0xAA);
+        let x = u16::from_le_bytes([mbc.read_ram(0xA020), mbc.read_ram(0xA030)]);
+        let y = u16::from_le_bytes([mbc.read_ram(0xA040), mbc.read_ram(0xA050)]);
+        assert_eq!(x, 0x81D0 + 0x70);
+        assert_eq!(y, 0x81D0 - 0x70);
+    }
+
+    #[test]
+    fn eeprom_write_then_read() {
+        let mut mbc = enabled_mbc();
+        // EWEN
+        send_command(&mut mbc, 0b00, 0b11000000);
+        deselect(&mut mbc);
+        // WRITE 0x1234 at 0x05
+        send_command(&mut mbc, 0b01, 0x05);
+        send_bits(&mut mbc, 0x1234, 16);
+        deselect(&mut mbc);
+        assert_eq!(mbc.get_eeprom().get_word(0x05), 0x1234);
+
+        // READ at 0x05, dummy 0 then the word, then the next one
+        send_command(&mut mbc, 0b10, 0x05);
+        assert_eq!(mbc.read_ram(0xA080) & 1, 0);
+        assert_eq!(receive_word(&mut mbc), 0x1234);
+        assert_eq!(receive_word(&mut mbc), 0xFFFF);
+        deselect(&mut mbc);
+    }
+}
@@ -0,0 +1,226 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+use super::{load_save_ram, read_ram_bank, read_rom_bank, write_ram_bank, Mbc};
+
+/// MMM01, the mapper of multi-game compilations.
+///
+/// It boots "unmapped", with the last 32KB of the ROM (the menu) at 0x0000-0x7FFF.
+/// The menu then selects the game by setting the outer bank bits and masks,
+/// and maps it by setting bit 6 of the RAM enable register.
+/// Once mapped, the game sees a plain MBC1-like mapper limited to its own banks,
+/// the outer bits and masks are locked until the next reset.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mmm01 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mapped: bool,
+    ram_enabled: bool,
+    /// 9 bits, the low 5 bits are the MBC1 bank, the upper ones select the game.
+    rom_bank: u16,
+    /// Bits 1-4 of the ROM bank that are locked once mapped.
+    rom_bank_mask: u8,
+    /// 4 bits, the low 2 bits are the MBC1 bank, the upper ones select the game.
+    ram_bank: u8,
+    /// Bits 0-1 of the RAM bank that are locked once mapped.
+    ram_bank_mask: u8,
+    /// MBC1 banking mode, RAM banking is only enabled in mode 1.
+    mode: bool,
+    /// Once mapped, the game can't change the banking mode if set.
+    mode_locked: bool,
+}
+
+impl Mmm01 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        Mmm01 {
+            rom,
+            ram: vec![0; ram_size],
+            mapped: false,
+            ram_enabled: false,
+            rom_bank: 0,
+            rom_bank_mask: 0,
+            ram_bank: 0,
+            ram_bank_mask: 0,
+            mode: false,
+            mode_locked: false,
+        }
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        self.mapped
+    }
+
+    /// The ROM bank bits the game is allowed to change.
+    fn game_rom_bits(&self) -> u16 {
+        0x1F & !(u16::from(self.rom_bank_mask) << 1)
+    }
+
+    fn game_ram_bits(&self) -> u8 {
+        0b11 & !self.ram_bank_mask
+    }
+
+    fn get_rom_bank(&self, addr: u16) -> u16 {
+        if !self.mapped {
+            // the bank bits are all pulled up, wrapping to the last 32KB of the ROM
+            return if addr < 0x4000 { 0x1FE } else { 0x1FF };
+        }
+        let game_bits = self.game_rom_bits();
+        if addr < 0x4000 {
+            self.rom_bank & !game_bits
+        } else if self.rom_bank & game_bits == 0 {
+            // like the MBC1, bank 0 of the game can't be mapped in the switchable area
+            self.rom_bank | 1
+        } else {
+            self.rom_bank
+        }
+    }
+
+    fn get_ram_bank(&self) -> u8 {
+        if self.mode {
+            self.ram_bank
+        } else {
+            self.ram_bank & !self.game_ram_bits()
+        }
+    }
+}
+
+impl Mbc for Mmm01 {
+    #[cfg(feature = "serde")]
+    fn as_builtin(&self) -> Option<super::BuiltinMbc<'_>> {
+        Some(super::BuiltinMbc::Mmm01(self))
+    }
+
+    fn read_rom(&self, addr: u16) -> u8 {
+        read_rom_bank(&self.rom, self.get_rom_bank(addr).into(), addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => {
+                self.ram_enabled = value & 0x0F == 0x0A;
+                if !self.mapped {
+                    self.ram_bank_mask = (value >> 4) & 0b11;
+                    self.mapped = value & 0x40 != 0;
+                }
+            }
+            0x2000..=0x3FFF => {
+                let (bits, value) = if self.mapped {
+                    (self.game_rom_bits(), u16::from(value))
+                } else {
+                    // the menu also sets bits 5-6
+                    (0x7F, u16::from(value & 0x7F))
+                };
+                self.rom_bank = (self.rom_bank & !bits) | (value & bits);
+            }
+            0x4000..=0x5FFF => {
+                if self.mapped {
+                    let bits = self.game_ram_bits();
+                    self.ram_bank = (self.ram_bank & !bits) | (value & bits);
+                } else {
+                    self.ram_bank = value & 0x0F;
+                    self.rom_bank = (self.rom_bank & 0x7F) | (u16::from(value & 0x30) << 3);
+                    self.mode_locked = value & 0x40 != 0;
+                }
+            }
+            0x6000..=0x7FFF => {
+                if !self.mapped || !self.mode_locked {
+                    self.mode = value & 1 != 0;
+                }
+                if !self.mapped {
+                    self.rom_bank_mask = (value >> 2) & 0x0F;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if self.ram_enabled {
+            read_ram_bank(&self.ram, self.get_ram_bank().into(), addr)
+        } else {
+            0xFF
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if self.ram_enabled {
+            let bank = self.get_ram_bank();
+            write_ram_bank(&mut self.ram, bank.into(), addr, value);
+        }
+    }
+
+    fn get_save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_save_ram(&mut self.ram, data);
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.put_bool(self.mapped);
+        state.put_bool(self.ram_enabled);
+        state.put_u16(self.rom_bank);
+        state.put_u8(self.rom_bank_mask);
+        state.put_u8(self.ram_bank);
+        state.put_u8(self.ram_bank_mask);
+        state.put_bool(self.mode);
+        state.put_bool(self.mode_locked);
+        state.put_bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.mapped = state.get_bool()?;
+        self.ram_enabled = state.get_bool()?;
+        self.rom_bank = state.get_u16()?;
+        self.rom_bank_mask = state.get_u8()?;
+        self.ram_bank = state.get_u8()?;
+        self.ram_bank_mask = state.get_u8()?;
+        self.mode = state.get_bool()?;
+        self.mode_locked = state.get_bool()?;
+        state.get_bytes_into(&mut self.ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::mbc::{Mbc, ROM_BANK_SIZE};
+
+    use super::Mmm01;
+
+    fn mbc() -> Mmm01 {
+        // 64 banks, each filled with its number
+        let rom = (0..64)
+            .flat_map(|bank| vec![bank as u8; ROM_BANK_SIZE])
+            .collect();
+        Mmm01::new(rom, 0)
+    }
+
+    #[test]
+    fn boots_the_menu_from_the_last_32kb() {
+        let mbc = mbc();
+        assert!(!mbc.is_mapped());
+        assert_eq!(mbc.read_rom(0x0000), 62);
+        assert_eq!(mbc.read_rom(0x4000), 63);
+    }
+
+    #[test]
+    fn map_a_game() {
+        let mut mbc = mbc();
+        // game at banks 32-47: outer bit 5 and bank bit 4 locked
+        mbc.write_rom(0x2000, 0x20);
+        mbc.write_rom(0x6000, 0b1000 << 2);
+        mbc.write_rom(0x0000, 0x40);
+        assert!(mbc.is_mapped());
+
+        assert_eq!(mbc.read_rom(0x0000), 32);
+        assert_eq!(mbc.read_rom(0x4000), 33);
+        mbc.write_rom(0x2000, 0x0F);
+        assert_eq!(mbc.read_rom(0x4000), 47);
+        // the game can't escape its banks
+        mbc.write_rom(0x2000, 0x1F);
+        assert_eq!(mbc.read_rom(0x4000), 47);
+        mbc.write_rom(0x4000, 0x30);
+        assert_eq!(mbc.read_rom(0x0000), 32);
+    }
+}
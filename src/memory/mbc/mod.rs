@@ -0,0 +1,161 @@
+use std::fmt::Debug;
+
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+pub use self::{
+    mbc1::Mbc1, mbc2::Mbc2, mbc3::Mbc3, mbc5::Mbc5, mbc7::Mbc7, mmm01::Mmm01, rom_only::RomOnly,
+    wisdom_tree::WisdomTree,
+};
+
+use self::{mbc3::Rtc, mbc7::Accelerometer};
+
+pub mod mbc1;
+pub mod mbc2;
+pub mod mbc3;
+pub mod mbc5;
+pub mod mbc7;
+pub mod mmm01;
+pub mod rom_only;
+pub mod wisdom_tree;
+
+/// Memory Bank Controller, the mapper chip of a cartridge.
+///
+/// `Memory` dispatches every access to 0x0000-0x7FFF (ROM) and 0xA000-0xBFFF (external RAM) to it,
+/// addresses are passed as is (not relative to the start of the section).
+/// Implement it to plug custom mappers (homebrew, flashcarts, ...) with `Memory::new`.
+///
+/// Unmapped or disabled areas should read as 0xFF, like the open bus of the hardware.
+pub trait Mbc: Debug {
+    fn read_rom(&self, addr: u16) -> u8;
+    /// Writes to the ROM area don't write anything but set the mapper registers.
+    fn write_rom(&mut self, addr: u16, value: u8);
+    fn read_ram(&self, addr: u16) -> u8;
+    fn write_ram(&mut self, addr: u16, value: u8);
+    /// Called on every machine cycle with the elapsed clock cycles,
+    /// for mappers with their own clocked hardware.
+    fn step(&mut self, _cycles: u32) {}
+    /// The accelerometer of the cartridge, if it has one.
+    fn accelerometer(&mut self) -> Option<&mut Accelerometer> {
+        None
+    }
+    /// The real time clock of the cartridge, if it has one.
+    fn rtc(&mut self) -> Option<&mut Rtc> {
+        None
+    }
+    /// The battery backed data, what goes in the `.sav` file.
+    ///
+    /// This is the whole external RAM, all banks, not just the mapped one.
+    fn get_save_data(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    /// Restore the data of `get_save_data`, only the common part is loaded if the size differs.
+    fn load_save_data(&mut self, _data: &[u8]) {}
+    /// Serialize the mapper registers and the whole external RAM.
+    ///
+    /// The ROM is not included, states are loaded on the same cartridge.
+    fn save_state(&self, _state: &mut StateWriter) {}
+    fn load_state(&mut self, _state: &mut StateReader) -> Result<(), SaveStateError> {
+        Ok(())
+    }
+    /// The mapper as one of the built-in ones, to go through serde with `Memory`.
+    ///
+    /// Custom mappers don't have one, serializing a `Memory` using them fails.
+    #[cfg(feature = "serde")]
+    fn as_builtin(&self) -> Option<BuiltinMbc<'_>> {
+        None
+    }
+}
+
+/// The mappers of this crate, how the `Box<dyn Mbc>` of `Memory` is serialized.
+#[cfg(feature = "serde")]
+#[derive(Debug, serde::Serialize)]
+pub enum BuiltinMbc<'a> {
+    RomOnly(&'a RomOnly),
+    Mbc1(&'a Mbc1),
+    Mbc2(&'a Mbc2),
+    Mbc3(&'a Mbc3),
+    Mbc5(&'a Mbc5),
+    Mbc7(&'a Mbc7),
+    Mmm01(&'a Mmm01),
+    WisdomTree(&'a WisdomTree),
+}
+
+/// `#[serde(with)]` for `Box<dyn Mbc>`, through `Mbc::as_builtin`.
+#[cfg(feature = "serde")]
+pub(crate) mod serde_mbc {
+    use serde::{ser::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::*;
+
+    /// Same variants as `BuiltinMbc`, owned.
+    #[derive(Deserialize)]
+    enum OwnedMbc {
+        RomOnly(Box<RomOnly>),
+        Mbc1(Box<Mbc1>),
+        Mbc2(Box<Mbc2>),
+        Mbc3(Box<Mbc3>),
+        Mbc5(Box<Mbc5>),
+        Mbc7(Box<Mbc7>),
+        Mmm01(Box<Mmm01>),
+        WisdomTree(Box<WisdomTree>),
+    }
+
+    #[allow(clippy::borrowed_box)]
+    pub fn serialize<S: Serializer>(mbc: &Box<dyn Mbc>, serializer: S) -> Result<S::Ok, S::Error> {
+        mbc.as_builtin()
+            .ok_or_else(|| S::Error::custom("custom mappers can't be serialized"))?
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Box<dyn Mbc>, D::Error> {
+        Ok(match OwnedMbc::deserialize(deserializer)? {
+            OwnedMbc::RomOnly(mbc) => mbc,
+            OwnedMbc::Mbc1(mbc) => mbc,
+            OwnedMbc::Mbc2(mbc) => mbc,
+            OwnedMbc::Mbc3(mbc) => mbc,
+            OwnedMbc::Mbc5(mbc) => mbc,
+            OwnedMbc::Mbc7(mbc) => mbc,
+            OwnedMbc::Mmm01(mbc) => mbc,
+            OwnedMbc::WisdomTree(mbc) => mbc,
+        })
+    }
+}
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+/// Read in a ROM bank, the bank number wraps around the ROM size like on hardware
+/// where the upper bits of the bank number are just not connected.
+pub fn read_rom_bank(rom: &[u8], bank: usize, addr: u16) -> u8 {
+    read_banked(rom, ROM_BANK_SIZE, bank, addr)
+}
+
+pub fn read_ram_bank(ram: &[u8], bank: usize, addr: u16) -> u8 {
+    read_banked(ram, RAM_BANK_SIZE, bank, addr)
+}
+
+pub fn write_ram_bank(ram: &mut [u8], bank: usize, addr: u16, value: u8) {
+    if let Some(index) = banked_index(ram.len(), RAM_BANK_SIZE, bank, addr) {
+        ram[index] = value;
+    }
+}
+
+/// Load a `.sav` into the RAM, ignoring what doesn't fit or is missing.
+pub fn load_save_ram(ram: &mut [u8], data: &[u8]) {
+    let len = ram.len().min(data.len());
+    ram[..len].copy_from_slice(&data[..len]);
+}
+
+fn read_banked(data: &[u8], bank_size: usize, bank: usize, addr: u16) -> u8 {
+    banked_index(data.len(), bank_size, bank, addr).map_or(0xFF, |index| data[index])
+}
+
+fn banked_index(len: usize, bank_size: usize, bank: usize, addr: u16) -> Option<usize> {
+    if len == 0 {
+        return None;
+    }
+    let offset = addr as usize & (bank_size - 1);
+    Some((bank * bank_size + offset) % len)
+}
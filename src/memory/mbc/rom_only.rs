@@ -0,0 +1,60 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+use super::{load_save_ram, read_ram_bank, read_rom_bank, write_ram_bank, Mbc};
+
+/// Cartridge without mapper, 32KB of ROM and optionally up to 8KB of RAM.
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RomOnly {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl RomOnly {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        RomOnly {
+            rom,
+            ram: vec![0; ram_size],
+        }
+    }
+}
+
+impl Mbc for RomOnly {
+    #[cfg(feature = "serde")]
+    fn as_builtin(&self) -> Option<super::BuiltinMbc<'_>> {
+        Some(super::BuiltinMbc::RomOnly(self))
+    }
+
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = (addr >= 0x4000).into();
+        read_rom_bank(&self.rom, bank, addr)
+    }
+
+    fn write_rom(&mut self, _addr: u16, _value: u8) {
+        // no registers
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        read_ram_bank(&self.ram, 0, addr)
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        write_ram_bank(&mut self.ram, 0, addr, value);
+    }
+
+    fn get_save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_save_ram(&mut self.ram, data);
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.put_bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        state.get_bytes_into(&mut self.ram)
+    }
+}
@@ -0,0 +1,79 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+use super::{read_rom_bank, Mbc};
+
+/// Wisdom Tree mapper, used by unlicensed releases.
+///
+/// Switches the whole 0x0000-0x7FFF area by 32KB banks,
+/// the bank number is the low byte of the address written to in 0x0000-0x3FFF, the value is ignored.
+/// No RAM.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WisdomTree {
+    rom: Vec<u8>,
+    /// 32KB bank
+    bank: u8,
+}
+
+impl WisdomTree {
+    pub fn new(rom: Vec<u8>) -> Self {
+        WisdomTree { rom, bank: 0 }
+    }
+}
+
+impl Mbc for WisdomTree {
+    #[cfg(feature = "serde")]
+    fn as_builtin(&self) -> Option<super::BuiltinMbc<'_>> {
+        Some(super::BuiltinMbc::WisdomTree(self))
+    }
+
+    fn read_rom(&self, addr: u16) -> u8 {
+        // 32KB banks are two 16KB banks
+        let bank = usize::from(self.bank) * 2 + usize::from(addr >= 0x4000);
+        read_rom_bank(&self.rom, bank, addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, _value: u8) {
+        if addr < 0x4000 {
+            self.bank = addr as u8;
+        }
+    }
+
+    fn read_ram(&self, _addr: u16) -> u8 {
+        0xFF
+    }
+
+    fn write_ram(&mut self, _addr: u16, _value: u8) {}
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.put_u8(self.bank);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.bank = state.get_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::mbc::{Mbc, ROM_BANK_SIZE};
+
+    use super::WisdomTree;
+
+    #[test]
+    fn switch_32kb_banks() {
+        // 8 banks of 16KB, each filled with its number
+        let rom = (0..8).flat_map(|bank| vec![bank; ROM_BANK_SIZE]).collect();
+        let mut mbc = WisdomTree::new(rom);
+        assert_eq!(mbc.read_rom(0x0000), 0);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        // the value is ignored, the address selects the bank
+        mbc.write_rom(0x0102, 0xFF);
+        assert_eq!(mbc.read_rom(0x0000), 4);
+        assert_eq!(mbc.read_rom(0x7FFF), 5);
+        // wraps around
+        mbc.write_rom(0x0005, 0);
+        assert_eq!(mbc.read_rom(0x0000), 2);
+    }
+}
@@ -1,7 +1,9 @@
-use super::Memory;
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MemorySection<const N: usize> {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::array"))]
     mem: [u8; N],
 }
 
@@ -17,6 +19,19 @@ impl<const N: usize> MemorySection<N> {
     pub fn set(&mut self, addr: u16, value: u8) {
         self.mem[addr as usize] = value;
     }
+
+    /// Fill the whole section from `fill`, for power on patterns.
+    pub fn init_with(&mut self, fill: impl FnOnce(&mut [u8])) {
+        fill(&mut self.mem);
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.put_bytes(&self.mem);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        state.get_bytes_into(&mut self.mem)
+    }
 }
 
 impl<const N: usize> Default for MemorySection<N> {
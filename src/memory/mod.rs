@@ -1,23 +1,79 @@
-use std::ops::Range;
+use std::{
+    fmt::Display,
+    ops::{Bound, RangeBounds},
+};
 
-use self::memory_section::MemorySection;
+use crate::{
+    apu::Apu,
+    config::{EmuConfig, RamInit, Rng},
+    debugger::{WatchKind, Watchpoints},
+    metrics::{Metrics, Stopwatch},
+    ppu::{Mode, Ppu},
+    savestate::{SaveStateError, StateReader, StateWriter},
+    serial::{SerialDevice, SerialPort, Unplugged},
+};
 
+use self::{
+    boot_rom::BootRom,
+    cartridge::CartridgeError,
+    dma::OamDma,
+    hdma::Hdma,
+    interrupts::Interrupt,
+    io::{IoRegister, IoRegisters},
+    joypad::{Button, Joypad},
+    mbc::{Mbc, RomOnly},
+    memory_section::MemorySection,
+    timer::Timer,
+    work_ram::WorkRam,
+};
+
+pub mod boot_rom;
+pub mod bus;
+pub mod cartridge;
+pub mod dma;
+pub mod hdma;
+pub mod interrupts;
+pub mod io;
+pub mod joypad;
+pub mod mbc;
 pub mod memory_section;
+pub mod timer;
+pub mod work_ram;
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Memory {
-    rom: MemorySection<{ Self::ROM_BANK_SIZE }>,
-    switchable_rom: MemorySection<{ Self::SWITCHABLE_ROM_BANK_SIZE }>,
-    vram: MemorySection<{ Self::VRAM_SIZE }>,
-    switchable_ram: MemorySection<{ Self::SWITCHABLE_RAM_BANK_SIZE }>,
-    internal_ram: MemorySection<{ Self::INTERNAL_RAM_SIZE }>,
-    internal_ram_echo: MemorySection<{ Self::INTERNAL_RAM_ECHO_SIZE }>,
-    oam: MemorySection<{ Self::OAM_SIZE }>,
-    empty: MemorySection<{ Self::EMPTY_SIZE }>,
-    io_ports: MemorySection<{ Self::IO_PORTS_SIZE }>,
-    empty_two: MemorySection<{ Self::EMPTY_TWO_SIZE }>,
-    internal_ram_two: MemorySection<{ Self::INTERNAL_RAM_TWO_SIZE }>,
+    #[cfg_attr(feature = "serde", serde(with = "mbc::serde_mbc"))]
+    mbc: Box<dyn Mbc>,
+    /// Mapped over the start of the cartridge while the console boots.
+    boot_rom: Option<BootRom>,
+    /// 0xC000-0xDFFF and its echo.
+    work_ram: WorkRam,
+    /// Who handles each register of 0xFF00-0xFF7F.
+    io: IoRegisters,
+    internal_ram_two: MemorySection<{ Memory::INTERNAL_RAM_TWO_SIZE }>,
+    interrupt_flag: u8,
     interrupt_enable_register: u8,
+    serial: SerialPort,
+    timer: Timer,
+    dma: OamDma,
+    /// CGB only.
+    hdma: Hdma,
+    joypad: Joypad,
+    apu: Apu,
+    ppu: Ppu,
+    config: EmuConfig,
+    /// CGB double speed, and the switch requested through KEY1 for the next STOP.
+    double_speed: bool,
+    speed_switch_armed: bool,
+    /// In double speed the APU is ticked every other M-cycle, set when it was skipped.
+    apu_skipped: bool,
+    rng: Rng,
+    /// Time spent in the PPU, the APU and the rest of the bus.
+    metrics: Metrics,
+    /// Host side, not part of the machine state.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    watchpoints: Watchpoints,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -30,8 +86,7 @@ enum Bank {
     InternalRamEcho,
     Oam,
     Empty,
-    IOPorts,
-    EmptyTwo,
+    Io,
     InternalRamTwo,
 }
 
@@ -61,12 +116,7 @@ impl Bank {
             Memory::EMPTY_START..=Memory::EMPTY_END => {
                 Some((Bank::Empty, addr - Memory::EMPTY_START))
             }
-            Memory::IO_PORTS_START..=Memory::IO_PORTS_END => {
-                Some((Bank::IOPorts, addr - Memory::IO_PORTS_START))
-            }
-            Memory::EMPTY_TWO_START..=Memory::EMPTY_TWO_END => {
-                Some((Bank::EmptyTwo, addr - Memory::EMPTY_TWO_START))
-            }
+            IoRegisters::START..=IoRegisters::END => Some((Bank::Io, addr - IoRegisters::START)),
             Memory::INTERNAL_RAM_TWO_START..=Memory::INTERNAL_RAM_TWO_END => {
                 Some((Bank::InternalRamTwo, addr - Memory::INTERNAL_RAM_TWO_START))
             }
@@ -84,25 +134,12 @@ impl Memory {
     const INTERNAL_RAM_ECHO_START: u16 = 0xE000;
     const OAM_START: u16 = 0xFE00;
     const EMPTY_START: u16 = 0xFEA0;
-    const IO_PORTS_START: u16 = 0xFF00;
-    const EMPTY_TWO_START: u16 = 0xFF4C;
     const INTERNAL_RAM_TWO_START: u16 = 0xFF80;
     const INTERRUPT_ENABLE_REGISTER_START: u16 = 0xFFFF;
+    const INTERRUPT_FLAG_REGISTER: u16 = 0xFF0F;
+    /// CGB only, the speed switch.
+    pub const KEY1_REGISTER: u16 = 0xFF4D;
 
-    const ROM_BANK_SIZE: usize = (Self::SWITCHABLE_ROM_BANK_START - Self::ROM_BANK_START) as usize;
-    const SWITCHABLE_ROM_BANK_SIZE: usize =
-        (Self::VRAM_START - Self::SWITCHABLE_ROM_BANK_START) as usize;
-    const VRAM_SIZE: usize = (Self::SWITCHABLE_RAM_BANK_START - Self::VRAM_START) as usize;
-    const SWITCHABLE_RAM_BANK_SIZE: usize =
-        (Self::INTERNAL_RAM_START - Self::SWITCHABLE_RAM_BANK_START) as usize;
-    const INTERNAL_RAM_SIZE: usize =
-        (Self::INTERNAL_RAM_ECHO_START - Self::INTERNAL_RAM_START) as usize;
-    const INTERNAL_RAM_ECHO_SIZE_U16: u16 = Self::OAM_START - Self::INTERNAL_RAM_ECHO_START;
-    const INTERNAL_RAM_ECHO_SIZE: usize = Self::INTERNAL_RAM_ECHO_SIZE_U16 as usize;
-    const OAM_SIZE: usize = (Self::EMPTY_START - Self::OAM_START) as usize;
-    const EMPTY_SIZE: usize = (Self::IO_PORTS_START - Self::EMPTY_START) as usize;
-    const IO_PORTS_SIZE: usize = (Self::EMPTY_TWO_START - Self::IO_PORTS_START) as usize;
-    const EMPTY_TWO_SIZE: usize = (Self::INTERNAL_RAM_TWO_START - Self::EMPTY_TWO_START) as usize;
     const INTERNAL_RAM_TWO_SIZE: usize =
         (Self::INTERRUPT_ENABLE_REGISTER_START - Self::INTERNAL_RAM_TWO_START) as usize;
 
@@ -113,25 +150,479 @@ impl Memory {
     const INTERNAL_RAM_END: u16 = Self::INTERNAL_RAM_ECHO_START - 1;
     const INTERNAL_RAM_ECHO_END: u16 = Self::OAM_START - 1;
     const OAM_END: u16 = Self::EMPTY_START - 1;
-    const EMPTY_END: u16 = Self::IO_PORTS_START - 1;
-    const IO_PORTS_END: u16 = Self::EMPTY_TWO_START - 1;
-    const EMPTY_TWO_END: u16 = Self::INTERNAL_RAM_TWO_START - 1;
+    const EMPTY_END: u16 = IoRegisters::START - 1;
     const INTERNAL_RAM_TWO_END: u16 = Self::INTERRUPT_ENABLE_REGISTER_START - 1;
 
+    pub fn new(mbc: Box<dyn Mbc>) -> Self {
+        Self::with_config(mbc, EmuConfig::default())
+    }
+
+    pub fn with_config(mbc: Box<dyn Mbc>, config: EmuConfig) -> Self {
+        let mut memory = Memory {
+            mbc,
+            boot_rom: None,
+            work_ram: WorkRam::default(),
+            io: IoRegisters::new(config.model),
+            internal_ram_two: Default::default(),
+            interrupt_flag: 0,
+            interrupt_enable_register: 0,
+            serial: SerialPort::default(),
+            timer: Timer::default(),
+            dma: OamDma::default(),
+            hdma: Hdma::default(),
+            joypad: Joypad::default(),
+            apu: Apu::default(),
+            ppu: Ppu::new(config.model),
+            double_speed: false,
+            speed_switch_armed: false,
+            apu_skipped: false,
+            rng: Rng::new(config.seed),
+            config,
+            metrics: Metrics::default(),
+            watchpoints: Watchpoints::default(),
+        };
+        memory.init_ram();
+        memory
+    }
+
+    fn init_ram(&mut self) {
+        let rng = &mut self.rng;
+        let mut fill = |dest: &mut [u8]| match self.config.ram_init {
+            RamInit::Zero => dest.fill(0),
+            RamInit::Filled(value) => dest.fill(value),
+            RamInit::Random => rng.fill(dest),
+        };
+        self.work_ram.init_with(&mut fill);
+        self.internal_ram_two.init_with(&mut fill);
+    }
+
+    pub fn get_config(&self) -> &EmuConfig {
+        &self.config
+    }
+
+    /// Read with the side effects only the CPU sees, like open bus noise
+    /// or the VRAM and OAM being blocked while the PPU uses them.
+    pub fn read(&mut self, addr: u16) -> u8 {
+        let value = self.read_unwatched(addr);
+        if !self.watchpoints.is_empty() {
+            self.watchpoints.check(addr, WatchKind::Read, value, value);
+        }
+        value
+    }
+
+    fn read_unwatched(&mut self, addr: u16) -> u8 {
+        if self.config.open_bus_noise && (Self::EMPTY_START..=Self::EMPTY_END).contains(&addr) {
+            return self.rng.next_u8();
+        }
+        if self.config.stub_ly && addr == Ppu::LY_REGISTER {
+            return 0x90;
+        }
+        if let Some(value) = self.get_dma_conflict(addr) {
+            return value;
+        }
+        if self.is_blocked(addr) {
+            return 0xFF;
+        }
+        self.get(addr)
+    }
+
+    /// Write as the CPU, ignored if the PPU or the OAM DMA is using the VRAM or the OAM.
+    pub fn write(&mut self, addr: u16, value: u8) {
+        if !self.watchpoints.is_empty() {
+            let old = self.get(addr);
+            self.watchpoints.check(addr, WatchKind::Write, old, value);
+        }
+        if self.get_dma_conflict(addr).is_none() && !self.is_blocked(addr) {
+            self.put(addr, value);
+        }
+    }
+
+    fn is_blocked(&self, addr: u16) -> bool {
+        match Bank::from_addr(addr) {
+            Some((Bank::Vram, _)) => !self.ppu.is_vram_accessible(),
+            // the unusable area reads 0xFF with the OAM
+            Some((Bank::Oam | Bank::Empty, _)) => !self.ppu.is_oam_accessible(),
+            _ => false,
+        }
+    }
+
+    /// What the CPU reads while the OAM DMA holds the bus of `addr`, if it does.
+    ///
+    /// The OAM reads 0xFF, the bus the DMA reads from gives the byte being copied.
+    fn get_dma_conflict(&self, addr: u16) -> Option<u8> {
+        if !self.dma.is_active() {
+            return None;
+        }
+        let is_vram = |addr| (Self::VRAM_START..=Self::VRAM_END).contains(&addr);
+        let is_external = |addr| addr < Self::OAM_START && !is_vram(addr);
+        let source = self.dma.get_source();
+        if (Self::OAM_START..=Self::OAM_END).contains(&addr) {
+            Some(0xFF)
+        } else if (is_vram(source) && is_vram(addr)) || (is_external(source) && is_external(addr)) {
+            Some(self.dma.get_current())
+        } else {
+            None
+        }
+    }
+
+    /// Load a cartridge ROM, the mapper is selected from the cartridge header.
+    pub fn from_rom(rom: Vec<u8>) -> Result<Self, CartridgeError> {
+        cartridge::load(rom).map(Self::new)
+    }
+
+    pub fn get_mbc(&self) -> &dyn Mbc {
+        self.mbc.as_ref()
+    }
+
+    pub fn get_mbc_mut(&mut self) -> &mut dyn Mbc {
+        self.mbc.as_mut()
+    }
+
+    /// Swap the cartridge, returning the previous one.
+    pub fn replace_mbc(&mut self, mbc: Box<dyn Mbc>) -> Box<dyn Mbc> {
+        std::mem::replace(&mut self.mbc, mbc)
+    }
+
+    /// Power cycle, everything but the cartridge, the boot ROM, the link port device
+    /// and the audio output settings is cleared.
+    ///
+    /// The mapper keeps its registers, as well as the battery backed RAM.
+    pub fn reset(&mut self) {
+        let mbc = self.replace_mbc(Box::<RomOnly>::default());
+        let device = self.set_serial_device(Box::new(Unplugged));
+        let mut apu = std::mem::take(&mut self.apu);
+        apu.reset();
+        let mut boot_rom = self.boot_rom.take();
+        if let Some(boot_rom) = &mut boot_rom {
+            boot_rom.set_active(true);
+        }
+        let watchpoints = std::mem::take(&mut self.watchpoints);
+        *self = Memory::with_config(mbc, self.config.clone());
+        self.set_serial_device(device);
+        self.watchpoints = watchpoints;
+        self.apu = apu;
+        self.boot_rom = boot_rom;
+    }
+
+    /// Insert a boot ROM dump, it is mapped until the boot unmaps it.
+    /// Should be done before running, the CPU only starts from it at power on.
+    pub fn set_boot_rom(&mut self, boot_rom: Option<BootRom>) -> Option<BootRom> {
+        std::mem::replace(&mut self.boot_rom, boot_rom)
+    }
+
+    pub fn get_boot_rom(&self) -> Option<&BootRom> {
+        self.boot_rom.as_ref()
+    }
+
+    /// Whether the boot ROM is still mapped over the cartridge.
+    pub fn is_boot_rom_active(&self) -> bool {
+        self.boot_rom.as_ref().is_some_and(BootRom::is_active)
+    }
+
+    /// Plug a device in the link port, returning the previous one.
+    pub fn set_serial_device(&mut self, device: Box<dyn SerialDevice>) -> Box<dyn SerialDevice> {
+        self.serial.set_device(device)
+    }
+
+    pub fn get_serial_port_mut(&mut self) -> &mut SerialPort {
+        &mut self.serial
+    }
+
+    /// Checked on the reads and writes of the CPU.
+    pub fn get_watchpoints(&self) -> &Watchpoints {
+        &self.watchpoints
+    }
+
+    pub fn get_watchpoints_mut(&mut self) -> &mut Watchpoints {
+        &mut self.watchpoints
+    }
+
+    pub fn get_timer(&self) -> &Timer {
+        &self.timer
+    }
+
+    pub fn get_timer_mut(&mut self) -> &mut Timer {
+        &mut self.timer
+    }
+
+    pub fn get_joypad(&self) -> &Joypad {
+        &self.joypad
+    }
+
+    /// Press or release a button, a press can request the joypad interrupt.
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if self.joypad.set_button(button, pressed) {
+            self.request_interrupt(Interrupt::Joypad);
+        }
+    }
+
+    pub fn release_all_buttons(&mut self) {
+        self.joypad.release_all();
+    }
+
+    pub fn get_oam_dma(&self) -> &OamDma {
+        &self.dma
+    }
+
+    pub fn get_apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    pub fn get_apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    pub fn get_ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn get_work_ram(&self) -> &WorkRam {
+        &self.work_ram
+    }
+
+    pub fn get_ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    /// Cycles: 4
+    pub fn tick(&mut self) {
+        let stopwatch = Stopwatch::start();
+        // the CPU clock is twice as fast in double speed, not the rest
+        let dots = if self.double_speed { 2 } else { 4 };
+        self.mbc.step(dots);
+        if self.serial.step(4) {
+            self.request_interrupt(Interrupt::Serial);
+        }
+        if self.timer.tick() {
+            self.request_interrupt(Interrupt::Timer);
+        }
+        stopwatch.stop(&mut self.metrics.bus);
+        let stopwatch = Stopwatch::start();
+        if !self.double_speed {
+            self.apu.tick(self.timer.get_counter());
+        } else {
+            self.apu_skipped = !self.apu_skipped;
+            if !self.apu_skipped {
+                // the sequencer then follows the next bit of DIV
+                self.apu.tick(self.timer.get_counter() >> 1);
+            }
+        }
+        stopwatch.stop(&mut self.metrics.apu);
+        let stopwatch = Stopwatch::start();
+        if let Some((source, offset)) = self.dma.tick() {
+            let value = self.get(source);
+            self.dma.set_current(value);
+            self.ppu.write_oam(offset, value);
+        }
+        // the VRAM DMA goes at the normal speed too
+        let hdma_bytes = if self.double_speed {
+            Hdma::BYTES_PER_CYCLE / 2
+        } else {
+            Hdma::BYTES_PER_CYCLE
+        };
+        for _ in 0..hdma_bytes {
+            if let Some((source, offset)) = self.hdma.next_byte() {
+                let value = self.get(source);
+                self.ppu.write_vram(offset, value);
+            }
+        }
+        stopwatch.stop(&mut self.metrics.bus);
+        let stopwatch = Stopwatch::start();
+        let was_hblank = self.ppu.get_mode() == Mode::HBlank;
+        for interrupt in self.ppu.step(dots) {
+            self.request_interrupt(interrupt);
+        }
+        if !was_hblank && self.ppu.get_mode() == Mode::HBlank && self.ppu.is_enabled() {
+            self.hdma.start_hblank();
+        }
+        stopwatch.stop(&mut self.metrics.ppu);
+    }
+
+    pub fn is_double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    /// Switch the speed if KEY1 armed it, called on STOP. Returns whether it switched.
+    pub fn switch_speed(&mut self) -> bool {
+        if !std::mem::take(&mut self.speed_switch_armed) {
+            return false;
+        }
+        self.double_speed = !self.double_speed;
+        self.apu_skipped = false;
+        true
+    }
+
+    /// Whether the CGB VRAM DMA holds the bus, the CPU can't run meanwhile.
+    pub fn is_hdma_copying(&self) -> bool {
+        self.hdma.is_copying()
+    }
+
+    /// Time spent in the PPU, the APU and the bus since the last call, the CPU time isn't known here.
+    pub fn take_metrics(&mut self) -> Metrics {
+        std::mem::take(&mut self.metrics)
+    }
+
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag |= interrupt.get_mask();
+    }
+
+    /// Reset the DIV register, like any write to it or STOP.
+    pub fn reset_div(&mut self) {
+        self.timer.reset_div();
+    }
+
+    /// Clear the IF bit of an interrupt being serviced.
+    pub fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag &= !interrupt.get_mask();
+    }
+
+    /// Interrupts both requested and enabled, as IF & IE.
+    pub fn get_pending_interrupts(&self) -> u8 {
+        self.interrupt_flag & self.interrupt_enable_register & 0b00011111
+    }
+
+    pub fn get_interrupt_flag(&self) -> u8 {
+        // upper 3 bits are unused and always read as 1
+        self.interrupt_flag | 0b11100000
+    }
+
+    pub fn get_interrupt_enable(&self) -> u8 {
+        self.interrupt_enable_register
+    }
+
+    /// The unusable area after the OAM (0xFEA0-0xFEFF), when the PPU doesn't block it.
+    ///
+    /// The DMG reads 0, the CGB repeats the high nibble of the low byte of the address
+    /// (like the later revisions, CGB-D and E).
+    fn get_prohibited(&self, addr: u16) -> u8 {
+        if self.config.model.is_cgb() {
+            let nibble = (addr as u8) & 0xF0;
+            nibble | (nibble >> 4)
+        } else {
+            0x00
+        }
+    }
+
+    fn read_io(&self, addr: u16) -> u8 {
+        match self.io.get(addr) {
+            IoRegister::Joypad => self.joypad.get_register(),
+            IoRegister::SerialData => self.serial.get_data(),
+            IoRegister::SerialControl => self.serial.get_control(),
+            IoRegister::Div => self.timer.get_div(),
+            IoRegister::Tima => self.timer.get_tima(),
+            IoRegister::Tma => self.timer.get_tma(),
+            IoRegister::Tac => self.timer.get_tac(),
+            IoRegister::InterruptFlag => self.get_interrupt_flag(),
+            IoRegister::Apu => self.apu.get_register(addr),
+            IoRegister::Lcd | IoRegister::CgbPpu => self.ppu.get_register(addr),
+            IoRegister::OamDma => self.dma.get_register(),
+            // the unused bits read as 1
+            IoRegister::Key1 => {
+                0x7E | (u8::from(self.double_speed) << 7) | u8::from(self.speed_switch_armed)
+            }
+            IoRegister::Hdma => self.hdma.get_register(addr),
+            IoRegister::Svbk => self.work_ram.get_register(),
+            // write only
+            IoRegister::BootRom | IoRegister::Unused => 0xFF,
+        }
+    }
+
+    fn write_io(&mut self, addr: u16, value: u8) {
+        match self.io.get(addr) {
+            IoRegister::Joypad => {
+                if self.joypad.set_register(value) {
+                    self.request_interrupt(Interrupt::Joypad);
+                }
+            }
+            IoRegister::SerialData => self.serial.set_data(value),
+            IoRegister::SerialControl => self.serial.set_control(value),
+            IoRegister::Div => self.timer.reset_div(),
+            IoRegister::Tima => self.timer.set_tima(value),
+            IoRegister::Tma => self.timer.set_tma(value),
+            IoRegister::Tac => self.timer.set_tac(value),
+            IoRegister::InterruptFlag => self.interrupt_flag = value & 0b00011111,
+            IoRegister::Apu => self.apu.set_register(addr, value),
+            IoRegister::Lcd | IoRegister::CgbPpu => self.ppu.set_register(addr, value),
+            IoRegister::OamDma => self.dma.start(value),
+            IoRegister::BootRom => {
+                if let Some(boot_rom) = &mut self.boot_rom {
+                    boot_rom.set_register(value);
+                }
+            }
+            IoRegister::Key1 => self.speed_switch_armed = value & 1 != 0,
+            IoRegister::Hdma => {
+                let lcd_enabled = self.ppu.is_enabled();
+                self.hdma.set_register(addr, value, lcd_enabled);
+            }
+            IoRegister::Svbk => self.work_ram.set_register(value),
+            IoRegister::Unused => {}
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.put_u64(self.config.seed);
+        state.put_u64(self.rng.get_state());
+        self.work_ram.save_state(state);
+        self.internal_ram_two.save_state(state);
+        state.put_u8(self.interrupt_flag);
+        state.put_u8(self.interrupt_enable_register);
+        state.put_bool(self.is_boot_rom_active());
+        state.put_bool(self.double_speed);
+        state.put_bool(self.speed_switch_armed);
+        state.put_bool(self.apu_skipped);
+        self.serial.save_state(state);
+        self.timer.save_state(state);
+        self.dma.save_state(state);
+        self.hdma.save_state(state);
+        self.joypad.save_state(state);
+        self.apu.save_state(state);
+        self.ppu.save_state(state);
+        self.mbc.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.config.seed = state.get_u64()?;
+        self.rng.set_state(state.get_u64()?);
+        self.work_ram.load_state(state)?;
+        self.internal_ram_two.load_state(state)?;
+        self.interrupt_flag = state.get_u8()?;
+        self.interrupt_enable_register = state.get_u8()?;
+        let boot_rom_active = state.get_bool()?;
+        self.double_speed = state.get_bool()?;
+        self.speed_switch_armed = state.get_bool()?;
+        self.apu_skipped = state.get_bool()?;
+        if let Some(boot_rom) = &mut self.boot_rom {
+            boot_rom.set_active(boot_rom_active);
+        }
+        self.serial.load_state(state)?;
+        self.timer.load_state(state)?;
+        self.dma.load_state(state)?;
+        self.hdma.load_state(state)?;
+        self.joypad.load_state(state)?;
+        self.apu.load_state(state)?;
+        self.ppu.load_state(state)?;
+        self.mbc.load_state(state)
+    }
+
     pub fn get(&self, addr: u16) -> u8 {
-        if let Some((bank, addr)) = Bank::from_addr(addr) {
+        if let Some((bank, offset)) = Bank::from_addr(addr) {
             match bank {
-                Bank::Rom => self.rom.get(addr),
-                Bank::SwitchableRom => self.switchable_rom.get(addr),
-                Bank::Vram => self.vram.get(addr),
-                Bank::SwitchableRam => self.switchable_ram.get(addr),
-                Bank::InternalRam => self.internal_ram.get(addr),
-                Bank::InternalRamEcho => self.internal_ram_echo.get(addr),
-                Bank::Oam => self.oam.get(addr),
-                Bank::Empty => self.empty.get(addr),
-                Bank::IOPorts => self.io_ports.get(addr),
-                Bank::EmptyTwo => self.empty_two.get(addr),
-                Bank::InternalRamTwo => self.internal_ram_two.get(addr),
+                Bank::Rom
+                    if self
+                        .boot_rom
+                        .as_ref()
+                        .is_some_and(|rom| rom.is_mapped(addr)) =>
+                {
+                    self.boot_rom.as_ref().unwrap().read(addr)
+                }
+                Bank::Rom | Bank::SwitchableRom => self.mbc.read_rom(addr),
+                Bank::Vram => self.ppu.read_vram(offset),
+                Bank::SwitchableRam => self.mbc.read_ram(addr),
+                Bank::InternalRam | Bank::InternalRamEcho => self.work_ram.get(offset),
+                Bank::Oam => self.ppu.read_oam(offset),
+                Bank::Empty => self.get_prohibited(addr),
+                Bank::Io => self.read_io(addr),
+                Bank::InternalRamTwo => self.internal_ram_two.get(offset),
             }
         } else {
             self.interrupt_enable_register
@@ -139,22 +630,463 @@ impl Memory {
     }
 
     pub fn put(&mut self, addr: u16, value: u8) {
-        if let Some((bank, addr)) = Bank::from_addr(addr) {
+        if let Some((bank, offset)) = Bank::from_addr(addr) {
             match bank {
-                Bank::Rom => self.rom.set(addr, value),
-                Bank::SwitchableRom => self.switchable_rom.set(addr, value),
-                Bank::Vram => self.vram.set(addr, value),
-                Bank::SwitchableRam => self.switchable_ram.set(addr, value),
-                Bank::InternalRam => self.internal_ram.set(addr, value),
-                Bank::InternalRamEcho => self.internal_ram_echo.set(addr, value),
-                Bank::Oam => self.oam.set(addr, value),
-                Bank::Empty => self.empty.set(addr, value),
-                Bank::IOPorts => self.io_ports.set(addr, value),
-                Bank::EmptyTwo => self.empty_two.set(addr, value),
-                Bank::InternalRamTwo => self.internal_ram_two.set(addr, value),
+                Bank::Rom | Bank::SwitchableRom => self.mbc.write_rom(addr, value),
+                Bank::Vram => self.ppu.write_vram(offset, value),
+                Bank::SwitchableRam => self.mbc.write_ram(addr, value),
+                Bank::InternalRam | Bank::InternalRamEcho => self.work_ram.set(offset, value),
+                Bank::Oam => self.ppu.write_oam(offset, value),
+                // nothing there
+                Bank::Empty => {}
+                Bank::Io => self.write_io(addr, value),
+                Bank::InternalRamTwo => self.internal_ram_two.set(offset, value),
             }
         } else {
             self.interrupt_enable_register = value;
         }
     }
+
+    /// The bytes of `range` as `get` sees them, without side effects.
+    pub fn read_range(&self, range: impl RangeBounds<u16>) -> Vec<u8> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => usize::from(start),
+            Bound::Excluded(&start) => usize::from(start) + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => usize::from(end) + 1,
+            Bound::Excluded(&end) => usize::from(end),
+            Bound::Unbounded => 0x10000,
+        };
+        (start..end).map(|addr| self.get(addr as u16)).collect()
+    }
+
+    /// `put` each byte from `addr`, wrapping around at the end of the address space.
+    ///
+    /// Like `put`, the writes to the ROM area go to the registers of the cartridge
+    /// and the ones to the IO registers have their effects.
+    pub fn write_slice(&mut self, addr: u16, bytes: &[u8]) {
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.put(addr.wrapping_add(i as u16), byte);
+        }
+    }
+
+    /// The whole address space in rows of 16 bytes, for hex viewers.
+    pub fn rows(&self) -> impl Iterator<Item = MemoryRow> + '_ {
+        (0..0x1000u16).map(|row| {
+            let addr = row * MemoryRow::LENGTH as u16;
+            let bytes = std::array::from_fn(|i| self.get(addr + i as u16));
+            MemoryRow { addr, bytes }
+        })
+    }
+}
+
+/// A line of a hex viewer, see `Memory::rows`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRow {
+    pub addr: u16,
+    pub bytes: [u8; MemoryRow::LENGTH],
+}
+
+impl MemoryRow {
+    pub const LENGTH: usize = 16;
+}
+
+/// `C000: 48 65 6C 6C 6F 00 ...  Hello...........`
+impl Display for MemoryRow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04X}:", self.addr)?;
+        for byte in self.bytes {
+            write!(f, " {:02X}", byte)?;
+        }
+        write!(f, "  ")?;
+        for byte in self.bytes {
+            let c = if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            };
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new(Box::<RomOnly>::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use crate::{
+        config::{EmuConfig, Model, RamInit},
+        ppu::{Mode, Ppu},
+        savestate::{StateReader, StateWriter},
+    };
+
+    use super::{
+        bus::Bus,
+        interrupts::Interrupt,
+        joypad::Button,
+        mbc::{Mbc, RomOnly},
+        Memory,
+    };
+
+    /// Flat 32KB of writable ROM and no RAM, counting the elapsed cycles.
+    #[derive(Debug)]
+    struct FlashCart {
+        rom: Vec<u8>,
+        cycles: Rc<Cell<u32>>,
+    }
+
+    impl Mbc for FlashCart {
+        fn read_rom(&self, addr: u16) -> u8 {
+            self.rom[addr as usize]
+        }
+
+        fn write_rom(&mut self, addr: u16, value: u8) {
+            self.rom[addr as usize] = value;
+        }
+
+        fn read_ram(&self, _addr: u16) -> u8 {
+            0xFF
+        }
+
+        fn write_ram(&mut self, _addr: u16, _value: u8) {}
+
+        fn step(&mut self, cycles: u32) {
+            self.cycles.set(self.cycles.get() + cycles);
+        }
+    }
+
+    #[test]
+    fn custom_mbc() {
+        let cycles = Rc::new(Cell::new(0));
+        let mbc = FlashCart {
+            rom: vec![0; 0x8000],
+            cycles: cycles.clone(),
+        };
+        let mut memory = Memory::new(Box::new(mbc));
+        memory.put(0x0000, 0x12);
+        memory.put(0x7FFF, 0x34);
+        memory.put(0xA000, 0x56);
+        memory.tick();
+        memory.tick();
+
+        assert_eq!(memory.get(0x0000), 0x12);
+        assert_eq!(memory.get(0x7FFF), 0x34);
+        assert_eq!(memory.get(0xA000), 0xFF);
+        assert_eq!(cycles.get(), 8);
+    }
+
+    #[test]
+    fn bulk_access() {
+        let mut memory = Memory::default();
+        memory.write_slice(0xC000, b"Hello, world!\n");
+        assert_eq!(memory.read_range(0xC000..0xC005), b"Hello");
+        assert_eq!(memory.read_range(0xC007..=0xC00B), b"world");
+        // the echo of the WRAM
+        assert_eq!(memory.read_range(0xE000..0xE005), b"Hello");
+        // wraps to the ROM, which is only the registers of the cartridge
+        let rom = memory.get(0x0000);
+        memory.write_slice(0xFFFF, &[0x1F, rom.wrapping_add(1)]);
+        assert_eq!(memory.read_range(0xFFFF..), [0x1F]);
+        assert_eq!(memory.get(0x0000), rom);
+        assert_eq!(memory.read_range(..).len(), 0x10000);
+
+        let rows: Vec<_> = memory.rows().collect();
+        assert_eq!(rows.len(), 0x1000);
+        let row = rows[0xC00];
+        assert_eq!(row.addr, 0xC000);
+        assert_eq!(
+            row.to_string(),
+            "C000: 48 65 6C 6C 6F 2C 20 77 6F 72 6C 64 21 0A 00 00  Hello, world!..."
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let mut memory = Memory::new(Box::new(crate::memory::mbc::Mbc1::new(
+            vec![0; 0x8000],
+            0x2000,
+        )));
+        memory.write(0x0000, 0x0A);
+        memory.write(0xA123, 0x42);
+        memory.write(0xC456, 0x43);
+        memory.write(0x8789, 0x44);
+        memory.write(0xFF80, 0x45);
+
+        let json = serde_json::to_string(&memory).unwrap();
+        let loaded: Memory = serde_json::from_str(&json).unwrap();
+        for addr in [0xA123, 0xC456, 0x8789, 0xFF80] {
+            assert_eq!(loaded.get(addr), memory.get(addr), "{:#06X}", addr);
+        }
+        assert_eq!(serde_json::to_string(&loaded).unwrap(), json);
+
+        let custom = Memory::new(Box::new(FlashCart {
+            rom: vec![0; 0x8000],
+            cycles: Rc::default(),
+        }));
+        assert!(serde_json::to_string(&custom).is_err());
+    }
+
+    #[test]
+    fn seeded_power_on() {
+        let config = |seed| EmuConfig {
+            seed,
+            ram_init: RamInit::Random,
+            open_bus_noise: true,
+            ..Default::default()
+        };
+        let wram = |memory: &Memory| {
+            (0xC000..0xE000)
+                .map(|addr| memory.get(addr))
+                .collect::<Vec<_>>()
+        };
+        let mut memory = Memory::with_config(Box::<RomOnly>::default(), config(42));
+        let same = Memory::with_config(Box::<RomOnly>::default(), config(42));
+        let other = Memory::with_config(Box::<RomOnly>::default(), config(43));
+        assert_eq!(wram(&memory), wram(&same));
+        assert_ne!(wram(&memory), wram(&other));
+        assert_eq!(memory.get(0xE123), memory.get(0xC123));
+
+        // the noise picks up where the state was saved
+        let mut state = StateWriter::new();
+        memory.save_state(&mut state);
+        let state = state.into_inner();
+        let noise: Vec<u8> = (0..8).map(|_| memory.read(0xFEA0)).collect();
+        let mut loaded = Memory::with_config(Box::<RomOnly>::default(), config(0));
+        loaded.load_state(&mut StateReader::new(&state)).unwrap();
+        assert_eq!(loaded.get_config().seed, 42);
+        assert_eq!(
+            (0..8).map(|_| loaded.read(0xFEA0)).collect::<Vec<_>>(),
+            noise
+        );
+
+        let filled = EmuConfig {
+            ram_init: RamInit::Filled(0xFF),
+            ..Default::default()
+        };
+        let memory = Memory::with_config(Box::<RomOnly>::default(), filled);
+        assert_eq!(memory.get(0xFF80), 0xFF);
+        assert_eq!(memory.get(0xFEA0), 0x00);
+    }
+
+    #[test]
+    fn interrupt_requests_set_their_if_bit() {
+        let expected = [
+            (Interrupt::VBlank, 0b00001),
+            (Interrupt::LcdStat, 0b00010),
+            (Interrupt::Timer, 0b00100),
+            (Interrupt::Serial, 0b01000),
+            (Interrupt::Joypad, 0b10000),
+        ];
+        for (interrupt, bit) in expected {
+            let mut memory = Memory::new(Box::<RomOnly>::default());
+            assert_eq!(memory.read(0xFF0F) & 0x1F, 0);
+            memory.request_interrupt(interrupt);
+            assert_eq!(memory.read(0xFF0F), 0xE0 | bit, "{:?}", interrupt);
+        }
+
+        let mut memory = Memory::default();
+        for interrupt in Interrupt::ALL {
+            Bus::request_interrupt(&mut memory, interrupt);
+        }
+        assert_eq!(memory.read(0xFF0F), 0xFF);
+        memory.write(0xFF0F, 0x00);
+        assert_eq!(memory.read(0xFF0F), 0xE0);
+    }
+
+    #[test]
+    fn interrupt_sources_set_their_if_bit() {
+        /// Tick until an interrupt is requested, at most a frame, returns the IF bits then.
+        fn tick_until_requested(memory: &mut Memory) -> u8 {
+            for _ in 0..70224 / 4 {
+                memory.tick();
+                if memory.read(0xFF0F) & 0x1F != 0 {
+                    return memory.read(0xFF0F) & 0x1F;
+                }
+            }
+            panic!("no interrupt requested");
+        }
+
+        // entering the VBlank, with every STAT source off
+        let mut memory = Memory::default();
+        memory.write(Ppu::LCDC_REGISTER, 0x91);
+        assert_eq!(tick_until_requested(&mut memory), 0b00001);
+
+        // STAT on the HBlank
+        let mut memory = Memory::default();
+        memory.write(Ppu::STAT_REGISTER, 0x08);
+        memory.write(Ppu::LCDC_REGISTER, 0x91);
+        assert_eq!(tick_until_requested(&mut memory), 0b00010);
+        assert_eq!(memory.get_ppu().get_mode(), Mode::HBlank);
+
+        // TIMA overflow, at 262144Hz
+        let mut memory = Memory::default();
+        memory.write(0xFF05, 0xFF);
+        memory.write(0xFF07, 0x05);
+        assert_eq!(tick_until_requested(&mut memory), 0b00100);
+
+        // end of a transfer on the internal clock
+        let mut memory = Memory::default();
+        memory.write(0xFF01, 0x42);
+        memory.write(0xFF02, 0x81);
+        assert_eq!(tick_until_requested(&mut memory), 0b01000);
+        assert_eq!(memory.read(0xFF02) & 0x80, 0);
+
+        // falling edge of a selected line
+        let mut memory = Memory::default();
+        memory.write(0xFF00, 0x20);
+        memory.set_button(Button::Start, true);
+        assert_eq!(memory.read(0xFF0F) & 0x1F, 0);
+        memory.set_button(Button::Right, true);
+        assert_eq!(memory.read(0xFF0F) & 0x1F, 0b10000);
+    }
+
+    #[test]
+    fn vram_and_oam_blocking() {
+        let mut memory = Memory::default();
+        // LCD off, everything is accessible
+        memory.write(0x8000, 0x12);
+        memory.write(0xFE00, 0x34);
+        assert_eq!(memory.read(0x8000), 0x12);
+        assert_eq!(memory.read(0xFE00), 0x34);
+
+        // mode 2, only the VRAM
+        memory.put(Ppu::LCDC_REGISTER, 0x91);
+        assert_eq!(memory.read(0x8000), 0x12);
+        assert_eq!(memory.read(0xFE00), 0xFF);
+        memory.write(0xFE00, 0x56);
+        assert_eq!(memory.get(0xFE00), 0x34);
+
+        // mode 3, none
+        for _ in 0..20 {
+            memory.tick();
+        }
+        assert_eq!(memory.get_ppu().get_mode(), Mode::Drawing);
+        assert_eq!(memory.read(0x8000), 0xFF);
+        memory.write(0x8000, 0x56);
+        assert_eq!(memory.get(0x8000), 0x12);
+        assert_eq!(memory.read(0xFE00), 0xFF);
+
+        // HBlank, both
+        for _ in 0..43 {
+            memory.tick();
+        }
+        assert_eq!(memory.get_ppu().get_mode(), Mode::HBlank);
+        memory.write(0x8000, 0x56);
+        assert_eq!(memory.read(0x8000), 0x56);
+        assert_eq!(memory.read(0xFE00), 0x34);
+    }
+
+    #[test]
+    fn oam_dma() {
+        let mut memory = Memory::default();
+        for i in 0..0xA0 {
+            memory.put(0xC100 + i, i as u8);
+        }
+        memory.put(0xFF80, 0x42);
+        memory.write(0xFF46, 0xC1);
+        assert_eq!(memory.read(0xFF46), 0xC1);
+        // starts after a 1 M-cycle delay
+        memory.tick();
+        assert!(memory.get_oam_dma().is_active());
+        assert_eq!(memory.get(0xFE00), 0);
+        memory.tick();
+        assert_eq!(memory.get(0xFE00), 0);
+        memory.tick();
+        assert_eq!(memory.get(0xFE01), 1);
+
+        // only HRAM and the other bus are accessible
+        assert_eq!(memory.read(0xFE00), 0xFF);
+        assert_eq!(memory.read(0x0000), 1);
+        memory.write(0xC000, 0x12);
+        assert_eq!(memory.get(0xC000), 0);
+        assert_eq!(memory.read(0xFF80), 0x42);
+        memory.write(0x8000, 0x12);
+        assert_eq!(memory.read(0x8000), 0x12);
+
+        for _ in 0..158 {
+            memory.tick();
+        }
+        assert!(!memory.get_oam_dma().is_active());
+        assert!((0..0xA0).all(|i| memory.read(0xFE00 + i) == i as u8));
+        assert_eq!(memory.read(0x0000), memory.get(0x0000));
+    }
+
+    #[test]
+    fn hdma() {
+        let config = EmuConfig {
+            model: Model::Cgb,
+            ..Default::default()
+        };
+        let mut memory = Memory::with_config(Box::<RomOnly>::default(), config);
+        for i in 0..0x30 {
+            memory.put(0xC000 + i, i as u8);
+        }
+        assert_eq!(memory.read(0xFF55), 0xFF);
+        // general purpose, 2 blocks from 0xC000 to 0x8100, 8 M-cycles per block
+        memory.write(0xFF51, 0xC0);
+        memory.write(0xFF52, 0x00);
+        memory.write(0xFF53, 0x81);
+        memory.write(0xFF54, 0x00);
+        memory.write(0xFF55, 0x01);
+        for _ in 0..16 {
+            assert!(memory.is_hdma_copying());
+            memory.tick();
+        }
+        assert!(!memory.is_hdma_copying());
+        assert_eq!(memory.read(0xFF55), 0xFF);
+        assert!((0..0x20).all(|i| memory.get(0x8100 + i) == i as u8));
+
+        // HBlank, one block per line, the source goes on where it stopped
+        memory.write(0xFF40, 0x91);
+        memory.write(0xFF55, 0x81);
+        assert_eq!(memory.read(0xFF55), 0x01);
+        assert!(!memory.is_hdma_copying());
+        while memory.get_ppu().get_mode() != Mode::HBlank {
+            memory.tick();
+        }
+        assert!(memory.is_hdma_copying());
+        for _ in 0..8 {
+            memory.tick();
+        }
+        assert_eq!(memory.read(0xFF55), 0x00);
+        assert_eq!(memory.get(0x8120), 0x20);
+        // cancelled before the next HBlank
+        memory.write(0xFF55, 0x00);
+        assert_eq!(memory.read(0xFF55), 0x80);
+        for _ in 0..114 {
+            memory.tick();
+        }
+        assert!(!memory.is_hdma_copying());
+        assert_eq!(memory.get(0x8130), 0x00);
+    }
+
+    #[test]
+    fn prohibited_area() {
+        let mut dmg = Memory::default();
+        dmg.write(0xFEA0, 0x12);
+        assert_eq!(dmg.read(0xFEA0), 0x00);
+        // blocked with the OAM
+        dmg.write(0xFF40, 0x91);
+        assert_eq!(dmg.get_ppu().get_mode(), Mode::OamScan);
+        assert_eq!(dmg.read(0xFEA0), 0xFF);
+
+        let config = EmuConfig {
+            model: Model::Cgb,
+            ..Default::default()
+        };
+        let mut cgb = Memory::with_config(Box::<RomOnly>::default(), config);
+        cgb.write(0xFEB5, 0x12);
+        assert_eq!(cgb.read(0xFEB5), 0xBB);
+        assert_eq!(cgb.read(0xFEF0), 0xFF);
+    }
 }
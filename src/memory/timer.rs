@@ -0,0 +1,237 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+/// Where TIMA is after an overflow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum Reload {
+    #[default]
+    None,
+    /// TIMA overflowed and reads 0 for one M-cycle, writing it cancels the reload.
+    Pending,
+    /// TMA has just been loaded, writes to TIMA are ignored and writes to TMA go through.
+    Reloaded,
+}
+
+/// The timer, DIV (0xFF04), TIMA (0xFF05), TMA (0xFF06) and TAC (0xFF07) registers.
+///
+/// DIV is the upper byte of a 16 bits counter incremented every clock cycle,
+/// TIMA is incremented on the falling edge of one of its bits (selected by TAC) ANDed with the enable bit.
+/// This is what makes writes to DIV or TAC able to increment TIMA.
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    reload: Reload,
+}
+
+impl Timer {
+    pub const DIV_REGISTER: u16 = 0xFF04;
+    pub const TIMA_REGISTER: u16 = 0xFF05;
+    pub const TMA_REGISTER: u16 = 0xFF06;
+    pub const TAC_REGISTER: u16 = 0xFF07;
+
+    const ENABLE_MASK: u8 = 0b100;
+
+    pub fn get_counter(&self) -> u16 {
+        self.counter
+    }
+
+    /// Set the whole counter, for the state left by the boot ROM.
+    pub fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
+    }
+
+    pub fn get_div(&self) -> u8 {
+        (self.counter >> 8) as u8
+    }
+
+    /// Any write to DIV resets the whole counter.
+    pub fn reset_div(&mut self) {
+        let signal = self.get_signal();
+        self.counter = 0;
+        self.check_falling_edge(signal);
+    }
+
+    pub fn get_tima(&self) -> u8 {
+        self.tima
+    }
+
+    pub fn set_tima(&mut self, value: u8) {
+        match self.reload {
+            Reload::Reloaded => {}
+            Reload::Pending => {
+                self.reload = Reload::None;
+                self.tima = value;
+            }
+            Reload::None => self.tima = value,
+        }
+    }
+
+    pub fn get_tma(&self) -> u8 {
+        self.tma
+    }
+
+    pub fn set_tma(&mut self, value: u8) {
+        self.tma = value;
+        if self.reload == Reload::Reloaded {
+            self.tima = value;
+        }
+    }
+
+    pub fn get_tac(&self) -> u8 {
+        // unused bits read as 1
+        self.tac | 0xF8
+    }
+
+    pub fn set_tac(&mut self, value: u8) {
+        let signal = self.get_signal();
+        self.tac = value & 0b111;
+        self.check_falling_edge(signal);
+    }
+
+    /// Bit of the counter watched by TIMA.
+    fn get_bit(&self) -> u16 {
+        match self.tac & 0b11 {
+            0b00 => 1 << 9, // 4096Hz
+            0b01 => 1 << 3, // 262144Hz
+            0b10 => 1 << 5, // 65536Hz
+            _ => 1 << 7,    // 16384Hz
+        }
+    }
+
+    fn get_signal(&self) -> bool {
+        self.tac & Self::ENABLE_MASK != 0 && self.counter & self.get_bit() != 0
+    }
+
+    fn check_falling_edge(&mut self, old_signal: bool) {
+        if old_signal && !self.get_signal() {
+            self.increment();
+        }
+    }
+
+    fn increment(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflow {
+            self.reload = Reload::Pending;
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.put_u16(self.counter);
+        state.put_u8(self.tima);
+        state.put_u8(self.tma);
+        state.put_u8(self.tac);
+        state.put_u8(self.reload as u8);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.counter = state.get_u16()?;
+        self.tima = state.get_u8()?;
+        self.tma = state.get_u8()?;
+        self.tac = state.get_u8()?;
+        self.reload = match state.get_u8()? {
+            0 => Reload::None,
+            1 => Reload::Pending,
+            2 => Reload::Reloaded,
+            value => return Err(SaveStateError::InvalidValue(value)),
+        };
+        Ok(())
+    }
+
+    /// Advance one M-cycle, returns true to request the timer interrupt.
+    ///
+    /// Cycles: 4
+    pub fn tick(&mut self) -> bool {
+        let mut interrupt = false;
+        self.reload = match self.reload {
+            Reload::Pending => {
+                self.tima = self.tma;
+                interrupt = true;
+                Reload::Reloaded
+            }
+            Reload::None | Reload::Reloaded => Reload::None,
+        };
+        let signal = self.get_signal();
+        self.counter = self.counter.wrapping_add(4);
+        self.check_falling_edge(signal);
+        interrupt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Timer;
+
+    /// Timer at 262144Hz (every 16 cycles).
+    fn timer(tima: u8) -> Timer {
+        let mut timer = Timer::default();
+        timer.set_tac(0b101);
+        timer.set_tima(tima);
+        timer
+    }
+
+    #[test]
+    fn tima_frequency() {
+        let mut timer = timer(0);
+        for _ in 0..16 {
+            assert!(!timer.tick());
+        }
+        assert_eq!(timer.get_tima(), 4);
+        assert_eq!(timer.get_div(), 0);
+        for _ in 0..48 {
+            timer.tick();
+        }
+        assert_eq!(timer.get_div(), 1);
+    }
+
+    #[test]
+    fn div_write_can_tick_tima() {
+        let mut timer = timer(0);
+        timer.tick();
+        timer.tick();
+        // bit 3 is set, resetting the counter is a falling edge
+        assert_eq!(timer.get_counter(), 8);
+        timer.reset_div();
+        assert_eq!(timer.get_tima(), 1);
+        assert_eq!(timer.get_counter(), 0);
+
+        // same with disabling the timer
+        timer.tick();
+        timer.tick();
+        timer.set_tac(0b001);
+        assert_eq!(timer.get_tima(), 2);
+    }
+
+    #[test]
+    fn reload_is_delayed() {
+        let mut timer = timer(0xFF);
+        timer.set_tma(0x42);
+        for _ in 0..4 {
+            assert!(!timer.tick());
+        }
+        // reads 0 for a cycle before the reload
+        assert_eq!(timer.get_tima(), 0);
+        assert!(timer.tick());
+        assert_eq!(timer.get_tima(), 0x42);
+        // TIMA writes are ignored on the reload cycle, but TMA ones are copied
+        timer.set_tima(0x10);
+        timer.set_tma(0x20);
+        assert_eq!(timer.get_tima(), 0x20);
+    }
+
+    #[test]
+    fn tima_write_cancels_reload() {
+        let mut timer = timer(0xFF);
+        timer.set_tma(0x42);
+        for _ in 0..4 {
+            timer.tick();
+        }
+        timer.set_tima(0x10);
+        assert!(!timer.tick());
+        assert_eq!(timer.get_tima(), 0x10);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+/// The internal RAM at 0xC000-0xDFFF, echoed at 0xE000-0xFDFF.
+///
+/// The DMG has 2 banks of 4KB, the CGB 8, the one at 0xD000-0xDFFF being selected by SVBK.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WorkRam {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::boxed_array"))]
+    ram: Box<[u8; Self::BANK_SIZE * Self::BANKS]>,
+    /// SVBK, only the 3 lower bits are kept, 0 selects bank 1.
+    bank: u8,
+}
+
+impl Default for WorkRam {
+    fn default() -> Self {
+        WorkRam {
+            ram: Box::new([0; Self::BANK_SIZE * Self::BANKS]),
+            bank: 0,
+        }
+    }
+}
+
+impl WorkRam {
+    /// CGB only.
+    pub const SVBK_REGISTER: u16 = 0xFF70;
+    pub const BANK_SIZE: usize = 0x1000;
+    pub const BANKS: usize = 8;
+
+    const BANK_MASK: u8 = 0b111;
+
+    /// Bank mapped at 0xD000-0xDFFF, 1-7.
+    pub fn get_bank(&self) -> u8 {
+        self.bank.max(1)
+    }
+
+    /// Bits 3-7 are unused and read as 1.
+    pub fn get_register(&self) -> u8 {
+        !Self::BANK_MASK | self.bank
+    }
+
+    pub fn set_register(&mut self, value: u8) {
+        self.bank = value & Self::BANK_MASK;
+    }
+
+    /// `offset` is relative to 0xC000, or 0xE000 for the echo.
+    fn get_index(&self, offset: u16) -> usize {
+        let offset = usize::from(offset) % (Self::BANK_SIZE * 2);
+        if offset < Self::BANK_SIZE {
+            offset
+        } else {
+            usize::from(self.get_bank()) * Self::BANK_SIZE + offset - Self::BANK_SIZE
+        }
+    }
+
+    pub fn get(&self, offset: u16) -> u8 {
+        self.ram[self.get_index(offset)]
+    }
+
+    pub fn set(&mut self, offset: u16, value: u8) {
+        self.ram[self.get_index(offset)] = value;
+    }
+
+    /// Read a bank whatever SVBK is, for debuggers.
+    pub fn get_banked(&self, bank: u8, offset: u16) -> u8 {
+        let bank = usize::from(bank & Self::BANK_MASK);
+        self.ram[bank * Self::BANK_SIZE + usize::from(offset) % Self::BANK_SIZE]
+    }
+
+    /// Fill every bank from `fill`, for power on patterns.
+    pub fn init_with(&mut self, fill: impl FnOnce(&mut [u8])) {
+        fill(self.ram.as_mut_slice());
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.put_bytes(self.ram.as_slice());
+        state.put_u8(self.bank);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        state.get_bytes_into(self.ram.as_mut_slice())?;
+        self.set_register(state.get_u8()?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WorkRam;
+
+    #[test]
+    fn banks() {
+        let mut ram = WorkRam::default();
+        assert_eq!(ram.get_bank(), 1);
+        ram.set(0x1000, 1);
+        ram.set_register(0);
+        assert_eq!(ram.get_register(), 0xF8);
+        assert_eq!(ram.get(0x1000), 1);
+        ram.set_register(0xFA);
+        assert_eq!(ram.get_register(), 0xFA);
+        assert_eq!(ram.get(0x1000), 0);
+        ram.set(0x1000, 2);
+        // bank 0 is fixed, the echo follows the selected bank
+        ram.set(0x0000, 0x42);
+        assert_eq!(ram.get(0x2000), 0x42);
+        assert_eq!(ram.get(0x3000), 2);
+        assert_eq!(ram.get_banked(1, 0x1000), 1);
+        assert_eq!(ram.get_banked(2, 0x1000), 2);
+    }
+}
@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+#[cfg(feature = "metrics")]
+use std::time::Instant;
+
+/// Host time spent emulating a frame, by subsystem.
+///
+/// Only measured with the `metrics` feature, the timing itself slows the emulation down
+/// so the numbers are for comparing the subsystems rather than absolute.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Metrics {
+    /// Everything not counted by the others, fetching, decoding and executing instructions.
+    pub cpu: Duration,
+    /// Stepping the PPU, rendering the pixels.
+    pub ppu: Duration,
+    /// Stepping the APU, synthesizing and resampling the samples.
+    pub apu: Duration,
+    /// Routing the reads and writes of the CPU, and stepping the rest of the machine
+    /// (timer, serial port, OAM DMA, cartridge).
+    pub bus: Duration,
+}
+
+impl Metrics {
+    pub fn get_total(&self) -> Duration {
+        self.cpu + self.ppu + self.apu + self.bus
+    }
+}
+
+/// Measures the time until it's stopped, does nothing without the `metrics` feature.
+#[derive(Debug)]
+pub(crate) struct Stopwatch {
+    #[cfg(feature = "metrics")]
+    start: Instant,
+}
+
+impl Stopwatch {
+    #[inline(always)]
+    pub fn start() -> Self {
+        Stopwatch {
+            #[cfg(feature = "metrics")]
+            start: Instant::now(),
+        }
+    }
+
+    /// Add the elapsed time to `counter`.
+    #[inline(always)]
+    pub fn stop(self, _counter: &mut Duration) {
+        #[cfg(feature = "metrics")]
+        {
+            *_counter += self.start.elapsed();
+        }
+    }
+}
@@ -0,0 +1,437 @@
+//! Deterministic input recording and replay, for TAS tools and regression tests.
+//!
+//! A movie is where the run started and the buttons held during each frame.
+//! The buttons are sampled when a frame starts, so the frontend must only change them
+//! between frames, through the recorder.
+//!
+//! # Format
+//!
+//! Values are little endian, blocks are a `u32` length followed by the bytes.
+//!
+//! - the magic `GBMV`, then the format version as a `u16`
+//! - the start, a `u8`:
+//!   - 0, power on: the battery backed RAM as a block, then a `u8` set to 1 if the cartridge
+//!     has a RTC followed by its time as `days: u16, hours: u8, minutes: u8, seconds: u8`,
+//!     then (since version 2) the seed of the config as a `u64` and its RAM init as a `u8`:
+//!     0 zero, 1 filled followed by the value as a `u8`, 2 random
+//!   - 1, a savestate of `Emulator::save_state` as a block, which holds the seed
+//! - the frames as a block, one byte per frame of pressed buttons:
+//!   bit 0 Right, 1 Left, 2 Up, 3 Down, 4 A, 5 B, 6 Select, 7 Start
+
+use std::fmt::Display;
+
+use crate::{
+    config::RamInit,
+    emulator::{Emulator, StopReason},
+    memory::{joypad::Button, mbc::mbc3::RtcTime},
+    savestate::{SaveStateError, StateReader, StateWriter},
+};
+
+/// Start of every movie.
+pub const MAGIC: [u8; 4] = *b"GBMV";
+
+pub const FORMAT_VERSION: u16 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    /// The data doesn't start like a movie.
+    InvalidHeader,
+    /// Made by a newer version of the emulator.
+    UnsupportedVersion(u16),
+    /// The movie is truncated or corrupted, or its savestate didn't load.
+    InvalidData(SaveStateError),
+    /// Played on an emulator whose config doesn't power on like the recording,
+    /// this is the seed of the movie.
+    SeedMismatch(PowerOnSeed),
+}
+
+impl Display for MovieError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MovieError::InvalidHeader => write!(f, "not a movie"),
+            MovieError::UnsupportedVersion(version) => write!(
+                f,
+                "movie format {} is newer than the supported {}",
+                version, FORMAT_VERSION
+            ),
+            MovieError::InvalidData(err) => write!(f, "invalid movie: {}", err),
+            MovieError::SeedMismatch(seed) => write!(
+                f,
+                "the movie was recorded with the seed {} and {:?}, the emulator must use them",
+                seed.seed, seed.ram_init
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<SaveStateError> for MovieError {
+    fn from(err: SaveStateError) -> Self {
+        MovieError::InvalidData(err)
+    }
+}
+
+/// What a power on draws from the `EmuConfig`, recorded so a replay powers on the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerOnSeed {
+    pub seed: u64,
+    pub ram_init: RamInit,
+}
+
+impl PowerOnSeed {
+    fn of(emulator: &Emulator) -> Self {
+        let config = emulator.get_config();
+        PowerOnSeed {
+            seed: config.seed,
+            ram_init: config.ram_init,
+        }
+    }
+}
+
+/// Where a movie starts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieStart {
+    /// A reset with this battery backed RAM and RTC time.
+    ///
+    /// The emulator playing it must have the seed of the recording,
+    /// it is `None` for movies of format 1 which didn't record it.
+    PowerOn {
+        save_data: Vec<u8>,
+        rtc: Option<RtcTime>,
+        seed: Option<PowerOnSeed>,
+    },
+    /// A savestate, which holds the RTC and the battery backed RAM too.
+    State(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub start: MovieStart,
+    /// Pressed buttons of each frame, as `Joypad::get_pressed`.
+    pub frames: Vec<u8>,
+}
+
+impl Movie {
+    /// Put the emulator where the movie starts.
+    fn start(&self, emulator: &mut Emulator) -> Result<(), MovieError> {
+        match &self.start {
+            MovieStart::PowerOn {
+                save_data,
+                rtc,
+                seed,
+            } => {
+                if let Some(seed) = seed {
+                    if *seed != PowerOnSeed::of(emulator) {
+                        return Err(MovieError::SeedMismatch(*seed));
+                    }
+                }
+                emulator.reset();
+                emulator.load_save_data(save_data);
+                if let (Some(time), Some(rtc)) = (rtc, emulator.get_rtc_mut()) {
+                    rtc.set_time(*time);
+                }
+            }
+            MovieStart::State(state) => emulator.load_state(state)?,
+        }
+        emulator.release_all_buttons();
+        Ok(())
+    }
+
+    /// Serialize in the format described in the module documentation.
+    ///
+    /// Imported movies of format 1 are written back in that format, they have no seed.
+    pub fn export(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        for byte in MAGIC {
+            writer.put_u8(byte);
+        }
+        let version = match &self.start {
+            MovieStart::PowerOn { seed: None, .. } => 1,
+            _ => FORMAT_VERSION,
+        };
+        writer.put_u16(version);
+        match &self.start {
+            MovieStart::PowerOn {
+                save_data,
+                rtc,
+                seed,
+            } => {
+                writer.put_u8(0);
+                writer.put_bytes(save_data);
+                writer.put_bool(rtc.is_some());
+                if let Some(time) = rtc {
+                    writer.put_u16(time.days);
+                    writer.put_u8(time.hours);
+                    writer.put_u8(time.minutes);
+                    writer.put_u8(time.seconds);
+                }
+                if let Some(seed) = seed {
+                    writer.put_u64(seed.seed);
+                    match seed.ram_init {
+                        RamInit::Zero => writer.put_u8(0),
+                        RamInit::Filled(value) => {
+                            writer.put_u8(1);
+                            writer.put_u8(value);
+                        }
+                        RamInit::Random => writer.put_u8(2),
+                    }
+                }
+            }
+            MovieStart::State(state) => {
+                writer.put_u8(1);
+                writer.put_bytes(state);
+            }
+        }
+        writer.put_bytes(&self.frames);
+        writer.into_inner()
+    }
+
+    pub fn import(data: &[u8]) -> Result<Self, MovieError> {
+        let mut reader = StateReader::new(data);
+        let mut magic = [0; 4];
+        for byte in magic.iter_mut() {
+            *byte = reader.get_u8().map_err(|_| MovieError::InvalidHeader)?;
+        }
+        if magic != MAGIC {
+            return Err(MovieError::InvalidHeader);
+        }
+        let version = reader.get_u16()?;
+        if version > FORMAT_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let start = match reader.get_u8()? {
+            0 => {
+                let save_data = reader.get_bytes()?.to_vec();
+                let rtc = if reader.get_bool()? {
+                    Some(RtcTime {
+                        days: reader.get_u16()?,
+                        hours: reader.get_u8()?,
+                        minutes: reader.get_u8()?,
+                        seconds: reader.get_u8()?,
+                    })
+                } else {
+                    None
+                };
+                let seed = if version >= 2 {
+                    let seed = reader.get_u64()?;
+                    let ram_init = match reader.get_u8()? {
+                        0 => RamInit::Zero,
+                        1 => RamInit::Filled(reader.get_u8()?),
+                        2 => RamInit::Random,
+                        kind => return Err(SaveStateError::InvalidValue(kind).into()),
+                    };
+                    Some(PowerOnSeed { seed, ram_init })
+                } else {
+                    None
+                };
+                MovieStart::PowerOn {
+                    save_data,
+                    rtc,
+                    seed,
+                }
+            }
+            1 => MovieStart::State(reader.get_bytes()?.to_vec()),
+            kind => return Err(SaveStateError::InvalidValue(kind).into()),
+        };
+        let frames = reader.get_bytes()?.to_vec();
+        Ok(Movie { start, frames })
+    }
+}
+
+/// Runs the emulator a frame at a time, logging the buttons held during each one.
+#[derive(Debug)]
+pub struct InputRecorder {
+    movie: Movie,
+}
+
+impl InputRecorder {
+    /// Record from the current state of the emulator.
+    pub fn from_state(emulator: &Emulator) -> Self {
+        Self::with_start(MovieStart::State(emulator.save_state()))
+    }
+
+    /// Reset the emulator and record from power on, the boot included.
+    pub fn from_power_on(emulator: &mut Emulator) -> Self {
+        emulator.reset();
+        let rtc = emulator.get_rtc_mut().map(|rtc| rtc.get_time());
+        Self::with_start(MovieStart::PowerOn {
+            save_data: emulator.get_save_data(),
+            rtc,
+            seed: Some(PowerOnSeed::of(emulator)),
+        })
+    }
+
+    fn with_start(start: MovieStart) -> Self {
+        InputRecorder {
+            movie: Movie {
+                start,
+                frames: Vec::new(),
+            },
+        }
+    }
+
+    /// Log the buttons held right now and run a frame with them.
+    pub fn run_frame(&mut self, emulator: &mut Emulator) -> StopReason {
+        let pressed = emulator.get_cpu().get_bus().get_joypad().get_pressed();
+        self.movie.frames.push(pressed);
+        emulator.run_frame()
+    }
+
+    /// Frames recorded so far.
+    pub fn get_frame_count(&self) -> usize {
+        self.movie.frames.len()
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+/// Feeds the buttons of a movie back, a frame at a time.
+#[derive(Debug)]
+pub struct MoviePlayer {
+    movie: Movie,
+    frame: usize,
+}
+
+impl MoviePlayer {
+    /// Put the emulator where the movie starts, the frontend shouldn't touch
+    /// the buttons until the end of the movie.
+    pub fn start(movie: Movie, emulator: &mut Emulator) -> Result<Self, MovieError> {
+        movie.start(emulator)?;
+        Ok(MoviePlayer { movie, frame: 0 })
+    }
+
+    /// Run the next frame with its buttons, `None` once the movie is over,
+    /// the buttons are then all released.
+    pub fn run_frame(&mut self, emulator: &mut Emulator) -> Option<StopReason> {
+        let Some(&pressed) = self.movie.frames.get(self.frame) else {
+            emulator.release_all_buttons();
+            return None;
+        };
+        self.frame += 1;
+        for button in Button::ALL {
+            emulator.set_button(button, pressed & button.get_mask() != 0);
+        }
+        Some(emulator.run_frame())
+    }
+
+    /// Frames played so far.
+    pub fn get_frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+
+    pub fn get_movie(&self) -> &Movie {
+        &self.movie
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::EmuConfig, cpu::registers::Register};
+
+    fn test_emulator() -> Emulator {
+        test_emulator_with_config(EmuConfig::default())
+    }
+
+    fn test_emulator_with_config(config: EmuConfig) -> Emulator {
+        // LD A, 0x91; LDH (0x40), A; LD A, 0x20; LDH (0x00), A; LDH A, (0x00); ADD B; LD B, A; JR -6
+        let program = [
+            0x3E, 0x91, 0xE0, 0x40, 0x3E, 0x20, 0xE0, 0x00, 0xF0, 0x00, 0x80, 0x47, 0x18, 0xFA,
+        ];
+        Emulator::from_program_with_config(&program, config)
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let mut emulator = test_emulator();
+        emulator.run_frame();
+        let mut recorder = InputRecorder::from_state(&emulator);
+        for frame in 0..10 {
+            emulator.set_button(Button::Right, frame % 3 == 0);
+            emulator.set_button(Button::Up, frame >= 5);
+            recorder.run_frame(&mut emulator);
+        }
+        let end = emulator.save_state();
+        let b = emulator.get_cpu().get_reg(Register::B);
+        let movie = recorder.finish();
+        assert_eq!(movie.frames[..4], [0x01, 0x00, 0x00, 0x01]);
+        assert_eq!(movie.frames[5], 0x04);
+
+        let movie = Movie::import(&movie.export()).unwrap();
+        let mut replay = test_emulator();
+        let mut player = MoviePlayer::start(movie, &mut replay).unwrap();
+        while !player.is_finished() {
+            player.run_frame(&mut replay).unwrap();
+        }
+        assert_eq!(player.get_frame(), 10);
+        assert_eq!(replay.get_cpu().get_reg(Register::B), b);
+        assert_eq!(replay.save_state(), end);
+        assert_eq!(player.run_frame(&mut replay), None);
+
+        let power_on = Movie {
+            start: MovieStart::PowerOn {
+                save_data: vec![1, 2, 3],
+                rtc: Some(RtcTime {
+                    days: 300,
+                    hours: 1,
+                    minutes: 2,
+                    seconds: 3,
+                }),
+                seed: Some(PowerOnSeed {
+                    seed: 42,
+                    ram_init: RamInit::Filled(0xAA),
+                }),
+            },
+            frames: vec![0xFF, 0x00],
+        };
+        assert_eq!(Movie::import(&power_on.export()).unwrap(), power_on);
+        let mut format_1 = power_on.clone();
+        if let MovieStart::PowerOn { seed, .. } = &mut format_1.start {
+            *seed = None;
+        }
+        assert_eq!(format_1.export()[4..6], 1u16.to_le_bytes());
+        assert_eq!(Movie::import(&format_1.export()).unwrap(), format_1);
+        assert_eq!(Movie::import(b"GBST"), Err(MovieError::InvalidHeader));
+    }
+
+    #[test]
+    fn power_on_seed() {
+        let config = EmuConfig {
+            seed: 7,
+            ram_init: RamInit::Random,
+            ..Default::default()
+        };
+        let mut emulator = test_emulator_with_config(config.clone());
+        let mut recorder = InputRecorder::from_power_on(&mut emulator);
+        for _ in 0..3 {
+            recorder.run_frame(&mut emulator);
+        }
+        let end = emulator.save_state();
+        let movie = Movie::import(&recorder.finish().export()).unwrap();
+
+        let mut other = test_emulator_with_config(EmuConfig {
+            seed: 8,
+            ..config.clone()
+        });
+        let seed = PowerOnSeed {
+            seed: 7,
+            ram_init: RamInit::Random,
+        };
+        assert_eq!(
+            MoviePlayer::start(movie.clone(), &mut other).unwrap_err(),
+            MovieError::SeedMismatch(seed)
+        );
+
+        let mut replay = test_emulator_with_config(config);
+        let mut player = MoviePlayer::start(movie, &mut replay).unwrap();
+        while player.run_frame(&mut replay).is_some() {}
+        assert_eq!(replay.save_state(), end);
+    }
+}
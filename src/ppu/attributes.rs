@@ -0,0 +1,37 @@
+/// Attributes of a BG or window map entry, CGB only.
+///
+/// They are in the second VRAM bank, at the same address as the tile number in the first one.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BgAttributes(pub u8);
+
+impl BgAttributes {
+    const PALETTE: u8 = 0b111;
+    const BANK: u8 = 1 << 3;
+    const X_FLIP: u8 = 1 << 5;
+    const Y_FLIP: u8 = 1 << 6;
+    const PRIORITY: u8 = 1 << 7;
+
+    /// BG palette, 0-7.
+    pub fn get_palette(self) -> u8 {
+        self.0 & Self::PALETTE
+    }
+
+    /// VRAM bank of the tile data.
+    pub fn get_vram_bank(self) -> u8 {
+        u8::from(self.0 & Self::BANK != 0)
+    }
+
+    pub fn is_x_flipped(self) -> bool {
+        self.0 & Self::X_FLIP != 0
+    }
+
+    pub fn is_y_flipped(self) -> bool {
+        self.0 & Self::Y_FLIP != 0
+    }
+
+    /// Colors 1-3 of the tile are drawn over the sprites, unless LCDC bit 0 is cleared.
+    pub fn has_priority(self) -> bool {
+        self.0 & Self::PRIORITY != 0
+    }
+}
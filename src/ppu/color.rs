@@ -0,0 +1,117 @@
+/// A pixel color, 8 bits per channel.
+///
+/// Every output (screenshots, recordings, frontends) converts from this,
+/// so DMG shades and CGB colors end up the same everywhere.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const WHITE: Self = Self::new(0xFF, 0xFF, 0xFF);
+    pub const BLACK: Self = Self::new(0x00, 0x00, 0x00);
+
+    /// The DMG shades, a plain grey ramp from 0 (white) to 3 (black).
+    pub const SHADES: [Self; 4] = [
+        Self::WHITE,
+        Self::new(0xAA, 0xAA, 0xAA),
+        Self::new(0x55, 0x55, 0x55),
+        Self::BLACK,
+    ];
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Color { r, g, b }
+    }
+
+    /// From 0xRRGGBB.
+    pub const fn from_rgb888(color: u32) -> Self {
+        Self::new((color >> 16) as u8, (color >> 8) as u8, color as u8)
+    }
+
+    /// A DMG shade, only the 2 lower bits are used.
+    pub const fn from_shade(shade: u8) -> Self {
+        Self::SHADES[(shade & 0b11) as usize]
+    }
+
+    /// From the CGB format, 5 bits per channel, red in the low bits.
+    pub const fn from_rgb555(color: u16) -> Self {
+        const fn expand(channel: u16) -> u8 {
+            let channel = (channel & 0x1F) as u8;
+            // copy the high bits in the low ones so 0x1F gives 0xFF
+            (channel << 3) | (channel >> 2)
+        }
+        Self::new(expand(color), expand(color >> 5), expand(color >> 10))
+    }
+
+    pub const fn to_rgb555(self) -> u16 {
+        (self.r as u16 >> 3) | ((self.g as u16 >> 3) << 5) | ((self.b as u16 >> 3) << 10)
+    }
+
+    /// To 0xRRGGBB.
+    pub const fn to_rgb888(self) -> u32 {
+        ((self.r as u32) << 16) | ((self.g as u32) << 8) | self.b as u32
+    }
+
+    /// To 0xRRGGBBAA, opaque.
+    pub const fn to_rgba8888(self) -> u32 {
+        (self.to_rgb888() << 8) | 0xFF
+    }
+
+    /// To the 16 bits format of most small screens (and libretro), red in the high bits.
+    pub const fn to_rgb565(self) -> u16 {
+        ((self.r as u16 >> 3) << 11) | ((self.g as u16 >> 2) << 5) | (self.b as u16 >> 3)
+    }
+
+    pub const fn to_rgb(self) -> [u8; 3] {
+        [self.r, self.g, self.b]
+    }
+
+    pub const fn to_rgba(self) -> [u8; 4] {
+        [self.r, self.g, self.b, 0xFF]
+    }
+}
+
+/// Pixels as 24 bits RGB, byte by byte.
+pub fn to_rgb_bytes(pixels: impl IntoIterator<Item = Color>) -> Vec<u8> {
+    pixels.into_iter().flat_map(Color::to_rgb).collect()
+}
+
+/// Pixels as 32 bits RGBA, byte by byte, the layout of canvases and most textures.
+pub fn to_rgba_bytes(pixels: impl IntoIterator<Item = Color>) -> Vec<u8> {
+    pixels.into_iter().flat_map(Color::to_rgba).collect()
+}
+
+pub fn to_rgb565(pixels: impl IntoIterator<Item = Color>) -> Vec<u16> {
+    pixels.into_iter().map(Color::to_rgb565).collect()
+}
+
+/// Colors of a framebuffer of DMG shades.
+pub fn from_shades(shades: &[u8]) -> impl Iterator<Item = Color> + '_ {
+    shades.iter().map(|&shade| Color::from_shade(shade))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_shades, to_rgba_bytes, Color};
+
+    #[test]
+    fn conversions() {
+        assert_eq!(Color::from_rgb555(0x7FFF), Color::WHITE);
+        assert_eq!(Color::from_rgb555(0x001F), Color::new(0xFF, 0, 0));
+        let color = Color::from_rgb888(0x84A5FF);
+        assert_eq!(color.to_rgb555(), 0x7E90);
+        assert_eq!(Color::from_rgb555(color.to_rgb555()).to_rgb555(), 0x7E90);
+        assert_eq!(color.to_rgba8888(), 0x84A5FFFF);
+        assert_eq!(Color::WHITE.to_rgb565(), 0xFFFF);
+        assert_eq!(color.to_rgb565(), 0x853F);
+
+        let rgba = to_rgba_bytes(from_shades(&[0, 3, 6]));
+        assert_eq!(
+            rgba,
+            [0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0xFF, 0x55, 0x55, 0x55, 0xFF]
+        );
+    }
+}
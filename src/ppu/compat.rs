@@ -0,0 +1,257 @@
+use crate::memory::Memory;
+
+use super::{
+    color::{self, Color},
+    PixelSource, Ppu,
+};
+
+/// 4 colors, from color index 0 to 3 of a DMG palette.
+pub type Colors = [Color; 4];
+
+/// Colors the CGB gives to a DMG game, one set per DMG palette.
+///
+/// The CGB boot ROM picks them from the title of the cartridge,
+/// or from a direction + button combo held while the logo shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompatPalette {
+    pub bg: Colors,
+    pub obj0: Colors,
+    pub obj1: Colors,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComboDirection {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ComboButton {
+    #[default]
+    None,
+    A,
+    B,
+}
+
+const fn colors(colors: [u32; 4]) -> Colors {
+    [
+        Color::from_rgb888(colors[0]),
+        Color::from_rgb888(colors[1]),
+        Color::from_rgb888(colors[2]),
+        Color::from_rgb888(colors[3]),
+    ]
+}
+
+const fn same(bg: [u32; 4]) -> CompatPalette {
+    CompatPalette {
+        bg: colors(bg),
+        obj0: colors(bg),
+        obj1: colors(bg),
+    }
+}
+
+const fn split(bg: [u32; 4], obj0: [u32; 4], obj1: [u32; 4]) -> CompatPalette {
+    CompatPalette {
+        bg: colors(bg),
+        obj0: colors(obj0),
+        obj1: colors(obj1),
+    }
+}
+
+const WHITE: u32 = 0xFFFFFF;
+const BLACK: u32 = 0x000000;
+const RED: [u32; 4] = [WHITE, 0xFF8484, 0x943A3A, BLACK];
+const GREEN: [u32; 4] = [WHITE, 0x7BFF31, 0x008400, BLACK];
+const BLUE: [u32; 4] = [WHITE, 0x63A5FF, 0x0000FF, BLACK];
+const BROWN: [u32; 4] = [WHITE, 0xFFAD63, 0x843100, BLACK];
+
+/// A checksum of the title, and its 4th letter when several titles share the checksum.
+struct TitleEntry {
+    checksum: u8,
+    fourth_letter: Option<u8>,
+    palette: CompatPalette,
+}
+
+/// The titles recognized, a part of the table of the CGB boot ROM.
+const TITLES: [TitleEntry; 6] = [
+    // POKEMON RED
+    TitleEntry {
+        checksum: 0x14,
+        fourth_letter: None,
+        palette: split(RED, GREEN, BLUE),
+    },
+    // POKEMON GREEN
+    TitleEntry {
+        checksum: 0xAA,
+        fourth_letter: None,
+        palette: split([WHITE, 0x7BFF31, 0x0063C5, BLACK], RED, RED),
+    },
+    // POKEMON BLUE
+    TitleEntry {
+        checksum: 0x61,
+        fourth_letter: Some(b'E'),
+        palette: split(BLUE, RED, BLUE),
+    },
+    // SUPER MARIOLAND
+    TitleEntry {
+        checksum: 0x46,
+        fourth_letter: Some(b'E'),
+        palette: split(
+            [WHITE, 0xADAD84, 0x42737B, BLACK],
+            [WHITE, 0xFF7300, 0x944200, BLACK],
+            [WHITE, 0x5ABDFF, 0xFF0000, 0x0000FF],
+        ),
+    },
+    // ZELDA
+    TitleEntry {
+        checksum: 0x70,
+        fourth_letter: None,
+        palette: split(RED, [WHITE, 0x00FF00, 0x318400, 0x004A00], RED),
+    },
+    // TETRIS
+    TitleEntry {
+        checksum: 0xDB,
+        fourth_letter: None,
+        palette: split(
+            [WHITE, 0xFFFF00, 0xFF0000, BLACK],
+            [WHITE, 0xFFFF00, 0xFF0000, BLACK],
+            [WHITE, 0x5ABDFF, 0xFF0000, 0x0000FF],
+        ),
+    },
+];
+
+impl CompatPalette {
+    const TITLE_START: u16 = 0x0134;
+    const TITLE_END: u16 = 0x0143;
+    const NEW_LICENSEE: u16 = 0x0144;
+    const OLD_LICENSEE: u16 = 0x014B;
+
+    /// Unknown titles, and games not published by Nintendo, get the same as Right + A.
+    pub const DEFAULT: Self = split([WHITE, 0x7BFF31, 0x0063C5, BLACK], RED, RED);
+
+    /// Plain grey shades, the colors are the same for all palettes.
+    pub const GREYSCALE: Self = same([WHITE, 0xA5A5A5, 0x525252, BLACK]);
+
+    /// The palette selected by holding a direction and optionally A or B during the boot.
+    pub const fn from_combo(direction: ComboDirection, button: ComboButton) -> Self {
+        match (direction, button) {
+            (ComboDirection::Up, ComboButton::None) => same(BROWN),
+            (ComboDirection::Up, ComboButton::A) => split(RED, GREEN, BLUE),
+            (ComboDirection::Up, ComboButton::B) => same([0xFFE6C5, 0xCE9C84, 0x846B29, 0x5A3108]),
+            (ComboDirection::Left, ComboButton::None) => split(BLUE, RED, GREEN),
+            (ComboDirection::Left, ComboButton::A) => {
+                split([WHITE, 0x8C8CDE, 0x52528C, BLACK], RED, BROWN)
+            }
+            (ComboDirection::Left, ComboButton::B) => Self::GREYSCALE,
+            (ComboDirection::Down, ComboButton::None) => {
+                same([0xFFFFA5, 0xFF9494, 0x9494FF, BLACK])
+            }
+            (ComboDirection::Down, ComboButton::A) => same([WHITE, 0xFFFF00, 0xFF0000, BLACK]),
+            (ComboDirection::Down, ComboButton::B) => {
+                split([WHITE, 0xFFFF00, 0x7B4A00, BLACK], BLUE, GREEN)
+            }
+            (ComboDirection::Right, ComboButton::None) => same([WHITE, 0x52FF00, 0xFF4200, BLACK]),
+            (ComboDirection::Right, ComboButton::A) => Self::DEFAULT,
+            (ComboDirection::Right, ComboButton::B) => same([BLACK, 0x008484, 0xFFDE00, WHITE]),
+        }
+    }
+
+    /// The palette the CGB boot ROM picks from the header of the cartridge.
+    ///
+    /// Only games published by Nintendo are colorized, from the sum of the bytes of their title,
+    /// with the 4th letter to tell apart titles with the same sum.
+    pub fn for_cartridge(memory: &Memory) -> Self {
+        let licensee = memory.get(Self::OLD_LICENSEE);
+        let nintendo = licensee == 0x01
+            || (licensee == 0x33
+                && memory.get(Self::NEW_LICENSEE) == b'0'
+                && memory.get(Self::NEW_LICENSEE + 1) == b'1');
+        if !nintendo {
+            return Self::DEFAULT;
+        }
+        let checksum = (Self::TITLE_START..=Self::TITLE_END)
+            .fold(0u8, |sum, addr| sum.wrapping_add(memory.get(addr)));
+        let fourth_letter = memory.get(Self::TITLE_START + 3);
+        TITLES
+            .iter()
+            .find(|entry| {
+                entry.checksum == checksum
+                    && entry
+                        .fourth_letter
+                        .is_none_or(|letter| letter == fourth_letter)
+            })
+            .map_or(Self::DEFAULT, |entry| entry.palette)
+    }
+
+    /// Colors of the DMG palette `source`.
+    pub fn get_colors(&self, source: PixelSource) -> &Colors {
+        match source {
+            PixelSource::Bg => &self.bg,
+            PixelSource::Obj0 => &self.obj0,
+            PixelSource::Obj1 => &self.obj1,
+        }
+    }
+
+    /// The colors of the last frame of the PPU, row by row.
+    pub fn get_pixels<'a>(&'a self, ppu: &'a Ppu) -> impl Iterator<Item = Color> + 'a {
+        ppu.get_framebuffer()
+            .iter()
+            .zip(ppu.get_pixel_sources().iter())
+            .map(|(&shade, &source)| self.get_colors(source)[usize::from(shade & 0b11)])
+    }
+
+    /// The last frame of the PPU colorized, as 24 bits RGB, row by row.
+    pub fn colorize(&self, ppu: &Ppu) -> Vec<u8> {
+        color::to_rgb_bytes(self.get_pixels(ppu))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        memory::Memory,
+        ppu::{Color, PixelSource},
+    };
+
+    use super::{ComboButton, ComboDirection, CompatPalette};
+
+    fn memory_with_title(title: &[u8], licensee: u8) -> Memory {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0134..0x0134 + title.len()].copy_from_slice(title);
+        rom[0x014B] = licensee;
+        Memory::from_rom(rom).unwrap()
+    }
+
+    #[test]
+    fn title_lookup() {
+        let red = CompatPalette::for_cartridge(&memory_with_title(b"POKEMON RED", 0x01));
+        assert_eq!(red.bg[1], Color::new(0xFF, 0x84, 0x84));
+        assert_eq!(
+            red,
+            CompatPalette::from_combo(ComboDirection::Up, ComboButton::A)
+        );
+
+        // same checksum, told apart by the 4th letter
+        let mario = CompatPalette::for_cartridge(&memory_with_title(b"SUPER MARIOLAND", 0x01));
+        assert_eq!(mario.bg[1], Color::new(0xAD, 0xAD, 0x84));
+        let metroid = CompatPalette::for_cartridge(&memory_with_title(b"METROID2", 0x01));
+        assert_eq!(metroid, CompatPalette::DEFAULT);
+
+        // only for Nintendo games
+        let other = CompatPalette::for_cartridge(&memory_with_title(b"POKEMON RED", 0x02));
+        assert_eq!(other, CompatPalette::DEFAULT);
+    }
+
+    #[test]
+    fn colorize() {
+        let palette = CompatPalette::from_combo(ComboDirection::Right, ComboButton::B);
+        let memory = Memory::default();
+        let rgb = palette.colorize(memory.get_ppu());
+        assert_eq!(rgb.len(), 160 * 144 * 3);
+        // inverted, white is black
+        assert_eq!(rgb[..3], [0, 0, 0]);
+        assert_eq!(palette.get_colors(PixelSource::Obj1)[3], Color::WHITE);
+    }
+}
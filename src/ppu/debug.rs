@@ -0,0 +1,323 @@
+//! Decoded views of the VRAM and the OAM, for tile, map and sprite viewers.
+//!
+//! The pixels are color indexes (0-3) before any palette, apply `get_register(BGP_REGISTER)`
+//! or the palette RAM to show them like the game does.
+
+use super::{BgAttributes, Ppu, Sprite};
+
+/// A tile of the VRAM, decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tile {
+    /// 0-383, the tile at 0x8000 + 16 * index.
+    pub index: u16,
+    /// VRAM bank of the tile, 1 only exists on CGB.
+    pub bank: u8,
+    /// Color indexes, row by row.
+    pub pixels: [u8; Tile::SIZE * Tile::SIZE],
+}
+
+impl Tile {
+    pub const SIZE: usize = 8;
+    /// Tiles in a VRAM bank.
+    pub const COUNT: usize = 384;
+
+    pub fn get_addr(&self) -> u16 {
+        0x8000 + self.index * 16
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * Self::SIZE + x]
+    }
+}
+
+/// One of the two tile maps, LCDC selects which one the BG and the window use.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TileMap {
+    /// At 0x9800.
+    #[default]
+    Low,
+    /// At 0x9C00.
+    High,
+}
+
+impl TileMap {
+    /// Offset of the map in the VRAM.
+    fn get_offset(self) -> u16 {
+        match self {
+            TileMap::Low => 0x1800,
+            TileMap::High => 0x1C00,
+        }
+    }
+}
+
+/// A rectangle of a tile map, in pixels.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MapRect {
+    pub x: u8,
+    pub y: u8,
+    pub width: u8,
+    pub height: u8,
+}
+
+/// A whole tile map drawn with the tile data selected by LCDC, and the CGB attributes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TileMapView {
+    pub map: TileMap,
+    /// Color indexes, row by row.
+    pub pixels: Box<[u8; TileMapView::SIZE * TileMapView::SIZE]>,
+    /// What the screen shows of the map, at SCX/SCY, when it is the BG map.
+    /// It wraps around the edges of the map.
+    pub viewport: Option<MapRect>,
+    /// What the screen shows of the map as the window, when it is the window map
+    /// and the window is enabled and on screen.
+    pub window: Option<MapRect>,
+}
+
+impl TileMapView {
+    pub const SIZE: usize = 256;
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * Self::SIZE + x]
+    }
+}
+
+/// The OAM, and what the OAM scan made of it, for a sprite debugger.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpriteView {
+    /// The 40 entries, in OAM order.
+    pub sprites: [Sprite; Sprite::COUNT],
+    /// 8 or 16, per LCDC.
+    pub height: u8,
+    /// LY, the line of the selection.
+    pub line: u8,
+    /// OAM indexes of the sprites selected on the line, see `Ppu::get_selected_sprites`.
+    pub selected: Vec<u8>,
+}
+
+impl SpriteView {
+    pub fn is_selected(&self, index: u8) -> bool {
+        self.selected.contains(&index)
+    }
+
+    /// The selected sprites, in OAM order.
+    pub fn selected_sprites(&self) -> impl Iterator<Item = &Sprite> + '_ {
+        self.selected
+            .iter()
+            .map(|&index| &self.sprites[usize::from(index)])
+    }
+}
+
+impl Ppu {
+    /// Offset in the VRAM of the tile numbered `tile` in the maps, per LCDC bit 4.
+    pub(super) fn get_tile_data_offset(&self, tile: u8) -> u16 {
+        if self.lcdc & Self::TILE_DATA != 0 {
+            u16::from(tile) * 16
+        } else {
+            // tiles 0-127 are at 0x9000, 128-255 at 0x8800
+            0x1000u16.wrapping_add_signed(i16::from(tile as i8) * 16)
+        }
+    }
+
+    /// The 8 color indexes of the row of the tile at `offset`, from the left.
+    fn decode_tile_row(&self, bank: u8, offset: u16) -> [u8; Tile::SIZE] {
+        let low = self.read_vram_bank(bank, offset);
+        let high = self.read_vram_bank(bank, offset + 1);
+        std::array::from_fn(|column| Self::get_color(low, high, column as u8))
+    }
+
+    /// Every tile of the VRAM, those of the second bank after the first one on CGB.
+    pub fn debug_tiles(&self) -> impl Iterator<Item = Tile> + '_ {
+        let banks = if self.model.is_cgb() { 2 } else { 1 };
+        (0..banks).flat_map(move |bank| {
+            (0..Tile::COUNT as u16).map(move |index| {
+                let mut pixels = [0; Tile::SIZE * Tile::SIZE];
+                for (y, row) in pixels.chunks_exact_mut(Tile::SIZE).enumerate() {
+                    row.copy_from_slice(&self.decode_tile_row(bank, index * 16 + y as u16 * 2));
+                }
+                Tile {
+                    index,
+                    bank,
+                    pixels,
+                }
+            })
+        })
+    }
+
+    /// Every OAM entry, with the sprites selected on the current line.
+    pub fn debug_sprites(&self) -> SpriteView {
+        SpriteView {
+            sprites: std::array::from_fn(|index| self.get_sprite(index as u8)),
+            height: self.get_sprite_height(),
+            line: self.ly,
+            selected: self.get_selected_sprites(self.ly).to_vec(),
+        }
+    }
+
+    pub fn get_bg_tilemap(&self) -> TileMap {
+        if self.lcdc & Self::BG_TILE_MAP != 0 {
+            TileMap::High
+        } else {
+            TileMap::Low
+        }
+    }
+
+    pub fn get_window_tilemap(&self) -> TileMap {
+        if self.lcdc & Self::WINDOW_TILE_MAP != 0 {
+            TileMap::High
+        } else {
+            TileMap::Low
+        }
+    }
+
+    /// Draw the whole 256x256 `map`, the BG being drawn or not.
+    pub fn debug_tilemap(&self, map: TileMap) -> TileMapView {
+        let mut pixels = Box::new([0; TileMapView::SIZE * TileMapView::SIZE]);
+        for entry in 0..32 * 32 {
+            let offset = map.get_offset() + entry;
+            let tile = self.read_vram_bank(0, offset);
+            let attributes = if self.model.is_cgb() {
+                self.get_bg_attributes(offset)
+            } else {
+                BgAttributes::default()
+            };
+            let data = self.get_tile_data_offset(tile);
+            let (tile_x, tile_y) = (usize::from(entry % 32) * 8, usize::from(entry / 32) * 8);
+            for y in 0..Tile::SIZE {
+                let row = if attributes.is_y_flipped() { 7 - y } else { y };
+                let mut colors =
+                    self.decode_tile_row(attributes.get_vram_bank(), data + row as u16 * 2);
+                if attributes.is_x_flipped() {
+                    colors.reverse();
+                }
+                let start = (tile_y + y) * TileMapView::SIZE + tile_x;
+                pixels[start..start + Tile::SIZE].copy_from_slice(&colors);
+            }
+        }
+
+        let viewport = (self.get_bg_tilemap() == map).then_some(MapRect {
+            x: self.scx,
+            y: self.scy,
+            width: Self::WIDTH as u8,
+            height: Self::HEIGHT as u8,
+        });
+        let window_x = self.wx.saturating_sub(7);
+        let window_shown = self.lcdc & Self::WINDOW_ENABLE != 0
+            && usize::from(window_x) < Self::WIDTH
+            && usize::from(self.wy) < Self::HEIGHT;
+        let window = (self.get_window_tilemap() == map && window_shown).then(|| MapRect {
+            x: 0,
+            y: 0,
+            width: Self::WIDTH as u8 - window_x,
+            height: Self::HEIGHT as u8 - self.wy,
+        });
+        TileMapView {
+            map,
+            pixels,
+            viewport,
+            window,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MapRect, TileMap};
+    use crate::{
+        config::Model,
+        ppu::{Ppu, Sprite},
+    };
+
+    #[test]
+    fn tiles_and_maps() {
+        let mut ppu = Ppu::default();
+        // tile 1: a diagonal of color 3, tile 0x81 at 0x8810: color 1 on the top row
+        for y in 0..8 {
+            ppu.write_vram(16 + y * 2, 0x80 >> y);
+            ppu.write_vram(16 + y * 2 + 1, 0x80 >> y);
+        }
+        ppu.write_vram(0x810, 0xFF);
+
+        let tiles: Vec<_> = ppu.debug_tiles().collect();
+        assert_eq!(tiles.len(), 384);
+        assert_eq!(tiles[1].get_addr(), 0x8010);
+        assert_eq!(tiles[1].get_pixel(2, 2), 3);
+        assert_eq!(tiles[1].get_pixel(3, 2), 0);
+        assert_eq!(tiles[0x81].pixels[..8], [1; 8]);
+        assert_eq!(Ppu::new(Model::Cgb).debug_tiles().count(), 768);
+
+        // 0x9800 (33, 0) is tile 1, (1, 1) in tiles
+        ppu.write_vram(0x1800 + 33, 1);
+        ppu.set_register(Ppu::LCDC_REGISTER, 0x91);
+        ppu.set_register(Ppu::SCX_REGISTER, 200);
+        ppu.set_register(Ppu::SCY_REGISTER, 8);
+        let view = ppu.debug_tilemap(TileMap::Low);
+        assert_eq!(view.get_pixel(8 + 5, 8 + 5), 3);
+        assert_eq!(view.get_pixel(8 + 5, 8 + 4), 0);
+        assert_eq!(
+            view.viewport,
+            Some(MapRect {
+                x: 200,
+                y: 8,
+                width: 160,
+                height: 144
+            })
+        );
+        assert_eq!(view.window, None);
+
+        // the signed addressing: tile 1 is at 0x9010, 0x81 at 0x8810
+        ppu.set_register(Ppu::LCDC_REGISTER, 0xE1);
+        ppu.set_register(Ppu::WX_REGISTER, 87);
+        ppu.set_register(Ppu::WY_REGISTER, 100);
+        let view = ppu.debug_tilemap(TileMap::Low);
+        assert_eq!(view.get_pixel(8 + 5, 8 + 5), 0);
+        assert_eq!(view.window, None);
+        ppu.write_vram(0x1800, 0x81);
+        let view = ppu.debug_tilemap(TileMap::High);
+        assert_eq!(view.viewport, None);
+        assert_eq!(
+            view.window,
+            Some(MapRect {
+                x: 0,
+                y: 0,
+                width: 80,
+                height: 44
+            })
+        );
+        assert_eq!(ppu.debug_tilemap(TileMap::Low).pixels[..8], [1; 8]);
+    }
+
+    #[test]
+    fn sprites() {
+        let mut ppu = Ppu::default();
+        // sprite 3 on lines 0-7, sprite 5 on lines 8-23 once they are 16 high
+        ppu.write_oam(3 * 4, 16);
+        ppu.write_oam(3 * 4 + 1, 40);
+        ppu.write_oam(3 * 4 + 2, 0x12);
+        ppu.write_oam(3 * 4 + 3, 0x20);
+        ppu.write_oam(5 * 4, 16);
+        ppu.set_register(Ppu::LCDC_REGISTER, 0x87);
+        let view = ppu.debug_sprites();
+        assert_eq!(
+            view.sprites[3],
+            Sprite {
+                index: 3,
+                y: 16,
+                x: 40,
+                tile: 0x12,
+                attributes: 0x20
+            }
+        );
+        assert!(view.sprites[3].is_x_flipped());
+        assert_eq!(view.height, 16);
+        assert_eq!(view.line, 0);
+
+        // the selection is made by the OAM scan
+        ppu.step(80);
+        let view = ppu.debug_sprites();
+        assert_eq!(view.selected, [3, 5]);
+        assert!(view.is_selected(5));
+        assert!(!view.is_selected(4));
+        let tiles: Vec<_> = view.selected_sprites().map(|sprite| sprite.tile).collect();
+        assert_eq!(tiles, [0x12, 0]);
+    }
+}
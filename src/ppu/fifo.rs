@@ -0,0 +1,435 @@
+use std::collections::VecDeque;
+
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+use super::{BgAttributes, PixelSource, Ppu, Sprite};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum FetchStep {
+    #[default]
+    Tile,
+    DataLow,
+    DataHigh,
+    /// Waiting for the BG FIFO to be empty to push the 8 pixels.
+    Push,
+}
+
+impl FetchStep {
+    fn from_bits(value: u8) -> Result<Self, SaveStateError> {
+        match value {
+            0 => Ok(FetchStep::Tile),
+            1 => Ok(FetchStep::DataLow),
+            2 => Ok(FetchStep::DataHigh),
+            3 => Ok(FetchStep::Push),
+            value => Err(SaveStateError::InvalidValue(value)),
+        }
+    }
+}
+
+/// The background/window tile fetcher, each step but the push takes 2 dots.
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Fetcher {
+    step: FetchStep,
+    /// Dots spent in the current step.
+    dots: u8,
+    /// Tile column fetched next, relative to SCX or the window.
+    x: u8,
+    tile: u8,
+    /// Attributes of the tile, always 0 on DMG.
+    attributes: BgAttributes,
+    low: u8,
+    high: u8,
+    /// Fetching the window instead of the background.
+    window: bool,
+}
+
+/// State of mode 3, pixels go through the FIFOs one per dot, so the length of the mode
+/// depends on the fine scroll, the window and the sprites fetched on the line.
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) struct PixelPipeline {
+    fetcher: Fetcher,
+    /// Color indexes of the background/window, with the CGB palette in bits 2-4
+    /// and the priority attribute in bit 7.
+    bg: VecDeque<u8>,
+    /// Color index of sprite pixels, with the priority and palette bits of the attributes,
+    /// on CGB the palette is in bits 2-4 like for the BG.
+    obj: VecDeque<u8>,
+    /// Pixels pushed to the LCD on the line.
+    lx: u8,
+    /// Pixels still to drop from the BG FIFO, for the fine scroll.
+    discard: u8,
+    /// Dots left of the first fetch of the line, which is thrown away.
+    stall: u8,
+    /// Next sprite of the line, they are sorted by X.
+    next_sprite: u8,
+    /// Dots left of the sprite fetch in progress, nothing moves meanwhile.
+    sprite_dots: u8,
+    /// The window started on this line.
+    window: bool,
+}
+
+impl PixelPipeline {
+    /// Dots of the first, discarded, tile fetch.
+    const STARTUP_DOTS: u8 = 6;
+    const SPRITE_FETCH_DOTS: u8 = 6;
+    /// Attribute bits kept with the sprite pixels.
+    const OBJ_ATTRIBUTES: u8 = 0x90;
+    const BG_PRIORITY: u8 = 0x80;
+
+    pub(super) fn is_line_drawn(&self) -> bool {
+        usize::from(self.lx) == Ppu::WIDTH
+    }
+
+    /// Whether the window was drawn on the line.
+    pub(super) fn has_window(&self) -> bool {
+        self.window
+    }
+
+    pub(super) fn save_state(&self, state: &mut StateWriter) {
+        let fetcher = &self.fetcher;
+        state.put_u8(fetcher.step as u8);
+        for value in [
+            fetcher.dots,
+            fetcher.x,
+            fetcher.tile,
+            fetcher.attributes.0,
+            fetcher.low,
+            fetcher.high,
+        ] {
+            state.put_u8(value);
+        }
+        state.put_bool(fetcher.window);
+        for fifo in [&self.bg, &self.obj] {
+            state.put_u8(fifo.len() as u8);
+            for &pixel in fifo {
+                state.put_u8(pixel);
+            }
+        }
+        for value in [
+            self.lx,
+            self.discard,
+            self.stall,
+            self.next_sprite,
+            self.sprite_dots,
+        ] {
+            state.put_u8(value);
+        }
+        state.put_bool(self.window);
+    }
+
+    pub(super) fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        let fetcher = &mut self.fetcher;
+        fetcher.step = FetchStep::from_bits(state.get_u8()?)?;
+        for value in [
+            &mut fetcher.dots,
+            &mut fetcher.x,
+            &mut fetcher.tile,
+            &mut fetcher.attributes.0,
+            &mut fetcher.low,
+            &mut fetcher.high,
+        ] {
+            *value = state.get_u8()?;
+        }
+        fetcher.window = state.get_bool()?;
+        for fifo in [&mut self.bg, &mut self.obj] {
+            fifo.clear();
+            let len = state.get_u8()?;
+            if len > 16 {
+                return Err(SaveStateError::InvalidValue(len));
+            }
+            for _ in 0..len {
+                fifo.push_back(state.get_u8()?);
+            }
+        }
+        for value in [
+            &mut self.lx,
+            &mut self.discard,
+            &mut self.stall,
+            &mut self.next_sprite,
+            &mut self.sprite_dots,
+        ] {
+            *value = state.get_u8()?;
+        }
+        self.window = state.get_bool()?;
+        Ok(())
+    }
+}
+
+impl Ppu {
+    /// Reset the pipeline at the start of mode 3.
+    pub(super) fn start_drawing(&mut self) {
+        let pipeline = &mut self.pipeline;
+        pipeline.fetcher = Fetcher::default();
+        pipeline.bg.clear();
+        pipeline.obj.clear();
+        pipeline.lx = 0;
+        pipeline.discard = self.scx % 8;
+        pipeline.stall = PixelPipeline::STARTUP_DOTS;
+        pipeline.next_sprite = 0;
+        pipeline.sprite_dots = 0;
+        pipeline.window = false;
+    }
+
+    /// One dot of mode 3, at most one pixel is pushed to the LCD.
+    pub(super) fn draw_dot(&mut self) {
+        let pipeline = &mut self.pipeline;
+        if pipeline.stall > 0 {
+            pipeline.stall -= 1;
+            return;
+        }
+        if pipeline.sprite_dots > 0 {
+            pipeline.sprite_dots -= 1;
+            if pipeline.sprite_dots == 0 {
+                self.fetch_sprite();
+            }
+            return;
+        }
+        self.check_window();
+        if self.get_pending_sprite().is_some() {
+            // the sprite fetch waits for the BG fetcher to get its tile data
+            let fetcher = &self.pipeline.fetcher;
+            let ready = matches!(fetcher.step, FetchStep::DataHigh | FetchStep::Push);
+            if ready && !self.pipeline.bg.is_empty() {
+                self.pipeline.sprite_dots = PixelPipeline::SPRITE_FETCH_DOTS - 1;
+            } else {
+                self.fetcher_dot();
+            }
+            return;
+        }
+        self.fetcher_dot();
+        self.shift_pixel();
+    }
+
+    /// Start fetching the window if it begins at the current pixel.
+    fn check_window(&mut self) {
+        if self.pipeline.window || !self.is_window_enabled() {
+            return;
+        }
+        // WX is the position + 7
+        if u16::from(self.pipeline.lx) + 7 < u16::from(self.wx) {
+            return;
+        }
+        let pipeline = &mut self.pipeline;
+        pipeline.window = true;
+        pipeline.bg.clear();
+        pipeline.fetcher = Fetcher {
+            window: true,
+            ..Default::default()
+        };
+        // the part left of the screen is never shown
+        pipeline.discard = if pipeline.lx == 0 {
+            7u8.saturating_sub(self.wx)
+        } else {
+            0
+        };
+    }
+
+    fn is_window_enabled(&self) -> bool {
+        // on DMG the BG enable bit hides the window too
+        let enabled = self.lcdc & Self::WINDOW_ENABLE != 0
+            && (self.model.is_cgb() || self.lcdc & Self::BG_ENABLE != 0);
+        // values above 166 are off screen
+        enabled && self.window_triggered && self.wx <= 166
+    }
+
+    /// The next sprite of the line if it starts at the current pixel.
+    fn get_pending_sprite(&self) -> Option<Sprite> {
+        if self.lcdc & Self::OBJ_ENABLE == 0 {
+            return None;
+        }
+        let sprite = self
+            .line_sprites
+            .get(usize::from(self.pipeline.next_sprite))?;
+        (u16::from(sprite.x) <= u16::from(self.pipeline.lx) + 8).then_some(*sprite)
+    }
+
+    /// Mix the next pending sprite in the sprite FIFO, earlier sprites keep their pixels.
+    fn fetch_sprite(&mut self) {
+        let Some(sprite) = self.get_pending_sprite() else {
+            return;
+        };
+        self.pipeline.next_sprite += 1;
+        let height = self.get_sprite_height();
+        let row = sprite.get_row(self.ly, height);
+        let tile = if height == 16 {
+            (sprite.tile & 0xFE) + row / 8
+        } else {
+            sprite.tile
+        };
+        let bank = if self.model.is_cgb() {
+            usize::from(sprite.get_vram_bank()) * Self::VRAM_SIZE
+        } else {
+            0
+        };
+        let addr = bank + usize::from(tile) * 16 + usize::from(row % 8) * 2;
+        let (low, high) = (self.vram[addr], self.vram[addr + 1]);
+        let attributes = if self.model.is_cgb() {
+            (sprite.attributes & PixelPipeline::BG_PRIORITY) | (sprite.get_cgb_palette() << 2)
+        } else {
+            sprite.attributes & PixelPipeline::OBJ_ATTRIBUTES
+        };
+        let lx = self.pipeline.lx;
+        let obj = &mut self.pipeline.obj;
+        obj.resize(8.max(obj.len()), 0);
+        for (i, pixel) in obj.iter_mut().take(8).enumerate() {
+            let Some(column) = sprite.get_column(lx.wrapping_add(i as u8)) else {
+                continue;
+            };
+            let color = Self::get_color(low, high, column);
+            if *pixel & 0b11 == 0 && color != 0 {
+                *pixel = color | attributes;
+            }
+        }
+    }
+
+    fn fetcher_dot(&mut self) {
+        let fetcher = &mut self.pipeline.fetcher;
+        if fetcher.step == FetchStep::Push {
+            if self.pipeline.bg.is_empty() {
+                let (low, high, attributes) = (fetcher.low, fetcher.high, fetcher.attributes);
+                fetcher.x = fetcher.x.wrapping_add(1);
+                fetcher.step = FetchStep::Tile;
+                let extra =
+                    (attributes.get_palette() << 2) | (attributes.0 & PixelPipeline::BG_PRIORITY);
+                self.pipeline.bg.extend((0..8).map(|column| {
+                    let column = if attributes.is_x_flipped() {
+                        7 - column
+                    } else {
+                        column
+                    };
+                    Self::get_color(low, high, column) | extra
+                }));
+            }
+            return;
+        }
+        fetcher.dots += 1;
+        if fetcher.dots < 2 {
+            return;
+        }
+        fetcher.dots = 0;
+        match fetcher.step {
+            FetchStep::Tile => {
+                let addr = self.get_fetched_map_addr();
+                let tile = self.vram[addr];
+                // the attributes are at the same place in the second bank
+                let attributes = if self.model.is_cgb() {
+                    BgAttributes(self.vram[Self::VRAM_SIZE + addr])
+                } else {
+                    BgAttributes::default()
+                };
+                let fetcher = &mut self.pipeline.fetcher;
+                fetcher.tile = tile;
+                fetcher.attributes = attributes;
+                fetcher.step = FetchStep::DataLow;
+            }
+            FetchStep::DataLow => {
+                let low = self.vram[self.get_fetched_row_addr()];
+                let fetcher = &mut self.pipeline.fetcher;
+                fetcher.low = low;
+                fetcher.step = FetchStep::DataHigh;
+            }
+            FetchStep::DataHigh => {
+                let high = self.vram[self.get_fetched_row_addr() + 1];
+                let fetcher = &mut self.pipeline.fetcher;
+                fetcher.high = high;
+                fetcher.step = FetchStep::Push;
+            }
+            FetchStep::Push => unreachable!(),
+        }
+    }
+
+    /// Line of the BG or window map being fetched, in pixels.
+    fn get_fetched_y(&self) -> u8 {
+        if self.pipeline.fetcher.window {
+            self.window_line
+        } else {
+            self.ly.wrapping_add(self.scy)
+        }
+    }
+
+    fn get_fetched_map_addr(&self) -> usize {
+        let fetcher = &self.pipeline.fetcher;
+        let (map_bit, x) = if fetcher.window {
+            (Self::WINDOW_TILE_MAP, fetcher.x)
+        } else {
+            (Self::BG_TILE_MAP, (self.scx / 8).wrapping_add(fetcher.x))
+        };
+        let map = if self.lcdc & map_bit != 0 {
+            0x1C00
+        } else {
+            0x1800
+        };
+        let y = self.get_fetched_y();
+        map + usize::from(y / 8) * 32 + usize::from(x % 32)
+    }
+
+    fn get_fetched_row_addr(&self) -> usize {
+        let Fetcher {
+            tile, attributes, ..
+        } = self.pipeline.fetcher;
+        let addr = usize::from(self.get_tile_data_offset(tile));
+        let row = self.get_fetched_y() % 8;
+        let row = if attributes.is_y_flipped() {
+            7 - row
+        } else {
+            row
+        };
+        usize::from(attributes.get_vram_bank()) * Self::VRAM_SIZE + addr + usize::from(row) * 2
+    }
+
+    /// Color index of the pixel `column` (0 is the leftmost) of a tile row.
+    pub(super) fn get_color(low: u8, high: u8, column: u8) -> u8 {
+        let bit = 7 - column;
+        (((high >> bit) & 1) << 1) | ((low >> bit) & 1)
+    }
+
+    /// Push a pixel to the LCD, the palettes are applied right now,
+    /// so changing them mid-line affects the rest of the line only.
+    fn shift_pixel(&mut self) {
+        let pipeline = &mut self.pipeline;
+        let Some(pixel) = pipeline.bg.pop_front() else {
+            return;
+        };
+        if pipeline.discard > 0 {
+            pipeline.discard -= 1;
+            return;
+        }
+        let obj = pipeline.obj.pop_front().unwrap_or(0);
+        // on CGB the bit doesn't hide the BG, sprites are just always on top of it
+        let bg_enable = self.lcdc & Self::BG_ENABLE != 0;
+        let bg_color = if bg_enable || self.model.is_cgb() {
+            pixel & 0b11
+        } else {
+            0
+        };
+        let obj_color = obj & 0b11;
+        let bg_priority = obj & 0x80 != 0 || pixel & PixelPipeline::BG_PRIORITY != 0;
+        let behind_bg = bg_enable && bg_priority && bg_color != 0;
+        let obj_visible = obj_color != 0 && self.lcdc & Self::OBJ_ENABLE != 0 && !behind_bg;
+        let index = usize::from(self.ly) * Self::WIDTH + usize::from(pipeline.lx);
+        let (shade, source) = if self.model.is_cgb() {
+            // no DMG palettes, the shade is the color index and the palette RAM gives the color
+            let (palettes, attributes, color, source) = if obj_visible {
+                (&self.obj_palettes, obj, obj_color, PixelSource::Obj0)
+            } else {
+                (&self.bg_palettes, pixel, bg_color, PixelSource::Bg)
+            };
+            self.colors[index] = palettes.get_color(attributes >> 2, color);
+            (color, source)
+        } else if obj_visible {
+            if obj & 0x10 != 0 {
+                (Self::apply_palette(self.obp1, obj_color), PixelSource::Obj1)
+            } else {
+                (Self::apply_palette(self.obp0, obj_color), PixelSource::Obj0)
+            }
+        } else {
+            (Self::apply_palette(self.bgp, bg_color), PixelSource::Bg)
+        };
+        self.framebuffer[index] = shade;
+        self.sources[index] = source;
+        pipeline.lx += 1;
+    }
+}
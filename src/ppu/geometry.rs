@@ -0,0 +1,76 @@
+use super::Ppu;
+
+/// Size of the picture given to the frontend, and how to show it.
+///
+/// It can change while running, frontends should check it after each frame
+/// rather than assuming 160x144.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayGeometry {
+    /// Size of the picture, the border included.
+    pub width: usize,
+    pub height: usize,
+    /// Where the Game Boy screen is in the picture.
+    pub screen_x: usize,
+    pub screen_y: usize,
+    /// Largest picture this core can output, to allocate once.
+    pub max_width: usize,
+    pub max_height: usize,
+    /// Width over height the picture should be shown at, pixels are square.
+    pub aspect_ratio: f32,
+}
+
+impl DisplayGeometry {
+    /// Size of the picture with a Super Game Boy border.
+    pub const SGB_WIDTH: usize = 256;
+    pub const SGB_HEIGHT: usize = 224;
+
+    /// The plain LCD, 160x144.
+    pub const NATIVE: Self = Self::new(Ppu::WIDTH, Ppu::HEIGHT, 0, 0);
+
+    /// The LCD in the middle of a Super Game Boy border.
+    pub const SGB_BORDER: Self = Self::new(
+        Self::SGB_WIDTH,
+        Self::SGB_HEIGHT,
+        (Self::SGB_WIDTH - Ppu::WIDTH) / 2,
+        (Self::SGB_HEIGHT - Ppu::HEIGHT) / 2,
+    );
+
+    const fn new(width: usize, height: usize, screen_x: usize, screen_y: usize) -> Self {
+        DisplayGeometry {
+            width,
+            height,
+            screen_x,
+            screen_y,
+            max_width: Self::SGB_WIDTH,
+            max_height: Self::SGB_HEIGHT,
+            aspect_ratio: width as f32 / height as f32,
+        }
+    }
+
+    /// The largest integer scale fitting in a `width`x`height` window, at least 1.
+    pub fn get_integer_scale(&self, width: usize, height: usize) -> usize {
+        (width / self.width).min(height / self.height).max(1)
+    }
+}
+
+impl Default for DisplayGeometry {
+    fn default() -> Self {
+        Self::NATIVE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DisplayGeometry;
+
+    #[test]
+    fn geometry() {
+        let native = DisplayGeometry::NATIVE;
+        assert_eq!(native.aspect_ratio, 10.0 / 9.0);
+        assert_eq!(native.get_integer_scale(1920, 1080), 7);
+        assert_eq!(native.get_integer_scale(100, 100), 1);
+        let sgb = DisplayGeometry::SGB_BORDER;
+        assert_eq!((sgb.screen_x, sgb.screen_y), (48, 40));
+        assert_eq!(sgb.aspect_ratio, 8.0 / 7.0);
+    }
+}
@@ -0,0 +1,1124 @@
+use crate::{
+    config::Model,
+    memory::interrupts::{Interrupt, Interrupts},
+    savestate::{SaveStateError, StateReader, StateWriter},
+};
+
+pub use self::attributes::BgAttributes;
+pub use self::color::Color;
+pub use self::compat::{ComboButton, ComboDirection, CompatPalette};
+pub use self::debug::{MapRect, SpriteView, Tile, TileMap, TileMapView};
+use self::fifo::PixelPipeline;
+pub use self::geometry::DisplayGeometry;
+pub use self::palette_ram::PaletteRam;
+pub use self::sprite::{Sprite, SpriteOverflow};
+
+pub mod attributes;
+pub mod color;
+pub mod compat;
+pub mod debug;
+mod fifo;
+pub mod geometry;
+pub mod palette_ram;
+pub mod sprite;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Mode {
+    /// Mode 0, after the pixels of the line are drawn.
+    HBlank,
+    /// Mode 1, the 10 lines after the last visible one.
+    VBlank,
+    /// Mode 2, searching the sprites of the line.
+    #[default]
+    OamScan,
+    /// Mode 3, pushing pixels to the LCD.
+    Drawing,
+}
+
+/// Which palette a pixel of the framebuffer went through.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PixelSource {
+    /// BGP, the background or the window.
+    #[default]
+    Bg,
+    /// OBP0
+    Obj0,
+    /// OBP1
+    Obj1,
+}
+
+impl Mode {
+    /// Value of the 2 lower bits of STAT.
+    pub const fn get_bits(self) -> u8 {
+        match self {
+            Mode::HBlank => 0,
+            Mode::VBlank => 1,
+            Mode::OamScan => 2,
+            Mode::Drawing => 3,
+        }
+    }
+}
+
+/// The Pixel Processing Unit, owns the VRAM, the OAM and the LCD registers (0xFF40-0xFF4B).
+///
+/// The framebuffer holds shades, from 0 (white) to 3 (black), after the palette is applied.
+/// On CGB it holds the color indexes instead, the colors from the palette RAM are in
+/// the color framebuffer.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ppu {
+    model: Model,
+    /// Both banks, the second one only exists on CGB.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::boxed_array"))]
+    vram: Box<[u8; Self::VRAM_SIZE * Self::VRAM_BANKS]>,
+    /// VBK, the bank the CPU sees at 0x8000.
+    vram_bank: u8,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::array"))]
+    oam: [u8; Self::OAM_SIZE],
+    lcdc: u8,
+    /// Only the writable bits (3-6), the rest is computed.
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    bg_palettes: PaletteRam,
+    obj_palettes: PaletteRam,
+    mode: Mode,
+    /// Dots elapsed in the current line.
+    dot: u16,
+    /// OR of the enabled STAT sources, the interrupt is requested on its rising edge only,
+    /// so a source becoming true while another one already is doesn't request anything.
+    stat_line: bool,
+    /// Interrupts requested since the last `step`, as IF bits.
+    requested: u8,
+    /// Set when entering VBlank, the framebuffer then holds a whole frame.
+    frame_complete: bool,
+    frames: u64,
+    /// Set once LY matched WY in the current frame, the window can only show up after that.
+    window_triggered: bool,
+    /// Line of the window to draw next, only advances on lines where the window is drawn,
+    /// so hiding it mid-frame resumes where it stopped instead of skipping lines.
+    window_line: u8,
+    /// Sprites of the current line, selected by the OAM scan and sorted by priority.
+    line_sprites: Vec<Sprite>,
+    /// OAM indexes of the sprites selected on each line of the frame, for debuggers.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::boxed_array"))]
+    selected_sprites: Box<[[u8; Sprite::MAX_PER_LINE]; Self::HEIGHT]>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::array"))]
+    selected_counts: [u8; Self::HEIGHT],
+    /// Lines of the frame being drawn where sprites were dropped.
+    overflows: Vec<SpriteOverflow>,
+    /// Same for the last completed frame.
+    last_overflows: Vec<SpriteOverflow>,
+    pipeline: PixelPipeline,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::boxed_array"))]
+    framebuffer: Box<[u8; Self::WIDTH * Self::HEIGHT]>,
+    /// Palette of each pixel of the framebuffer, to colorize it afterward.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::boxed_array"))]
+    sources: Box<[PixelSource; Self::WIDTH * Self::HEIGHT]>,
+    /// RGB555 colors of the pixels, only drawn on CGB.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::boxed_array"))]
+    colors: Box<[u16; Self::WIDTH * Self::HEIGHT]>,
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Ppu {
+            model: Model::default(),
+            vram: Box::new([0; Self::VRAM_SIZE * Self::VRAM_BANKS]),
+            vram_bank: 0,
+            oam: [0; Self::OAM_SIZE],
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            bg_palettes: PaletteRam::default(),
+            obj_palettes: PaletteRam::default(),
+            mode: Mode::default(),
+            dot: 0,
+            stat_line: false,
+            requested: 0,
+            frame_complete: false,
+            frames: 0,
+            window_triggered: false,
+            window_line: 0,
+            line_sprites: Vec::with_capacity(Sprite::MAX_PER_LINE),
+            selected_sprites: Box::new([[0; Sprite::MAX_PER_LINE]; Self::HEIGHT]),
+            selected_counts: [0; Self::HEIGHT],
+            overflows: Vec::new(),
+            last_overflows: Vec::new(),
+            pipeline: PixelPipeline::default(),
+            framebuffer: Box::new([0; Self::WIDTH * Self::HEIGHT]),
+            sources: Box::new([PixelSource::Bg; Self::WIDTH * Self::HEIGHT]),
+            colors: Box::new([0; Self::WIDTH * Self::HEIGHT]),
+        }
+    }
+}
+
+impl Ppu {
+    pub const WIDTH: usize = 160;
+    pub const HEIGHT: usize = 144;
+
+    pub const VRAM_SIZE: usize = 0x2000;
+    pub const VRAM_BANKS: usize = 2;
+    pub const OAM_SIZE: usize = 0xA0;
+
+    pub const LCDC_REGISTER: u16 = 0xFF40;
+    pub const STAT_REGISTER: u16 = 0xFF41;
+    pub const SCY_REGISTER: u16 = 0xFF42;
+    pub const SCX_REGISTER: u16 = 0xFF43;
+    pub const LY_REGISTER: u16 = 0xFF44;
+    pub const LYC_REGISTER: u16 = 0xFF45;
+    pub const BGP_REGISTER: u16 = 0xFF47;
+    pub const OBP0_REGISTER: u16 = 0xFF48;
+    pub const OBP1_REGISTER: u16 = 0xFF49;
+    pub const WY_REGISTER: u16 = 0xFF4A;
+    pub const WX_REGISTER: u16 = 0xFF4B;
+    /// CGB only.
+    pub const VBK_REGISTER: u16 = 0xFF4F;
+    /// CGB only.
+    pub const BCPS_REGISTER: u16 = 0xFF68;
+    /// CGB only.
+    pub const BCPD_REGISTER: u16 = 0xFF69;
+    /// CGB only.
+    pub const OCPS_REGISTER: u16 = 0xFF6A;
+    /// CGB only.
+    pub const OCPD_REGISTER: u16 = 0xFF6B;
+
+    const DOTS_PER_LINE: u16 = 456;
+    const LINES: u8 = 154;
+    const OAM_SCAN_DOTS: u16 = 80;
+
+    const LYC_SOURCE: u8 = 1 << 6;
+    const OAM_SCAN_SOURCE: u8 = 1 << 5;
+    const VBLANK_SOURCE: u8 = 1 << 4;
+    const HBLANK_SOURCE: u8 = 1 << 3;
+
+    const LCD_ENABLE: u8 = 1 << 7;
+    const WINDOW_TILE_MAP: u8 = 1 << 6;
+    const WINDOW_ENABLE: u8 = 1 << 5;
+    const BG_TILE_MAP: u8 = 1 << 3;
+    const TILE_DATA: u8 = 1 << 4;
+    const OBJ_SIZE: u8 = 1 << 2;
+    const OBJ_ENABLE: u8 = 1 << 1;
+    const BG_ENABLE: u8 = 1 << 0;
+
+    pub fn new(model: Model) -> Self {
+        Ppu {
+            model,
+            ..Default::default()
+        }
+    }
+
+    pub fn get_model(&self) -> Model {
+        self.model
+    }
+
+    pub fn get_framebuffer(&self) -> &[u8; Self::WIDTH * Self::HEIGHT] {
+        &self.framebuffer
+    }
+
+    /// RGB555 colors of the last frame, CGB only, see `Color::from_rgb555`.
+    pub fn get_color_framebuffer(&self) -> &[u16; Self::WIDTH * Self::HEIGHT] {
+        &self.colors
+    }
+
+    /// The last frame in RGB, from the palette RAM on CGB, from the DMG shades otherwise.
+    pub fn get_pixels(&self) -> Vec<Color> {
+        if self.model.is_cgb() {
+            self.colors
+                .iter()
+                .map(|&color| Color::from_rgb555(color))
+                .collect()
+        } else {
+            color::from_shades(self.framebuffer.as_slice()).collect()
+        }
+    }
+
+    pub fn get_bg_palettes(&self) -> &PaletteRam {
+        &self.bg_palettes
+    }
+
+    pub fn get_obj_palettes(&self) -> &PaletteRam {
+        &self.obj_palettes
+    }
+
+    /// Which palette each pixel of the framebuffer went through.
+    pub fn get_pixel_sources(&self) -> &[PixelSource; Self::WIDTH * Self::HEIGHT] {
+        &self.sources
+    }
+
+    /// OAM indexes of the sprites the OAM scan selected on `line` (in the current frame
+    /// for the lines already drawn, the previous one for the others), in OAM order.
+    pub fn get_selected_sprites(&self, line: u8) -> &[u8] {
+        let line = usize::from(line);
+        match self.selected_counts.get(line) {
+            Some(&count) => &self.selected_sprites[line][..usize::from(count)],
+            None => &[],
+        }
+    }
+
+    /// Lines of the last completed frame where the 10 sprites per line limit dropped some,
+    /// what makes games flicker sprites.
+    pub fn get_sprite_overflows(&self) -> &[SpriteOverflow] {
+        &self.last_overflows
+    }
+
+    pub fn get_sprite(&self, index: u8) -> Sprite {
+        Sprite::from_oam(&self.oam, index)
+    }
+
+    /// Whether a frame was completed since the last call, to know when to present it.
+    pub fn take_frame_complete(&mut self) -> bool {
+        std::mem::take(&mut self.frame_complete)
+    }
+
+    /// Frames completed since power on.
+    pub fn get_frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn get_mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn get_ly(&self) -> u8 {
+        self.ly
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.lcdc & Self::LCD_ENABLE != 0
+    }
+
+    /// The PPU reads the VRAM during mode 3, the CPU can't access it then.
+    pub fn is_vram_accessible(&self) -> bool {
+        !self.is_enabled() || self.mode != Mode::Drawing
+    }
+
+    /// The PPU reads the OAM during modes 2 and 3, the CPU can't access it then.
+    pub fn is_oam_accessible(&self) -> bool {
+        !self.is_enabled() || matches!(self.mode, Mode::HBlank | Mode::VBlank)
+    }
+
+    /// `offset` is relative to 0x8000, in the bank selected by VBK.
+    pub fn read_vram(&self, offset: u16) -> u8 {
+        self.read_vram_bank(self.vram_bank, offset)
+    }
+
+    pub fn write_vram(&mut self, offset: u16, value: u8) {
+        let index = usize::from(self.vram_bank) * Self::VRAM_SIZE + usize::from(offset);
+        self.vram[index] = value;
+    }
+
+    /// Read a VRAM bank whatever VBK is, for debuggers.
+    pub fn read_vram_bank(&self, bank: u8, offset: u16) -> u8 {
+        self.vram[usize::from(bank & 1) * Self::VRAM_SIZE + usize::from(offset)]
+    }
+
+    /// Attributes of the map entry at `offset` (relative to 0x8000, so 0x1800-0x1FFF), CGB only.
+    pub fn get_bg_attributes(&self, offset: u16) -> BgAttributes {
+        BgAttributes(self.read_vram_bank(1, offset))
+    }
+
+    /// `offset` is relative to 0xFE00.
+    pub fn read_oam(&self, offset: u16) -> u8 {
+        self.oam[offset as usize]
+    }
+
+    pub fn write_oam(&mut self, offset: u16, value: u8) {
+        self.oam[offset as usize] = value;
+    }
+
+    /// Read one of the LCD registers.
+    pub fn get_register(&self, addr: u16) -> u8 {
+        match addr {
+            Self::LCDC_REGISTER => self.lcdc,
+            // bit 7 is unused and reads as 1
+            Self::STAT_REGISTER => 0x80 | self.stat | self.get_lyc_flag() | self.mode.get_bits(),
+            Self::SCY_REGISTER => self.scy,
+            Self::SCX_REGISTER => self.scx,
+            Self::LY_REGISTER => self.ly,
+            Self::LYC_REGISTER => self.lyc,
+            Self::BGP_REGISTER => self.bgp,
+            Self::OBP0_REGISTER => self.obp0,
+            Self::OBP1_REGISTER => self.obp1,
+            Self::WY_REGISTER => self.wy,
+            Self::WX_REGISTER => self.wx,
+            // only bit 0 is used, the others read as 1
+            Self::VBK_REGISTER if self.model.is_cgb() => 0xFE | self.vram_bank,
+            Self::BCPS_REGISTER if self.model.is_cgb() => self.bg_palettes.get_spec(),
+            Self::OCPS_REGISTER if self.model.is_cgb() => self.obj_palettes.get_spec(),
+            // the PPU reads the palettes during mode 3
+            Self::BCPD_REGISTER | Self::OCPD_REGISTER if !self.is_vram_accessible() => 0xFF,
+            Self::BCPD_REGISTER if self.model.is_cgb() => self.bg_palettes.read_data(),
+            Self::OCPD_REGISTER if self.model.is_cgb() => self.obj_palettes.read_data(),
+            _ => 0xFF,
+        }
+    }
+
+    /// Write one of the LCD registers.
+    pub fn set_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            Self::LCDC_REGISTER => self.set_lcdc(value),
+            Self::STAT_REGISTER => {
+                if self.model.has_stat_write_bug() {
+                    // every source is enabled for a cycle before the write goes through
+                    self.stat = 0b01111000;
+                    self.update_stat_line();
+                }
+                self.stat = value & 0b01111000;
+                self.update_stat_line();
+            }
+            Self::SCY_REGISTER => self.scy = value,
+            Self::SCX_REGISTER => self.scx = value,
+            // LY is read only
+            Self::LY_REGISTER => {}
+            Self::LYC_REGISTER => {
+                self.lyc = value;
+                self.update_stat_line();
+            }
+            Self::BGP_REGISTER => self.bgp = value,
+            Self::OBP0_REGISTER => self.obp0 = value,
+            Self::OBP1_REGISTER => self.obp1 = value,
+            Self::WY_REGISTER => self.wy = value,
+            Self::WX_REGISTER => self.wx = value,
+            Self::VBK_REGISTER if self.model.is_cgb() => self.vram_bank = value & 1,
+            Self::BCPS_REGISTER if self.model.is_cgb() => self.bg_palettes.set_spec(value),
+            Self::OCPS_REGISTER if self.model.is_cgb() => self.obj_palettes.set_spec(value),
+            Self::BCPD_REGISTER | Self::OCPD_REGISTER if !self.is_vram_accessible() => {}
+            Self::BCPD_REGISTER if self.model.is_cgb() => self.bg_palettes.write_data(value),
+            Self::OCPD_REGISTER if self.model.is_cgb() => self.obj_palettes.write_data(value),
+            _ => {}
+        }
+    }
+
+    fn set_lcdc(&mut self, value: u8) {
+        let was_enabled = self.is_enabled();
+        self.lcdc = value;
+        if was_enabled && !self.is_enabled() {
+            // turning the LCD off resets the line, it starts back at line 0
+            self.ly = 0;
+            self.dot = 0;
+            self.mode = Mode::HBlank;
+            self.stat_line = false;
+        } else if !was_enabled && self.is_enabled() {
+            self.mode = Mode::OamScan;
+            self.start_frame();
+            self.start_line();
+            self.update_stat_line();
+        }
+    }
+
+    fn get_stat_line(&self) -> bool {
+        let source = match self.mode {
+            Mode::HBlank => Self::HBLANK_SOURCE,
+            Mode::VBlank => Self::VBLANK_SOURCE,
+            Mode::OamScan => Self::OAM_SCAN_SOURCE,
+            Mode::Drawing => 0,
+        };
+        let lyc = self.ly == self.lyc && self.stat & Self::LYC_SOURCE != 0;
+        self.is_enabled() && (lyc || self.stat & source != 0)
+    }
+
+    fn update_stat_line(&mut self) {
+        let line = self.get_stat_line();
+        if line && !self.stat_line {
+            self.requested |= Interrupt::LcdStat.get_mask();
+        }
+        self.stat_line = line;
+    }
+
+    fn get_lyc_flag(&self) -> u8 {
+        if self.ly == self.lyc {
+            1 << 2
+        } else {
+            0
+        }
+    }
+
+    /// Advance by `cycles` dots, returns the interrupts requested meanwhile.
+    pub fn step(&mut self, cycles: u32) -> Interrupts {
+        if self.is_enabled() {
+            for _ in 0..cycles {
+                self.dot();
+            }
+        }
+        Interrupt::from_bits(std::mem::take(&mut self.requested))
+    }
+
+    fn dot(&mut self) {
+        self.dot += 1;
+        if self.dot == Self::DOTS_PER_LINE {
+            self.dot = 0;
+            self.ly = (self.ly + 1) % Self::LINES;
+            if self.ly == 0 {
+                self.start_frame();
+            }
+        }
+        let visible = usize::from(self.ly) < Self::HEIGHT;
+        let mode = match self.dot {
+            _ if !visible => Mode::VBlank,
+            dot if dot < Self::OAM_SCAN_DOTS => Mode::OamScan,
+            dot if dot == Self::OAM_SCAN_DOTS => Mode::Drawing,
+            // mode 3 lasts until the whole line is pushed to the LCD
+            _ if self.mode == Mode::Drawing && !self.pipeline.is_line_drawn() => Mode::Drawing,
+            _ => Mode::HBlank,
+        };
+        if mode != self.mode {
+            self.mode = mode;
+            match mode {
+                Mode::OamScan => self.start_line(),
+                Mode::Drawing => self.start_drawing(),
+                Mode::HBlank => {
+                    if self.pipeline.has_window() {
+                        self.window_line += 1;
+                    }
+                }
+                Mode::VBlank => {
+                    self.requested |= Interrupt::VBlank.get_mask();
+                    self.frame_complete = true;
+                    self.frames += 1;
+                    self.last_overflows = std::mem::take(&mut self.overflows);
+                }
+            }
+        }
+        if self.mode == Mode::Drawing {
+            self.draw_dot();
+        }
+        self.update_stat_line();
+    }
+
+    fn start_frame(&mut self) {
+        self.window_triggered = false;
+        self.window_line = 0;
+    }
+
+    fn start_line(&mut self) {
+        // checked on every line, even with the window disabled
+        if self.ly == self.wy {
+            self.window_triggered = true;
+        }
+        if usize::from(self.ly) < Self::HEIGHT {
+            self.scan_oam();
+        }
+    }
+
+    fn get_sprite_height(&self) -> u8 {
+        if self.lcdc & Self::OBJ_SIZE != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Draw the line `ly` from the current VRAM, OAM and registers, without advancing time,
+    /// to see what a line would look like. Returns the shades of its pixels.
+    ///
+    /// The window is drawn like if it was shown on every line since WY.
+    pub fn render_line(&self, ly: u8) -> [u8; Self::WIDTH] {
+        self.draw_detached_line(ly).framebuffer[Self::get_line_range(ly)]
+            .try_into()
+            .unwrap()
+    }
+
+    /// Draw the line `ly` in the framebuffer right now, like `render_line`.
+    pub fn force_render_line(&mut self, ly: u8) {
+        let ppu = self.draw_detached_line(ly);
+        let line = Self::get_line_range(ly);
+        self.framebuffer[line.clone()].copy_from_slice(&ppu.framebuffer[line.clone()]);
+        self.sources[line.clone()].copy_from_slice(&ppu.sources[line.clone()]);
+        self.colors[line.clone()].copy_from_slice(&ppu.colors[line]);
+    }
+
+    fn get_line_range(ly: u8) -> std::ops::Range<usize> {
+        usize::from(ly) * Self::WIDTH..(usize::from(ly) + 1) * Self::WIDTH
+    }
+
+    /// A copy of the PPU that drew the line `ly`.
+    fn draw_detached_line(&self, ly: u8) -> Ppu {
+        assert!(usize::from(ly) < Self::HEIGHT, "line {} is not visible", ly);
+        let mut ppu = self.clone();
+        ppu.ly = ly;
+        ppu.lcdc |= Self::LCD_ENABLE;
+        ppu.window_triggered = ppu.wy <= ly;
+        ppu.window_line = ly.wrapping_sub(ppu.wy);
+        ppu.scan_oam();
+        ppu.mode = Mode::Drawing;
+        ppu.start_drawing();
+        while !ppu.pipeline.is_line_drawn() {
+            ppu.draw_dot();
+        }
+        ppu
+    }
+
+    /// Select the first 10 sprites of the OAM covering the line,
+    /// X doesn't matter, even sprites off screen count.
+    fn scan_oam(&mut self) {
+        let height = self.get_sprite_height();
+        self.line_sprites.clear();
+        let mut dropped = Vec::new();
+        for index in 0..Sprite::COUNT as u8 {
+            let sprite = Sprite::from_oam(&self.oam, index);
+            if sprite.is_on_line(self.ly, height) {
+                if self.line_sprites.len() < Sprite::MAX_PER_LINE {
+                    self.line_sprites.push(sprite);
+                } else {
+                    // the hardware stops there, the rest is only for debuggers
+                    dropped.push(index);
+                }
+            }
+        }
+        if !dropped.is_empty() {
+            self.overflows.push(SpriteOverflow {
+                line: self.ly,
+                dropped,
+            });
+        }
+        let line = usize::from(self.ly);
+        for (slot, sprite) in self.selected_sprites[line]
+            .iter_mut()
+            .zip(&self.line_sprites)
+        {
+            *slot = sprite.index;
+        }
+        self.selected_counts[line] = self.line_sprites.len() as u8;
+        // the smallest X is drawn on top, then the first in OAM
+        self.line_sprites
+            .sort_by_key(|sprite| (sprite.x, sprite.index));
+    }
+
+    fn apply_palette(palette: u8, color: u8) -> u8 {
+        (palette >> (color * 2)) & 0b11
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.put_bytes(self.vram.as_slice());
+        state.put_bytes(&self.oam);
+        for reg in [
+            self.lcdc,
+            self.stat,
+            self.scy,
+            self.scx,
+            self.ly,
+            self.lyc,
+            self.bgp,
+            self.obp0,
+            self.obp1,
+            self.wy,
+            self.wx,
+            self.vram_bank,
+        ] {
+            state.put_u8(reg);
+        }
+        self.bg_palettes.save_state(state);
+        self.obj_palettes.save_state(state);
+        state.put_u8(self.mode.get_bits());
+        state.put_u16(self.dot);
+        state.put_bool(self.stat_line);
+        state.put_bool(self.window_triggered);
+        state.put_u8(self.window_line);
+        self.pipeline.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        state.get_bytes_into(self.vram.as_mut_slice())?;
+        state.get_bytes_into(&mut self.oam)?;
+        for reg in [
+            &mut self.lcdc,
+            &mut self.stat,
+            &mut self.scy,
+            &mut self.scx,
+            &mut self.ly,
+            &mut self.lyc,
+            &mut self.bgp,
+            &mut self.obp0,
+            &mut self.obp1,
+            &mut self.wy,
+            &mut self.wx,
+            &mut self.vram_bank,
+        ] {
+            *reg = state.get_u8()?;
+        }
+        self.vram_bank &= 1;
+        self.bg_palettes.load_state(state)?;
+        self.obj_palettes.load_state(state)?;
+        self.mode = match state.get_u8()? {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::OamScan,
+            3 => Mode::Drawing,
+            value => return Err(SaveStateError::InvalidValue(value)),
+        };
+        self.dot = state.get_u16()?;
+        self.stat_line = state.get_bool()?;
+        self.window_triggered = state.get_bool()?;
+        self.window_line = state.get_u8()?;
+        self.pipeline.load_state(state)?;
+        if usize::from(self.ly) < Self::HEIGHT {
+            self.scan_oam();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{config::Model, memory::interrupts::Interrupt};
+
+    use super::{Color, Mode, PixelSource, Ppu, SpriteOverflow};
+
+    fn fill_vram(ppu: &mut Ppu, range: std::ops::Range<u16>, value: u8) {
+        for offset in range {
+            ppu.write_vram(offset, value);
+        }
+    }
+
+    fn run_frame(ppu: &mut Ppu) {
+        ppu.step(456 * 154);
+    }
+
+    #[test]
+    fn modes_per_line() {
+        let mut ppu = Ppu::default();
+        ppu.set_register(Ppu::LCDC_REGISTER, 0x91);
+        assert_eq!(ppu.get_mode(), Mode::OamScan);
+        ppu.step(80);
+        assert_eq!(ppu.get_mode(), Mode::Drawing);
+        ppu.step(172);
+        assert_eq!(ppu.get_mode(), Mode::HBlank);
+        ppu.step(204);
+        assert_eq!(ppu.get_mode(), Mode::OamScan);
+        assert_eq!(ppu.get_ly(), 1);
+        ppu.step(456 * 143);
+        assert_eq!(ppu.get_ly(), 144);
+        assert_eq!(ppu.get_mode(), Mode::VBlank);
+        assert_eq!(ppu.get_register(Ppu::STAT_REGISTER) & 0b11, 1);
+        assert!(ppu.take_frame_complete());
+        assert!(!ppu.take_frame_complete());
+        assert_eq!(ppu.get_frame_count(), 1);
+        ppu.step(456 * 10);
+        assert_eq!(ppu.get_ly(), 0);
+        assert_eq!(ppu.get_mode(), Mode::OamScan);
+
+        // turning the LCD off goes back to line 0
+        ppu.step(456 * 3);
+        ppu.set_register(Ppu::LCDC_REGISTER, 0x11);
+        assert_eq!(ppu.get_ly(), 0);
+        ppu.step(456);
+        assert_eq!(ppu.get_ly(), 0);
+    }
+
+    /// Dots until the next STAT interrupt, 4 at a time.
+    fn next_stat_interrupt(ppu: &mut Ppu) -> u32 {
+        let mut dots = 0;
+        while !ppu.step(4).any(|interrupt| interrupt == Interrupt::LcdStat) {
+            dots += 4;
+        }
+        dots + 4
+    }
+
+    #[test]
+    fn vblank_interrupt() {
+        let mut ppu = Ppu::default();
+        ppu.set_register(Ppu::LCDC_REGISTER, 0x91);
+        let mut dots = 0;
+        while !ppu.step(4).any(|interrupt| interrupt == Interrupt::VBlank) {
+            dots += 4;
+        }
+        assert_eq!(dots + 4, 456 * 144);
+        assert_eq!(ppu.get_ly(), 144);
+        // once per frame
+        dots = 0;
+        while !ppu.step(4).any(|interrupt| interrupt == Interrupt::VBlank) {
+            dots += 4;
+        }
+        assert_eq!(dots + 4, 456 * 154);
+    }
+
+    #[test]
+    fn stat_interrupts() {
+        let mut ppu = Ppu::default();
+        ppu.set_register(Ppu::LYC_REGISTER, 2);
+        ppu.set_register(Ppu::STAT_REGISTER, 0x40);
+        ppu.set_register(Ppu::LCDC_REGISTER, 0x91);
+        assert_eq!(ppu.get_register(Ppu::STAT_REGISTER), 0xC2);
+        assert_eq!(next_stat_interrupt(&mut ppu), 456 * 2);
+        assert_eq!(ppu.get_register(Ppu::STAT_REGISTER), 0xC6);
+
+        // HBlank, once per line
+        ppu.set_register(Ppu::STAT_REGISTER, 0x08);
+        assert_eq!(next_stat_interrupt(&mut ppu), 252);
+        assert_eq!(ppu.get_mode(), Mode::HBlank);
+        assert_eq!(next_stat_interrupt(&mut ppu), 456);
+
+        // HBlank and OAM scan back to back are a single high line, so no OAM scan interrupt
+        ppu.set_register(Ppu::STAT_REGISTER, 0x28);
+        assert_eq!(next_stat_interrupt(&mut ppu), 456);
+        assert_eq!(ppu.get_mode(), Mode::HBlank);
+
+        // VBlank
+        ppu.set_register(Ppu::STAT_REGISTER, 0x10);
+        next_stat_interrupt(&mut ppu);
+        assert_eq!(ppu.get_ly(), 144);
+        assert_eq!(ppu.get_mode(), Mode::VBlank);
+
+        // enabling a source that is already true requests it right away
+        ppu.set_register(Ppu::LYC_REGISTER, 150);
+        ppu.step(456 * 6);
+        assert_eq!(ppu.step(0).next(), None);
+        ppu.set_register(Ppu::STAT_REGISTER, 0x40);
+        // blocked, VBlank source already high
+        assert_eq!(ppu.step(0).next(), None);
+        ppu.set_register(Ppu::STAT_REGISTER, 0x00);
+        ppu.set_register(Ppu::STAT_REGISTER, 0x40);
+        assert_eq!(ppu.step(0).collect::<Vec<_>>(), [Interrupt::LcdStat]);
+    }
+
+    #[test]
+    fn stat_write_bug() {
+        for (model, requested) in [(Model::Dmg, Some(Interrupt::LcdStat)), (Model::Cgb, None)] {
+            let mut ppu = Ppu::new(model);
+            ppu.set_register(Ppu::LCDC_REGISTER, 0x91);
+            // HBlank of the first line
+            ppu.step(252);
+            assert_eq!(ppu.get_mode(), Mode::HBlank);
+            ppu.set_register(Ppu::STAT_REGISTER, 0x00);
+            assert_eq!(ppu.step(0).next(), requested, "{:?}", model);
+        }
+    }
+
+    #[test]
+    fn cgb_bg_attributes() {
+        let mut ppu = Ppu::new(Model::Cgb);
+        fill_vram(&mut ppu, 0x1800..0x1C00, 1);
+        ppu.set_register(Ppu::VBK_REGISTER, 0xFF);
+        assert_eq!(ppu.get_register(Ppu::VBK_REGISTER), 0xFF);
+        // tile 1 of bank 1 has only its top left pixel set, drawn flipped from bank 1
+        ppu.write_vram(16, 0x80);
+        fill_vram(&mut ppu, 0x1800..0x1C00, 0x68);
+        ppu.set_register(Ppu::VBK_REGISTER, 0);
+        assert_eq!(ppu.get_register(Ppu::VBK_REGISTER), 0xFE);
+        assert_eq!(ppu.read_vram(0x1800), 1);
+        assert!(ppu.get_bg_attributes(0x1800).is_y_flipped());
+        ppu.set_register(Ppu::BGP_REGISTER, 0b11100100);
+        ppu.set_register(Ppu::LCDC_REGISTER, 0x91);
+        run_frame(&mut ppu);
+        let pixel = |ppu: &Ppu, x: usize, y: usize| ppu.get_framebuffer()[y * Ppu::WIDTH + x];
+        assert_eq!(pixel(&ppu, 7, 7), 1);
+        assert_eq!(pixel(&ppu, 0, 7), 0);
+        assert_eq!(pixel(&ppu, 7, 0), 0);
+
+        // no second bank on DMG
+        let mut ppu = Ppu::new(Model::Dmg);
+        ppu.set_register(Ppu::VBK_REGISTER, 1);
+        assert_eq!(ppu.get_register(Ppu::VBK_REGISTER), 0xFF);
+    }
+
+    #[test]
+    fn cgb_palettes() {
+        let mut ppu = Ppu::new(Model::Cgb);
+        let write_palette = |ppu: &mut Ppu, spec, data, colors: [u16; 4]| {
+            ppu.set_register(spec, 0x80 | 16);
+            for byte in colors.iter().flat_map(|color| color.to_le_bytes()) {
+                ppu.set_register(data, byte);
+            }
+        };
+        write_palette(
+            &mut ppu,
+            Ppu::BCPS_REGISTER,
+            Ppu::BCPD_REGISTER,
+            [0x7FFF, 0x001F, 0x03E0, 0x7C00],
+        );
+        write_palette(
+            &mut ppu,
+            Ppu::OCPS_REGISTER,
+            Ppu::OCPD_REGISTER,
+            [0x0000, 0x0000, 0x03E0, 0x0000],
+        );
+        // auto increment stopped at the next palette, reads don't move
+        assert_eq!(ppu.get_register(Ppu::BCPS_REGISTER), 0xC0 | 24);
+        ppu.set_register(Ppu::BCPS_REGISTER, 16 + 2);
+        assert_eq!(ppu.get_register(Ppu::BCPD_REGISTER), 0x1F);
+        assert_eq!(ppu.get_register(Ppu::BCPD_REGISTER), 0x1F);
+
+        // the BG is color 1 of palette 2, a sprite with color 2 of palette 2 on the first line
+        fill_vram(&mut ppu, 16..32, 0);
+        for row in 0..8 {
+            ppu.write_vram(16 + row * 2, 0xFF);
+        }
+        ppu.write_vram(32 + 1, 0xFF);
+        fill_vram(&mut ppu, 0x1800..0x1C00, 1);
+        ppu.set_register(Ppu::VBK_REGISTER, 1);
+        fill_vram(&mut ppu, 0x1800..0x1C00, 2);
+        for (offset, value) in [16, 8, 2, 2].into_iter().enumerate() {
+            ppu.write_oam(offset as u16, value);
+        }
+        ppu.set_register(Ppu::LCDC_REGISTER, 0x93);
+        run_frame(&mut ppu);
+        let colors = ppu.get_color_framebuffer();
+        assert_eq!(colors[0], 0x03E0);
+        assert_eq!(colors[8], 0x001F);
+        assert_eq!(colors[Ppu::WIDTH], 0x001F);
+        assert_eq!(ppu.get_pixels()[Ppu::WIDTH], Color::new(0xFF, 0, 0));
+    }
+
+    #[test]
+    fn window() {
+        let mut ppu = Ppu::default();
+        // tile 1 is black, the window map is at 0x9C00 and all black
+        fill_vram(&mut ppu, 16..32, 0xFF);
+        fill_vram(&mut ppu, 0x1C00..0x2000, 1);
+        ppu.set_register(Ppu::BGP_REGISTER, 0b11100100);
+        ppu.set_register(Ppu::WY_REGISTER, 10);
+        ppu.set_register(Ppu::WX_REGISTER, 7 + 100);
+        ppu.set_register(Ppu::LCDC_REGISTER, 0xF1);
+        run_frame(&mut ppu);
+        let pixel = |ppu: &Ppu, x: usize, y: usize| ppu.get_framebuffer()[y * Ppu::WIDTH + x];
+        assert_eq!(pixel(&ppu, 100, 9), 0);
+        assert_eq!(pixel(&ppu, 99, 10), 0);
+        assert_eq!(pixel(&ppu, 100, 10), 3);
+        assert_eq!(pixel(&ppu, 159, 143), 3);
+    }
+
+    #[test]
+    fn window_line_counter() {
+        let mut ppu = Ppu::default();
+        // the window is black on its first 8 lines
+        fill_vram(&mut ppu, 16..32, 0xFF);
+        fill_vram(&mut ppu, 0x1C00..0x1C20, 1);
+        ppu.set_register(Ppu::BGP_REGISTER, 0b11100100);
+        ppu.set_register(Ppu::WY_REGISTER, 0);
+        ppu.set_register(Ppu::WX_REGISTER, 7);
+        ppu.set_register(Ppu::LCDC_REGISTER, 0xF1);
+        // draw 4 lines, hide the window for 4 lines, show it again
+        ppu.step(456 * 4);
+        ppu.set_register(Ppu::LCDC_REGISTER, 0xD1);
+        ppu.step(456 * 4);
+        ppu.set_register(Ppu::LCDC_REGISTER, 0xF1);
+        ppu.step(456 * 146);
+        let line = |ppu: &Ppu, y: usize| ppu.get_framebuffer()[y * Ppu::WIDTH];
+        assert_eq!(line(&ppu, 3), 3);
+        assert_eq!(line(&ppu, 4), 0);
+        // resumes at window line 4, so 4 more black lines
+        assert_eq!(line(&ppu, 11), 3);
+        assert_eq!(line(&ppu, 12), 0);
+    }
+
+    fn put_sprite(ppu: &mut Ppu, index: u16, y: u8, x: u8, tile: u8, attributes: u8) {
+        for (i, value) in [y, x, tile, attributes].into_iter().enumerate() {
+            ppu.write_oam(index * 4 + i as u16, value);
+        }
+    }
+
+    #[test]
+    fn sprites() {
+        let mut ppu = Ppu::default();
+        // tile 1 has color 3 on its left half, tile 2 is color 1
+        for row in 0..8 {
+            ppu.write_vram(16 + row * 2, 0xF0);
+            ppu.write_vram(16 + row * 2 + 1, 0xF0);
+            ppu.write_vram(32 + row * 2, 0xFF);
+        }
+        ppu.set_register(Ppu::BGP_REGISTER, 0b11100100);
+        ppu.set_register(Ppu::OBP0_REGISTER, 0b11100100);
+        ppu.set_register(Ppu::OBP1_REGISTER, 0b00011011);
+        // at (0, 0), the transparent right half shows the sprite under it
+        put_sprite(&mut ppu, 0, 16, 8, 1, 0);
+        put_sprite(&mut ppu, 1, 16, 12, 2, 0);
+        // same X, the lower index is on top
+        put_sprite(&mut ppu, 2, 16, 40, 2, 0x10);
+        put_sprite(&mut ppu, 3, 16, 40, 1, 0);
+        // x flipped at line 20
+        put_sprite(&mut ppu, 4, 36, 8, 1, 0x20);
+        ppu.set_register(Ppu::LCDC_REGISTER, 0x93);
+        run_frame(&mut ppu);
+
+        let line = &ppu.get_framebuffer()[..Ppu::WIDTH];
+        assert_eq!(line[..8], [3, 3, 3, 3, 1, 1, 1, 1]);
+        assert_eq!(line[8..12], [1, 1, 1, 1]);
+        // OBP1 inverts color 1 to 2
+        assert_eq!(line[32..34], [2, 2]);
+        let line = &ppu.get_framebuffer()[Ppu::WIDTH * 20..Ppu::WIDTH * 21];
+        assert_eq!(line[..8], [0, 0, 0, 0, 3, 3, 3, 3]);
+
+        assert_eq!(ppu.get_selected_sprites(0), [0, 1, 2, 3]);
+        assert_eq!(ppu.get_selected_sprites(20), [4]);
+        assert_eq!(ppu.get_selected_sprites(30), [0u8; 0]);
+    }
+
+    #[test]
+    fn sprite_limit_and_priority() {
+        let mut ppu = Ppu::default();
+        // tiles 1 and 2 are color 1, the BG is tile 2 everywhere but the first column
+        for row in 0..16 {
+            ppu.write_vram(16 + row * 2, 0xFF);
+        }
+        for offset in (0x1800..0x1C00).filter(|offset| offset % 32 != 0) {
+            ppu.write_vram(offset, 2);
+        }
+        ppu.set_register(Ppu::BGP_REGISTER, 0b11100100);
+        ppu.set_register(Ppu::OBP0_REGISTER, 0b11111100);
+        // 12 sprites on the first line, the last two are dropped
+        for i in 0..12 {
+            put_sprite(&mut ppu, i, 16, 8 + i as u8 * 8, 2, 0);
+        }
+        // behind the BG, only shows over color 0
+        put_sprite(&mut ppu, 20, 46, 12, 2, 0x80);
+        // the tile index ignores bit 0 in 8x16: tile 0 (empty) on top, tile 1 below
+        put_sprite(&mut ppu, 21, 66, 8, 1, 0);
+        ppu.set_register(Ppu::LCDC_REGISTER, 0x97);
+        run_frame(&mut ppu);
+
+        let line = &ppu.get_framebuffer()[..Ppu::WIDTH];
+        assert_eq!(line[..80], [3; 80]);
+        assert_eq!(line[80..96], [1; 16]);
+        assert_eq!(ppu.get_selected_sprites(0).len(), 10);
+        assert_eq!(
+            ppu.get_sprite_overflows(),
+            // 8x16 sprites
+            (0..16)
+                .map(|line| SpriteOverflow {
+                    line,
+                    dropped: vec![10, 11]
+                })
+                .collect::<Vec<_>>()
+        );
+
+        let line = &ppu.get_framebuffer()[Ppu::WIDTH * 30..Ppu::WIDTH * 31];
+        assert_eq!(line[4..12], [3, 3, 3, 3, 1, 1, 1, 1]);
+
+        let pixel = |ppu: &Ppu, y: usize| ppu.get_framebuffer()[Ppu::WIDTH * y];
+        assert_eq!(pixel(&ppu, 50), 0);
+        assert_eq!(pixel(&ppu, 58), 3);
+    }
+
+    /// Dots of mode 3 on the next line.
+    fn next_drawing_length(ppu: &mut Ppu) -> u32 {
+        while ppu.get_mode() != Mode::OamScan {
+            ppu.step(1);
+        }
+        ppu.step(80);
+        let mut dots = 0;
+        while ppu.get_mode() == Mode::Drawing {
+            ppu.step(1);
+            dots += 1;
+        }
+        dots
+    }
+
+    #[test]
+    fn drawing_length() {
+        let mut ppu = Ppu::default();
+        ppu.set_register(Ppu::LCDC_REGISTER, 0x93);
+        assert_eq!(next_drawing_length(&mut ppu), 172);
+
+        // the fine scroll is dropped from the first tile
+        ppu.set_register(Ppu::SCX_REGISTER, 3);
+        assert_eq!(next_drawing_length(&mut ppu), 175);
+        ppu.set_register(Ppu::SCX_REGISTER, 8);
+        assert_eq!(next_drawing_length(&mut ppu), 172);
+
+        // starting the window restarts the fetcher
+        ppu.set_register(Ppu::WX_REGISTER, 7 + 80);
+        ppu.set_register(Ppu::LCDC_REGISTER, 0xB3);
+        assert_eq!(next_drawing_length(&mut ppu), 178);
+        ppu.set_register(Ppu::LCDC_REGISTER, 0x93);
+
+        // a sprite costs 6 dots, plus the wait for the BG fetcher
+        let line = ppu.get_ly() + 1;
+        put_sprite(&mut ppu, 0, line + 16, 8, 0, 0);
+        assert_eq!(next_drawing_length(&mut ppu), 172 + 11);
+        put_sprite(&mut ppu, 0, line + 17, 13, 0, 0);
+        assert_eq!(next_drawing_length(&mut ppu), 172 + 6);
+        // disabled sprites are not fetched
+        ppu.set_register(Ppu::LCDC_REGISTER, 0x91);
+        assert_eq!(next_drawing_length(&mut ppu), 172);
+    }
+
+    #[test]
+    fn mid_line_palette_change() {
+        let mut ppu = Ppu::default();
+        // the BG is color 1 everywhere
+        fill_vram(&mut ppu, 0..16, 0xFF);
+        for offset in (0..16).step_by(2) {
+            ppu.write_vram(offset + 1, 0);
+        }
+        ppu.set_register(Ppu::BGP_REGISTER, 0b00000100);
+        ppu.set_register(Ppu::LCDC_REGISTER, 0x91);
+        // mode 3 starts on dot 80 and pushes its first pixel on dot 92, the 50th on dot 141
+        ppu.step(141);
+        ppu.set_register(Ppu::BGP_REGISTER, 0b00001100);
+        ppu.step(456);
+        let line = &ppu.get_framebuffer()[..Ppu::WIDTH];
+        assert_eq!(line[..50], [1; 50]);
+        assert_eq!(line[50..], [3; 110]);
+    }
+
+    #[test]
+    fn background() {
+        let mut ppu = Ppu::default();
+        // tile 1 is vertical stripes of colors 0 1 2 3
+        for row in 0..8 {
+            ppu.write_vram(16 + row * 2, 0b01010101);
+            ppu.write_vram(16 + row * 2 + 1, 0b00110011);
+        }
+        // the second tile of the map
+        ppu.write_vram(0x1801, 1);
+        ppu.set_register(Ppu::BGP_REGISTER, 0b11100100);
+        ppu.set_register(Ppu::LCDC_REGISTER, 0x91);
+        run_frame(&mut ppu);
+        let line = &ppu.get_framebuffer()[..Ppu::WIDTH];
+        assert_eq!(line[..8], [0; 8]);
+        assert_eq!(line[8..16], [0, 1, 2, 3, 0, 1, 2, 3]);
+
+        // scrolled 2 pixels left, with an inverted palette
+        ppu.set_register(Ppu::SCX_REGISTER, 2);
+        ppu.set_register(Ppu::BGP_REGISTER, 0b00011011);
+        run_frame(&mut ppu);
+        let line = &ppu.get_framebuffer()[Ppu::WIDTH * 7..Ppu::WIDTH * 8];
+        assert_eq!(line[6..16], [3, 2, 1, 0, 3, 2, 1, 0, 3, 3]);
+    }
+
+    #[test]
+    fn render_line() {
+        let mut ppu = Ppu::default();
+        fill_vram(&mut ppu, 16..32, 0xFF);
+        fill_vram(&mut ppu, 0x1C00..0x2000, 1);
+        ppu.set_register(Ppu::BGP_REGISTER, 0b11100100);
+        ppu.set_register(Ppu::OBP0_REGISTER, 0b11100100);
+        ppu.set_register(Ppu::WY_REGISTER, 10);
+        ppu.set_register(Ppu::WX_REGISTER, 7 + 100);
+        put_sprite(&mut ppu, 0, 16 + 20, 8, 1, 0);
+        ppu.set_register(Ppu::LCDC_REGISTER, 0x73);
+
+        // the LCD is off, nothing moves
+        let line = ppu.render_line(20);
+        assert_eq!(line[..10], [3, 3, 3, 3, 3, 3, 3, 3, 0, 0]);
+        assert_eq!(line[99..101], [0, 3]);
+        assert_eq!(ppu.render_line(5)[..8], [0; 8]);
+        assert_eq!(ppu.get_framebuffer()[20 * Ppu::WIDTH], 0);
+        assert_eq!(ppu.get_ly(), 0);
+
+        ppu.force_render_line(20);
+        assert_eq!(
+            ppu.get_framebuffer()[20 * Ppu::WIDTH..21 * Ppu::WIDTH],
+            line
+        );
+        assert_eq!(ppu.get_pixel_sources()[20 * Ppu::WIDTH], PixelSource::Obj0);
+    }
+}
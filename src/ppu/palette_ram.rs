@@ -0,0 +1,72 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+/// The 8 palettes of 4 colors of the CGB, for the BG or the sprites,
+/// accessed through an index register (BCPS/OCPS) and a data register (BCPD/OCPD).
+///
+/// The colors are RGB555, little endian, red in the low bits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PaletteRam {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::array"))]
+    data: [u8; Self::SIZE],
+    /// Bits 0-5 are the byte accessed through the data register, bit 7 the auto increment.
+    spec: u8,
+}
+
+impl Default for PaletteRam {
+    fn default() -> Self {
+        // the boot ROM leaves them white
+        PaletteRam {
+            data: [0xFF; Self::SIZE],
+            spec: 0,
+        }
+    }
+}
+
+impl PaletteRam {
+    pub const SIZE: usize = 64;
+    pub const PALETTES: u8 = 8;
+
+    const INDEX: u8 = 0x3F;
+    const AUTO_INCREMENT: u8 = 1 << 7;
+
+    /// BCPS/OCPS, bit 6 is unused and reads as 1.
+    pub fn get_spec(&self) -> u8 {
+        self.spec | 0x40
+    }
+
+    pub fn set_spec(&mut self, value: u8) {
+        self.spec = value & (Self::AUTO_INCREMENT | Self::INDEX);
+    }
+
+    /// BCPD/OCPD
+    pub fn read_data(&self) -> u8 {
+        self.data[usize::from(self.spec & Self::INDEX)]
+    }
+
+    /// Writes move to the next byte with the auto increment, reads never do.
+    pub fn write_data(&mut self, value: u8) {
+        self.data[usize::from(self.spec & Self::INDEX)] = value;
+        if self.spec & Self::AUTO_INCREMENT != 0 {
+            let index = (self.spec + 1) & Self::INDEX;
+            self.spec = Self::AUTO_INCREMENT | index;
+        }
+    }
+
+    /// RGB555 color `color` (0-3) of the palette `palette` (0-7).
+    pub fn get_color(&self, palette: u8, color: u8) -> u16 {
+        let index = usize::from(palette & 0b111) * 8 + usize::from(color & 0b11) * 2;
+        u16::from_le_bytes([self.data[index], self.data[index + 1]])
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.put_bytes(&self.data);
+        state.put_u8(self.spec);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        state.get_bytes_into(&mut self.data)?;
+        self.set_spec(state.get_u8()?);
+        Ok(())
+    }
+}
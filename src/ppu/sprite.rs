@@ -0,0 +1,107 @@
+/// A line where the OAM scan dropped sprites, past the 10 per line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpriteOverflow {
+    pub line: u8,
+    /// OAM indexes of the sprites covering the line that weren't selected, in OAM order.
+    pub dropped: Vec<u8>,
+}
+
+/// An OAM entry.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sprite {
+    /// Position in the OAM, 0-39, lower indexes win ties on X.
+    pub index: u8,
+    /// Screen Y + 16
+    pub y: u8,
+    /// Screen X + 8
+    pub x: u8,
+    pub tile: u8,
+    pub attributes: u8,
+}
+
+impl Sprite {
+    pub const SIZE: usize = 4;
+    pub const COUNT: usize = 40;
+    /// Sprites selected per line by the OAM scan.
+    pub const MAX_PER_LINE: usize = 10;
+
+    const BEHIND_BG: u8 = 1 << 7;
+    const Y_FLIP: u8 = 1 << 6;
+    const X_FLIP: u8 = 1 << 5;
+    const PALETTE: u8 = 1 << 4;
+    const BANK: u8 = 1 << 3;
+    const CGB_PALETTE: u8 = 0b111;
+
+    pub fn from_oam(oam: &[u8], index: u8) -> Self {
+        let start = usize::from(index) * Self::SIZE;
+        let [y, x, tile, attributes] = oam[start..start + Self::SIZE].try_into().unwrap();
+        Sprite {
+            index,
+            y,
+            x,
+            tile,
+            attributes,
+        }
+    }
+
+    /// BG and window colors 1-3 are drawn over the sprite.
+    pub fn is_behind_bg(&self) -> bool {
+        self.attributes & Self::BEHIND_BG != 0
+    }
+
+    pub fn is_y_flipped(&self) -> bool {
+        self.attributes & Self::Y_FLIP != 0
+    }
+
+    pub fn is_x_flipped(&self) -> bool {
+        self.attributes & Self::X_FLIP != 0
+    }
+
+    /// Uses OBP1 instead of OBP0.
+    pub fn uses_obp1(&self) -> bool {
+        self.attributes & Self::PALETTE != 0
+    }
+
+    /// VRAM bank of the tile, CGB only.
+    pub fn get_vram_bank(&self) -> u8 {
+        u8::from(self.attributes & Self::BANK != 0)
+    }
+
+    /// OBJ palette, 0-7, CGB only.
+    pub fn get_cgb_palette(&self) -> u8 {
+        self.attributes & Self::CGB_PALETTE
+    }
+
+    /// Whether the sprite covers the screen line `ly`.
+    pub fn is_on_line(&self, ly: u8, height: u8) -> bool {
+        let top = i16::from(self.y) - 16;
+        let line = i16::from(ly);
+        (top..top + i16::from(height)).contains(&line)
+    }
+
+    /// Row of the sprite drawn on `ly`, flip applied, 0-15.
+    pub fn get_row(&self, ly: u8, height: u8) -> u8 {
+        let row = (i16::from(ly) - (i16::from(self.y) - 16)) as u8;
+        if self.is_y_flipped() {
+            height - 1 - row
+        } else {
+            row
+        }
+    }
+
+    /// Column of the sprite at the screen pixel `x`, flip applied, if covered.
+    pub fn get_column(&self, x: u8) -> Option<u8> {
+        let column = i16::from(x) - (i16::from(self.x) - 8);
+        if !(0..8).contains(&column) {
+            return None;
+        }
+        let column = column as u8;
+        Some(if self.is_x_flipped() {
+            7 - column
+        } else {
+            column
+        })
+    }
+}
@@ -0,0 +1,321 @@
+use std::io::{self, Write};
+
+use crate::{
+    emulator::{Emulator, StopReason},
+    ppu::{color, Ppu},
+};
+
+pub use self::nut::NutEncoder;
+pub use self::wav::{AudioFingerprint, PcmBuffer, WavEncoder};
+
+pub mod nut;
+pub mod wav;
+
+/// A frame as captured, one shade per pixel from 0 (white) to 3 (black).
+pub type Frame = [u8; Ppu::WIDTH * Ppu::HEIGHT];
+
+/// Frame as 24 bits RGB, row by row, the shades as `Color::SHADES`.
+pub fn to_rgb(frame: &Frame) -> Vec<u8> {
+    color::to_rgb_bytes(color::from_shades(frame))
+}
+
+/// Where the recorder sends the captures, one implementation per output format.
+pub trait Encoder {
+    fn encode_frame(&mut self, frame: &Frame) -> io::Result<()>;
+
+    /// Interleaved stereo samples, encoders without audio can ignore them.
+    fn encode_audio(&mut self, _samples: &[i16]) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Flush everything, called once at the end of the recording.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Headless capture of the emulator output.
+pub struct Recorder {
+    encoder: Box<dyn Encoder>,
+    frames: u64,
+}
+
+impl Recorder {
+    pub fn new(encoder: Box<dyn Encoder>) -> Self {
+        Recorder { encoder, frames: 0 }
+    }
+
+    pub fn get_frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    /// Capture the last frame drawn by the emulator.
+    pub fn capture(&mut self, emulator: &Emulator) -> io::Result<()> {
+        self.capture_frame(emulator.get_framebuffer())
+    }
+
+    pub fn capture_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        self.frames += 1;
+        self.encoder.encode_frame(frame)
+    }
+
+    pub fn capture_audio(&mut self, samples: &[i16]) -> io::Result<()> {
+        self.encoder.encode_audio(samples)
+    }
+
+    /// Run `frames` frames, capturing each of them with the audio produced during it.
+    ///
+    /// Fails if a frame doesn't complete (breakpoint, illegal opcode, ...),
+    /// what was captured up to there is kept.
+    pub fn record(&mut self, emulator: &mut Emulator, frames: u64) -> io::Result<()> {
+        for _ in 0..frames {
+            let reason = emulator.run_frame();
+            if reason != StopReason::FrameComplete {
+                return Err(io::Error::other(format!(
+                    "recording stopped after {} frames: {:?}",
+                    self.frames, reason
+                )));
+            }
+            self.capture_audio(&emulator.take_audio_samples())?;
+            self.capture(emulator)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.encoder.finish()
+    }
+}
+
+/// Raw RGB24 frames back to back, and optionally the audio as raw s16le,
+/// to be muxed by an external tool, or see `NutEncoder` for a single file:
+///
+/// `ffmpeg -f rawvideo -pix_fmt rgb24 -s 160x144 -r 59.73 -i video.raw output.mp4`
+pub struct RawEncoder<V: Write, A: Write = io::Sink> {
+    video: V,
+    audio: Option<A>,
+}
+
+impl<V: Write> RawEncoder<V> {
+    pub fn new(video: V) -> Self {
+        RawEncoder { video, audio: None }
+    }
+}
+
+impl<V: Write, A: Write> RawEncoder<V, A> {
+    pub fn with_audio(video: V, audio: A) -> Self {
+        RawEncoder {
+            video,
+            audio: Some(audio),
+        }
+    }
+
+    pub fn into_inner(self) -> (V, Option<A>) {
+        (self.video, self.audio)
+    }
+}
+
+impl<V: Write, A: Write> Encoder for RawEncoder<V, A> {
+    fn encode_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        self.video.write_all(&to_rgb(frame))
+    }
+
+    fn encode_audio(&mut self, samples: &[i16]) -> io::Result<()> {
+        let Some(audio) = &mut self.audio else {
+            return Ok(());
+        };
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        audio.write_all(&bytes)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.video.flush()?;
+        match &mut self.audio {
+            Some(audio) => audio.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "png")]
+pub use self::png_sequence::PngSequence;
+
+#[cfg(feature = "png")]
+mod png_sequence {
+    use std::{
+        fs::File,
+        io::{self, BufWriter, Write},
+        path::PathBuf,
+    };
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use crate::ppu::Ppu;
+
+    use super::{to_rgb, Encoder, Frame};
+
+    /// One PNG file per frame, `frame_000000.png`, `frame_000001.png`, ... in a directory.
+    #[derive(Debug)]
+    pub struct PngSequence {
+        dir: PathBuf,
+        next: u64,
+    }
+
+    impl PngSequence {
+        pub(super) const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+        /// The directory must exist.
+        pub fn new(dir: impl Into<PathBuf>) -> Self {
+            PngSequence {
+                dir: dir.into(),
+                next: 0,
+            }
+        }
+
+        /// Encode a frame as a truecolor 8 bits PNG.
+        pub fn encode(frame: &Frame) -> io::Result<Vec<u8>> {
+            let mut png = Self::SIGNATURE.to_vec();
+
+            let mut header = Vec::with_capacity(13);
+            header.extend_from_slice(&(Ppu::WIDTH as u32).to_be_bytes());
+            header.extend_from_slice(&(Ppu::HEIGHT as u32).to_be_bytes());
+            // bit depth 8, truecolor, default compression, filter and no interlace
+            header.extend_from_slice(&[8, 2, 0, 0, 0]);
+            Self::put_chunk(&mut png, b"IHDR", &header);
+
+            let rgb = to_rgb(frame);
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            for row in rgb.chunks(Ppu::WIDTH * 3) {
+                // no filter
+                encoder.write_all(&[0])?;
+                encoder.write_all(row)?;
+            }
+            Self::put_chunk(&mut png, b"IDAT", &encoder.finish()?);
+            Self::put_chunk(&mut png, b"IEND", &[]);
+            Ok(png)
+        }
+
+        fn put_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+            png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            png.extend_from_slice(kind);
+            png.extend_from_slice(data);
+            let mut crc = crc32fast::Hasher::new();
+            crc.update(kind);
+            crc.update(data);
+            png.extend_from_slice(&crc.finalize().to_be_bytes());
+        }
+    }
+
+    impl Encoder for PngSequence {
+        fn encode_frame(&mut self, frame: &Frame) -> io::Result<()> {
+            let path = self.dir.join(format!("frame_{:06}.png", self.next));
+            self.next += 1;
+            let mut file = BufWriter::new(File::create(path)?);
+            file.write_all(&Self::encode(frame)?)?;
+            file.flush()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        emulator::{Emulator, StopReason},
+        ppu::Ppu,
+    };
+
+    use std::{
+        cell::RefCell,
+        io::{self, Write},
+        rc::Rc,
+    };
+
+    use super::{Encoder, Frame, PcmBuffer, RawEncoder, Recorder};
+
+    /// Writes where the test can still read it once the encoder is boxed.
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn raw_output() {
+        let mut frame: Frame = [0; Ppu::WIDTH * Ppu::HEIGHT];
+        frame[1] = 3;
+        let mut encoder = RawEncoder::with_audio(Vec::new(), Vec::new());
+        encoder.encode_frame(&frame).unwrap();
+        encoder.encode_frame(&frame).unwrap();
+        encoder.encode_audio(&[1, -1]).unwrap();
+        let (video, audio) = encoder.into_inner();
+        assert_eq!(video.len(), 2 * Ppu::WIDTH * Ppu::HEIGHT * 3);
+        assert_eq!(video[..6], [0xFF, 0xFF, 0xFF, 0, 0, 0]);
+        assert_eq!(audio.unwrap(), [1, 0, 0xFF, 0xFF]);
+    }
+
+    fn looping_emulator() -> Emulator {
+        // JR -2
+        Emulator::from_program(&[0x18, 0xFE])
+    }
+
+    #[test]
+    fn record_audio() {
+        let mut emulator = looping_emulator();
+        emulator.set_audio_sample_rate(48000);
+        let audio = Rc::new(RefCell::new(Vec::new()));
+        let mut recorder = Recorder::new(Box::new(RawEncoder::with_audio(
+            io::sink(),
+            SharedOutput(audio.clone()),
+        )));
+        recorder.record(&mut emulator, 60).unwrap();
+        // about a second of 16 bits stereo
+        let bytes = audio.borrow().len();
+        assert!(bytes.abs_diff(48000 * 4) < 48000 * 4 / 100, "{}", bytes);
+    }
+
+    #[test]
+    fn record_stops_on_breakpoint() {
+        let mut emulator = looping_emulator();
+        let mut recorder = Recorder::new(Box::new(PcmBuffer::new()));
+        recorder.record(&mut emulator, 2).unwrap();
+        assert_eq!(recorder.get_frame_count(), 2);
+
+        emulator.get_debugger_mut().add_breakpoint(0x0000);
+        let err = recorder.record(&mut emulator, 2).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&format!("{:?}", StopReason::Breakpoint(0x0000))));
+        assert_eq!(recorder.get_frame_count(), 2);
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn png_frame() {
+        use flate2::read::ZlibDecoder;
+        use std::io::Read;
+
+        use super::PngSequence;
+
+        let frame: Frame = [2; Ppu::WIDTH * Ppu::HEIGHT];
+        let png = PngSequence::encode(&frame).unwrap();
+        assert_eq!(png[..8], PngSequence::SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        // IDAT right after IHDR (8 bytes of header, 13 of data and 4 of CRC)
+        let idat = 8 + 25;
+        let len = u32::from_be_bytes(png[idat..idat + 4].try_into().unwrap()) as usize;
+        assert_eq!(&png[idat + 4..idat + 8], b"IDAT");
+        let mut pixels = Vec::new();
+        ZlibDecoder::new(&png[idat + 8..idat + 8 + len])
+            .read_to_end(&mut pixels)
+            .unwrap();
+        assert_eq!(pixels.len(), Ppu::HEIGHT * (Ppu::WIDTH * 3 + 1));
+        assert_eq!(pixels[..4], [0, 0x55, 0x55, 0x55]);
+        assert!(png.ends_with(&[0xAE, 0x42, 0x60, 0x82]));
+    }
+}
@@ -0,0 +1,325 @@
+use std::io::{self, Write};
+
+use crate::ppu::Ppu;
+
+use super::{to_rgb, Encoder, Frame};
+
+/// NUT container with the frames as raw RGB24 and optionally the audio as raw s16le,
+/// one file ffmpeg reads as is: `ffmpeg -i capture.nut output.mp4`.
+///
+/// Every frame is a keyframe preceded by a syncpoint, there is no index.
+/// See the NUT specification (`nut.txt` in the ffmpeg documentation) for the format.
+#[derive(Debug)]
+pub struct NutEncoder<W: Write> {
+    writer: W,
+    /// Stream 1 when set.
+    sample_rate: Option<u32>,
+    /// Frames written so far, the pts of the video.
+    frames: u64,
+    /// Sample frames (one per channel) written so far, the pts of the audio.
+    samples: u64,
+    header_written: bool,
+}
+
+impl<W: Write> NutEncoder<W> {
+    const FILE_ID: &'static [u8] = b"nut/multimedia container\0";
+
+    const MAIN_STARTCODE: u64 = 0x4E4D_7A56_1F5F_04AD;
+    const STREAM_STARTCODE: u64 = 0x4E53_1140_5BF2_F9DB;
+    const SYNCPOINT_STARTCODE: u64 = 0x4E4B_E4AD_EECA_4569;
+
+    const VERSION: u64 = 3;
+    const MAX_DISTANCE: u64 = 65536;
+    const MSB_PTS_SHIFT: u64 = 7;
+
+    const VIDEO_FOURCC: [u8; 4] = [b'R', b'G', b'B', 24];
+    const AUDIO_FOURCC: [u8; 4] = [b'P', b'S', b'D', 16];
+    const CHANNELS: u64 = 2;
+    /// Seconds per frame, `CYCLES_PER_FRAME` / 4194304 reduced.
+    const FRAME_TIME_BASE: (u64, u64) = (4389, 262_144);
+
+    const FLAG_KEY: u64 = 1;
+    const FLAG_CODED_PTS: u64 = 8;
+    const FLAG_STREAM_ID: u64 = 16;
+    const FLAG_SIZE_MSB: u64 = 32;
+    const FLAG_CHECKSUM: u64 = 64;
+    const FLAG_CODED: u64 = 4096;
+    /// Everything of a frame is coded in its header, with the frame code 0.
+    const FRAME_FLAGS: u64 = Self::FLAG_KEY
+        | Self::FLAG_CODED_PTS
+        | Self::FLAG_STREAM_ID
+        | Self::FLAG_SIZE_MSB
+        | Self::FLAG_CHECKSUM;
+
+    /// Video only.
+    pub fn new(writer: W) -> Self {
+        NutEncoder {
+            writer,
+            sample_rate: None,
+            frames: 0,
+            samples: 0,
+            header_written: false,
+        }
+    }
+
+    /// Video and the interleaved stereo samples, at `sample_rate`.
+    pub fn with_audio(writer: W, sample_rate: u32) -> Self {
+        NutEncoder {
+            sample_rate: Some(sample_rate),
+            ..Self::new(writer)
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_headers(&mut self) -> io::Result<()> {
+        self.writer.write_all(Self::FILE_ID)?;
+
+        let mut main = Vec::new();
+        put_v(&mut main, Self::VERSION);
+        put_v(&mut main, 1 + u64::from(self.sample_rate.is_some()));
+        put_v(&mut main, Self::MAX_DISTANCE);
+        let mut time_bases = vec![Self::FRAME_TIME_BASE];
+        if let Some(rate) = self.sample_rate {
+            time_bases.push((1, rate.into()));
+        }
+        put_v(&mut main, time_bases.len() as u64);
+        for (num, den) in time_bases {
+            put_v(&mut main, num);
+            put_v(&mut main, den);
+        }
+        // a single run of frame codes, all of them coding the whole header themselves:
+        // flags, field count, pts delta, size mul, stream, size lsb, reserved and count,
+        // 0x4E ('N') is skipped as it starts the startcodes
+        put_v(&mut main, Self::FLAG_CODED);
+        put_v(&mut main, 6);
+        for field in [0, 1, 0, 0, 0, 255] {
+            put_v(&mut main, field);
+        }
+        // no elision headers
+        put_v(&mut main, 0);
+        self.put_packet(Self::MAIN_STARTCODE, &main)?;
+
+        let mut video = Vec::new();
+        self.put_stream_header(&mut video, 0, 0, &Self::VIDEO_FOURCC);
+        put_v(&mut video, Ppu::WIDTH as u64);
+        put_v(&mut video, Ppu::HEIGHT as u64);
+        // unknown aspect ratio and colorspace
+        for field in [0, 0, 0] {
+            put_v(&mut video, field);
+        }
+        self.put_packet(Self::STREAM_STARTCODE, &video)?;
+
+        if let Some(rate) = self.sample_rate {
+            let mut audio = Vec::new();
+            self.put_stream_header(&mut audio, 1, 1, &Self::AUDIO_FOURCC);
+            put_v(&mut audio, rate.into());
+            put_v(&mut audio, 1);
+            put_v(&mut audio, Self::CHANNELS);
+            self.put_packet(Self::STREAM_STARTCODE, &audio)?;
+        }
+        Ok(())
+    }
+
+    fn put_stream_header(&self, data: &mut Vec<u8>, stream: u64, class: u64, fourcc: &[u8; 4]) {
+        put_v(data, stream);
+        put_v(data, class);
+        put_v(data, fourcc.len() as u64);
+        data.extend_from_slice(fourcc);
+        // the time base of the stream is the one with the same index
+        put_v(data, stream);
+        put_v(data, Self::MSB_PTS_SHIFT);
+        // max pts distance, decode delay, flags and codec data size
+        for field in [1 << Self::MSB_PTS_SHIFT, 0, 0, 0] {
+            put_v(data, field);
+        }
+    }
+
+    /// Startcode, forward pointer, data and its checksum,
+    /// long packets also have a checksum of their header.
+    fn put_packet(&mut self, startcode: u64, data: &[u8]) -> io::Result<()> {
+        let forward_ptr = data.len() as u64 + 4;
+        let mut header = startcode.to_be_bytes().to_vec();
+        put_v(&mut header, forward_ptr);
+        if forward_ptr > 4096 {
+            let checksum = crc32(&header);
+            header.extend_from_slice(&checksum.to_be_bytes());
+        }
+        self.writer.write_all(&header)?;
+        self.writer.write_all(data)?;
+        self.writer.write_all(&crc32(data).to_be_bytes())
+    }
+
+    /// A syncpoint then the frame, every frame can be decoded on its own.
+    fn put_frame(&mut self, stream: u64, pts: u64, data: &[u8]) -> io::Result<()> {
+        if !self.header_written {
+            self.write_headers()?;
+            self.header_written = true;
+        }
+        let mut syncpoint = Vec::new();
+        // global key pts, then the back pointer to a syncpoint to start from, this one
+        put_v(
+            &mut syncpoint,
+            pts * (1 + u64::from(self.sample_rate.is_some())) + stream,
+        );
+        put_v(&mut syncpoint, 0);
+        self.put_packet(Self::SYNCPOINT_STARTCODE, &syncpoint)?;
+
+        let mut header = vec![0];
+        // the flags of the frame code are xored with these, giving them all
+        put_v(&mut header, Self::FRAME_FLAGS);
+        put_v(&mut header, stream);
+        // above the msb shift, the pts is given in full
+        put_v(&mut header, pts + (1 << Self::MSB_PTS_SHIFT));
+        put_v(&mut header, data.len() as u64);
+        let checksum = crc32(&header);
+        header.extend_from_slice(&checksum.to_be_bytes());
+        self.writer.write_all(&header)?;
+        self.writer.write_all(data)
+    }
+}
+
+impl<W: Write> Encoder for NutEncoder<W> {
+    fn encode_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        self.put_frame(0, self.frames, &to_rgb(frame))?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn encode_audio(&mut self, samples: &[i16]) -> io::Result<()> {
+        if self.sample_rate.is_none() || samples.is_empty() {
+            return Ok(());
+        }
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        self.put_frame(1, self.samples, &bytes)?;
+        self.samples += samples.len() as u64 / Self::CHANNELS;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            self.write_headers()?;
+            self.header_written = true;
+        }
+        self.writer.flush()
+    }
+}
+
+/// Variable length number, 7 bits per byte from the most significant,
+/// the high bit set on all bytes but the last.
+fn put_v(data: &mut Vec<u8>, value: u64) {
+    let mut shift = 63 / 7 * 7;
+    while shift > 0 && value >> shift == 0 {
+        shift -= 7;
+    }
+    while shift > 0 {
+        data.push(0x80 | (value >> shift) as u8 & 0x7F);
+        shift -= 7;
+    }
+    data.push(value as u8 & 0x7F);
+}
+
+/// CRC-32 with the 0x04C11DB7 polynomial, most significant bit first, starting from 0.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for &byte in data {
+        crc ^= u32::from(byte) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ppu::Ppu, recording::Encoder};
+
+    use super::{crc32, put_v, NutEncoder};
+
+    fn get_v(data: &[u8], pos: &mut usize) -> u64 {
+        let mut value = 0;
+        loop {
+            let byte = data[*pos];
+            *pos += 1;
+            value = (value << 7) | u64::from(byte & 0x7F);
+            if byte & 0x80 == 0 {
+                return value;
+            }
+        }
+    }
+
+    #[test]
+    fn numbers() {
+        let mut data = Vec::new();
+        for value in [0, 0x7F, 0x80, 4096, u64::MAX] {
+            put_v(&mut data, value);
+        }
+        assert_eq!(data[..6], [0x00, 0x7F, 0x81, 0x00, 0xA0, 0x00]);
+        let mut pos = 0;
+        let values: Vec<_> = (0..5).map(|_| get_v(&data, &mut pos)).collect();
+        assert_eq!(values, [0, 0x7F, 0x80, 4096, u64::MAX]);
+        assert_eq!(pos, data.len());
+        // the check value of this CRC variant
+        assert_eq!(crc32(b"123456789"), 0x89A1_897F);
+    }
+
+    #[test]
+    fn packets() {
+        let frame = [3; Ppu::WIDTH * Ppu::HEIGHT];
+        let mut nut = NutEncoder::with_audio(Vec::new(), 48000);
+        nut.encode_audio(&[1, -1, 2, -2]).unwrap();
+        nut.encode_frame(&frame).unwrap();
+        nut.encode_frame(&frame).unwrap();
+        nut.finish().unwrap();
+        let data = nut.into_inner();
+
+        let mut pos = NutEncoder::<Vec<u8>>::FILE_ID.len();
+        assert_eq!(&data[..pos], b"nut/multimedia container\0");
+        let mut startcodes = Vec::new();
+        let mut frames = Vec::new();
+        while pos < data.len() {
+            if data[pos] == b'N' {
+                let startcode = u64::from_be_bytes(data[pos..pos + 8].try_into().unwrap());
+                pos += 8;
+                let forward_ptr = get_v(&data, &mut pos) as usize;
+                // the checksum of a packet is part of it
+                assert_eq!(crc32(&data[pos..pos + forward_ptr]), 0);
+                startcodes.push(startcode);
+                pos += forward_ptr;
+                continue;
+            }
+            let start = pos;
+            assert_eq!(data[pos], 0);
+            pos += 1;
+            let flags = get_v(&data, &mut pos);
+            let stream = get_v(&data, &mut pos);
+            let pts = get_v(&data, &mut pos) - 128;
+            let size = get_v(&data, &mut pos) as usize;
+            assert_eq!(crc32(&data[start..pos + 4]), 0);
+            pos += 4 + size;
+            frames.push((flags, stream, pts, size));
+        }
+        assert_eq!(pos, data.len());
+        let main = NutEncoder::<Vec<u8>>::MAIN_STARTCODE;
+        let stream = NutEncoder::<Vec<u8>>::STREAM_STARTCODE;
+        let sync = NutEncoder::<Vec<u8>>::SYNCPOINT_STARTCODE;
+        assert_eq!(startcodes, [main, stream, stream, sync, sync, sync]);
+        let video_size = Ppu::WIDTH * Ppu::HEIGHT * 3;
+        let flags = NutEncoder::<Vec<u8>>::FRAME_FLAGS;
+        assert_eq!(
+            frames,
+            [
+                (flags, 1, 0, 8),
+                (flags, 0, 0, video_size),
+                (flags, 0, 1, video_size)
+            ]
+        );
+    }
+}
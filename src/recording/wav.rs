@@ -0,0 +1,233 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use super::{Encoder, Frame};
+
+/// 16 bits stereo WAV output, the video frames are ignored.
+///
+/// The sizes in the header are patched in `finish`, hence the `Seek` bound.
+#[derive(Debug)]
+pub struct WavEncoder<W: Write + Seek> {
+    writer: W,
+    sample_rate: u32,
+    /// Bytes of samples written so far.
+    data_len: u32,
+    header_written: bool,
+}
+
+impl<W: Write + Seek> WavEncoder<W> {
+    const CHANNELS: u16 = 2;
+    const BITS_PER_SAMPLE: u16 = 16;
+    const HEADER_LEN: u32 = 44;
+
+    pub fn new(writer: W, sample_rate: u32) -> Self {
+        WavEncoder {
+            writer,
+            sample_rate,
+            data_len: 0,
+            header_written: false,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let block_align = Self::CHANNELS * Self::BITS_PER_SAMPLE / 8;
+        let byte_rate = self.sample_rate * u32::from(block_align);
+        let mut header = Vec::with_capacity(Self::HEADER_LEN as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(Self::HEADER_LEN - 8 + self.data_len).to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        // PCM
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&Self::CHANNELS.to_le_bytes());
+        header.extend_from_slice(&self.sample_rate.to_le_bytes());
+        header.extend_from_slice(&byte_rate.to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&Self::BITS_PER_SAMPLE.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&self.data_len.to_le_bytes());
+        self.writer.write_all(&header)
+    }
+}
+
+impl<W: Write + Seek> Encoder for WavEncoder<W> {
+    fn encode_frame(&mut self, _frame: &Frame) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn encode_audio(&mut self, samples: &[i16]) -> io::Result<()> {
+        if !self.header_written {
+            // placeholder sizes until `finish`
+            self.write_header()?;
+            self.header_written = true;
+        }
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        self.writer.write_all(&bytes)?;
+        self.data_len += bytes.len() as u32;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.header_written = true;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+}
+
+/// Keeps the samples in memory, for tests or frontends doing their own processing.
+#[derive(Debug, Default, Clone)]
+pub struct PcmBuffer {
+    samples: Vec<i16>,
+}
+
+impl PcmBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Interleaved stereo samples.
+    pub fn get_samples(&self) -> &[i16] {
+        &self.samples
+    }
+
+    pub fn into_samples(self) -> Vec<i16> {
+        self.samples
+    }
+}
+
+impl Encoder for PcmBuffer {
+    fn encode_frame(&mut self, _frame: &Frame) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn encode_audio(&mut self, samples: &[i16]) -> io::Result<()> {
+        self.samples.extend_from_slice(samples);
+        Ok(())
+    }
+}
+
+/// Summary of a recording, to compare the output against golden recordings
+/// without requiring bit exact samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioFingerprint {
+    /// Root mean square, normalized to 0.0-1.0.
+    pub rms: f32,
+    /// Sign changes per sample, a rough measure of the dominant frequency.
+    pub zero_crossing_rate: f32,
+    /// Energy in low, mid and high bands, normalized to sum to 1.0.
+    pub bands: [f32; 3],
+}
+
+impl AudioFingerprint {
+    /// Fingerprint of mono samples, downmix stereo ones first with `downmix`.
+    pub fn new(samples: &[i16]) -> Self {
+        if samples.is_empty() {
+            return AudioFingerprint {
+                rms: 0.0,
+                zero_crossing_rate: 0.0,
+                bands: [0.0; 3],
+            };
+        }
+        let normalized: Vec<f32> = samples.iter().map(|&s| f32::from(s) / 32768.0).collect();
+        let sum_squares: f32 = normalized.iter().map(|s| s * s).sum();
+        let rms = (sum_squares / normalized.len() as f32).sqrt();
+        let crossings = normalized
+            .windows(2)
+            .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+            .count();
+        let zero_crossing_rate = crossings as f32 / normalized.len() as f32;
+
+        // crude 3 bands split with one pole filters: low < ~1/16 of the rate, high > ~1/4
+        let (mut slow, mut fast) = (0.0f32, 0.0f32);
+        let mut energy = [0.0f32; 3];
+        for &s in &normalized {
+            slow += (s - slow) / 16.0;
+            fast += (s - fast) / 4.0;
+            energy[0] += slow * slow;
+            energy[1] += (fast - slow) * (fast - slow);
+            energy[2] += (s - fast) * (s - fast);
+        }
+        let total: f32 = energy.iter().sum();
+        let bands = if total > 0.0 {
+            energy.map(|e| e / total)
+        } else {
+            [0.0; 3]
+        };
+        AudioFingerprint {
+            rms,
+            zero_crossing_rate,
+            bands,
+        }
+    }
+
+    /// Average the channels of interleaved stereo samples.
+    pub fn downmix(samples: &[i16]) -> Vec<i16> {
+        samples
+            .chunks_exact(2)
+            .map(|pair| ((i32::from(pair[0]) + i32::from(pair[1])) / 2) as i16)
+            .collect()
+    }
+
+    /// Whether every measure is within `tolerance` (absolute) of the other fingerprint.
+    pub fn matches(&self, other: &AudioFingerprint, tolerance: f32) -> bool {
+        let close = |a: f32, b: f32| (a - b).abs() <= tolerance;
+        close(self.rms, other.rms)
+            && close(self.zero_crossing_rate, other.zero_crossing_rate)
+            && self
+                .bands
+                .iter()
+                .zip(other.bands)
+                .all(|(&a, b)| close(a, b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::recording::Encoder;
+
+    use super::{AudioFingerprint, WavEncoder};
+
+    fn square(period: usize, len: usize) -> Vec<i16> {
+        (0..len)
+            .map(|i| if i % period < period / 2 { 8000 } else { -8000 })
+            .collect()
+    }
+
+    #[test]
+    fn wav_header() {
+        let mut wav = WavEncoder::new(Cursor::new(Vec::new()), 48000);
+        wav.encode_audio(&[1, 2, 3, 4]).unwrap();
+        wav.encode_audio(&[5, 6]).unwrap();
+        wav.finish().unwrap();
+        let data = wav.into_inner().into_inner();
+        assert_eq!(data.len(), 44 + 12);
+        assert_eq!(&data[..4], b"RIFF");
+        assert_eq!(data[4..8], (36u32 + 12).to_le_bytes());
+        assert_eq!(data[24..28], 48000u32.to_le_bytes());
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(data[40..44], 12u32.to_le_bytes());
+        assert_eq!(data[44..46], [1, 0]);
+    }
+
+    #[test]
+    fn fingerprints() {
+        let low = AudioFingerprint::new(&square(200, 48000));
+        let high = AudioFingerprint::new(&square(4, 48000));
+        let quiet =
+            AudioFingerprint::new(&square(200, 48000).iter().map(|s| s / 4).collect::<Vec<_>>());
+        assert!((low.rms - 8000.0 / 32768.0).abs() < 0.001);
+        assert!(low.matches(&low, 0.0));
+        assert!(!low.matches(&quiet, 0.05));
+        assert!(low.zero_crossing_rate < high.zero_crossing_rate);
+        assert!(low.bands[0] > low.bands[2]);
+        assert!(high.bands[2] > high.bands[0]);
+        assert_eq!(AudioFingerprint::downmix(&[10, 20, -4, 0]), [15, -2]);
+    }
+}
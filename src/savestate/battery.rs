@@ -0,0 +1,172 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::memory::mbc::mbc3::Rtc;
+
+/// Keeps the `.sav` file of a battery backed cartridge up to date.
+///
+/// Games write their save over several frames, so writing the file on every change
+/// can leave a half-written save if the process is killed in the middle.
+/// Like the "safe save" of frontends, the file is only written
+/// once the RAM has been stable for `stable_frames` frames,
+/// and it is written to a temporary file first then renamed over the old one.
+///
+/// Call `update` once per frame with `Emulator::get_save_data`,
+/// and `flush` on shutdown so the last changes are not lost.
+/// For cartridges with an RTC, `with_rtc_footer` stamps the time of the save in the file.
+#[derive(Debug)]
+pub struct SaveFlusher {
+    path: PathBuf,
+    stable_frames: u32,
+    /// The data ends with the RTC footer, its timestamp is set when writing.
+    rtc_footer: bool,
+    /// What is currently in the file.
+    saved: Vec<u8>,
+    /// Latest data not yet written.
+    pending: Option<Vec<u8>>,
+    /// Frames since the pending data last changed.
+    unchanged_frames: u32,
+}
+
+impl SaveFlusher {
+    /// `saved` is what the file contains right now, usually what was loaded at startup.
+    pub fn new(path: impl Into<PathBuf>, stable_frames: u32, saved: Vec<u8>) -> Self {
+        SaveFlusher {
+            path: path.into(),
+            stable_frames,
+            rtc_footer: false,
+            saved,
+            pending: None,
+            unchanged_frames: 0,
+        }
+    }
+
+    /// The save data ends with the RTC footer of `Rtc::SAVE_SIZE` bytes.
+    ///
+    /// Its timestamp is ignored when looking for changes, and set to now when the file is written.
+    pub fn with_rtc_footer(mut self) -> Self {
+        self.rtc_footer = true;
+        // the file was stamped, the save data of the emulator isn't
+        Rtc::clear_save_stamp(&mut self.saved);
+        self
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Changes not written to the file yet.
+    pub fn is_dirty(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Feed the save data of the frame, writes the file if it has been stable long enough.
+    ///
+    /// Returns whether the file was written.
+    pub fn update(&mut self, data: &[u8]) -> io::Result<bool> {
+        match &self.pending {
+            Some(pending) if pending == data => self.unchanged_frames += 1,
+            _ if self.saved == data => {
+                // back to what is already saved
+                self.pending = None;
+                return Ok(false);
+            }
+            _ => {
+                self.pending = Some(data.to_vec());
+                self.unchanged_frames = 0;
+            }
+        }
+        if self.unchanged_frames >= self.stable_frames {
+            self.flush()?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Write the pending changes now, for shutdown paths.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let Some(data) = self.pending.take() else {
+            return Ok(());
+        };
+        let result = if self.rtc_footer {
+            let mut stamped = data.clone();
+            Rtc::stamp_save_data(&mut stamped);
+            write_atomically(&self.path, &stamped)
+        } else {
+            write_atomically(&self.path, &data)
+        };
+        if let Err(err) = result {
+            // keep it to retry later
+            self.pending = Some(data);
+            return Err(err);
+        }
+        self.saved = data;
+        self.unchanged_frames = 0;
+        Ok(())
+    }
+}
+
+/// Write next to the file then rename it, the rename replaces the file in one go.
+fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::SaveFlusher;
+
+    #[test]
+    fn wait_for_stable_ram() {
+        let path = std::env::temp_dir().join(format!("gb_emul_flusher_{}.sav", std::process::id()));
+        let mut flusher = SaveFlusher::new(&path, 2, vec![0; 4]);
+
+        assert!(!flusher.update(&[0; 4]).unwrap());
+        assert!(!flusher.is_dirty());
+
+        // the game writes over several frames
+        assert!(!flusher.update(&[1, 0, 0, 0]).unwrap());
+        assert!(!flusher.update(&[1, 2, 0, 0]).unwrap());
+        assert!(!flusher.update(&[1, 2, 0, 0]).unwrap());
+        assert!(!path.exists());
+        assert!(flusher.update(&[1, 2, 0, 0]).unwrap());
+        assert_eq!(fs::read(&path).unwrap(), [1, 2, 0, 0]);
+        assert!(!flusher.is_dirty());
+
+        // shutdown before it is stable
+        assert!(!flusher.update(&[1, 2, 3, 0]).unwrap());
+        assert!(flusher.is_dirty());
+        flusher.flush().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), [1, 2, 3, 0]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rtc_timestamp() {
+        let path = std::env::temp_dir().join(format!("gb_emul_rtc_{}.sav", std::process::id()));
+        // loaded from a file stamped some time ago
+        let mut saved = vec![0; 48];
+        saved[40] = 0x42;
+        let mut flusher = SaveFlusher::new(&path, 0, saved).with_rtc_footer();
+
+        assert!(!flusher.update(&[0; 48]).unwrap());
+        assert!(!flusher.is_dirty());
+
+        let mut data = vec![0; 48];
+        data[0] = 1;
+        assert!(flusher.update(&data).unwrap());
+        let written = fs::read(&path).unwrap();
+        assert_eq!(written[..40], data[..40]);
+        assert_ne!(written[40..], [0; 8]);
+        assert!(!flusher.update(&data).unwrap());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+use std::{fmt::Display, io};
+
+/// Compression applied to savestates and rewind snapshots.
+///
+/// Snapshots are mostly made of large, sparsely used memory sections,
+/// so even a fast compressor drastically reduces their footprint.
+/// Methods take `&mut self` so implementations can reuse their internal buffers/contexts
+/// between snapshots.
+pub trait Compressor {
+    fn compress(&mut self, data: &[u8]) -> Vec<u8>;
+    fn decompress(&mut self, data: &[u8]) -> Result<Vec<u8>, CompressionError>;
+}
+
+#[derive(Debug)]
+pub enum CompressionError {
+    /// The compressed data is corrupted or was produced by another compressor.
+    InvalidData(io::Error),
+}
+
+impl Display for CompressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompressionError::InvalidData(err) => write!(f, "invalid compressed data: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for CompressionError {}
+
+impl From<io::Error> for CompressionError {
+    fn from(err: io::Error) -> Self {
+        CompressionError::InvalidData(err)
+    }
+}
+
+/// Store the data as is.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoCompression;
+
+impl Compressor for NoCompression {
+    fn compress(&mut self, data: &[u8]) -> Vec<u8> {
+        data.to_vec()
+    }
+
+    fn decompress(&mut self, data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        Ok(data.to_vec())
+    }
+}
+
+#[cfg(feature = "zstd")]
+pub use self::zstd_compressor::ZstdCompressor;
+
+#[cfg(feature = "zstd")]
+mod zstd_compressor {
+    use super::{CompressionError, Compressor};
+
+    /// Zstandard compression, the best ratio/speed tradeoff for rewind snapshots.
+    #[derive(Debug, Clone, Copy)]
+    pub struct ZstdCompressor {
+        level: i32,
+    }
+
+    impl ZstdCompressor {
+        pub const DEFAULT_LEVEL: i32 = 3;
+
+        pub fn new(level: i32) -> Self {
+            ZstdCompressor { level }
+        }
+    }
+
+    impl Default for ZstdCompressor {
+        fn default() -> Self {
+            Self::new(Self::DEFAULT_LEVEL)
+        }
+    }
+
+    impl Compressor for ZstdCompressor {
+        fn compress(&mut self, data: &[u8]) -> Vec<u8> {
+            // writing into a Vec can't fail
+            zstd::encode_all(data, self.level).expect("zstd compression into memory failed")
+        }
+
+        fn decompress(&mut self, data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+            Ok(zstd::decode_all(data)?)
+        }
+    }
+}
+
+#[cfg(feature = "deflate")]
+pub use self::deflate_compressor::DeflateCompressor;
+
+#[cfg(feature = "deflate")]
+mod deflate_compressor {
+    use std::io::{Read, Write};
+
+    use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+    use super::{CompressionError, Compressor};
+
+    /// Raw deflate compression, slower than zstd but pure rust.
+    #[derive(Debug, Clone, Copy)]
+    pub struct DeflateCompressor {
+        level: u32,
+    }
+
+    impl DeflateCompressor {
+        pub const DEFAULT_LEVEL: u32 = 6;
+
+        /// `level` goes from 0 (no compression) to 9 (best compression).
+        pub fn new(level: u32) -> Self {
+            DeflateCompressor {
+                level: level.min(9),
+            }
+        }
+    }
+
+    impl Default for DeflateCompressor {
+        fn default() -> Self {
+            Self::new(Self::DEFAULT_LEVEL)
+        }
+    }
+
+    impl Compressor for DeflateCompressor {
+        fn compress(&mut self, data: &[u8]) -> Vec<u8> {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::new(self.level));
+            // writing into a Vec can't fail
+            encoder
+                .write_all(data)
+                .and_then(|_| encoder.finish())
+                .expect("deflate compression into memory failed")
+        }
+
+        fn decompress(&mut self, data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+            let mut decompressed = Vec::new();
+            DeflateDecoder::new(data).read_to_end(&mut decompressed)?;
+            Ok(decompressed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Compressor, NoCompression};
+
+    fn snapshot_like() -> Vec<u8> {
+        // mostly zeroes with a bit of noise, like a real snapshot
+        let mut data = vec![0; 0x10000];
+        for (i, byte) in data.iter_mut().enumerate().step_by(97) {
+            *byte = (i % 251) as u8;
+        }
+        data
+    }
+
+    fn round_trip(compressor: &mut dyn Compressor) -> usize {
+        let data = snapshot_like();
+        let compressed = compressor.compress(&data);
+        let decompressed = compressor.decompress(&compressed).unwrap();
+        assert_eq!(decompressed, data);
+        compressed.len()
+    }
+
+    #[test]
+    fn no_compression() {
+        assert_eq!(round_trip(&mut NoCompression), 0x10000);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd() {
+        assert!(round_trip(&mut super::ZstdCompressor::default()) < 0x10000 / 4);
+        assert!(super::ZstdCompressor::default()
+            .decompress(&[1, 2, 3])
+            .is_err());
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn deflate() {
+        assert!(round_trip(&mut super::DeflateCompressor::default()) < 0x10000 / 4);
+    }
+}
@@ -0,0 +1,265 @@
+use std::fmt::Display;
+
+pub mod battery;
+pub mod compression;
+pub mod rewind;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SaveStateError {
+    /// The state ended before everything was read.
+    UnexpectedEnd,
+    /// A block doesn't have the size of what it is loaded into,
+    /// like the RAM of a different cartridge.
+    SizeMismatch { expected: usize, found: usize },
+    /// A value that can't have been written by a save.
+    InvalidValue(u8),
+    /// The data doesn't start like a savestate.
+    InvalidHeader,
+    /// Made by a newer version of the emulator.
+    UnsupportedVersion(u16),
+}
+
+impl Display for SaveStateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveStateError::UnexpectedEnd => write!(f, "unexpected end of savestate"),
+            SaveStateError::SizeMismatch { expected, found } => write!(
+                f,
+                "savestate block of {} bytes where {} were expected",
+                found, expected
+            ),
+            SaveStateError::InvalidValue(value) => {
+                write!(f, "invalid value {:#04X} in savestate", value)
+            }
+            SaveStateError::InvalidHeader => write!(f, "not a savestate"),
+            SaveStateError::UnsupportedVersion(version) => write!(
+                f,
+                "savestate format {} is newer than the supported {}",
+                version, FORMAT_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+/// Start of every whole machine state.
+pub const MAGIC: [u8; 4] = *b"GBST";
+
+/// Bumped on each change of the format, the states of the previous versions
+/// are still loaded by checking `StateReader::get_version` where things changed.
+pub const FORMAT_VERSION: u16 = 1;
+
+/// Version of the states made before they had a header, their data is the one of version 1.
+pub const HEADERLESS_VERSION: u16 = 0;
+
+/// Append only buffer the machine serializes itself into.
+///
+/// Values are little endian, blocks are prefixed by their length.
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// For a whole machine state, starts with the magic and the format version.
+    pub fn with_header() -> Self {
+        let mut state = Self::new();
+        state.data.extend_from_slice(&MAGIC);
+        state.put_u16(FORMAT_VERSION);
+        state
+    }
+
+    pub fn put_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn put_bool(&mut self, value: bool) {
+        self.put_u8(value.into());
+    }
+
+    pub fn put_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.put_u32(bytes.len() as u32);
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Reading counterpart of `StateWriter`.
+#[derive(Debug)]
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    version: u16,
+}
+
+impl<'a> StateReader<'a> {
+    /// The data is read as the current format version, unless `read_header` says otherwise.
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader {
+            data,
+            version: FORMAT_VERSION,
+        }
+    }
+
+    /// Read `data` as the given format version, for the states without a header.
+    pub fn with_version(data: &'a [u8], version: u16) -> Self {
+        StateReader { data, version }
+    }
+
+    /// Check the header written by `StateWriter::with_header`, and take its version.
+    pub fn read_header(&mut self) -> Result<(), SaveStateError> {
+        let magic = self
+            .take::<4>()
+            .map_err(|_| SaveStateError::InvalidHeader)?;
+        if magic != MAGIC {
+            return Err(SaveStateError::InvalidHeader);
+        }
+        let version = self.get_u16()?;
+        if version > FORMAT_VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+        self.version = version;
+        Ok(())
+    }
+
+    /// Format version of the state, to load what an older version wrote.
+    pub fn get_version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], SaveStateError> {
+        let bytes = self.take_slice(N)?;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn take_slice(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.data.len() < len {
+            return Err(SaveStateError::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub fn get_u8(&mut self) -> Result<u8, SaveStateError> {
+        self.take::<1>().map(|[value]| value)
+    }
+
+    pub fn get_bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.get_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(SaveStateError::InvalidValue(value)),
+        }
+    }
+
+    pub fn get_u16(&mut self) -> Result<u16, SaveStateError> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    pub fn get_u32(&mut self) -> Result<u32, SaveStateError> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    pub fn get_u64(&mut self) -> Result<u64, SaveStateError> {
+        self.take().map(u64::from_le_bytes)
+    }
+
+    pub fn get_bytes(&mut self) -> Result<&'a [u8], SaveStateError> {
+        let len = self.get_u32()? as usize;
+        self.take_slice(len)
+    }
+
+    /// Read a block into `dest`, which must have the same size.
+    pub fn get_bytes_into(&mut self, dest: &mut [u8]) -> Result<(), SaveStateError> {
+        let bytes = self.get_bytes()?;
+        if bytes.len() != dest.len() {
+            return Err(SaveStateError::SizeMismatch {
+                expected: dest.len(),
+                found: bytes.len(),
+            });
+        }
+        dest.copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SaveStateError, StateReader, StateWriter, FORMAT_VERSION};
+
+    #[test]
+    fn round_trip() {
+        let mut state = StateWriter::new();
+        state.put_u8(0x12);
+        state.put_bool(true);
+        state.put_u16(0x3456);
+        state.put_u64(u64::MAX);
+        state.put_bytes(&[1, 2, 3]);
+        let data = state.into_inner();
+
+        let mut state = StateReader::new(&data);
+        assert_eq!(state.get_u8(), Ok(0x12));
+        assert_eq!(state.get_bool(), Ok(true));
+        assert_eq!(state.get_u16(), Ok(0x3456));
+        assert_eq!(state.get_u64(), Ok(u64::MAX));
+        let mut bytes = [0; 2];
+        assert_eq!(
+            state.get_bytes_into(&mut bytes),
+            Err(SaveStateError::SizeMismatch {
+                expected: 2,
+                found: 3
+            })
+        );
+        assert!(state.is_empty());
+        assert_eq!(state.get_u8(), Err(SaveStateError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn header() {
+        let mut state = StateWriter::with_header();
+        state.put_u8(0x42);
+        let data = state.into_inner();
+        let mut state = StateReader::new(&data);
+        assert_eq!(state.read_header(), Ok(()));
+        assert_eq!(state.get_version(), FORMAT_VERSION);
+        assert_eq!(state.get_u8(), Ok(0x42));
+
+        assert_eq!(
+            StateReader::new(b"GB").read_header(),
+            Err(SaveStateError::InvalidHeader)
+        );
+        assert_eq!(
+            StateReader::new(b"GBSV\x01\x00").read_header(),
+            Err(SaveStateError::InvalidHeader)
+        );
+        let newer = [b'G', b'B', b'S', b'T', 0xFF, 0xFF];
+        assert_eq!(
+            StateReader::new(&newer).read_header(),
+            Err(SaveStateError::UnsupportedVersion(0xFFFF))
+        );
+    }
+}
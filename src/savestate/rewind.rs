@@ -0,0 +1,237 @@
+use std::{collections::VecDeque, fmt::Debug};
+
+use super::compression::{Compressor, NoCompression};
+
+/// The last moments of emulation, savestates taken every few frames in a bounded amount of memory.
+///
+/// Only the newest snapshot is kept whole. Each older one is stored as its difference
+/// with the next one, mostly zeroes since little changes in a few frames, run-length encoded
+/// then given to the compressor. Going back undoes the differences from the newest snapshot,
+/// and the oldest snapshots are dropped once the memory budget is exceeded.
+///
+/// Give it to `Emulator::set_rewind`, it then snapshots the machine as frames complete
+/// (none are completed while the LCD is off) and `Emulator::rewind` steps back in time.
+pub struct RewindBuffer {
+    /// Frames between two snapshots.
+    interval: u32,
+    max_bytes: usize,
+    compressor: Box<dyn Compressor>,
+    /// Newest snapshot.
+    latest: Option<Vec<u8>>,
+    /// Older ones, oldest first.
+    deltas: VecDeque<Vec<u8>>,
+    delta_bytes: usize,
+    /// Frames completed since the newest snapshot.
+    elapsed: u32,
+    /// Frame count of the PPU when last checked.
+    last_frame: u64,
+}
+
+impl Debug for RewindBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RewindBuffer")
+            .field("interval", &self.interval)
+            .field("max_bytes", &self.max_bytes)
+            .field("snapshots", &self.len())
+            .field("memory_usage", &self.get_memory_usage())
+            .finish()
+    }
+}
+
+impl RewindBuffer {
+    /// A snapshot every `interval` frames (at least one), keeping at most `max_bytes` of them,
+    /// the newest is always kept even if it alone is bigger.
+    pub fn new(interval: u32, max_bytes: usize) -> Self {
+        RewindBuffer {
+            interval: interval.max(1),
+            max_bytes,
+            compressor: Box::new(NoCompression),
+            latest: None,
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+            elapsed: 0,
+            last_frame: 0,
+        }
+    }
+
+    /// Compress the differences between the snapshots, which are only run-length encoded otherwise.
+    pub fn with_compressor(mut self, compressor: Box<dyn Compressor>) -> Self {
+        self.clear();
+        self.compressor = compressor;
+        self
+    }
+
+    pub fn get_interval(&self) -> u32 {
+        self.interval
+    }
+
+    /// Snapshots in the buffer.
+    pub fn len(&self) -> usize {
+        self.deltas.len() + usize::from(self.latest.is_some())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    /// Bytes taken by the snapshots.
+    pub fn get_memory_usage(&self) -> usize {
+        self.delta_bytes + self.latest.as_ref().map_or(0, Vec::len)
+    }
+
+    /// How far back the buffer goes, in frames.
+    pub fn get_available_frames(&self) -> u64 {
+        if self.latest.is_none() {
+            return 0;
+        }
+        u64::from(self.elapsed) + self.deltas.len() as u64 * u64::from(self.interval)
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.delta_bytes = 0;
+        self.elapsed = 0;
+    }
+
+    /// Count the frame if it's a new one, returns whether a snapshot is due.
+    pub(crate) fn update_frame(&mut self, frame: u64) -> bool {
+        if frame != self.last_frame {
+            self.last_frame = frame;
+            self.elapsed += 1;
+        }
+        self.latest.is_none() || self.elapsed >= self.interval
+    }
+
+    pub(crate) fn push(&mut self, state: Vec<u8>) {
+        if let Some(previous) = self.latest.take() {
+            let delta = self.compressor.compress(&encode_delta(&previous, &state));
+            self.delta_bytes += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.latest = Some(state);
+        self.elapsed = 0;
+        while self.get_memory_usage() > self.max_bytes {
+            match self.deltas.pop_front() {
+                Some(delta) => self.delta_bytes -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Go back to the newest snapshot at least `frames` frames ago, or the oldest one.
+    /// Returns the frames actually gone back and the state to load, which stays the newest.
+    pub(crate) fn rewind(&mut self, frames: u64) -> Option<(u64, Vec<u8>)> {
+        let mut latest = self.latest.take()?;
+        let mut gone = u64::from(self.elapsed);
+        while gone < frames {
+            let Some(delta) = self.deltas.pop_back() else {
+                break;
+            };
+            self.delta_bytes -= delta.len();
+            let delta = self
+                .compressor
+                .decompress(&delta)
+                .expect("rewind snapshots are decompressed by the compressor that made them");
+            latest = decode_delta(&latest, &delta);
+            gone += u64::from(self.interval);
+        }
+        self.elapsed = 0;
+        self.latest = Some(latest.clone());
+        Some((gone, latest))
+    }
+}
+
+/// `older` XOR `newer`, as runs of zeroes and literals:
+/// the length of `older`, then `[zeroes: u32][literals: u32][literals]` until its end.
+/// `newer` is padded with zeroes, or truncated, to the length of `older`.
+fn encode_delta(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let xor = |i: usize| older[i] ^ newer.get(i).copied().unwrap_or(0);
+    let mut delta = Vec::new();
+    delta.extend_from_slice(&(older.len() as u32).to_le_bytes());
+    let mut i = 0;
+    while i < older.len() {
+        let start = i;
+        while i < older.len() && xor(i) == 0 {
+            i += 1;
+        }
+        let zeroes = i - start;
+        let start = i;
+        while i < older.len() && xor(i) != 0 {
+            i += 1;
+        }
+        delta.extend_from_slice(&(zeroes as u32).to_le_bytes());
+        delta.extend_from_slice(&((i - start) as u32).to_le_bytes());
+        delta.extend((start..i).map(xor));
+    }
+    delta
+}
+
+/// Undo `encode_delta`, getting `older` back from `newer`.
+fn decode_delta(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    let read_u32 = |delta: &mut &[u8]| {
+        let (value, rest) = delta.split_at(4);
+        *delta = rest;
+        u32::from_le_bytes(value.try_into().unwrap()) as usize
+    };
+    let mut delta = delta;
+    let len = read_u32(&mut delta);
+    let mut older: Vec<u8> = (0..len)
+        .map(|i| newer.get(i).copied().unwrap_or(0))
+        .collect();
+    let mut i = 0;
+    while !delta.is_empty() {
+        i += read_u32(&mut delta);
+        let literals = read_u32(&mut delta);
+        let (bytes, rest) = delta.split_at(literals);
+        for (byte, xor) in older[i..i + literals].iter_mut().zip(bytes) {
+            *byte ^= xor;
+        }
+        i += literals;
+        delta = rest;
+    }
+    older
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_delta, encode_delta, RewindBuffer};
+
+    #[test]
+    fn delta_round_trip() {
+        let older = [0, 1, 2, 3, 4, 5, 6, 7];
+        for newer in [&[0, 1, 9, 3, 4, 5, 6, 8][..], &[0, 1, 2], &[1; 12], &older] {
+            let delta = encode_delta(&older, newer);
+            assert_eq!(decode_delta(newer, &delta), older);
+        }
+        // nothing changed, one run of zeroes
+        assert_eq!(encode_delta(&older, &older).len(), 12);
+    }
+
+    #[test]
+    fn bounded_ring() {
+        let state = |frame: u8| {
+            let mut state = vec![0; 100];
+            state[99] = frame;
+            state
+        };
+        let mut rewind = RewindBuffer::new(2, 100 + 3 * 13);
+        assert!(rewind.update_frame(0));
+        rewind.push(state(0));
+        for frame in 1..=10 {
+            if rewind.update_frame(frame) {
+                rewind.push(state(frame as u8));
+            }
+        }
+        // snapshots of frames 4, 6, 8 and 10, one delta is 13 bytes
+        assert_eq!(rewind.len(), 4);
+        assert_eq!(rewind.get_available_frames(), 6);
+
+        assert_eq!(rewind.rewind(0), Some((0, state(10))));
+        assert_eq!(rewind.rewind(3), Some((4, state(6))));
+        assert_eq!(rewind.rewind(100), Some((2, state(4))));
+        assert_eq!(rewind.len(), 1);
+        assert!(!rewind.update_frame(11));
+        assert!(rewind.update_frame(12));
+    }
+}
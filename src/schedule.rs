@@ -0,0 +1,71 @@
+use crate::{emulator::Emulator, memory::mbc::Mbc};
+
+/// When a scheduled action happens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduledAt {
+    /// At the start of the given frame.
+    Frame(u64),
+    /// At the given clock cycle.
+    Cycle(u64),
+}
+
+impl ScheduledAt {
+    pub const fn get_cycle(self) -> u64 {
+        match self {
+            ScheduledAt::Frame(frame) => frame * Emulator::CYCLES_PER_FRAME,
+            ScheduledAt::Cycle(cycle) => cycle,
+        }
+    }
+}
+
+/// Something done to the machine from outside, as a script would.
+#[derive(Debug)]
+pub enum ControlAction {
+    /// Power cycle, the cartridge stays in.
+    Reset,
+    /// Restore a state made by `Emulator::save_state`.
+    LoadState(Vec<u8>),
+    /// Pull the cartridge out and put this one in, without turning the console off.
+    SwapCartridge(Box<dyn Mbc>),
+}
+
+/// Control actions waiting for their cycle, sorted by it.
+///
+/// Instructions are not interrupted, so an action runs at the first instruction boundary
+/// at or after its cycle. Actions for the same cycle run in the order they were scheduled.
+#[derive(Debug, Default)]
+pub struct Schedule {
+    actions: Vec<(u64, ControlAction)>,
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, at: ScheduledAt, action: ControlAction) {
+        let cycle = at.get_cycle();
+        let index = self.actions.partition_point(|(c, _)| *c <= cycle);
+        self.actions.insert(index, (cycle, action));
+    }
+
+    /// Remove and return the next action if it is due at `cycle`.
+    pub fn pop_due(&mut self, cycle: u64) -> Option<ControlAction> {
+        match self.actions.first() {
+            Some((due, _)) if *due <= cycle => Some(self.actions.remove(0).1),
+            _ => None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.actions.clear();
+    }
+}
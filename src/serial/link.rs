@@ -0,0 +1,124 @@
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
+
+use super::SerialDevice;
+
+/// Nothing plugged, the data line is pulled up.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Unplugged;
+
+impl SerialDevice for Unplugged {
+    fn exchange(&mut self, _byte: u8) -> u8 {
+        0xFF
+    }
+}
+
+/// Link cable connecting the Game Boy to itself, every byte sent is received back.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Loopback;
+
+impl SerialDevice for Loopback {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        byte
+    }
+}
+
+/// Peer replaying captured traffic, answering each transfer with the next byte of the script.
+///
+/// Once the script is over it behaves as if unplugged.
+/// The bytes sent by the game are kept, to check them against the capture.
+///
+/// Clones share the same script and sent bytes, keep one to check them and plug the other.
+#[derive(Debug, Default, Clone)]
+pub struct ScriptedPeer {
+    replies: Arc<Mutex<VecDeque<u8>>>,
+    sent: Arc<Mutex<Vec<u8>>>,
+}
+
+impl ScriptedPeer {
+    pub fn new(replies: impl IntoIterator<Item = u8>) -> Self {
+        ScriptedPeer {
+            replies: Arc::new(Mutex::new(replies.into_iter().collect())),
+            sent: Arc::default(),
+        }
+    }
+
+    /// Bytes sent by the game so far.
+    pub fn get_sent(&self) -> Vec<u8> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.replies.lock().unwrap().is_empty()
+    }
+}
+
+impl SerialDevice for ScriptedPeer {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        self.sent.lock().unwrap().push(byte);
+        self.replies.lock().unwrap().pop_front().unwrap_or(0xFF)
+    }
+}
+
+/// Nothing plugged, but the bytes sent are kept, test ROMs like Blargg's print their results this way.
+///
+/// Clones share the same output, keep one to read it and plug the other.
+#[derive(Debug, Default, Clone)]
+pub struct SerialCapture {
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl SerialCapture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_output(&self) -> Vec<u8> {
+        self.output.lock().unwrap().clone()
+    }
+
+    /// The output as text, invalid UTF-8 is replaced.
+    pub fn get_text(&self) -> String {
+        String::from_utf8_lossy(&self.output.lock().unwrap()).into_owned()
+    }
+
+    pub fn clear(&mut self) {
+        self.output.lock().unwrap().clear();
+    }
+}
+
+impl SerialDevice for SerialCapture {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        self.output.lock().unwrap().push(byte);
+        0xFF
+    }
+}
+
+/// Nothing plugged, each byte sent is given to a callback.
+pub struct SerialCallback {
+    callback: Box<dyn FnMut(u8)>,
+}
+
+impl SerialCallback {
+    pub fn new(callback: impl FnMut(u8) + 'static) -> Self {
+        SerialCallback {
+            callback: Box::new(callback),
+        }
+    }
+}
+
+impl Debug for SerialCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SerialCallback").finish_non_exhaustive()
+    }
+}
+
+impl SerialDevice for SerialCallback {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        (self.callback)(byte);
+        0xFF
+    }
+}
@@ -0,0 +1,273 @@
+use std::{
+    fmt::Debug,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
+    time::Duration,
+};
+
+use super::SerialDevice;
+
+/// What goes through the cable between 2 emulators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkMessage {
+    /// A byte shifted out by the side providing the clock.
+    Transfer(u8),
+    /// The byte the other side shifted out in exchange.
+    Reply(u8),
+}
+
+impl LinkMessage {
+    fn to_bytes(self) -> [u8; 2] {
+        match self {
+            LinkMessage::Transfer(byte) => [0, byte],
+            LinkMessage::Reply(byte) => [1, byte],
+        }
+    }
+
+    fn from_bytes(bytes: [u8; 2]) -> io::Result<Self> {
+        match bytes {
+            [0, byte] => Ok(LinkMessage::Transfer(byte)),
+            [1, byte] => Ok(LinkMessage::Reply(byte)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid link cable message",
+            )),
+        }
+    }
+}
+
+/// Transport between the 2 ends of a link cable.
+pub trait LinkCable: Debug {
+    fn send(&mut self, message: LinkMessage) -> io::Result<()>;
+    /// Wait up to `timeout` for a message, a zero timeout only polls.
+    fn receive(&mut self, timeout: Duration) -> io::Result<Option<LinkMessage>>;
+}
+
+/// Cable between 2 emulators of the same process, each end can be moved to its own thread.
+#[derive(Debug)]
+pub struct ChannelLink {
+    sender: Sender<LinkMessage>,
+    receiver: Receiver<LinkMessage>,
+}
+
+impl ChannelLink {
+    /// The 2 ends of a cable.
+    pub fn pair() -> (Self, Self) {
+        let (sender_a, receiver_b) = mpsc::channel();
+        let (sender_b, receiver_a) = mpsc::channel();
+        let a = ChannelLink {
+            sender: sender_a,
+            receiver: receiver_a,
+        };
+        let b = ChannelLink {
+            sender: sender_b,
+            receiver: receiver_b,
+        };
+        (a, b)
+    }
+}
+
+fn disconnected() -> io::Error {
+    io::Error::from(io::ErrorKind::NotConnected)
+}
+
+impl LinkCable for ChannelLink {
+    fn send(&mut self, message: LinkMessage) -> io::Result<()> {
+        self.sender.send(message).map_err(|_| disconnected())
+    }
+
+    fn receive(&mut self, timeout: Duration) -> io::Result<Option<LinkMessage>> {
+        if timeout.is_zero() {
+            return match self.receiver.try_recv() {
+                Ok(message) => Ok(Some(message)),
+                Err(TryRecvError::Empty) => Ok(None),
+                Err(TryRecvError::Disconnected) => Err(disconnected()),
+            };
+        }
+        match self.receiver.recv_timeout(timeout) {
+            Ok(message) => Ok(Some(message)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(disconnected()),
+        }
+    }
+}
+
+/// Cable over TCP, for emulators on different processes or machines.
+#[derive(Debug)]
+pub struct TcpLink {
+    stream: TcpStream,
+    /// A message can arrive in 2 reads.
+    buffer: Vec<u8>,
+}
+
+impl TcpLink {
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+
+    /// Wait for the other end to connect.
+    pub fn accept(listener: &TcpListener) -> io::Result<Self> {
+        let (stream, _) = listener.accept()?;
+        Self::new(stream)
+    }
+
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        // messages are tiny and latency is everything
+        stream.set_nodelay(true)?;
+        Ok(TcpLink {
+            stream,
+            buffer: Vec::with_capacity(2),
+        })
+    }
+}
+
+impl LinkCable for TcpLink {
+    fn send(&mut self, message: LinkMessage) -> io::Result<()> {
+        self.stream.write_all(&message.to_bytes())
+    }
+
+    fn receive(&mut self, timeout: Duration) -> io::Result<Option<LinkMessage>> {
+        if timeout.is_zero() {
+            self.stream.set_nonblocking(true)?;
+        } else {
+            self.stream.set_nonblocking(false)?;
+            self.stream.set_read_timeout(Some(timeout))?;
+        }
+        while self.buffer.len() < 2 {
+            let mut byte = [0];
+            match self.stream.read(&mut byte) {
+                Ok(0) => return Err(disconnected()),
+                Ok(_) => self.buffer.push(byte[0]),
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Ok(None)
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        let bytes = [self.buffer[0], self.buffer[1]];
+        self.buffer.clear();
+        LinkMessage::from_bytes(bytes).map(Some)
+    }
+}
+
+/// The link port end of a cable, to plug in the serial port.
+///
+/// Which side is the master is decided by the games: the one using the internal clock sends
+/// its byte and waits for the other to answer with its own, the other answers
+/// once it waits for an external clock transfer. The master waits up to the timeout,
+/// which paces the 2 emulators, then gives up and reads 0xFF like with nothing plugged.
+/// If both use the internal clock at the same time, each gets the byte of the other.
+#[derive(Debug)]
+pub struct LinkPort<C: LinkCable> {
+    cable: C,
+    timeout: Duration,
+    /// Byte clocked in by the other side, the transfer completes on the next poll.
+    received: Option<u8>,
+}
+
+impl<C: LinkCable> LinkPort<C> {
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(500);
+
+    pub fn new(cable: C) -> Self {
+        LinkPort {
+            cable,
+            timeout: Self::DEFAULT_TIMEOUT,
+            received: None,
+        }
+    }
+
+    /// How long to wait for the other side when clocking a transfer.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn get_cable_mut(&mut self) -> &mut C {
+        &mut self.cable
+    }
+}
+
+impl<C: LinkCable> SerialDevice for LinkPort<C> {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        if self.cable.send(LinkMessage::Transfer(byte)).is_err() {
+            return 0xFF;
+        }
+        match self.cable.receive(self.timeout) {
+            Ok(Some(LinkMessage::Reply(reply))) => reply,
+            // both sides are masters, the bits crossed
+            Ok(Some(LinkMessage::Transfer(other))) => other,
+            Ok(None) | Err(_) => 0xFF,
+        }
+    }
+
+    fn poll(&mut self, data: u8) -> Option<u8> {
+        if self.received.is_none() {
+            // replies left from a timed out transfer are dropped
+            while let Ok(Some(message)) = self.cable.receive(Duration::ZERO) {
+                if let LinkMessage::Transfer(byte) = message {
+                    self.received = Some(byte);
+                    break;
+                }
+            }
+        }
+        let received = self.received.take()?;
+        self.cable.send(LinkMessage::Reply(data)).ok()?;
+        Some(received)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use crate::serial::SerialPort;
+
+    use super::{ChannelLink, LinkCable, LinkPort, TcpLink};
+
+    /// Wait for a transfer clocked by the other side.
+    fn slave<C: LinkCable + 'static>(cable: C, byte: u8) -> u8 {
+        let mut port = SerialPort::default();
+        port.set_device(Box::new(LinkPort::new(cable)));
+        port.set_data(byte);
+        port.set_control(0x80);
+        while !port.step(4) {}
+        port.get_data()
+    }
+
+    fn master<C: LinkCable + 'static>(cable: C, byte: u8) -> u8 {
+        let mut port = SerialPort::default();
+        port.set_device(Box::new(LinkPort::new(cable)));
+        port.set_data(byte);
+        port.set_control(0x81);
+        while !port.step(4) {}
+        port.get_data()
+    }
+
+    #[test]
+    fn channel() {
+        let (a, b) = ChannelLink::pair();
+        let other = thread::spawn(move || slave(b, 0x42));
+        assert_eq!(master(a, 0x17), 0x42);
+        assert_eq!(other.join().unwrap(), 0x17);
+
+        // both masters
+        let (a, b) = ChannelLink::pair();
+        let other = thread::spawn(move || master(b, 0x42));
+        assert_eq!(master(a, 0x17), 0x42);
+        assert_eq!(other.join().unwrap(), 0x17);
+    }
+
+    #[test]
+    fn tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let other = thread::spawn(move || slave(TcpLink::accept(&listener).unwrap(), 0x42));
+        assert_eq!(master(TcpLink::connect(addr).unwrap(), 0x17), 0x42);
+        assert_eq!(other.join().unwrap(), 0x17);
+    }
+}
@@ -0,0 +1,339 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Debug,
+    io::{self, Read, Write},
+    net::{Ipv4Addr, SocketAddrV4, TcpStream},
+};
+
+use super::SerialDevice;
+
+/// Where the TCP traffic of the adapter goes.
+pub trait MobileBridge: Debug {
+    fn open(&mut self, addr: SocketAddrV4) -> io::Result<()>;
+    /// Send the data and return what the server answered so far.
+    fn transfer(&mut self, data: &[u8]) -> io::Result<Vec<u8>>;
+    fn close(&mut self);
+}
+
+/// Bridge to real sockets of the host.
+#[derive(Debug, Default)]
+pub struct TcpBridge {
+    stream: Option<TcpStream>,
+}
+
+impl MobileBridge for TcpBridge {
+    fn open(&mut self, addr: SocketAddrV4) -> io::Result<()> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nonblocking(true)?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn transfer(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        stream.write_all(data)?;
+        let mut received = vec![0; 254];
+        let len = match stream.read(&mut received) {
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => 0,
+            Err(err) => return Err(err),
+        };
+        received.truncate(len);
+        Ok(received)
+    }
+
+    fn close(&mut self) {
+        self.stream = None;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReceiveState {
+    Magic,
+    MagicTwo,
+    /// Command, 2 unused bytes and length.
+    Header(u8),
+    Data,
+    Checksum(u8),
+}
+
+/// Stub of the Mobile Adapter GB, the cellphone adapter used by Pokémon Crystal JP and a few others.
+///
+/// It speaks the packet protocol of the adapter: the game sends a packet
+/// (magic 0x99 0x66, command, length, data, checksum), both sides acknowledge it,
+/// and the adapter answers with a packet of its own.
+/// Answers are canned, configurable per command, except for the configuration memory
+/// and the TCP commands that can be bridged to real sockets.
+#[derive(Debug)]
+pub struct MobileAdapter {
+    state: ReceiveState,
+    command: u8,
+    data: Vec<u8>,
+    length: u8,
+    checksum: u16,
+    /// Bytes to send back, takes priority over receiving.
+    outgoing: VecDeque<u8>,
+    responses: HashMap<u8, Vec<u8>>,
+    config: [u8; Self::CONFIG_SIZE],
+    bridge: Option<Box<dyn MobileBridge>>,
+}
+
+impl Default for MobileAdapter {
+    fn default() -> Self {
+        MobileAdapter {
+            state: ReceiveState::Magic,
+            command: 0,
+            data: Vec::new(),
+            length: 0,
+            checksum: 0,
+            outgoing: VecDeque::new(),
+            responses: HashMap::new(),
+            config: [0; Self::CONFIG_SIZE],
+            bridge: None,
+        }
+    }
+}
+
+impl MobileAdapter {
+    const MAGIC: [u8; 2] = [0x99, 0x66];
+    /// What the adapter sends while there is nothing to say.
+    const IDLE: u8 = 0xD2;
+    /// Blue adapter (PDC), the acknowledgement byte has bit 7 set.
+    const DEVICE_ID: u8 = 0x88;
+    const CHECKSUM_ERROR: u8 = 0xF1;
+    const CONFIG_SIZE: usize = 192;
+
+    pub const BEGIN_SESSION: u8 = 0x10;
+    pub const END_SESSION: u8 = 0x11;
+    pub const TRANSFER_DATA: u8 = 0x15;
+    pub const READ_CONFIG: u8 = 0x19;
+    pub const WRITE_CONFIG: u8 = 0x1A;
+    pub const OPEN_TCP: u8 = 0x23;
+    pub const CLOSE_TCP: u8 = 0x24;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer `command` with `data` instead of the default answer.
+    pub fn set_response(&mut self, command: u8, data: Vec<u8>) {
+        self.responses.insert(command, data);
+    }
+
+    /// The configuration memory, holding the user settings (ISP, email, ...).
+    pub fn get_config_mut(&mut self) -> &mut [u8] {
+        &mut self.config
+    }
+
+    /// Forward the TCP commands to `bridge` instead of answering them with canned data.
+    pub fn set_bridge(&mut self, bridge: Box<dyn MobileBridge>) {
+        self.bridge = Some(bridge);
+    }
+
+    fn receive(&mut self, byte: u8) {
+        self.state = match self.state {
+            ReceiveState::Magic if byte == Self::MAGIC[0] => ReceiveState::MagicTwo,
+            ReceiveState::Magic => ReceiveState::Magic,
+            ReceiveState::MagicTwo if byte == Self::MAGIC[1] => {
+                self.checksum = 0;
+                self.data.clear();
+                ReceiveState::Header(0)
+            }
+            ReceiveState::MagicTwo => ReceiveState::Magic,
+            ReceiveState::Header(index) => {
+                self.checksum = self.checksum.wrapping_add(byte.into());
+                match index {
+                    0 => self.command = byte,
+                    3 => self.length = byte,
+                    _ => {}
+                }
+                match (index, self.length) {
+                    (3, 0) => ReceiveState::Checksum(0),
+                    (3, _) => ReceiveState::Data,
+                    _ => ReceiveState::Header(index + 1),
+                }
+            }
+            ReceiveState::Data => {
+                self.checksum = self.checksum.wrapping_add(byte.into());
+                self.data.push(byte);
+                if self.data.len() == usize::from(self.length) {
+                    ReceiveState::Checksum(0)
+                } else {
+                    ReceiveState::Data
+                }
+            }
+            ReceiveState::Checksum(0) => {
+                self.checksum ^= u16::from(byte) << 8;
+                ReceiveState::Checksum(1)
+            }
+            ReceiveState::Checksum(_) => {
+                // the checksum matches if xoring it with the expected one gives 0
+                self.checksum ^= u16::from(byte);
+                self.packet_received();
+                ReceiveState::Magic
+            }
+        };
+    }
+
+    fn packet_received(&mut self) {
+        if self.checksum != 0 {
+            self.outgoing
+                .extend([Self::DEVICE_ID, Self::CHECKSUM_ERROR]);
+            return;
+        }
+        let reply = self.command ^ 0x80;
+        self.outgoing.extend([Self::DEVICE_ID, reply]);
+        let data = self.respond();
+        self.send_packet(reply, &data);
+    }
+
+    fn respond(&mut self) -> Vec<u8> {
+        if let Some(data) = self.responses.get(&self.command) {
+            return data.clone();
+        }
+        match self.command {
+            Self::BEGIN_SESSION => self.data.clone(),
+            Self::READ_CONFIG => match *self.data.as_slice() {
+                [offset, len, ..] => {
+                    let start = usize::from(offset).min(Self::CONFIG_SIZE);
+                    let end = (start + usize::from(len)).min(Self::CONFIG_SIZE);
+                    let mut data = vec![offset];
+                    data.extend_from_slice(&self.config[start..end]);
+                    data
+                }
+                _ => Vec::new(),
+            },
+            Self::WRITE_CONFIG => match self.data.split_first() {
+                Some((&offset, bytes)) => {
+                    let start = usize::from(offset).min(Self::CONFIG_SIZE);
+                    let end = (start + bytes.len()).min(Self::CONFIG_SIZE);
+                    self.config[start..end].copy_from_slice(&bytes[..end - start]);
+                    vec![offset, (end - start) as u8]
+                }
+                None => Vec::new(),
+            },
+            Self::OPEN_TCP => match (&mut self.bridge, self.data.as_slice()) {
+                (Some(bridge), &[a, b, c, d, port_high, port_low, ..]) => {
+                    let addr = Ipv4Addr::new(a, b, c, d);
+                    let port = u16::from_be_bytes([port_high, port_low]);
+                    match bridge.open(SocketAddrV4::new(addr, port)) {
+                        // connection id
+                        Ok(()) => vec![0x00],
+                        Err(_) => vec![0xFF],
+                    }
+                }
+                _ => vec![0x00],
+            },
+            Self::TRANSFER_DATA => match (&mut self.bridge, self.data.split_first()) {
+                (Some(bridge), Some((&connection, data))) => {
+                    let mut reply = vec![connection];
+                    if let Ok(received) = bridge.transfer(data) {
+                        reply.extend(received);
+                    }
+                    reply
+                }
+                (_, Some((&connection, _))) => vec![connection],
+                _ => Vec::new(),
+            },
+            Self::CLOSE_TCP | Self::END_SESSION => {
+                if let Some(bridge) = &mut self.bridge {
+                    bridge.close();
+                }
+                self.data.clone()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn send_packet(&mut self, command: u8, data: &[u8]) {
+        // the packets are limited to 254 bytes of data
+        let data = &data[..data.len().min(254)];
+        let header = [command, 0x00, 0x00, data.len() as u8];
+        let checksum = header
+            .iter()
+            .chain(data)
+            .fold(0u16, |sum, &byte| sum.wrapping_add(byte.into()));
+        self.outgoing.extend(Self::MAGIC);
+        self.outgoing.extend(header);
+        self.outgoing.extend(data);
+        self.outgoing.extend(checksum.to_be_bytes());
+        // acknowledgement, the game answers with its own id and the command
+        self.outgoing.extend([Self::DEVICE_ID, 0x00]);
+    }
+}
+
+impl SerialDevice for MobileAdapter {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        if let Some(out) = self.outgoing.pop_front() {
+            return out;
+        }
+        self.receive(byte);
+        Self::IDLE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::serial::SerialDevice;
+
+    use super::MobileAdapter;
+
+    fn packet(command: u8, data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x99, 0x66, command, 0x00, 0x00, data.len() as u8];
+        packet.extend_from_slice(data);
+        let checksum = packet[2..]
+            .iter()
+            .fold(0u16, |sum, &byte| sum.wrapping_add(byte.into()));
+        packet.extend(checksum.to_be_bytes());
+        packet
+    }
+
+    /// Send a packet and return what the adapter answered after it.
+    fn send(adapter: &mut MobileAdapter, packet: &[u8]) -> Vec<u8> {
+        for &byte in packet {
+            assert_eq!(adapter.exchange(byte), MobileAdapter::IDLE);
+        }
+        let mut answer = Vec::new();
+        // acknowledgement, then the whole answer while the game sends idle bytes
+        answer.push(adapter.exchange(0x81));
+        answer.push(adapter.exchange(0x00));
+        while let Some(&byte) = adapter.outgoing.front() {
+            answer.push(byte);
+            adapter.exchange(0x4B);
+        }
+        answer
+    }
+
+    #[test]
+    fn begin_session_handshake() {
+        let mut adapter = MobileAdapter::new();
+        let answer = send(&mut adapter, &packet(0x10, b"NINTENDO"));
+        let mut expected = vec![0x88, 0x90];
+        expected.extend(packet(0x90, b"NINTENDO"));
+        expected.extend([0x88, 0x00]);
+        assert_eq!(answer, expected);
+    }
+
+    #[test]
+    fn canned_response_and_config() {
+        let mut adapter = MobileAdapter::new();
+        adapter.set_response(0x17, vec![0x04]);
+        let answer = send(&mut adapter, &packet(0x17, &[]));
+        assert_eq!(&answer[2..answer.len() - 2], packet(0x97, &[0x04]));
+
+        adapter.get_config_mut()[..2].copy_from_slice(b"MA");
+        let answer = send(&mut adapter, &packet(0x19, &[0x00, 0x02]));
+        assert_eq!(&answer[2..answer.len() - 2], packet(0x99, b"\x00MA"));
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let mut adapter = MobileAdapter::new();
+        let mut packet = packet(0x10, b"NINTENDO");
+        *packet.last_mut().unwrap() ^= 1;
+        assert_eq!(send(&mut adapter, &packet), [0x88, 0xF1]);
+    }
+}
@@ -0,0 +1,227 @@
+use std::fmt::Debug;
+
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+pub use self::link::{Loopback, ScriptedPeer, SerialCallback, SerialCapture, Unplugged};
+pub use self::link_cable::{ChannelLink, LinkCable, LinkMessage, LinkPort, TcpLink};
+pub use self::mobile_adapter::{MobileAdapter, MobileBridge, TcpBridge};
+
+pub mod link;
+pub mod link_cable;
+pub mod mobile_adapter;
+
+/// Something plugged in the link port.
+///
+/// With the internal clock, the device answers each byte shifted out with the byte shifted in.
+/// With the external clock, only devices providing a clock (another Game Boy) complete the transfer.
+pub trait SerialDevice: Debug {
+    fn exchange(&mut self, byte: u8) -> u8;
+
+    /// Called regularly while the Game Boy waits for an external clock transfer, with SB.
+    ///
+    /// Returns the byte shifted in if the device clocked a transfer, which completes it.
+    fn poll(&mut self, _data: u8) -> Option<u8> {
+        None
+    }
+}
+
+/// The link port, SB (0xFF01) and SC (0xFF02) registers.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SerialPort {
+    /// SB
+    data: u8,
+    /// SC, bit 7 is the transfer flag and bit 0 the clock select.
+    control: u8,
+    /// Not part of the serialized state, comes back unplugged.
+    #[cfg_attr(feature = "serde", serde(skip, default = "unplugged"))]
+    device: Box<dyn SerialDevice>,
+    /// Clock cycles until the transfer in progress completes.
+    remaining_cycles: u32,
+    /// Clock cycles since the device was last polled for an external clock transfer.
+    poll_cycles: u32,
+    /// Byte of the last completed transfer not taken yet.
+    sent: Option<u8>,
+}
+
+#[cfg(feature = "serde")]
+fn unplugged() -> Box<dyn SerialDevice> {
+    Box::new(Unplugged)
+}
+
+impl Default for SerialPort {
+    fn default() -> Self {
+        SerialPort {
+            data: 0,
+            control: 0,
+            device: Box::new(Unplugged),
+            remaining_cycles: 0,
+            poll_cycles: 0,
+            sent: None,
+        }
+    }
+}
+
+impl SerialPort {
+    pub const DATA_REGISTER: u16 = 0xFF01;
+    pub const CONTROL_REGISTER: u16 = 0xFF02;
+
+    const TRANSFER_MASK: u8 = 0x80;
+    const INTERNAL_CLOCK_MASK: u8 = 0x01;
+    /// 8 bits at 8192Hz
+    const TRANSFER_CYCLES: u32 = 8 * 512;
+    /// The device is polled once per bit time while waiting for an external clock.
+    const POLL_CYCLES: u32 = 512;
+
+    pub fn get_data(&self) -> u8 {
+        self.data
+    }
+
+    pub fn set_data(&mut self, value: u8) {
+        self.data = value;
+    }
+
+    pub fn get_control(&self) -> u8 {
+        // unused bits read as 1
+        self.control | 0x7E
+    }
+
+    pub fn set_control(&mut self, value: u8) {
+        self.control = value & (Self::TRANSFER_MASK | Self::INTERNAL_CLOCK_MASK);
+        let internal_clock = value & Self::INTERNAL_CLOCK_MASK != 0;
+        self.remaining_cycles = if value & Self::TRANSFER_MASK != 0 && internal_clock {
+            Self::TRANSFER_CYCLES
+        } else {
+            // with the external clock, nothing happens until a peer clocks the transfer
+            0
+        };
+    }
+
+    fn is_waiting_external_clock(&self) -> bool {
+        self.control & (Self::TRANSFER_MASK | Self::INTERNAL_CLOCK_MASK) == Self::TRANSFER_MASK
+    }
+
+    /// Plug a device, returning the previous one.
+    pub fn set_device(&mut self, device: Box<dyn SerialDevice>) -> Box<dyn SerialDevice> {
+        std::mem::replace(&mut self.device, device)
+    }
+
+    pub fn get_device_mut(&mut self) -> &mut dyn SerialDevice {
+        self.device.as_mut()
+    }
+
+    /// The byte the console sent in the last completed transfer, if not taken since.
+    pub fn take_sent_byte(&mut self) -> Option<u8> {
+        self.sent.take()
+    }
+
+    /// The plugged device is not part of the state.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.put_u8(self.data);
+        state.put_u8(self.control);
+        state.put_u32(self.remaining_cycles);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.data = state.get_u8()?;
+        self.control = state.get_u8()?;
+        self.remaining_cycles = state.get_u32()?;
+        Ok(())
+    }
+
+    /// Returns true when a transfer completes, to request the serial interrupt.
+    pub fn step(&mut self, cycles: u32) -> bool {
+        if self.is_waiting_external_clock() {
+            self.poll_cycles += cycles;
+            if self.poll_cycles < Self::POLL_CYCLES {
+                return false;
+            }
+            self.poll_cycles = 0;
+            let Some(byte) = self.device.poll(self.data) else {
+                return false;
+            };
+            self.sent = Some(self.data);
+            self.data = byte;
+            self.control &= !Self::TRANSFER_MASK;
+            return true;
+        }
+        if self.remaining_cycles == 0 {
+            return false;
+        }
+        self.remaining_cycles = self.remaining_cycles.saturating_sub(cycles);
+        if self.remaining_cycles > 0 {
+            return false;
+        }
+        self.sent = Some(self.data);
+        self.data = self.device.exchange(self.data);
+        self.control &= !Self::TRANSFER_MASK;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Loopback, ScriptedPeer, SerialCapture, SerialPort};
+
+    fn transfer(port: &mut SerialPort, byte: u8) -> u8 {
+        port.set_data(byte);
+        port.set_control(0x81);
+        let mut cycles = 0;
+        while !port.step(4) {
+            cycles += 4;
+        }
+        assert_eq!(cycles + 4, SerialPort::TRANSFER_CYCLES);
+        assert_eq!(port.get_control(), 0x7F);
+        port.get_data()
+    }
+
+    #[test]
+    fn unplugged_reads_ff() {
+        let mut port = SerialPort::default();
+        assert_eq!(transfer(&mut port, 0x42), 0xFF);
+    }
+
+    #[test]
+    fn loopback() {
+        let mut port = SerialPort::default();
+        port.set_device(Box::new(Loopback));
+        assert_eq!(transfer(&mut port, 0x42), 0x42);
+    }
+
+    #[test]
+    fn scripted_peer() {
+        let mut port = SerialPort::default();
+        let peer = ScriptedPeer::new([0x01, 0x02]);
+        port.set_device(Box::new(peer.clone()));
+        assert_eq!(transfer(&mut port, 0x10), 0x01);
+        assert!(!peer.is_done());
+        assert_eq!(transfer(&mut port, 0x20), 0x02);
+        assert!(peer.is_done());
+        // the script is over, like if the peer was unplugged
+        assert_eq!(transfer(&mut port, 0x30), 0xFF);
+        assert_eq!(peer.get_sent(), [0x10, 0x20, 0x30]);
+    }
+
+    #[test]
+    fn external_clock_waits_for_the_peer() {
+        let mut port = SerialPort::default();
+        port.set_device(Box::new(Loopback));
+        port.set_data(0x42);
+        port.set_control(0x80);
+        for _ in 0..10_000 {
+            assert!(!port.step(4));
+        }
+        assert_eq!(port.get_control(), 0xFE);
+    }
+
+    #[test]
+    fn capture() {
+        let capture = SerialCapture::new();
+        let mut port = SerialPort::default();
+        port.set_device(Box::new(capture.clone()));
+        for &byte in b"Passed" {
+            assert_eq!(transfer(&mut port, byte), 0xFF);
+        }
+        assert_eq!(capture.get_text(), "Passed");
+    }
+}
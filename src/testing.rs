@@ -0,0 +1,124 @@
+//! Running test ROMs headless, to check the emulator in CI.
+
+use std::fmt::Display;
+
+use crate::{
+    cpu::{registers::Register, Cpu},
+    debugger::Debugger,
+    emulator::{Emulator, StopReason},
+};
+
+/// How a test ROM ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestOutcome {
+    Passed,
+    Failed,
+    /// The ROM didn't report anything within the budget.
+    Timeout,
+    /// The emulation stopped for something else, like an illegal opcode.
+    Stopped(StopReason),
+}
+
+impl TestOutcome {
+    pub fn is_passed(self) -> bool {
+        self == TestOutcome::Passed
+    }
+}
+
+impl Display for TestOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TestOutcome::Passed => write!(f, "passed"),
+            TestOutcome::Failed => write!(f, "failed"),
+            TestOutcome::Timeout => write!(f, "timed out"),
+            TestOutcome::Stopped(reason) => write!(f, "stopped: {:?}", reason),
+        }
+    }
+}
+
+/// What the Mooneye ROMs put in B, C, D, E, H and L before their final `LD B, B` when passing,
+/// the start of the Fibonacci sequence. They put 0x42 everywhere when failing.
+pub const MOONEYE_PASS_SIGNATURE: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+const MOONEYE_REGISTERS: [Register; 6] = [
+    Register::B,
+    Register::C,
+    Register::D,
+    Register::E,
+    Register::H,
+    Register::L,
+];
+
+/// The outcome told by the registers once a Mooneye ROM reached its `LD B, B`.
+pub fn get_mooneye_outcome(cpu: &Cpu) -> TestOutcome {
+    let signature = MOONEYE_REGISTERS.map(|reg| cpu.get_reg(reg));
+    if signature == MOONEYE_PASS_SIGNATURE {
+        TestOutcome::Passed
+    } else {
+        TestOutcome::Failed
+    }
+}
+
+/// Run a Mooneye test ROM until it executes `LD B, B`, for at most `frame_budget` frames.
+///
+/// The ROMs expect the state left by the boot ROM, build the emulator with
+/// `BootMode::Hle` or `BootMode::Skip`. The other breakpoints are left as they are,
+/// one hit ends the run with `TestOutcome::Stopped`.
+pub fn run_mooneye(emulator: &mut Emulator, frame_budget: u64) -> TestOutcome {
+    let debugger = emulator.get_debugger_mut();
+    let break_on_ld_b_b = debugger.get_break_on_ld_b_b();
+    debugger.set_break_on_ld_b_b(true);
+    let mut outcome = TestOutcome::Timeout;
+    for _ in 0..frame_budget {
+        match emulator.run_frame() {
+            StopReason::FrameComplete => {}
+            StopReason::Breakpoint(pc)
+                if emulator.get_cpu().peek_memory(pc) == Debugger::MAGIC_BREAKPOINT =>
+            {
+                outcome = get_mooneye_outcome(emulator.get_cpu());
+                break;
+            }
+            reason => {
+                outcome = TestOutcome::Stopped(reason);
+                break;
+            }
+        }
+    }
+    emulator
+        .get_debugger_mut()
+        .set_break_on_ld_b_b(break_on_ld_b_b);
+    outcome
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_mooneye, TestOutcome};
+    use crate::emulator::{Emulator, StopReason};
+
+    #[test]
+    fn mooneye() {
+        // LD B, 3; LD C, 5; LD D, 8; LD E, 13; LD H, 21; LD L, 34; LD B, B
+        let passing = [
+            0x06, 3, 0x0E, 5, 0x16, 8, 0x1E, 13, 0x26, 21, 0x2E, 34, 0x40,
+        ];
+        let mut emulator = Emulator::from_program(&passing);
+        assert_eq!(run_mooneye(&mut emulator, 10), TestOutcome::Passed);
+        assert!(!emulator.get_debugger().get_break_on_ld_b_b());
+
+        // LD B, 0x42; LD C, 0x42; LD B, B
+        let mut emulator = Emulator::from_program(&[0x06, 0x42, 0x0E, 0x42, 0x40]);
+        assert_eq!(run_mooneye(&mut emulator, 10), TestOutcome::Failed);
+
+        // JR -2
+        let mut emulator = Emulator::from_program(&[0x18, 0xFE]);
+        assert_eq!(run_mooneye(&mut emulator, 10), TestOutcome::Timeout);
+
+        let mut emulator = Emulator::from_program(&[0xD3]);
+        let outcome = run_mooneye(&mut emulator, 10);
+        assert!(matches!(
+            outcome,
+            TestOutcome::Stopped(StopReason::IllegalOpcode { pc: 0, .. })
+        ));
+        assert!(!outcome.is_passed());
+    }
+}
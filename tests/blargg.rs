@@ -0,0 +1,320 @@
+use std::{cell::RefCell, env, fs, io, path::PathBuf, rc::Rc};
+
+use gb_emul::{
+    config::{BootMode, EmuConfig},
+    emulator::{Emulator, StopReason},
+    recording::{AudioFingerprint, Encoder, Frame, Recorder},
+    serial::SerialCapture,
+};
+
+/// About 3 minutes of emulated time, each suite takes less than one.
+const FRAME_BUDGET: u64 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Passed,
+    Failed,
+}
+
+/// Where a ROM writes its results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    /// Printed to the link port.
+    Serial,
+    /// Written to the cartridge RAM, for the ROMs without a link port output like the sound ones.
+    Memory,
+}
+
+/// A ROM of the regression manifest, with what it prints on the DMG.
+///
+/// Some of them don't pass on it, like interrupt_time which needs the CGB double speed.
+/// The outcome is only filled in once a run of the ROM confirmed it, until then the test
+/// only requires the ROM to print a result.
+struct BlarggRom {
+    /// Environment variable overriding the path of the ROM.
+    var: &'static str,
+    /// File name in the ROM directory, see `get_path`.
+    file: &'static str,
+    output: Output,
+    dmg: Option<Outcome>,
+}
+
+const CPU_INSTRS: BlarggRom = BlarggRom {
+    var: "CPU_INSTRS_ROM",
+    output: Output::Serial,
+    file: "cpu_instrs.gb",
+    dmg: Some(Outcome::Passed),
+};
+
+const INSTR_TIMING: BlarggRom = BlarggRom {
+    var: "INSTR_TIMING_ROM",
+    output: Output::Serial,
+    file: "instr_timing.gb",
+    dmg: None,
+};
+
+const MEM_TIMING: BlarggRom = BlarggRom {
+    var: "MEM_TIMING_ROM",
+    output: Output::Serial,
+    file: "mem_timing.gb",
+    dmg: None,
+};
+
+const HALT_BUG: BlarggRom = BlarggRom {
+    var: "HALT_BUG_ROM",
+    output: Output::Serial,
+    file: "halt_bug.gb",
+    dmg: None,
+};
+
+const INTERRUPT_TIME: BlarggRom = BlarggRom {
+    var: "INTERRUPT_TIME_ROM",
+    output: Output::Serial,
+    file: "interrupt_time.gb",
+    dmg: Some(Outcome::Failed),
+};
+
+const DMG_SOUND: BlarggRom = BlarggRom {
+    var: "DMG_SOUND_ROM",
+    file: "dmg_sound.gb",
+    output: Output::Memory,
+    dmg: None,
+};
+
+/// Start of the results in the cartridge RAM: the status, the signature, then the text.
+const RESULT_ADDR: u16 = 0xA000;
+
+/// Written after the status once the results in memory are valid.
+const RESULT_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+
+/// Status while the tests are still running.
+const RESULT_RUNNING: u8 = 0x80;
+
+impl BlarggRom {
+    /// Blargg's test ROMs aren't distributed with the crate, set the environment variable
+    /// of the ROM, or put it in the directory of `BLARGG_ROMS` or in `roms/`.
+    fn get_path(&self) -> PathBuf {
+        if let Some(path) = env::var_os(self.var) {
+            return PathBuf::from(path);
+        }
+        let dir = env::var_os("BLARGG_ROMS")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("roms"));
+        dir.join(self.file)
+    }
+
+    /// Whether the ROM is there. With the `blargg` feature a missing ROM fails the test,
+    /// only the ignored tests run by hand skip it.
+    fn is_available(&self) -> bool {
+        let path = self.get_path();
+        if path.exists() {
+            return true;
+        }
+        let message = format!(
+            "{}: {:?} not found, set {} or BLARGG_ROMS",
+            self.file, path, self.var
+        );
+        if cfg!(feature = "blargg") {
+            panic!("{}", message);
+        }
+        eprintln!("skipping {}", message);
+        false
+    }
+
+    /// Run on the DMG, the only model emulated for now, and check the outcome.
+    fn check(&self) {
+        if !self.is_available() {
+            return;
+        }
+        let (outcome, output) = self.run();
+        match self.dmg {
+            Some(expected) => assert_eq!(outcome, expected, "{}:\n{}", self.file, output),
+            None => eprintln!("{}: {:?}, not checked yet\n{}", self.file, outcome, output),
+        }
+    }
+
+    fn load(&self) -> Emulator {
+        let path = self.get_path();
+        let rom = fs::read(&path).unwrap_or_else(|err| panic!("can't read {:?}: {}", path, err));
+        // the ROMs expect the state left by the boot ROM
+        let config = EmuConfig {
+            boot: BootMode::Hle,
+            ..Default::default()
+        };
+        Emulator::from_rom_with_config(rom, config).unwrap()
+    }
+
+    /// Blargg's ROMs print their results, "Passed" or "Failed" at the end.
+    fn run(&self) -> (Outcome, String) {
+        let mut emulator = self.load();
+        let capture = SerialCapture::new();
+        emulator.set_serial_device(Box::new(capture.clone()));
+
+        for _ in 0..FRAME_BUDGET {
+            let reason = emulator.run_frame_guarded().unwrap();
+            assert_eq!(reason, StopReason::FrameComplete, "{}", capture.get_text());
+            let output = match self.output {
+                Output::Serial => capture.get_text(),
+                Output::Memory => match read_memory_result(&emulator) {
+                    Some(output) => output,
+                    None => continue,
+                },
+            };
+            if output.contains("Passed") {
+                return (Outcome::Passed, output);
+            }
+            if output.contains("Failed") {
+                return (Outcome::Failed, output);
+            }
+        }
+        panic!(
+            "no result after {} frames:\n{}",
+            FRAME_BUDGET,
+            capture.get_text()
+        );
+    }
+}
+
+/// Samples of a recording, still readable once the encoder is given to the `Recorder`.
+#[derive(Default, Clone)]
+struct SampleCapture(Rc<RefCell<Vec<i16>>>);
+
+impl Encoder for SampleCapture {
+    fn encode_frame(&mut self, _frame: &Frame) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn encode_audio(&mut self, samples: &[i16]) -> io::Result<()> {
+        self.0.borrow_mut().extend_from_slice(samples);
+        Ok(())
+    }
+}
+
+/// Rate and length of the recordings compared against the golden fingerprints.
+const FINGERPRINT_RATE: u32 = 48000;
+const FINGERPRINT_FRAMES: u64 = 1200;
+const FINGERPRINT_TOLERANCE: f32 = 0.01;
+
+/// Record the audio of a ROM and compare its fingerprint with the one in `tests/golden/`.
+///
+/// With `BLARGG_BLESS` set the golden file is written from this run instead, check the ROM
+/// passes before committing it. A missing golden file fails.
+fn check_fingerprint(rom: &BlarggRom) {
+    if !rom.is_available() {
+        return;
+    }
+    let mut emulator = rom.load();
+    emulator.set_audio_sample_rate(FINGERPRINT_RATE);
+    let capture = SampleCapture::default();
+    let mut recorder = Recorder::new(Box::new(capture.clone()));
+    recorder
+        .record(&mut emulator, FINGERPRINT_FRAMES)
+        .unwrap_or_else(|err| panic!("{}: {}", rom.file, err));
+    let samples = AudioFingerprint::downmix(&capture.0.borrow());
+    let fingerprint = AudioFingerprint::new(&samples);
+
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(rom.file)
+        .with_extension("fingerprint");
+    if env::var_os("BLARGG_BLESS").is_some() {
+        let [low, mid, high] = fingerprint.bands;
+        let line = format!(
+            "{} {} {} {} {}\n",
+            fingerprint.rms, fingerprint.zero_crossing_rate, low, mid, high
+        );
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, line).unwrap();
+        eprintln!("wrote the golden fingerprint {:?}", path);
+        return;
+    }
+    let golden = fs::read_to_string(&path).unwrap_or_else(|err| {
+        panic!(
+            "{}: can't read the golden fingerprint {:?}: {}, write it with BLARGG_BLESS=1",
+            rom.file, path, err
+        )
+    });
+    let values: Vec<f32> = golden
+        .split_whitespace()
+        .map(|value| value.parse().unwrap())
+        .collect();
+    let [rms, zero_crossing_rate, low, mid, high] = values[..] else {
+        panic!("{:?}: expected 5 values", path);
+    };
+    let golden = AudioFingerprint {
+        rms,
+        zero_crossing_rate,
+        bands: [low, mid, high],
+    };
+    assert!(
+        fingerprint.matches(&golden, FINGERPRINT_TOLERANCE),
+        "{}: {:?}, expected {:?}",
+        rom.file,
+        fingerprint,
+        golden
+    );
+}
+
+/// The text written to the cartridge RAM, once the ROM is done.
+fn read_memory_result(emulator: &Emulator) -> Option<String> {
+    let bus = emulator.get_cpu().get_bus();
+    let status = bus.get(RESULT_ADDR);
+    let signature = [1, 2, 3].map(|i| bus.get(RESULT_ADDR + i));
+    if signature != RESULT_SIGNATURE || status == RESULT_RUNNING {
+        return None;
+    }
+    let text: Vec<_> = (RESULT_ADDR + 4..0xC000)
+        .map(|addr| bus.get(addr))
+        .take_while(|&byte| byte != 0)
+        .collect();
+    Some(String::from_utf8_lossy(&text).into_owned())
+}
+
+/// Run with `cargo test --release --features blargg`, or with `-- --ignored`.
+#[test]
+#[cfg_attr(not(feature = "blargg"), ignore = "needs the cpu_instrs ROM")]
+fn cpu_instrs() {
+    CPU_INSTRS.check();
+}
+
+/// Cycles taken by each instruction, measured with the timer.
+#[test]
+#[cfg_attr(not(feature = "blargg"), ignore = "needs the instr_timing ROM")]
+fn instr_timing() {
+    INSTR_TIMING.check();
+}
+
+/// On which M-cycle of an instruction its memory accesses happen.
+#[test]
+#[cfg_attr(not(feature = "blargg"), ignore = "needs the mem_timing ROM")]
+fn mem_timing() {
+    MEM_TIMING.check();
+}
+
+/// HALT with IME off and an interrupt pending doesn't increment PC after the next fetch.
+#[test]
+#[cfg_attr(not(feature = "blargg"), ignore = "needs the halt_bug ROM")]
+fn halt_bug() {
+    HALT_BUG.check();
+}
+
+/// Interrupt timing in normal and double speed, the DMG lacking the latter fails.
+#[test]
+#[cfg_attr(not(feature = "blargg"), ignore = "needs the interrupt_time ROM")]
+fn interrupt_time() {
+    INTERRUPT_TIME.check();
+}
+
+/// The sound registers and channels, its results are in the cartridge RAM.
+#[test]
+#[cfg_attr(not(feature = "blargg"), ignore = "needs the dmg_sound ROM")]
+fn dmg_sound() {
+    DMG_SOUND.check();
+}
+
+/// What the sound ROM plays, against its golden recording.
+#[test]
+#[cfg_attr(not(feature = "blargg"), ignore = "needs the dmg_sound ROM")]
+fn dmg_sound_fingerprint() {
+    check_fingerprint(&DMG_SOUND);
+}
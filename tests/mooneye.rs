@@ -0,0 +1,71 @@
+use std::{env, fs, path::PathBuf};
+
+use gb_emul::{
+    config::{BootMode, EmuConfig},
+    emulator::Emulator,
+    testing::{self, TestOutcome},
+};
+
+/// About 2 minutes of emulated time, the acceptance tests take a few seconds.
+const FRAME_BUDGET: u64 = 7_200;
+
+/// Mooneye's test ROMs aren't distributed with the crate, set `MOONEYE_ROMS`
+/// to a directory of them, like the `acceptance` one of the suite, or put them in `roms/mooneye/`.
+fn get_roms_dir() -> PathBuf {
+    env::var_os("MOONEYE_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("roms")
+                .join("mooneye")
+        })
+}
+
+/// The `.gb` files of `dir` and of its subdirectories.
+fn find_roms(dir: &PathBuf, roms: &mut Vec<PathBuf>) {
+    let entries = fs::read_dir(dir).unwrap_or_else(|err| panic!("can't read {:?}: {}", dir, err));
+    for entry in entries {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            find_roms(&path, roms);
+        } else if path.extension().is_some_and(|ext| ext == "gb") {
+            roms.push(path);
+        }
+    }
+}
+
+fn run(rom: Vec<u8>) -> TestOutcome {
+    let config = EmuConfig {
+        boot: BootMode::Hle,
+        ..Default::default()
+    };
+    let mut emulator = Emulator::from_rom_with_config(rom, config).unwrap();
+    testing::run_mooneye(&mut emulator, FRAME_BUDGET)
+}
+
+/// Run with `cargo test --release --test mooneye -- --ignored`,
+/// every ROM is run and the ones not passing are listed.
+#[test]
+#[ignore = "needs the Mooneye test ROMs"]
+fn mooneye() {
+    let dir = get_roms_dir();
+    let mut roms = Vec::new();
+    find_roms(&dir, &mut roms);
+    roms.sort();
+    let mut failures = Vec::new();
+    for path in &roms {
+        let outcome = run(fs::read(path).unwrap());
+        let name = path.strip_prefix(&dir).unwrap_or(path).display();
+        eprintln!("{}: {}", name, outcome);
+        if !outcome.is_passed() {
+            failures.push(name.to_string());
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} of {} ROMs didn't pass: {}",
+        failures.len(),
+        roms.len(),
+        failures.join(" ")
+    );
+}
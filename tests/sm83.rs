@@ -0,0 +1,313 @@
+//! The SingleStepTests SM83 suite: thousands of random machine states per opcode,
+//! each run for one instruction on a flat bus and compared register by register
+//! and M-cycle by M-cycle.
+//!
+//! The tests model the fetch overlap of the CPU: the opcode was fetched by the
+//! previous instruction, so the initial PC is one past it, and the last M-cycle
+//! fetches the next opcode. The CPU here fetches its own opcode instead,
+//! the runner shifts the PC and the cycles to line them up.
+
+use std::{
+    env,
+    fmt::Debug,
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+};
+
+use gb_emul::{
+    cpu::{
+        registers::{LongRegister, Register},
+        Cpu,
+    },
+    instructions::Instruction,
+    memory::bus::{BusAccess, FlatBus},
+};
+use serde_json::{value::Index, Value};
+
+/// Failures printed per file, the rest are only counted.
+const REPORTED_FAILURES: usize = 5;
+
+const REGISTERS: [(&str, Register); 8] = [
+    ("a", Register::A),
+    ("b", Register::B),
+    ("c", Register::C),
+    ("d", Register::D),
+    ("e", Register::E),
+    ("f", Register::F),
+    ("h", Register::H),
+    ("l", Register::L),
+];
+
+/// What a test expects of an M-cycle, `None` when the bus is idle.
+type Cycle = Option<(char, u16, u8)>;
+
+/// `key` is a field name or an array index.
+fn get_u16<K: Index + Debug>(state: &Value, key: K) -> Result<u16, String> {
+    state
+        .get(&key)
+        .and_then(Value::as_u64)
+        .and_then(|value| u16::try_from(value).ok())
+        .ok_or_else(|| format!("missing or invalid {:?}", key))
+}
+
+fn get_u8<K: Index + Debug>(state: &Value, key: K) -> Result<u8, String> {
+    get_u16(state, key).and_then(|value| u8::try_from(value).map_err(|err| err.to_string()))
+}
+
+fn get_ram(state: &Value) -> Result<Vec<(u16, u8)>, String> {
+    let ram = state["ram"].as_array().ok_or("missing ram")?;
+    ram.iter()
+        .map(|entry| Ok((get_u16(entry, 0)?, get_u8(entry, 1)?)))
+        .collect()
+}
+
+fn setup(initial: &Value) -> Result<Cpu<FlatBus>, String> {
+    let mut cpu = Cpu::new(FlatBus::default());
+    for (name, reg) in REGISTERS {
+        cpu.put_reg(reg, get_u8(initial, name)?);
+    }
+    cpu.put_long_reg(LongRegister::SP, get_u16(initial, "sp")?);
+    // back on the opcode the test considers fetched
+    cpu.set_pc(get_u16(initial, "pc")?.wrapping_sub(1));
+    if get_u8(initial, "ime")? != 0 {
+        cpu.enable_interrupts();
+    }
+    let bus = cpu.get_bus_mut();
+    for (addr, value) in get_ram(initial)? {
+        bus.memory[addr as usize] = value;
+    }
+    bus.memory[0xFFFF] = get_u8(initial, "ie")?;
+    Ok(cpu)
+}
+
+fn get_cycles(test: &Value) -> Result<Vec<Cycle>, String> {
+    let cycles = test["cycles"].as_array().ok_or("missing cycles")?;
+    cycles
+        .iter()
+        .map(|cycle| {
+            if cycle.is_null() {
+                return Ok(None);
+            }
+            let pins = cycle[2].as_str().ok_or("invalid cycle")?;
+            let kind = if pins.contains('r') {
+                'r'
+            } else if pins.contains('w') {
+                'w'
+            } else {
+                return Ok(None);
+            };
+            Ok(Some((kind, get_u16(cycle, 0)?, get_u8(cycle, 1)?)))
+        })
+        .collect()
+}
+
+/// The accesses of the M-cycles after the opcode fetch, as the test lists them.
+fn get_accesses(bus: &FlatBus) -> Vec<Cycle> {
+    // the access of the M-cycle `m` is stamped at the end of it, (m + 1) * 4
+    let count = (bus.cycles / 4) as usize;
+    let mut cycles = vec![None; count.saturating_sub(1)];
+    for access in &bus.accesses {
+        let (kind, cycle, addr, value) = match *access {
+            BusAccess::Read { cycle, addr, value } => ('r', cycle, addr, value),
+            BusAccess::Write { cycle, addr, value } => ('w', cycle, addr, value),
+        };
+        let m = (cycle / 4) as usize;
+        if m >= 2 {
+            cycles[m - 2] = Some((kind, addr, value));
+        }
+    }
+    cycles
+}
+
+fn check(cpu: &Cpu<FlatBus>, test: &Value) -> Result<(), String> {
+    let expected = &test["final"];
+    let mut errors = Vec::new();
+    for (name, reg) in REGISTERS {
+        let (found, wanted) = (cpu.get_reg(reg), get_u8(expected, name)?);
+        if found != wanted {
+            errors.push(format!(
+                "{}: {:#04X} instead of {:#04X}",
+                name, found, wanted
+            ));
+        }
+    }
+    let sp = cpu.get_long_reg(LongRegister::SP);
+    if sp != get_u16(expected, "sp")? {
+        errors.push(format!("sp: {:#06X}", sp));
+    }
+    // the test already fetched the next opcode
+    let pc = cpu.get_pc().wrapping_add(1);
+    if pc != get_u16(expected, "pc")? {
+        errors.push(format!("pc: {:#06X}", pc));
+    }
+    if let Ok(ime) = get_u8(expected, "ime") {
+        if cpu.get_ime() != (ime != 0) {
+            errors.push(format!("ime: {}", cpu.get_ime()));
+        }
+    }
+    let bus = cpu.get_bus();
+    for (addr, value) in get_ram(expected)? {
+        let found = bus.memory[addr as usize];
+        if found != value {
+            errors.push(format!(
+                "[{:#06X}]: {:#04X} instead of {:#04X}",
+                addr, found, value
+            ));
+        }
+    }
+
+    let cycles = get_cycles(test)?;
+    if (bus.cycles / 4) as usize != cycles.len() {
+        errors.push(format!(
+            "{} M-cycles instead of {}",
+            bus.cycles / 4,
+            cycles.len()
+        ));
+    } else {
+        let accesses = get_accesses(bus);
+        // the last cycle is the fetch of the next opcode
+        let expected = &cycles[..cycles.len().saturating_sub(1)];
+        for (m, (found, wanted)) in accesses.iter().zip(expected).enumerate() {
+            if found != wanted {
+                errors.push(format!(
+                    "M-cycle {}: {:X?} instead of {:X?}",
+                    m + 1,
+                    found,
+                    wanted
+                ));
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join(", "))
+    }
+}
+
+fn run_test(test: &Value) -> Result<(), String> {
+    let mut cpu = setup(&test["initial"])?;
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        Instruction::fetch(&mut cpu).map(|instruction| instruction.execute(&mut cpu))
+    }));
+    match result {
+        Ok(Some(())) => check(&cpu, test),
+        Ok(None) => Err("illegal opcode".to_string()),
+        Err(_) => Err("panicked".to_string()),
+    }
+}
+
+/// Run the tests of a file, returns the failures as `name: errors`.
+fn run_tests(tests: &Value) -> Vec<String> {
+    let Some(tests) = tests.as_array() else {
+        return vec!["not an array of tests".to_string()];
+    };
+    tests
+        .iter()
+        .filter_map(|test| {
+            run_test(test)
+                .err()
+                .map(|err| format!("{}: {}", test["name"].as_str().unwrap_or("?"), err))
+        })
+        .collect()
+}
+
+/// The suite isn't distributed with the crate, set `SM83_TESTS` to the directory
+/// of its JSON files or put them in `roms/sm83/`.
+fn get_tests_dir() -> PathBuf {
+    env::var_os("SM83_TESTS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("roms")
+                .join("sm83")
+        })
+}
+
+/// Run with `cargo test --release --test sm83 -- --ignored`.
+#[test]
+#[ignore = "needs the SM83 JSON tests"]
+fn sm83() {
+    let dir = get_tests_dir();
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .unwrap_or_else(|err| panic!("can't read {:?}: {}", dir, err))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    // a panicking opcode is already reported as a failure, keep the output readable
+    panic::set_hook(Box::new(|_| {}));
+    let mut failed_files = Vec::new();
+    for path in &paths {
+        let tests: Value = serde_json::from_slice(&fs::read(path).unwrap())
+            .unwrap_or_else(|err| panic!("invalid {:?}: {}", path, err));
+        let failures = run_tests(&tests);
+        if failures.is_empty() {
+            continue;
+        }
+        let name = path.file_name().unwrap().to_string_lossy();
+        eprintln!("{}: {} failed", name, failures.len());
+        for failure in failures.iter().take(REPORTED_FAILURES) {
+            eprintln!("  {}", failure);
+        }
+        failed_files.push(name.into_owned());
+    }
+    let _ = panic::take_hook();
+    assert!(
+        failed_files.is_empty(),
+        "{} of {} opcodes failed: {}",
+        failed_files.len(),
+        paths.len(),
+        failed_files.join(" ")
+    );
+}
+
+/// Two hand written tests in the format of the suite, to keep the runner honest.
+#[test]
+fn runner() {
+    let tests: Value = serde_json::from_str(
+        r#"[
+            {
+                "name": "80 add a, b",
+                "initial": {
+                    "a": 58, "b": 198, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0,
+                    "pc": 257, "sp": 65534, "ime": 0, "ie": 0,
+                    "ram": [[256, 128], [257, 0]]
+                },
+                "final": {
+                    "a": 0, "b": 198, "c": 0, "d": 0, "e": 0, "f": 176, "h": 0, "l": 0,
+                    "pc": 258, "sp": 65534, "ime": 0, "ie": 0,
+                    "ram": [[256, 128], [257, 0]]
+                },
+                "cycles": [[257, 0, "r-m"]]
+            },
+            {
+                "name": "77 ld [hl], a",
+                "initial": {
+                    "a": 66, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 192, "l": 0,
+                    "pc": 513, "sp": 65534, "ime": 0, "ie": 0,
+                    "ram": [[512, 119], [513, 0], [49152, 0]]
+                },
+                "final": {
+                    "a": 66, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 192, "l": 0,
+                    "pc": 514, "sp": 65534, "ime": 0, "ie": 0,
+                    "ram": [[512, 119], [513, 0], [49152, 66]]
+                },
+                "cycles": [[49152, 66, "-wm"], [513, 0, "r-m"]]
+            }
+        ]"#,
+    )
+    .unwrap();
+    assert_eq!(run_tests(&tests), Vec::<String>::new());
+
+    let mut wrong = tests.clone();
+    wrong[1]["final"]["ram"][2][1] = 0.into();
+    wrong[1]["cycles"][0] = Value::Null;
+    assert_eq!(
+        run_tests(&wrong),
+        ["77 ld [hl], a: [0xC000]: 0x42 instead of 0x00, \
+             M-cycle 1: Some(('w', C000, 42)) instead of None"]
+    );
+}